@@ -0,0 +1,130 @@
+//! gRPC service streaming the sampler's verification events
+use std::{net::SocketAddr, pin::Pin};
+
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use tiny_logger::logs::info;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    sampler::{self, VerificationEvent},
+    tinydancer::ClientService,
+};
+
+pub mod proto {
+    tonic::include_proto!("tinydancer.verification");
+}
+
+use proto::verification_server::{Verification, VerificationServer};
+
+pub struct GrpcService {
+    grpc_handle: JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+pub struct GrpcServiceConfig {
+    pub addr: SocketAddr,
+    pub verification_events: broadcast::Sender<VerificationEvent>,
+}
+
+pub(crate) struct VerificationStreamer {
+    verification_events: broadcast::Sender<VerificationEvent>,
+}
+
+impl VerificationStreamer {
+    pub(crate) fn new(verification_events: broadcast::Sender<VerificationEvent>) -> Self {
+        Self {
+            verification_events,
+        }
+    }
+}
+
+#[async_trait]
+impl ClientService<GrpcServiceConfig> for GrpcService {
+    type ServiceError = tokio::task::JoinError;
+
+    fn new(config: GrpcServiceConfig) -> Self {
+        let streamer = VerificationStreamer::new(config.verification_events);
+        let grpc_handle = tokio::spawn(async move {
+            info!("gRPC Server started at {:?}", config.addr);
+            Server::builder()
+                .add_service(VerificationServer::new(streamer))
+                .serve(config.addr)
+                .await
+        });
+
+        Self { grpc_handle }
+    }
+
+    async fn join(self) -> std::result::Result<(), Self::ServiceError> {
+        let _ = self.grpc_handle.await?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Verification for VerificationStreamer {
+    type SubscribeVerificationEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::VerificationEvent, Status>> + Send>>;
+
+    async fn subscribe_verification_events(
+        &self,
+        request: Request<proto::SubscribeVerificationEventsRequest>,
+    ) -> Result<Response<Self::SubscribeVerificationEventsStream>, Status> {
+        let fraud_proofs_only = request.into_inner().fraud_proofs_only;
+
+        let stream = BroadcastStream::new(self.verification_events.subscribe()).filter_map(
+            move |event| async move {
+                match event {
+                    Ok(VerificationEvent::Slot(_)) if fraud_proofs_only => None,
+                    Ok(event) => Some(Ok(event.into())),
+                    // lagging subscribers miss the events that were overwritten
+                    Err(_) => None,
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+impl From<sampler::ShredCheck> for proto::ShredCheck {
+    fn from(check: sampler::ShredCheck) -> Self {
+        Self {
+            index: check.index,
+            shred_type: check.shred_type,
+            signature_verified: check.signature_verified,
+            merkle_verified: check.merkle_verified,
+        }
+    }
+}
+
+impl From<VerificationEvent> for proto::VerificationEvent {
+    fn from(event: VerificationEvent) -> Self {
+        let event = match event {
+            VerificationEvent::Slot(record) => {
+                proto::verification_event::Event::SlotVerification(proto::SlotVerification {
+                    slot: record.slot,
+                    leader: record.leader,
+                    source: record.source,
+                    checks: record.checks.into_iter().map(Into::into).collect(),
+                    verified: record.verified,
+                    sampled_at: record.sampled_at,
+                    backfilled: record.backfilled,
+                })
+            }
+            VerificationEvent::FraudProof(proof) => {
+                proto::verification_event::Event::FraudProof(proto::FraudProof {
+                    slot: proof.slot,
+                    leader: proof.leader,
+                    source: proof.source,
+                    check: Some(proof.check.into()),
+                    shred_payload: proof.shred_payload,
+                })
+            }
+        };
+
+        Self { event: Some(event) }
+    }
+}
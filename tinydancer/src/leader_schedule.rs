@@ -0,0 +1,368 @@
+//! Leader schedule derived locally from epoch stakes, so that the leader a shred
+//! is verified against does not come from the rpc being sampled.
+//!
+//! The cluster samples the schedule of an epoch from the stakes it snapshotted when
+//! that epoch became the leader schedule epoch, i.e. from the stakes active during
+//! the epoch before it. Those are snapshotted here the same way and persisted, so a
+//! schedule can only be derived once tinydancer saw the epoch before it.
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_response::RpcVoteAccountStatus};
+use solana_ledger::leader_schedule::LeaderSchedule;
+use solana_sdk::{
+    clock::{Epoch, Slot, NUM_CONSECUTIVE_LEADER_SLOTS},
+    commitment_config::CommitmentConfig,
+    epoch_schedule::EpochSchedule,
+    hash::hashv,
+    pubkey::Pubkey,
+};
+use tiny_logger::logs::{info, warn};
+use tokio::{sync::RwLock, task::JoinHandle, time::Instant};
+
+use crate::sampler::{get_serialized, put_serialized};
+
+/// Column family the stakes of each leader schedule epoch are persisted in
+pub const EPOCH_STAKES_CF: &str = "epoch_stakes";
+
+/// Number of derived schedules kept around, older epochs are evicted but the newest
+/// two, the current epoch and possibly the next, never are
+const MAX_CACHED_EPOCHS: usize = 4;
+/// Age after which the cached vote accounts are fetched again
+const VOTE_ACCOUNTS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the stakes of the leader schedule epoch are checked for a snapshot
+const STAKES_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct LeaderScheduleCache {
+    rpc_client: Arc<RpcClient>,
+    epoch_schedule: EpochSchedule,
+    db_instance: Arc<rocksdb::DB>,
+    schedules: Arc<RwLock<HashMap<Epoch, Arc<LeaderSchedule>>>>,
+    vote_accounts: Arc<RwLock<Option<(Instant, RpcVoteAccountStatus)>>>,
+}
+
+impl LeaderScheduleCache {
+    pub async fn new(
+        rpc_client: Arc<RpcClient>,
+        db_instance: Arc<rocksdb::DB>,
+    ) -> anyhow::Result<Self> {
+        let epoch_schedule = rpc_client.get_epoch_schedule().await?;
+
+        Ok(Self {
+            rpc_client,
+            epoch_schedule,
+            db_instance,
+            schedules: Default::default(),
+            vote_accounts: Default::default(),
+        })
+    }
+
+    pub fn epoch_schedule(&self) -> &EpochSchedule {
+        &self.epoch_schedule
+    }
+
+    /// Leader of `slot` according to the locally derived schedule
+    pub async fn slot_leader(&self, slot: Slot) -> anyhow::Result<Pubkey> {
+        let (epoch, slot_index) = self.epoch_schedule.get_epoch_and_slot_index(slot);
+        let schedule = self.get_schedule(epoch).await?;
+        Ok(schedule[slot_index])
+    }
+
+    /// Leaders of the `limit` slots starting at `start_slot`, possibly spanning epochs.
+    /// Only schedules already derived are used, `None` if one of the slots falls
+    /// outside of them
+    pub async fn cached_slot_leaders(&self, start_slot: Slot, limit: u64) -> Option<Vec<Pubkey>> {
+        let schedules = self.schedules.read().await;
+        let mut leaders = Vec::with_capacity(limit as usize);
+        let mut slot = start_slot;
+
+        while (leaders.len() as u64) < limit {
+            let (epoch, slot_index) = self.epoch_schedule.get_epoch_and_slot_index(slot);
+            let schedule = schedules.get(&epoch)?;
+            let epoch_leaders = &schedule.get_slot_leaders()[slot_index as usize..];
+
+            let remaining = (limit - leaders.len() as u64) as usize;
+            let taken = epoch_leaders.len().min(remaining);
+            leaders.extend_from_slice(&epoch_leaders[..taken]);
+            slot += taken as u64;
+        }
+
+        Some(leaders)
+    }
+
+    /// Returns the schedule of `epoch`, deriving it once per epoch from its snapshotted
+    /// stakes. Fails until the stakes of `epoch` were snapshotted
+    pub async fn get_schedule(&self, epoch: Epoch) -> anyhow::Result<Arc<LeaderSchedule>> {
+        if let Some(schedule) = self.schedules.read().await.get(&epoch) {
+            return Ok(schedule.clone());
+        }
+
+        let stakes = self
+            .stored_stakes(epoch)?
+            .ok_or_else(|| anyhow!("stakes of epoch {} weren't snapshotted", epoch))?;
+        let schedule =
+            derive_schedule(epoch, stakes, self.epoch_schedule.get_slots_in_epoch(epoch))
+                .ok_or_else(|| anyhow!("no staked nodes found for epoch {}", epoch))?;
+        let schedule = Arc::new(schedule);
+
+        let mut schedules = self.schedules.write().await;
+        schedules.insert(epoch, schedule.clone());
+        if schedules.len() > MAX_CACHED_EPOCHS {
+            let newest = schedules.keys().max().copied().unwrap_or_default();
+            if let Some(oldest) = schedules
+                .keys()
+                .filter(|cached| **cached + 1 < newest)
+                .min()
+                .copied()
+            {
+                schedules.remove(&oldest);
+            }
+        }
+
+        Ok(schedule)
+    }
+
+    /// Vote accounts of the cluster, refetched once the cached ones are older than
+    /// `VOTE_ACCOUNTS_REFRESH_INTERVAL`
+    pub async fn vote_accounts(&self) -> anyhow::Result<RpcVoteAccountStatus> {
+        if let Some((fetched_at, vote_accounts)) = &*self.vote_accounts.read().await {
+            if fetched_at.elapsed() < VOTE_ACCOUNTS_REFRESH_INTERVAL {
+                return Ok(vote_accounts.clone());
+            }
+        }
+
+        let vote_accounts = self.rpc_client.get_vote_accounts().await?;
+        *self.vote_accounts.write().await = Some((Instant::now(), vote_accounts.clone()));
+
+        Ok(vote_accounts)
+    }
+
+    /// Activated stake of every node, summed over the vote accounts it votes with
+    pub async fn node_stakes(&self) -> anyhow::Result<HashMap<Pubkey, u64>> {
+        node_stakes(&self.vote_accounts().await?)
+    }
+
+    /// Snapshots the stakes of each leader schedule epoch as it comes up and derives
+    /// the schedules of the current and the leader schedule epoch ahead of their use
+    pub fn snapshot_stakes(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STAKES_SNAPSHOT_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let slot = match self.snapshot_epoch_stakes().await {
+                    Ok(slot) => slot,
+                    Err(err) => {
+                        warn!("Couldn't snapshot the epoch stakes {}", err);
+                        continue;
+                    }
+                };
+
+                let current_epoch = self.epoch_schedule.get_epoch(slot);
+                let leader_schedule_epoch = self.epoch_schedule.get_leader_schedule_epoch(slot);
+                for epoch in current_epoch..=leader_schedule_epoch {
+                    if let Err(err) = self.get_schedule(epoch).await {
+                        warn!("Leader schedule of epoch {} isn't ready {}", epoch, err);
+                    }
+                }
+            }
+        })
+    }
+
+    /// The active stakes of the current epoch are the ones the leader schedule epoch
+    /// is sampled from, they're persisted once per epoch. Returns the finalized slot
+    /// they were taken at
+    async fn snapshot_epoch_stakes(&self) -> anyhow::Result<Slot> {
+        let commitment = CommitmentConfig::finalized();
+        let slot = self.rpc_client.get_slot_with_commitment(commitment).await?;
+        let epoch = self.epoch_schedule.get_leader_schedule_epoch(slot);
+        if self.stored_stakes(epoch)?.is_some() {
+            return Ok(slot);
+        }
+
+        let vote_accounts = self
+            .rpc_client
+            .get_vote_accounts_with_commitment(commitment)
+            .await?;
+        // stakes taken across an epoch boundary can't be told apart, try again later
+        let slot_after = self.rpc_client.get_slot_with_commitment(commitment).await?;
+        if self.epoch_schedule.get_leader_schedule_epoch(slot_after) != epoch {
+            return Err(anyhow!(
+                "epoch changed while fetching the stakes of epoch {}",
+                epoch
+            ));
+        }
+
+        let stakes: Vec<(Pubkey, u64)> = node_stakes(&vote_accounts)?.into_iter().collect();
+        let cf = self
+            .db_instance
+            .cf_handle(EPOCH_STAKES_CF)
+            .ok_or_else(|| anyhow!("column family {} not found", EPOCH_STAKES_CF))?;
+        put_serialized(&self.db_instance, cf, epoch_stakes_key(epoch), &stakes)
+            .map_err(|err| anyhow!(err))?;
+
+        info!(
+            "Snapshotted the stakes of {} nodes for epoch {}",
+            stakes.len(),
+            epoch
+        );
+
+        Ok(slot_after)
+    }
+
+    fn stored_stakes(&self, epoch: Epoch) -> anyhow::Result<Option<Vec<(Pubkey, u64)>>> {
+        let cf = self
+            .db_instance
+            .cf_handle(EPOCH_STAKES_CF)
+            .ok_or_else(|| anyhow!("column family {} not found", EPOCH_STAKES_CF))?;
+        get_serialized(&self.db_instance, cf, epoch_stakes_key(epoch)).map_err(|err| anyhow!(err))
+    }
+}
+
+fn epoch_stakes_key(epoch: Epoch) -> [u8; 32] {
+    hashv(&[EPOCH_STAKES_CF.as_bytes(), &epoch.to_le_bytes()]).to_bytes()
+}
+
+fn node_stakes(vote_accounts: &RpcVoteAccountStatus) -> anyhow::Result<HashMap<Pubkey, u64>> {
+    let mut stakes: HashMap<Pubkey, u64> = HashMap::new();
+    for vote_account in vote_accounts
+        .current
+        .iter()
+        .chain(vote_accounts.delinquent.iter())
+    {
+        let node = vote_account.node_pubkey.parse::<Pubkey>()?;
+        *stakes.entry(node).or_default() += vote_account.activated_stake;
+    }
+
+    Ok(stakes)
+}
+
+/// Samples the schedule of `epoch` from its node stakes the way the validator does,
+/// seeded with the epoch. `None` if no node is staked
+pub fn derive_schedule(
+    epoch: Epoch,
+    stakes: Vec<(Pubkey, u64)>,
+    slots_in_epoch: u64,
+) -> Option<LeaderSchedule> {
+    let mut stakes: Vec<(Pubkey, u64)> =
+        stakes.into_iter().filter(|(_, stake)| *stake > 0).collect();
+    if stakes.is_empty() {
+        return None;
+    }
+    sort_stakes(&mut stakes);
+
+    let mut seed = [0u8; 32];
+    seed[0..8].copy_from_slice(&epoch.to_le_bytes());
+
+    Some(LeaderSchedule::new(
+        &stakes,
+        seed,
+        slots_in_epoch,
+        NUM_CONSECUTIVE_LEADER_SLOTS,
+    ))
+}
+
+/// Same ordering the validator uses before sampling the schedule
+fn sort_stakes(stakes: &mut Vec<(Pubkey, u64)>) {
+    stakes.sort_unstable_by(|(l_pubkey, l_stake), (r_pubkey, r_stake)| {
+        if r_stake == l_stake {
+            r_pubkey.cmp(l_pubkey)
+        } else {
+            r_stake.cmp(l_stake)
+        }
+    });
+    stakes.dedup();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPOCH: Epoch = 420;
+    const SLOTS_IN_EPOCH: u64 = 64;
+
+    fn stakes() -> Vec<(Pubkey, u64)> {
+        vec![
+            (Pubkey::new_from_array([1; 32]), 10_000),
+            (Pubkey::new_from_array([2; 32]), 30_000),
+            (Pubkey::new_from_array([3; 32]), 30_000),
+            (Pubkey::new_from_array([4; 32]), 0),
+        ]
+    }
+
+    #[test]
+    fn test_derive_schedule_matches_validator() {
+        // leader of every NUM_CONSECUTIVE_LEADER_SLOTS slots of epoch 512 as the
+        // validator samples them, computed outside of solana_ledger with ChaCha20
+        // seeded by the epoch and the stakes sorted by stake then pubkey, descending.
+        // Nodes are `Pubkey::new_from_array([n; 32])`
+        const FIXTURE_EPOCH: Epoch = 512;
+        const FIXTURE_STAKES: [(u8, u64); 6] = [
+            (1, 5_000),
+            (2, 120_000),
+            (3, 40_000),
+            (4, 40_000),
+            (5, 0),
+            (6, 15_000),
+        ];
+        const FIXTURE_LEADERS: [u8; 32] = [
+            6, 2, 2, 2, 2, 6, 2, 2, 2, 2, 6, 3, 2, 2, 3, 4, 3, 1, 3, 6, 4, 4, 1, 2, 2, 4, 2, 2, 2,
+            3, 2, 3,
+        ];
+        let slots_in_epoch = FIXTURE_LEADERS.len() as u64 * NUM_CONSECUTIVE_LEADER_SLOTS;
+
+        let mut stakes: Vec<(Pubkey, u64)> = FIXTURE_STAKES
+            .iter()
+            .map(|(node, stake)| (Pubkey::new_from_array([*node; 32]), *stake))
+            .collect();
+        let expected: Vec<Pubkey> = FIXTURE_LEADERS
+            .iter()
+            .flat_map(|node| {
+                std::iter::repeat(Pubkey::new_from_array([*node; 32]))
+                    .take(NUM_CONSECUTIVE_LEADER_SLOTS as usize)
+            })
+            .collect();
+
+        let schedule = derive_schedule(FIXTURE_EPOCH, stakes.clone(), slots_in_epoch).unwrap();
+        assert_eq!(schedule.get_slot_leaders(), expected.as_slice());
+
+        // the order stakes are fetched in doesn't matter
+        stakes.reverse();
+        let schedule = derive_schedule(FIXTURE_EPOCH, stakes, slots_in_epoch).unwrap();
+        assert_eq!(schedule.get_slot_leaders(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_derive_schedule_rotates_leaders() {
+        let schedule = derive_schedule(EPOCH, stakes(), SLOTS_IN_EPOCH).unwrap();
+        let leaders = schedule.get_slot_leaders();
+
+        assert_eq!(leaders.len() as u64, SLOTS_IN_EPOCH);
+        for slots in leaders.chunks(NUM_CONSECUTIVE_LEADER_SLOTS as usize) {
+            assert!(slots.iter().all(|leader| *leader == slots[0]));
+        }
+        // unstaked nodes never lead
+        assert!(!leaders.contains(&Pubkey::new_from_array([4; 32])));
+    }
+
+    #[test]
+    fn test_derive_schedule_single_node() {
+        let node = Pubkey::new_from_array([7; 32]);
+        let schedule = derive_schedule(EPOCH, vec![(node, 1)], SLOTS_IN_EPOCH).unwrap();
+
+        assert!(schedule
+            .get_slot_leaders()
+            .iter()
+            .all(|leader| *leader == node));
+    }
+
+    #[test]
+    fn test_derive_schedule_without_stake() {
+        let unstaked = vec![(Pubkey::new_from_array([1; 32]), 0)];
+
+        assert!(derive_schedule(EPOCH, unstaked, SLOTS_IN_EPOCH).is_none());
+        assert!(derive_schedule(EPOCH, vec![], SLOTS_IN_EPOCH).is_none());
+    }
+}
@@ -31,23 +31,41 @@
 mod tinydancer;
 use crossterm::style::Stylize;
 use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use sampler::{pull_and_verify_shreds, ArchiveConfig};
+use rpc_wrapper::configs::{
+    AccessConfig, CleanerConfig, PriorityFeePolicy, ProgramAccountsLimits, TlsConfig, TpuConfig,
+    TxTuningConfig,
+};
+use rpc_wrapper::{
+    encoding::BinaryEncoding,
+    workers::{read_journal, JournalOutcome},
+};
+use sampler::{pull_and_verify_shreds, read_sample_record, ArchiveConfig, SamplingStrategy};
+use sampling_peers::ShredSource;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::TransactionConfirmationStatus;
 use spinoff::{spinners, Color, Spinner};
 use std::{
     f32::consts::E,
     fs::{self, File, OpenOptions},
-    io,
+    io::{self, Write},
     path::{Path, PathBuf},
     thread::sleep,
     time::Duration,
 };
 use tinydancer::{endpoint, Cluster, TinyDancer, TinyDancerConfig};
 mod macros;
+mod proof_sharing;
 use colored::Colorize;
+mod grpc;
+mod leader_schedule;
+mod metrics;
 mod rpc_wrapper;
 mod sampler;
+mod sampling_peers;
+mod spot_check;
 mod ui;
 
 use anyhow::{anyhow, Result};
@@ -85,6 +103,62 @@ pub enum Commands {
         /// Duration after which shreds will be purged
         #[clap(required = false, default_value_t = 10000000)]
         shred_archive_duration: u64,
+
+        /// Address to stream verification events over gRPC on, e.g. 0.0.0.0:50051
+        #[clap(long, required = false)]
+        grpc_addr: Option<std::net::SocketAddr>,
+
+        /// Seconds between bank spot checks of a verified slot, disabled if not set
+        #[clap(long, required = false)]
+        spot_check_interval_secs: Option<u64>,
+
+        /// Threads used to verify shreds, defaults to one per core
+        #[clap(long, default_value_t = 0)]
+        verify_threads: usize,
+
+        /// Address to share verification verdicts and fraud proofs with other instances on
+        #[clap(long, required = false)]
+        proof_sharing_addr: Option<std::net::SocketAddr>,
+
+        /// Address the lite rpc http server binds, overrides the config
+        #[clap(long, required = false)]
+        lite_rpc_http_addr: Option<std::net::SocketAddr>,
+
+        /// Address the lite rpc websocket server binds, overrides the config
+        #[clap(long, required = false)]
+        lite_rpc_ws_addr: Option<std::net::SocketAddr>,
+
+        /// Only accept lite rpc connections from this machine, keeping the configured ports
+        #[clap(long, default_value_t = false)]
+        lite_rpc_localhost_only: bool,
+
+        /// Address to serve the lite rpc over gRPC on, e.g. 0.0.0.0:50052
+        #[clap(long, required = false)]
+        lite_grpc_addr: Option<std::net::SocketAddr>,
+
+        /// Address to serve prometheus metrics on at /metrics, e.g. 0.0.0.0:9090
+        #[clap(long, required = false)]
+        metrics_addr: Option<std::net::SocketAddr>,
+
+        /// Keypair file of a staked validator identity to forward transactions with, overrides the config
+        #[clap(long, required = false)]
+        tpu_identity: Option<String>,
+
+        /// Milliseconds between cleanups of the lite rpc, overrides the config
+        #[clap(long, required = false)]
+        clean_interval_ms: Option<u64>,
+
+        /// Seconds the record of a sent transaction is kept, overrides the config
+        #[clap(long, required = false)]
+        tx_ttl_secs: Option<u64>,
+
+        /// Seconds a block is kept in the block store, overrides the config
+        #[clap(long, required = false)]
+        block_ttl_secs: Option<u64>,
+
+        /// Seconds a signature subscription waits for its transaction, overrides the config
+        #[clap(long, required = false)]
+        subscription_ttl_secs: Option<u64>,
     },
     /// Verify the samples for a single slot
     Verify {
@@ -93,6 +167,18 @@ pub enum Commands {
 
         #[clap(long, required = false, default_value = "10")]
         sample_qty: usize,
+
+        /// Seed the shred selection to reproduce an audit, overrides the configured strategy
+        #[clap(long, required = false)]
+        seed: Option<u64>,
+    },
+    /// Print the verification report of a slot from the sampling history
+    Report {
+        /// The slot to report on
+        slot: u64,
+        /// Rocks db path the client archives shreds in
+        #[clap(long)]
+        archive_path: String,
     },
     /// Stream the client logs to your terminal
     Logs {
@@ -102,6 +188,9 @@ pub enum Commands {
     /// Edit your client config
     #[clap(subcommand)]
     Config(ConfigSubcommands),
+    /// Export or rebroadcast the transactions the lite rpc accepted
+    #[clap(subcommand)]
+    Journal(JournalSubcommands),
     // Get the latest slot
     Slot,
 }
@@ -114,10 +203,51 @@ pub enum ConfigSubcommands {
         /// The cluster you want to run the client on (Mainnet, Localnet,Devnet, <custom-url>)
         #[clap(long, short, required = false, default_value = "Localnet")]
         cluster: String,
+        /// The genesis hash the cluster is expected to have, required to verify custom clusters
+        #[clap(long, required = false)]
+        genesis_hash: Option<String>,
     },
     Get,
 }
 
+#[derive(Debug, Subcommand)]
+pub enum JournalSubcommands {
+    /// Write the journaled transactions as json lines
+    Export {
+        /// Rocks db path the client archives shreds in
+        #[clap(long)]
+        archive_path: String,
+        /// File to write to, stdout if not set
+        #[clap(long, required = false)]
+        output: Option<String>,
+        /// Only transactions received at or after this unix time in millis
+        #[clap(long, default_value_t = 0)]
+        since_ms: u64,
+    },
+    /// Send the journaled transactions again
+    Rebroadcast {
+        /// Rocks db path the client archives shreds in
+        #[clap(long)]
+        archive_path: String,
+        /// Rpc to send the transactions to, the configured cluster if not set
+        #[clap(long, required = false)]
+        rpc_url: Option<String>,
+        /// Only transactions received at or after this unix time in millis
+        #[clap(long, default_value_t = 0)]
+        since_ms: u64,
+        /// Also send transactions that landed already
+        #[clap(long, default_value_t = false)]
+        include_landed: bool,
+        /// Also send transactions that were dropped or replaced before they were sent
+        #[clap(long, default_value_t = false)]
+        include_dropped: bool,
+        /// Also send expired transactions without a durable nonce, their blockhash is
+        /// likely too old to land
+        #[clap(long, default_value_t = false)]
+        include_expired: bool,
+    },
+}
+
 pub fn get_config_file() -> Result<ConfigSchema> {
     let home_path = std::env::var("HOME")?;
     let path = home_path + "/.config/tinydancer/config.json";
@@ -144,15 +274,83 @@ async fn main() -> Result<()> {
             archive_path,
             shred_archive_duration,
             tui_monitor,
+            grpc_addr,
+            spot_check_interval_secs,
+            verify_threads,
+            proof_sharing_addr,
+            lite_rpc_http_addr,
+            lite_rpc_ws_addr,
+            lite_rpc_localhost_only,
+            lite_grpc_addr,
+            metrics_addr,
+            tpu_identity,
+            clean_interval_ms,
+            tx_ttl_secs,
+            block_ttl_secs,
+            subscription_ttl_secs,
         } => {
             let config_file =
                 get_config_file().map_err(|_| anyhow!("tinydancer config not set"))?;
+            let bind_addr = |cli: Option<std::net::SocketAddr>,
+                             config: Option<std::net::SocketAddr>,
+                             default: &str|
+             -> Result<std::net::SocketAddr> {
+                let mut addr = match cli.or(config) {
+                    Some(addr) => addr,
+                    None => default.parse()?,
+                };
+                if lite_rpc_localhost_only {
+                    addr.set_ip(std::net::Ipv4Addr::LOCALHOST.into());
+                }
+                Ok(addr)
+            };
+            let lite_rpc_http_addr = bind_addr(
+                lite_rpc_http_addr,
+                config_file.lite_rpc_http_addr,
+                rpc_wrapper::DEFAULT_LITE_RPC_HTTP_BIND,
+            )?;
+            let lite_rpc_ws_addr = bind_addr(
+                lite_rpc_ws_addr,
+                config_file.lite_rpc_ws_addr,
+                rpc_wrapper::DEFAULT_LITE_RPC_WS_BIND,
+            )?;
             let config = TinyDancerConfig {
                 enable_ui_service,
                 rpc_endpoint: get_cluster(config_file.cluster),
+                upstreams: config_file.upstreams,
                 sample_qty,
                 tui_monitor,
                 log_path: config_file.log_path,
+                expected_genesis_hash: config_file.genesis_hash,
+                shred_sources: config_file.shred_sources,
+                pinned_shred_source: config_file.pinned_shred_source,
+                grpc_addr,
+                spot_check_interval: spot_check_interval_secs.map(Duration::from_secs),
+                verify_threads,
+                proof_sharing_addr,
+                proof_sharing_peers: config_file.proof_sharing_peers,
+                sampling_strategy: config_file.sampling_strategy,
+                program_accounts_limits: config_file.program_accounts,
+                block_engine_url: config_file.block_engine_url,
+                tx_webhook_url: config_file.tx_webhook_url,
+                tx_terminal_status: config_file.tx_terminal_status,
+                priority_fee_policy: config_file.priority_fee_policy,
+                tpu_config: config_file.tpu,
+                access_config: config_file.access,
+                tls_config: config_file.tls,
+                lite_rpc_http_addr,
+                lite_rpc_ws_addr,
+                lite_grpc_addr,
+                metrics_addr,
+                tpu_identity: tpu_identity.or(config_file.tpu_identity),
+                cleaner_config: CleanerConfig {
+                    interval_ms: clean_interval_ms.unwrap_or(config_file.cleaner.interval_ms),
+                    tx_ttl_s: tx_ttl_secs.unwrap_or(config_file.cleaner.tx_ttl_s),
+                    block_ttl_s: block_ttl_secs.unwrap_or(config_file.cleaner.block_ttl_s),
+                    subscription_ttl_s: subscription_ttl_secs
+                        .unwrap_or(config_file.cleaner.subscription_ttl_s),
+                    journal_retention_s: config_file.cleaner.journal_retention_s,
+                },
                 archive_config: {
                     archive_path
                         .map(|path| {
@@ -165,7 +363,7 @@ async fn main() -> Result<()> {
                 },
             };
 
-            TinyDancer::start(config).await.unwrap();
+            TinyDancer::start(config).await?;
         }
 
         Commands::Slot => {
@@ -226,7 +424,11 @@ async fn main() -> Result<()> {
                     );
                 }
             }
-            ConfigSubcommands::Set { log_path, cluster } => {
+            ConfigSubcommands::Set {
+                log_path,
+                cluster,
+                genesis_hash,
+            } => {
                 // println!("{:?}", fs::create_dir_all("~/.config/tinydancer"));
 
                 let home_path = std::env::var("HOME").unwrap();
@@ -259,6 +461,9 @@ async fn main() -> Result<()> {
                         // overwrite
                         config_file.log_path = log_path;
                         config_file.cluster = cluster;
+                        if genesis_hash.is_some() {
+                            config_file.genesis_hash = genesis_hash;
+                        }
                         std::fs::write(config_path, serde_json::to_string_pretty(&config_file)?)?;
                     }
                     Err(_) => {
@@ -274,7 +479,137 @@ async fn main() -> Result<()> {
                 }
             }
         },
-        Commands::Verify { slot, sample_qty } => {
+        Commands::Journal(sub_journal) => match sub_journal {
+            JournalSubcommands::Export {
+                archive_path,
+                output,
+                since_ms,
+            } => {
+                let entries = read_journal(&archive_path, since_ms)?;
+                let mut out: Box<dyn io::Write> = match &output {
+                    Some(output) => Box::new(File::create(output)?),
+                    None => Box::new(io::stdout()),
+                };
+                for entry in entries.iter() {
+                    let line = serde_json::json!({
+                        "signature": entry.signature,
+                        "transaction": BinaryEncoding::Base64.encode(&entry.tx),
+                        "receivedAtMs": entry.received_at_ms,
+                        "outcome": entry.outcome,
+                    });
+                    writeln!(out, "{line}")?;
+                }
+                if let Some(output) = output {
+                    println!(
+                        "Exported {} transaction(s) to {}",
+                        entries.len().to_string().green(),
+                        output
+                    );
+                }
+            }
+            JournalSubcommands::Rebroadcast {
+                archive_path,
+                rpc_url,
+                since_ms,
+                include_landed,
+                include_dropped,
+                include_expired,
+            } => {
+                let rpc_url = match rpc_url {
+                    Some(rpc_url) => rpc_url,
+                    None => get_endpoint(
+                        get_config_file()
+                            .map_err(|_| anyhow!("tinydancer config not set"))?
+                            .cluster,
+                    ),
+                };
+                let rpc_client = RpcClient::new(rpc_url);
+                let config = RpcSendTransactionConfig {
+                    skip_preflight: true,
+                    ..Default::default()
+                };
+
+                let entries = read_journal(&archive_path, since_ms)?
+                    .into_iter()
+                    .filter(|entry| match entry.outcome {
+                        JournalOutcome::Landed { .. } => include_landed,
+                        JournalOutcome::Dropped | JournalOutcome::Replaced { .. } => {
+                            include_dropped
+                        }
+                        JournalOutcome::Queued | JournalOutcome::Sent => true,
+                        // a nonce doesn't expire with the blockhash
+                        JournalOutcome::Expired => {
+                            include_expired
+                                || bincode::deserialize::<VersionedTransaction>(&entry.tx)
+                                    .map_or(false, |tx| tx.uses_durable_nonce())
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let mut failed = 0;
+                for entry in entries.iter() {
+                    let sent = match bincode::deserialize::<VersionedTransaction>(&entry.tx) {
+                        Ok(tx) => rpc_client
+                            .send_transaction_with_config(&tx, config)
+                            .await
+                            .map_err(|err| anyhow!(err)),
+                        Err(err) => Err(anyhow!(err)),
+                    };
+                    if let Err(err) = sent {
+                        failed += 1;
+                        println!("  {} {}: {}", "failed".red(), entry.signature, err);
+                    }
+                }
+                println!(
+                    "Rebroadcast {} transaction(s), {} failed",
+                    (entries.len() - failed).to_string().green(),
+                    failed.to_string().red()
+                );
+            }
+        },
+        Commands::Report { slot, archive_path } => {
+            let record = read_sample_record(&archive_path, slot)?;
+            match record {
+                Some(record) => {
+                    println!("Verification report for slot {}", slot.to_string().yellow());
+                    println!("  Leader:         {}", record.leader);
+                    println!("  Source:         {}", record.source);
+                    println!("  Sampled at:     {} ms", record.sampled_at);
+                    if record.backfilled {
+                        println!("  Backfilled:     yes");
+                    }
+                    println!("  Shreds sampled: {}", record.checks.len());
+                    for check in record.checks.iter() {
+                        let merkle = match check.merkle_verified {
+                            Some(true) => "ok".to_string().green(),
+                            Some(false) => "failed".to_string().red(),
+                            None => "not merkle".to_string().yellow(),
+                        };
+                        let signature = if check.signature_verified {
+                            "ok".to_string().green()
+                        } else {
+                            "failed".to_string().red()
+                        };
+                        println!(
+                            "    shred {:>5} ({}) signature: {} merkle: {}",
+                            check.index, check.shred_type, signature, merkle
+                        );
+                    }
+                    if record.verified {
+                        println!("Verdict: {} ✓", "Valid".to_string().green());
+                    } else {
+                        println!("Verdict: {} ❌", "Invalid".to_string().red());
+                    }
+                }
+                None => {
+                    println!("No sampling history for slot {}", slot.to_string().yellow());
+                }
+            }
+        }
+        Commands::Verify {
+            slot,
+            sample_qty,
+            seed,
+        } => {
             let _spinner = Spinner::new(
                 spinners::Dots,
                 format!("Verifying Shreds for Slot {}", slot),
@@ -283,8 +618,16 @@ async fn main() -> Result<()> {
 
             let config_file =
                 get_config_file().map_err(|_| anyhow!("tinydancer config not set"))?;
-            let is_verified =
-                pull_and_verify_shreds(slot, get_endpoint(config_file.cluster), sample_qty).await;
+            let strategy = seed
+                .map(|seed| SamplingStrategy::Seeded { seed })
+                .unwrap_or(config_file.sampling_strategy);
+            let is_verified = pull_and_verify_shreds(
+                slot,
+                get_endpoint(config_file.cluster),
+                sample_qty,
+                &strategy,
+            )
+            .await;
 
             if is_verified {
                 println!(
@@ -327,6 +670,66 @@ pub fn get_endpoint(cluster: String) -> String {
 pub struct ConfigSchema {
     pub log_path: String,
     pub cluster: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genesis_hash: Option<String>,
+    /// Nodes to sample shreds from, selected proportionally to their stake
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shred_sources: Vec<ShredSource>,
+    /// Always sample from this endpoint, ignoring `shred_sources`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_shred_source: Option<String>,
+    /// Proof sharing addresses of the instances to corroborate verdicts with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proof_sharing_peers: Vec<String>,
+    /// How shred indices are picked when sampling a slot
+    #[serde(default)]
+    pub sampling_strategy: SamplingStrategy,
+    /// Guardrails of the lite rpc's getProgramAccounts
+    #[serde(default)]
+    pub program_accounts: ProgramAccountsLimits,
+    /// Jito block engine bundles are forwarded to, sendBundle is disabled if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_engine_url: Option<String>,
+    /// Url the statuses of sent transactions are posted to once confirmed and once
+    /// finalized, unless they were sent with their own callback url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_webhook_url: Option<String>,
+    /// Confirmation status sent transactions are done at and signature subscriptions
+    /// default to, `finalized` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_terminal_status: Option<TransactionConfirmationStatus>,
+    /// Priority fee required of sent transactions, not enforced by default
+    #[serde(default)]
+    pub priority_fee_policy: PriorityFeePolicy,
+    /// Quic connection pool, stream limits, timeouts and retries of the sends to the
+    /// leaders' tpus
+    #[serde(default)]
+    pub tpu: TpuConfig,
+    /// Api keys and other restrictions on who may call the lite rpc
+    #[serde(default)]
+    pub access: AccessConfig,
+    /// Certificate to serve the lite rpc over tls with, plaintext if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Address the lite rpc http server binds, `[::]:8890` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lite_rpc_http_addr: Option<std::net::SocketAddr>,
+    /// Address the lite rpc websocket server binds, `[::]:8891` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lite_rpc_ws_addr: Option<std::net::SocketAddr>,
+    /// Upstream rpcs to fail over to when the cluster's endpoint degrades
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upstreams: Vec<String>,
+    /// Keypair file of a staked validator identity, leaders give transactions sent with
+    /// it stake weighted bandwidth. Unstaked or unset, transactions share the unstaked quota
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tpu_identity: Option<String>,
+    /// Fanout and batching of transactions, re-read on SIGHUP
+    #[serde(default)]
+    pub tx_tuning: TxTuningConfig,
+    /// How often the lite rpc cleans up and how long it keeps what it cleans up
+    #[serde(default)]
+    pub cleaner: CleanerConfig,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
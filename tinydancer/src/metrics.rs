@@ -0,0 +1,70 @@
+//! HTTP listener exposing the registered prometheus metrics for scraping
+use std::{convert::Infallible, net::SocketAddr};
+
+use async_trait::async_trait;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use prometheus::{Encoder, TextEncoder};
+use tiny_logger::logs::{info, warn};
+use tokio::task::JoinHandle;
+
+use crate::tinydancer::ClientService;
+
+/// Path the metrics are served on, anything else is a 404
+pub const METRICS_PATH: &str = "/metrics";
+
+pub struct MetricsService {
+    metrics_handle: JoinHandle<Result<(), hyper::Error>>,
+}
+
+pub struct MetricsServiceConfig {
+    pub addr: SocketAddr,
+}
+
+#[async_trait]
+impl ClientService<MetricsServiceConfig> for MetricsService {
+    type ServiceError = tokio::task::JoinError;
+
+    fn new(config: MetricsServiceConfig) -> Self {
+        let metrics_handle = tokio::spawn(async move {
+            let make_service =
+                make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve_metrics)) });
+
+            info!("Metrics Server started at {:?}", config.addr);
+            Server::try_bind(&config.addr)?.serve(make_service).await
+        });
+
+        Self { metrics_handle }
+    }
+
+    async fn join(self) -> std::result::Result<(), Self::ServiceError> {
+        let _ = self.metrics_handle.await?;
+        Ok(())
+    }
+}
+
+async fn serve_metrics(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        warn!("Couldn't encode metrics: {err}");
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(response);
+    }
+
+    let mut response = Response::new(Body::from(buffer));
+    if let Ok(content_type) = HeaderValue::from_str(encoder.format_type()) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    Ok(response)
+}
@@ -0,0 +1,252 @@
+//! Peer to peer sharing of verification verdicts and fraud proofs between tinydancer
+//! instances. Every instance streams its own verification events as newline delimited
+//! json to the peers connected to it, and corroborates the events of the peers it connects to.
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use solana_ledger::shred::Shred;
+use tiny_logger::logs::{error, info, warn};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+
+use crate::{
+    leader_schedule::LeaderScheduleCache,
+    sampler::{check_sample, get_sample_record, FraudProof, SlotSampleRecord, VerificationEvent},
+    tinydancer::ClientService,
+};
+
+const PEER_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Bytes a peer's message may hold, a fraud proof with its shred takes a few kilobytes.
+/// A peer sending a longer one is dropped
+const MAX_PEER_MESSAGE_LEN: usize = 64 * 1024;
+
+lazy_static::lazy_static! {
+    static ref PEER_VERDICTS: IntCounterVec = register_int_counter_vec!(
+        opts!("tinydancer_peer_verdicts", "Slot verdicts received from peers, by outcome of the comparison with our own"),
+        &["outcome"]
+    )
+    .unwrap();
+    static ref PEER_FRAUD_PROOFS: IntCounterVec = register_int_counter_vec!(
+        opts!("tinydancer_peer_fraud_proofs", "Fraud proofs received from peers, by outcome of local re-verification"),
+        &["outcome"]
+    )
+    .unwrap();
+}
+
+pub struct ProofSharingService {
+    proof_sharing_handle: JoinHandle<()>,
+}
+
+pub struct ProofSharingServiceConfig {
+    /// Address our verification events are served on, not served if `None`
+    pub listen_addr: Option<SocketAddr>,
+    /// Peers whose verification events are corroborated
+    pub peers: Vec<String>,
+    pub verification_events: broadcast::Sender<VerificationEvent>,
+    pub instance: Arc<rocksdb::DB>,
+    pub leader_schedule: LeaderScheduleCache,
+}
+
+#[async_trait]
+impl ClientService<ProofSharingServiceConfig> for ProofSharingService {
+    type ServiceError = tokio::task::JoinError;
+
+    fn new(config: ProofSharingServiceConfig) -> Self {
+        let proof_sharing_handle = tokio::spawn(async move {
+            let mut threads = Vec::default();
+
+            if let Some(listen_addr) = config.listen_addr {
+                threads.push(tokio::spawn(serve_events(
+                    listen_addr,
+                    config.verification_events.clone(),
+                )));
+            }
+
+            for peer in config.peers {
+                threads.push(tokio::spawn(follow_peer(
+                    peer,
+                    config.instance.clone(),
+                    config.leader_schedule.clone(),
+                )));
+            }
+
+            for thread in threads {
+                let _ = thread.await;
+            }
+        });
+
+        Self {
+            proof_sharing_handle,
+        }
+    }
+
+    async fn join(self) -> std::result::Result<(), Self::ServiceError> {
+        self.proof_sharing_handle.await
+    }
+}
+
+async fn serve_events(
+    listen_addr: SocketAddr,
+    verification_events: broadcast::Sender<VerificationEvent>,
+) {
+    let listener = match TcpListener::bind(listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Couldn't bind proof sharing server to {}: {}",
+                listen_addr, e
+            );
+            return;
+        }
+    };
+    info!("Proof sharing server started at {:?}", listen_addr);
+
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Error accepting proof sharing peer: {}", e);
+                continue;
+            }
+        };
+        info!("Proof sharing peer {} connected", peer_addr);
+
+        let mut events = verification_events.subscribe();
+        tokio::spawn(async move {
+            let mut stream = stream;
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Proof sharing peer {} missed {} events", peer_addr, missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(mut line) = serde_json::to_vec(&event) else {
+                    continue;
+                };
+                line.push(b'\n');
+                if stream.write_all(&line).await.is_err() {
+                    info!("Proof sharing peer {} disconnected", peer_addr);
+                    break;
+                }
+            }
+        });
+    }
+}
+
+async fn follow_peer(
+    peer: String,
+    instance: Arc<rocksdb::DB>,
+    leader_schedule: LeaderScheduleCache,
+) {
+    loop {
+        match TcpStream::connect(&peer).await {
+            Ok(stream) => {
+                info!("Following proof sharing peer {}", peer);
+                let mut lines = FramedRead::new(
+                    stream,
+                    LinesCodec::new_with_max_length(MAX_PEER_MESSAGE_LEN),
+                );
+                while let Some(line) = lines.next().await {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(LinesCodecError::MaxLineLengthExceeded) => {
+                            warn!(
+                                "Proof sharing peer {} sent a message over {} bytes",
+                                peer, MAX_PEER_MESSAGE_LEN
+                            );
+                            break;
+                        }
+                        Err(LinesCodecError::Io(_)) => break,
+                    };
+                    match serde_json::from_str::<VerificationEvent>(&line) {
+                        Ok(VerificationEvent::Slot(record)) => {
+                            corroborate_verdict(&peer, &instance, record)
+                        }
+                        Ok(VerificationEvent::FraudProof(proof)) => {
+                            corroborate_fraud_proof(&peer, &leader_schedule, proof).await
+                        }
+                        Err(e) => warn!("Invalid message from peer {}: {}", peer, e),
+                    }
+                }
+                warn!("Lost connection to proof sharing peer {}", peer);
+            }
+            Err(e) => warn!("Couldn't connect to proof sharing peer {}: {}", peer, e),
+        }
+        tokio::time::sleep(PEER_RECONNECT_INTERVAL).await;
+    }
+}
+
+/// compares a peer's verdict with our own sampling history of the slot
+fn corroborate_verdict(peer: &str, instance: &rocksdb::DB, record: SlotSampleRecord) {
+    let outcome = match get_sample_record(instance, record.slot) {
+        Ok(Some(ours)) if ours.verified == record.verified => "agreed",
+        Ok(Some(ours)) => {
+            warn!(
+                "peer {} marked slot {} as verified: {} but we marked it as verified: {}",
+                peer, record.slot, record.verified, ours.verified
+            );
+            "disagreed"
+        }
+        Ok(None) => "unsampled",
+        Err(e) => {
+            warn!("Couldn't read sample record of slot {}: {}", record.slot, e);
+            "unsampled"
+        }
+    };
+    PEER_VERDICTS.with_label_values(&[outcome]).inc();
+}
+
+/// re-verifies the shred of a peer's fraud proof against our own leader schedule
+async fn corroborate_fraud_proof(
+    peer: &str,
+    leader_schedule: &LeaderScheduleCache,
+    proof: FraudProof,
+) {
+    let shred = match Shred::new_from_serialized_shred(proof.shred_payload) {
+        Ok(shred) if shred.slot() == proof.slot => shred,
+        _ => {
+            warn!(
+                "peer {} sent a malformed fraud proof for slot {}",
+                peer, proof.slot
+            );
+            PEER_FRAUD_PROOFS.with_label_values(&["malformed"]).inc();
+            return;
+        }
+    };
+    let leader = match leader_schedule.slot_leader(proof.slot).await {
+        Ok(leader) => leader,
+        Err(e) => {
+            warn!("Couldn't get leader of slot {}: {}", proof.slot, e);
+            return;
+        }
+    };
+
+    if check_sample(&shred, leader).passed() {
+        warn!(
+            "peer {} sent a fraud proof for shred {} of slot {} that verifies",
+            peer,
+            shred.index(),
+            proof.slot
+        );
+        PEER_FRAUD_PROOFS.with_label_values(&["rejected"]).inc();
+    } else {
+        error!(
+            "fraud proof from peer {} corroborated: shred {} of slot {} served by {} is invalid",
+            peer,
+            shred.index(),
+            proof.slot,
+            proof.source
+        );
+        PEER_FRAUD_PROOFS.with_label_values(&["corroborated"]).inc();
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response, StatusCode};
+use prometheus::{opts, register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    configs::ApiKey,
+    middleware::{error_response, ACCESS_DENIED_CODE},
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_AUTHORIZED_REQUESTS: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_rpc_authorized_requests", "Number of requests authorized per api key"), &["key"]).unwrap();
+    static ref RPC_UNAUTHORIZED_REQUESTS: IntCounter =
+        register_int_counter!("literpc_rpc_unauthorized_requests", "Number of requests rejected for a missing or unknown api key").unwrap();
+}
+
+/// Header an api key may be presented in
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Query parameter an api key may be presented in, for clients that can't set headers
+pub const API_KEY_QUERY_PARAM: &str = "api-key";
+
+/// Rejects http requests and websocket upgrades not presenting one of the configured
+/// api keys, everything is let through if none are configured
+#[derive(Clone)]
+pub struct ApiKeyLayer {
+    /// key to the name it's reported under in metrics
+    keys: Arc<HashMap<String, String>>,
+}
+
+impl ApiKeyLayer {
+    pub fn new(api_keys: &[ApiKey]) -> Self {
+        Self {
+            keys: Arc::new(
+                api_keys
+                    .iter()
+                    .map(|api_key| (api_key.key.clone(), api_key.name.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Name of the key the request presents, if it's a configured one
+    fn authorize<B>(&self, request: &Request<B>) -> Option<&str> {
+        api_key(request)
+            .and_then(|key| self.keys.get(&key))
+            .map(String::as_str)
+    }
+
+    /// Whether the request presents a configured key, or none are configured
+    pub fn admits<B>(&self, request: &Request<B>) -> bool {
+        if self.keys.is_empty() {
+            return true;
+        }
+
+        match self.authorize(request) {
+            Some(name) => {
+                RPC_AUTHORIZED_REQUESTS.with_label_values(&[name]).inc();
+                true
+            }
+            None => {
+                RPC_UNAUTHORIZED_REQUESTS.inc();
+                false
+            }
+        }
+    }
+}
+
+/// Api key the request presents, configured or not
+pub fn api_key<B>(request: &Request<B>) -> Option<String> {
+    let from_header = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .map(String::from);
+
+    from_header.or_else(|| {
+        request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(param, _)| param == API_KEY_QUERY_PARAM)
+                .map(|(_, key)| key.into_owned())
+        })
+    })
+}
+
+impl<S> Layer<S> for ApiKeyLayer {
+    type Service = ApiKeyAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiKeyAuth {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiKeyAuth<S> {
+    inner: S,
+    layer: ApiKeyLayer,
+}
+
+impl<S> Service<Request<Body>> for ApiKeyAuth<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if self.layer.admits(&request) {
+            Box::pin(self.inner.call(request))
+        } else {
+            Box::pin(async {
+                Ok(error_response(
+                    StatusCode::UNAUTHORIZED,
+                    ACCESS_DENIED_CODE,
+                    "Missing or unknown api key",
+                ))
+            })
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response, StatusCode};
+use jsonrpsee::types::error::INVALID_REQUEST_CODE;
+use prometheus::{register_int_counter, IntCounter};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::middleware::{error_response, parse_calls, take_ready};
+
+lazy_static::lazy_static! {
+    static ref RPC_BATCHES_REJECTED: IntCounter =
+        register_int_counter!("literpc_rpc_batches_rejected", "Number of batch requests rejected for their size").unwrap();
+}
+
+/// Rejects http JSON-RPC batches holding more than `max_batch_size` calls,
+/// smaller batches are answered per item by the server
+#[derive(Clone)]
+pub struct BatchLimitLayer {
+    max_batch_size: usize,
+}
+
+impl BatchLimitLayer {
+    pub fn new(max_batch_size: usize) -> Self {
+        Self { max_batch_size }
+    }
+}
+
+impl<S> Layer<S> for BatchLimitLayer {
+    type Service = BatchLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BatchLimit {
+            inner,
+            max_batch_size: self.max_batch_size,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BatchLimit<S> {
+    inner: S,
+    max_batch_size: usize,
+}
+
+impl<S> Service<Request<Body>> for BatchLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error> + Send,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let mut inner = take_ready(&mut self.inner);
+        let max_batch_size = self.max_batch_size;
+
+        Box::pin(async move {
+            let (request, calls) = parse_calls(request).await?;
+
+            if calls.is_batch && calls.calls.len() > max_batch_size {
+                RPC_BATCHES_REJECTED.inc();
+                return Ok(error_response(
+                    StatusCode::OK,
+                    INVALID_REQUEST_CODE as i64,
+                    &format!("Batch too large; max {max_batch_size} calls"),
+                ));
+            }
+
+            inner.call(request).await
+        })
+    }
+}
@@ -1,4 +1,5 @@
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock as SyncRwLock};
 use std::time::Duration;
 
 use anyhow::Context;
@@ -19,15 +20,21 @@ lazy_static::lazy_static! {
 #[derive(Clone, Copy, Debug)]
 pub struct BlockInformation {
     pub slot: u64,
+    pub parent_slot: u64,
     pub block_height: u64,
+    pub block_time: Option<i64>,
     pub instant: Instant,
 }
 
+/// Lookups are synchronous map and lock reads, never held across an await, so the
+/// send path doesn't wait on the block listeners
 #[derive(Clone)]
 pub struct BlockStore {
     blocks: Arc<DashMap<String, BlockInformation>>,
-    latest_confirmed_block: Arc<RwLock<(String, BlockInformation)>>,
-    latest_finalized_block: Arc<RwLock<(String, BlockInformation)>>,
+    /// parent slot of every stored block by slot
+    parents: Arc<SyncRwLock<BTreeMap<u64, u64>>>,
+    latest_confirmed_block: Arc<SyncRwLock<(String, BlockInformation)>>,
+    latest_finalized_block: Arc<SyncRwLock<(String, BlockInformation)>>,
     last_add_block_metric: Arc<RwLock<Instant>>,
 }
 
@@ -38,15 +45,29 @@ impl BlockStore {
         let (finalized_blockhash, finalized_block) =
             Self::fetch_latest(rpc_client, CommitmentConfig::finalized()).await?;
 
-        Ok(Self {
-            latest_confirmed_block: Arc::new(RwLock::new((
+        Ok(Self::with_latest(
+            (confirmed_blockhash, confirmed_block),
+            (finalized_blockhash, finalized_block),
+        ))
+    }
+
+    fn with_latest(
+        (confirmed_blockhash, confirmed_block): (String, BlockInformation),
+        (finalized_blockhash, finalized_block): (String, BlockInformation),
+    ) -> Self {
+        Self {
+            latest_confirmed_block: Arc::new(SyncRwLock::new((
                 confirmed_blockhash.clone(),
                 confirmed_block,
             ))),
-            latest_finalized_block: Arc::new(RwLock::new((
+            latest_finalized_block: Arc::new(SyncRwLock::new((
                 finalized_blockhash.clone(),
                 finalized_block,
             ))),
+            parents: Arc::new(SyncRwLock::new(BTreeMap::from([
+                (confirmed_block.slot, confirmed_block.parent_slot),
+                (finalized_block.slot, finalized_block.parent_slot),
+            ]))),
             blocks: Arc::new({
                 let map = DashMap::new();
                 map.insert(confirmed_blockhash, confirmed_block);
@@ -54,7 +75,7 @@ impl BlockStore {
                 map
             }),
             last_add_block_metric: Arc::new(RwLock::new(Instant::now())),
-        })
+        }
     }
 
     pub async fn fetch_latest(
@@ -87,13 +108,15 @@ impl BlockStore {
             latest_block_hash,
             BlockInformation {
                 slot,
+                parent_slot: block.parent_slot,
                 block_height,
+                block_time: block.block_time,
                 instant: Instant::now(),
             },
         ))
     }
 
-    pub async fn get_block_info(&self, blockhash: &str) -> Option<BlockInformation> {
+    pub fn get_block_info(&self, blockhash: &str) -> Option<BlockInformation> {
         let Some(info) = self.blocks.get(blockhash) else {
             return None;
         };
@@ -101,10 +124,48 @@ impl BlockStore {
         Some(info.value().to_owned())
     }
 
+    /// Block time of `slot` if the block is still in the store
+    pub fn get_block_time(&self, slot: u64) -> Option<i64> {
+        self.blocks
+            .iter()
+            .find(|block| block.value().slot == slot)
+            .and_then(|block| block.value().block_time)
+    }
+
+    /// Slots of the blocks within `start..=end` on the chain of the latest block at
+    /// `commitment_config`, `None` unless every block of the range is stored. Walks
+    /// the parents back from the latest block, so a block missed or cleaned up is a
+    /// break in the chain rather than a skipped slot
+    pub fn get_block_slots(
+        &self,
+        start: u64,
+        end: u64,
+        commitment_config: CommitmentConfig,
+    ) -> Option<Vec<u64>> {
+        let parents = self.parents.read().unwrap();
+        let mut slot = self.latest_block_info(commitment_config).slot;
+        let mut slots = Vec::new();
+
+        while slot >= start {
+            if slot <= end {
+                slots.push(slot);
+            }
+            // the genesis block is its own parent
+            match parents.get(&slot) {
+                Some(&parent) if parent < slot => slot = parent,
+                Some(_) => break,
+                None => return None,
+            }
+        }
+
+        slots.reverse();
+        Some(slots)
+    }
+
     fn get_latest_block_arc(
         &self,
         commitment_config: CommitmentConfig,
-    ) -> Arc<RwLock<(String, BlockInformation)>> {
+    ) -> Arc<SyncRwLock<(String, BlockInformation)>> {
         if commitment_config.is_finalized() {
             self.latest_finalized_block.clone()
         } else {
@@ -113,18 +174,22 @@ impl BlockStore {
     }
 
     pub async fn get_latest_blockhash(&self, commitment_config: CommitmentConfig) -> String {
-        self.get_latest_block_arc(commitment_config)
-            .read()
-            .await
-            .0
-            .clone()
+        self.get_latest_block(commitment_config).await.0
     }
 
     pub async fn get_latest_block_info(
         &self,
         commitment_config: CommitmentConfig,
     ) -> BlockInformation {
-        self.get_latest_block_arc(commitment_config).read().await.1
+        self.latest_block_info(commitment_config)
+    }
+
+    /// `get_latest_block_info` for callers that mustn't yield
+    pub fn latest_block_info(&self, commitment_config: CommitmentConfig) -> BlockInformation {
+        self.get_latest_block_arc(commitment_config)
+            .read()
+            .unwrap()
+            .1
     }
 
     pub async fn get_latest_block(
@@ -133,7 +198,7 @@ impl BlockStore {
     ) -> (String, BlockInformation) {
         self.get_latest_block_arc(commitment_config)
             .read()
-            .await
+            .unwrap()
             .clone()
     }
 
@@ -154,14 +219,24 @@ impl BlockStore {
         // ask the map what it doesn't have rn
         let slot = block_info.slot;
         self.blocks.insert(blockhash.clone(), block_info);
+        self.parents
+            .write()
+            .unwrap()
+            .insert(slot, block_info.parent_slot);
         BLOCKS_IN_BLOCKSTORE.inc();
 
         let latest_block = self.get_latest_block_arc(commitment_config);
-        if slot > latest_block.read().await.1.slot {
-            *latest_block.write().await = (blockhash, block_info);
+        let mut latest_block = latest_block.write().unwrap();
+        if slot > latest_block.1.slot {
+            *latest_block = (blockhash, block_info);
         }
     }
 
+    /// Time since the block listeners last added a block
+    pub async fn last_block_elapsed(&self) -> Duration {
+        self.last_add_block_metric.read().await.elapsed()
+    }
+
     pub async fn clean(&self, cleanup_duration: Duration) {
         let latest_confirmed = self
             .get_latest_blockhash(CommitmentConfig {
@@ -175,11 +250,22 @@ impl BlockStore {
             .await;
 
         let before_length = self.blocks.len();
+        let mut cleaned = Vec::new();
         self.blocks.retain(|k, v| {
-            v.instant.elapsed() < cleanup_duration
+            let keep = v.instant.elapsed() < cleanup_duration
                 || k.eq(&latest_confirmed)
-                || k.eq(&latest_finalized)
+                || k.eq(&latest_finalized);
+            if !keep {
+                cleaned.push(v.slot);
+            }
+            keep
         });
+        {
+            let mut parents = self.parents.write().unwrap();
+            for slot in cleaned {
+                parents.remove(&slot);
+            }
+        }
         BLOCKS_IN_BLOCKSTORE.set(self.blocks.len() as i64);
 
         info!(
@@ -188,3 +274,67 @@ impl BlockStore {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(slot: u64, parent_slot: u64) -> (String, BlockInformation) {
+        (
+            format!("block{slot}"),
+            BlockInformation {
+                slot,
+                parent_slot,
+                block_height: slot,
+                block_time: None,
+                instant: Instant::now(),
+            },
+        )
+    }
+
+    async fn store(chain: &[(u64, u64)]) -> BlockStore {
+        let store = BlockStore::with_latest(block(chain[0].0, chain[0].1), block(0, 0));
+        for &(slot, parent_slot) in &chain[1..] {
+            let (blockhash, info) = block(slot, parent_slot);
+            store
+                .add_block(blockhash, info, CommitmentConfig::confirmed())
+                .await;
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn lists_the_slots_of_a_stored_range() {
+        // slot 13 was skipped
+        let store = store(&[(10, 9), (11, 10), (12, 11), (14, 12), (15, 14)]).await;
+
+        assert_eq!(
+            store.get_block_slots(11, 14, CommitmentConfig::confirmed()),
+            Some(vec![11, 12, 14])
+        );
+        assert_eq!(
+            store.get_block_slots(10, 20, CommitmentConfig::confirmed()),
+            Some(vec![10, 11, 12, 14, 15])
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_ranges_with_missing_blocks() {
+        // the block at 12 was missed
+        let store = store(&[(10, 9), (11, 10), (13, 12), (14, 13)]).await;
+
+        assert_eq!(
+            store.get_block_slots(13, 14, CommitmentConfig::confirmed()),
+            Some(vec![13, 14])
+        );
+        assert_eq!(
+            store.get_block_slots(11, 14, CommitmentConfig::confirmed()),
+            None
+        );
+        // older than the store
+        assert_eq!(
+            store.get_block_slots(5, 14, CommitmentConfig::confirmed()),
+            None
+        );
+    }
+}
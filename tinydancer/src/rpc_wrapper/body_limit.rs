@@ -0,0 +1,93 @@
+use std::{
+    error::Error as StdError,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{body::HttpBody, Body, Request, Response, StatusCode};
+use jsonrpsee::types::error::INVALID_REQUEST_CODE;
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::middleware::{error_response, take_ready};
+
+/// Buffers the request bodies limited by tower-http's `RequestBodyLimitLayer` for the
+/// layers after it. The limit layer refuses a declared content length over `max_size`
+/// itself, a chunked body going over it while read is answered with a JSON-RPC error
+#[derive(Clone)]
+pub struct BufferBodyLayer {
+    max_size: usize,
+}
+
+impl BufferBodyLayer {
+    pub fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+impl<S> Layer<S> for BufferBodyLayer {
+    type Service = BufferBody<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BufferBody {
+            inner,
+            max_size: self.max_size,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BufferBody<S> {
+    inner: S,
+    max_size: usize,
+}
+
+impl<S, B> Service<Request<B>> for BufferBody<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error> + Send,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let mut inner = take_ready(&mut self.inner);
+        let max_size = self.max_size;
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    let err: Box<dyn StdError + Send + Sync> = err.into();
+                    return match err.downcast::<hyper::Error>() {
+                        Ok(err) => Err((*err).into()),
+                        // the limit is the only error that isn't the connection's
+                        Err(_) => Ok(too_large(max_size)),
+                    };
+                }
+            };
+
+            inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+        })
+    }
+}
+
+fn too_large(max_size: usize) -> Response<Body> {
+    error_response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        INVALID_REQUEST_CODE as i64,
+        &format!("Request too large; max {max_size} bytes"),
+    )
+}
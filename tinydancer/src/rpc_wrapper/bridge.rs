@@ -1,62 +1,201 @@
 use crate::{
     get_endpoint,
+    leader_schedule::LeaderScheduleCache,
     rpc_wrapper::{
+        auth::ApiKeyLayer,
+        batch_limit::BatchLimitLayer,
         block_store::{BlockInformation, BlockStore},
-        configs::{IsBlockHashValidConfig, SendTransactionConfig},
+        body_limit::BufferBodyLayer,
+        caller::CallerLayer,
+        configs::{
+            AccessConfig, CleanerConfig, IsBlockHashValidConfig, PriorityFeeEstimateConfig,
+            PriorityFeePolicy, ProgramAccountsLimits, SendTransactionConfig, TlsConfig, TpuConfig,
+        },
         encoding::BinaryEncoding,
-        rpc::LiteRpcServer,
+        epoch_tracker::EpochTracker,
+        grpc::{GrpcAccessLayer, LiteGrpc, LiteGrpcConfig},
+        health::{Health, HealthLayer},
+        ip_filter::{client_ip_header, IpFilterLayer},
+        jwt::JwtLayer,
+        passthrough::PassthroughLayer,
+        prioritization_fees::{
+            compute_unit_price, MissingPriorityFee, PrioritizationFeeCache, PriorityFeeEnforcer,
+            PriorityFeeLevels,
+        },
+        program_accounts::ProgramAccounts,
+        proxy::PeerProxy,
+        rate_limit::{ClientRateLimits, MethodRateLimits, RateLimitLayer, RATE_LIMITED_CODE},
+        request_log::{self, RequestLogLayer},
+        rest::RestLayer,
+        rpc::{method, LiteRpcServer},
+        tls::TlsTerminator,
         tpu_manager::TpuManager,
-        workers::{BlockListener, Cleaner, TxSender, WireTransaction},
+        tx_quota::{QuotaExhausted, TxQuotas},
+        tx_validation::{InvalidTransaction, TxValidator},
+        upstreams::Upstreams,
+        workers::{
+            BlockListener, BundleForwarder, Cleaner, PubsubMultiplexer, QueuedTx,
+            SubscriptionRegistry, Tuner, TxSender, TxState, Unsent, Webhooks, WireTransaction,
+            MAX_BUNDLE_LEN, MAX_SIGNATURE_STATUSES_QUERY,
+        },
+        ws::{self, WsConfig, WsServer},
+        DEFAULT_LEADER_FORWARD_COUNT, DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_MULTIPLE_ACCOUNTS,
+        DEFAULT_MAX_REQUEST_SIZE, DEFAULT_MAX_SIGNATURES_FOR_ADDRESS, DEFAULT_RPC_SAMPLE_QTY,
+        DEFAULT_SIGNATURE_STATUS_FALLBACK, DEFAULT_TX_MAX_RETRIES, DEFAULT_TX_QUEUE_CAPACITY,
+        DEFAULT_TX_RETRY_INTERVAL_MS, DEFAULT_WS_PING_INTERVAL_S,
+    },
+    sampler::{
+        get_sample_record, get_serialized, lowest_sampled_slot, pull_and_verify_shreds,
+        SamplingStrategy, SlotSampleRecord, SHRED_CF,
     },
-    sampler::{get_serialized, pull_and_verify_shreds, SHRED_CF},
     tinydancer::Cluster,
     ConfigSchema,
 };
 use colored::Colorize;
-use hyper::Method;
+use hyper::{body::HttpBody, Body, Method};
 use reqwest::header;
-use serde::{self, Deserialize, Serialize};
+use serde::{self, de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use solana_account_decoder::{parse_token::UiTokenAmount, UiAccount};
 use solana_client::rpc_response::RpcApiVersion;
 use std::{
+    collections::HashSet,
     fs,
+    net::{Ipv4Addr, SocketAddr},
     ops::{Deref, Sub},
     path::Path,
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 
 use solana_ledger::shred::{Shred, ShredType, Slot};
 use tiny_logger::logs::{info, warn};
 
-use jsonrpsee::{server::ServerBuilder, types::SubscriptionResult, SubscriptionSink};
+use jsonrpsee::{
+    server::ServerBuilder,
+    types::{
+        error::{
+            CallError, ErrorObject, SubscriptionEmptyError, INVALID_PARAMS_CODE,
+            TOO_MANY_SUBSCRIPTIONS_CODE,
+        },
+        ErrorObjectOwned, SubscriptionResult,
+    },
+    SubscriptionSink,
+};
 use prometheus::{core::GenericGauge, opts, register_int_counter, register_int_gauge, IntCounter};
 use solana_rpc_client::{nonblocking::rpc_client::RpcClient, rpc_client::SerializableTransaction};
 use solana_rpc_client_api::{
-    config::{RpcContextConfig, RpcRequestAirdropConfig, RpcSignatureStatusConfig},
-    response::{Response as RpcResponse, RpcBlockhash, RpcResponseContext, RpcVersionInfo},
+    config::{
+        RpcAccountInfoConfig, RpcBlockConfig, RpcBlockSubscribeConfig, RpcBlockSubscribeFilter,
+        RpcContextConfig, RpcGetVoteAccountsConfig, RpcLeaderScheduleConfig,
+        RpcProgramAccountsConfig, RpcRequestAirdropConfig, RpcSignatureStatusConfig,
+        RpcSignatureSubscribeConfig, RpcSignaturesForAddressConfig, RpcSimulateTransactionConfig,
+        RpcTokenAccountsFilter, RpcTransactionConfig, RpcTransactionLogsConfig,
+        RpcTransactionLogsFilter,
+    },
+    request::{RpcRequest, MAX_GET_CONFIRMED_BLOCKS_RANGE, MAX_GET_SLOT_LEADERS},
+    response::{
+        Response as RpcResponse, RpcBlockhash, RpcConfirmedTransactionStatusWithSignature,
+        RpcContactInfo, RpcKeyedAccount, RpcLeaderSchedule, RpcPrioritizationFee,
+        RpcResponseContext, RpcVersionInfo, RpcVoteAccountStatus,
+    },
 };
 use solana_sdk::{
-    blake3::hashv, commitment_config::CommitmentConfig, hash::Hash, pubkey::Pubkey,
-    signature::Keypair, transaction::VersionedTransaction,
+    blake3::hashv,
+    clock::MAX_PROCESSING_AGE,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    epoch_info::EpochInfo,
+    epoch_schedule::EpochSchedule,
+    hash::Hash,
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    transaction::{VersionedTransaction, MAX_TX_ACCOUNT_LOCKS},
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionConfirmationStatus, TransactionDetails,
+    TransactionStatus, UiConfirmedBlock, UiTransactionEncoding,
 };
-use solana_transaction_status::TransactionStatus;
 use tokio::{
     net::ToSocketAddrs,
-    sync::mpsc::{self, UnboundedSender},
+    sync::mpsc::{self, error::TrySendError, Permit, Sender},
     task::JoinHandle,
 };
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    compression::{predicate::SizeAbove, CompressionBody, CompressionLayer},
+    cors::{Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    map_response_body::MapResponseBodyLayer,
+};
 
 lazy_static::lazy_static! {
     static ref RPC_SEND_TX: IntCounter =
     register_int_counter!(opts!("literpc_rpc_send_tx", "RPC call send transaction")).unwrap();
+    static ref RPC_DROP_TX: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_drop_tx", "RPC call drop transaction")).unwrap();
+    static ref RPC_REPLACE_TX: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_replace_tx", "RPC call replace transaction")).unwrap();
+    static ref RPC_SEND_BUNDLE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_send_bundle", "RPC call send bundle")).unwrap();
     static ref RPC_GET_LATEST_BLOCKHASH: IntCounter =
     register_int_counter!(opts!("literpc_rpc_get_latest_blockhash", "RPC call to get latest block hash")).unwrap();
     static ref RPC_IS_BLOCKHASH_VALID: IntCounter =
     register_int_counter!(opts!("literpc_rpc_is_blockhash_valid", "RPC call to check if blockhash is vali calld")).unwrap();
+    static ref RPC_GET_SLOT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_slot", "RPC call to get slot")).unwrap();
+    static ref RPC_GET_BLOCK: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_block", "RPC call to get block")).unwrap();
+    static ref RPC_GET_TRANSACTION: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_transaction", "RPC call to get transaction")).unwrap();
+    static ref RPC_GET_ACCOUNT_INFO: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_account_info", "RPC call to get account info")).unwrap();
+    static ref RPC_GET_BALANCE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_balance", "RPC call to get balance")).unwrap();
+    static ref RPC_GET_MULTIPLE_ACCOUNTS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_multiple_accounts", "RPC call to get multiple accounts")).unwrap();
+    static ref RPC_GET_PROGRAM_ACCOUNTS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_program_accounts", "RPC call to get program accounts")).unwrap();
+    static ref RPC_GET_EPOCH_INFO: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_epoch_info", "RPC call to get epoch info")).unwrap();
+    static ref RPC_GET_EPOCH_SCHEDULE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_epoch_schedule", "RPC call to get epoch schedule")).unwrap();
+    static ref RPC_GET_HEALTH: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_health", "RPC call to get health")).unwrap();
+    static ref RPC_GET_RECENT_PRIORITIZATION_FEES: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_recent_prioritization_fees", "RPC call to get recent prioritization fees")).unwrap();
+    static ref RPC_GET_FEE_FOR_MESSAGE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_fee_for_message", "RPC call to get fee for message")).unwrap();
+    static ref RPC_GET_TOKEN_ACCOUNT_BALANCE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_token_account_balance", "RPC call to get token account balance")).unwrap();
+    static ref RPC_GET_TOKEN_ACCOUNTS_BY_OWNER: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_token_accounts_by_owner", "RPC call to get token accounts by owner")).unwrap();
+    static ref RPC_GET_TOKEN_SUPPLY: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_token_supply", "RPC call to get token supply")).unwrap();
+    static ref RPC_MINIMUM_LEDGER_SLOT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_minimum_ledger_slot", "RPC call to get minimum ledger slot")).unwrap();
+    static ref RPC_GET_FIRST_AVAILABLE_BLOCK: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_first_available_block", "RPC call to get first available block")).unwrap();
+    static ref RPC_GET_CLUSTER_NODES: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_cluster_nodes", "RPC call to get cluster nodes")).unwrap();
+    static ref RPC_GET_VOTE_ACCOUNTS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_vote_accounts", "RPC call to get vote accounts")).unwrap();
+    static ref RPC_GET_LEADER_SCHEDULE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_leader_schedule", "RPC call to get leader schedule")).unwrap();
+    static ref RPC_GET_BLOCK_TIME: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_block_time", "RPC call to get block time")).unwrap();
+    static ref RPC_GET_SIGNATURES_FOR_ADDRESS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_signatures_for_address", "RPC call to get signatures for address")).unwrap();
+    static ref RPC_GET_BLOCKS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_blocks", "RPC call to get blocks")).unwrap();
+    static ref RPC_GET_BLOCKS_WITH_LIMIT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_blocks_with_limit", "RPC call to get blocks with limit")).unwrap();
+    static ref RPC_GET_SLOT_LEADERS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_slot_leaders", "RPC call to get slot leaders")).unwrap();
+    static ref RPC_GET_SLOT_VERIFICATION: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_slot_verification", "RPC call to get the verification of a slot")).unwrap();
     static ref RPC_GET_SIGNATURE_STATUSES: IntCounter =
     register_int_counter!(opts!("literpc_rpc_get_signature_statuses", "RPC call to get signature statuses")).unwrap();
     static ref RPC_GET_VERSION: IntCounter =
@@ -65,50 +204,834 @@ lazy_static::lazy_static! {
     register_int_counter!(opts!("literpc_rpc_airdrop", "RPC call to request airdrop")).unwrap();
     static ref RPC_SIGNATURE_SUBSCRIBE: IntCounter =
     register_int_counter!(opts!("literpc_rpc_signature_subscribe", "RPC call to subscribe to signature")).unwrap();
+    static ref RPC_ACCOUNT_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_account_subscribe", "RPC call to subscribe to account")).unwrap();
+    static ref RPC_LOGS_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_logs_subscribe", "RPC call to subscribe to logs")).unwrap();
+    static ref RPC_BLOCK_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_block_subscribe", "RPC call to subscribe to blocks")).unwrap();
+    static ref RPC_PROGRAM_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_program_subscribe", "RPC call to subscribe to program")).unwrap();
+    static ref RPC_SLOTS_UPDATES_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_slots_updates_subscribe", "RPC call to subscribe to slots updates")).unwrap();
+    static ref RPC_GET_PRIORITY_FEE_ESTIMATE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_priority_fee_estimate", "RPC call to estimate priority fees")).unwrap();
     pub static ref TXS_IN_CHANNEL: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_txs_in_channel", "Transactions in channel")).unwrap();
+    static ref TXS_REFUSED_QUEUE_FULL: IntCounter =
+    register_int_counter!(opts!("literpc_txs_refused_queue_full", "Transactions refused because the tpu queue was full")).unwrap();
+    static ref RPC_SEND_TX_PREFLIGHT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_send_tx_preflight", "Transactions simulated upstream before they were queued")).unwrap();
+    static ref TXS_PREFLIGHT_FAILED: IntCounter =
+    register_int_counter!(opts!("literpc_txs_preflight_failed", "Transactions refused because their preflight simulation failed")).unwrap();
+    static ref TXS_DEDUPED: IntCounter =
+    register_int_counter!(opts!("literpc_txs_deduped", "Transactions submitted again while already queued or sent, not queued twice")).unwrap();
+}
+
+/// Error code of transactions refused while the tpu queue is full, safe to retry
+pub const TX_QUEUE_FULL_CODE: i64 = -32006;
+/// Error code of transactions whose preflight simulation failed, same as the validator's
+pub const TX_PREFLIGHT_FAILURE_CODE: i64 = -32002;
+/// Error code of calls whose minimum context slot wasn't reached, same as the validator's
+pub const MIN_CONTEXT_SLOT_NOT_REACHED_CODE: i64 = -32016;
+/// Error code of transactions refused for not paying the required priority fee
+pub const TX_MISSING_PRIORITY_FEE_CODE: i64 = -32007;
+/// Error code of transactions whose signatures don't verify, same as the validator's
+pub const TX_SIGNATURE_VERIFICATION_FAILURE_CODE: i64 = -32003;
+
+/// How long the lowest sampled slots are cached, finding them scans the whole history
+const LOWEST_SAMPLED_SLOT_REFRESH: Duration = Duration::from_secs(60);
+
+/// Http responses smaller than this aren't worth compressing
+const MIN_COMPRESSED_SIZE: u16 = 1024;
+
+/// The tpu can't keep up, the transaction wasn't queued
+#[derive(Debug, thiserror::Error)]
+#[error("Transaction queue is full, retry later")]
+pub struct TxQueueFull;
+
+/// A transaction that passed the fee policy and the checks done before it's queued
+struct PreparedTx {
+    tx: VersionedTransaction,
+    raw_tx: WireTransaction,
+}
+
+/// First address `addr` resolves to
+async fn listen_addr<T: ToSocketAddrs + std::fmt::Debug + Clone>(
+    addr: T,
+) -> anyhow::Result<SocketAddr> {
+    tokio::net::lookup_host(addr.clone())
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("{addr:?} doesn't resolve to an address"))
+}
+
+/// Applies the priority fee policy and the checks that don't depend on the block store
+fn prepare_transaction(
+    tx_sender: &TxSender,
+    priority_fees: &PriorityFeeEnforcer,
+    raw_tx: WireTransaction,
+    callback_url: Option<&str>,
+) -> anyhow::Result<PreparedTx> {
+    let raw_tx = priority_fees.enforce(raw_tx)?;
+    let tx = bincode::deserialize::<VersionedTransaction>(&raw_tx)?;
+    if let Some(callback_url) = callback_url {
+        Webhooks::validate_url(callback_url)?;
+    }
+
+    if tx_sender.is_draining() {
+        bail!("Shutting down, not accepting transactions");
+    }
+
+    Ok(PreparedTx { tx, raw_tx })
+}
+
+/// Slot of the transaction's blockhash and the block height it's valid until, which
+/// durable nonce transactions don't have
+fn blockhash_expiry(
+    block_store: &BlockStore,
+    tx: &VersionedTransaction,
+) -> anyhow::Result<(Slot, Option<u64>)> {
+    if tx.uses_durable_nonce() {
+        let BlockInformation { slot, .. } =
+            block_store.latest_block_info(CommitmentConfig::confirmed());
+        return Ok((slot, None));
+    }
+
+    let Some(BlockInformation {
+        slot, block_height, ..
+    }) = block_store
+        .get_block_info(&tx.get_recent_blockhash().to_string()) else {
+            bail!("Blockhash not found in block store");
+    };
+    Ok((slot, Some(block_height + MAX_PROCESSING_AGE as u64)))
+}
+
+/// Claims a slot in the tx sender's queue, a full queue is refused
+fn reserve_queue(tx_send: &Sender<QueuedTx>) -> anyhow::Result<Permit<'_, QueuedTx>> {
+    match tx_send.try_reserve() {
+        Ok(permit) => Ok(permit),
+        Err(TrySendError::Full(())) => {
+            TXS_REFUSED_QUEUE_FULL.inc();
+            Err(TxQueueFull.into())
+        }
+        Err(TrySendError::Closed(())) => Err(anyhow!("Transaction sender stopped")),
+    }
+}
+
+/// Queues a prepared transaction in the slot claimed by `permit`, it can't fail anymore
+fn enqueue_transaction(
+    tx_sender: &TxSender,
+    permit: Permit<'_, QueuedTx>,
+    prepared: PreparedTx,
+    (slot, last_valid_block_height): (Slot, Option<u64>),
+    max_retries: Option<u16>,
+    callback_url: Option<String>,
+    target: Option<SocketAddr>,
+) -> Signature {
+    let PreparedTx { tx, raw_tx } = prepared;
+    let sig = *tx.get_signature();
+    let max_retries = max_retries.unwrap_or(DEFAULT_TX_MAX_RETRIES);
+    let retry_tx = (max_retries > 0).then(|| raw_tx.clone());
+
+    let queued = QueuedTx {
+        sig: sig.to_string(),
+        tx: raw_tx,
+        slot,
+        priority_fee: compute_unit_price(&tx),
+        target,
+    };
+    let journal_tx = queued.tx.clone();
+    // marked before it's sent, the tx sender skips transactions that aren't
+    let marked = tx_sender.mark_queued(
+        sig.to_string(),
+        Unsent {
+            fee_payer: tx.message.static_account_keys()[0],
+            priority_fee: queued.priority_fee,
+            target,
+        },
+        last_valid_block_height,
+        callback_url,
+    );
+    // a concurrent send of the same transaction got there first
+    if !marked {
+        TXS_DEDUPED.inc();
+        return sig;
+    }
+    permit.send(queued);
+    TXS_IN_CHANNEL.inc();
+    // only what was accepted is journaled
+    tx_sender.journal(&sig.to_string(), &journal_tx);
+
+    if tx.uses_durable_nonce() {
+        tx_sender.track_durable_nonce(sig.to_string());
+    }
+    if let Some(retry_tx) = retry_tx {
+        tx_sender.retry_until_confirmed(
+            sig.to_string(),
+            retry_tx,
+            max_retries,
+            last_valid_block_height,
+            target,
+        );
+    }
+
+    sig
+}
+
+/// Queues a bincode serialized transaction for the tpu and returns its signature,
+/// transactions whose blockhash isn't in the block store are rejected. Durable nonce
+/// transactions skip that check, their nonce isn't a recent blockhash. Until it's
+/// confirmed the transaction is rebroadcast up to `max_retries` times. A transaction
+/// that's tracked already isn't queued again. The priority fee policy may append a
+/// compute unit price first, changing the signature. It never waits, the block store
+/// lookups are map reads and a full queue is refused, so the signature is returned
+/// right away. With a `target` tpu it's sent and rebroadcast to that validator alone.
+/// It's taken from the caller's transaction quota once nothing else can refuse it
+#[allow(clippy::too_many_arguments)]
+pub fn queue_transaction(
+    block_store: &BlockStore,
+    tx_sender: &TxSender,
+    tx_send: &Sender<QueuedTx>,
+    priority_fees: &PriorityFeeEnforcer,
+    tx_quotas: &TxQuotas,
+    raw_tx: WireTransaction,
+    max_retries: Option<u16>,
+    callback_url: Option<String>,
+    target: Option<SocketAddr>,
+) -> anyhow::Result<Signature> {
+    let prepared = prepare_transaction(tx_sender, priority_fees, raw_tx, callback_url.as_deref())?;
+
+    let sig = *prepared.tx.get_signature();
+    if tx_sender.is_tracked(&sig.to_string()) {
+        TXS_DEDUPED.inc();
+        return Ok(sig);
+    }
+
+    let expiry = blockhash_expiry(block_store, &prepared.tx)?;
+    let permit = reserve_queue(tx_send)?;
+    tx_quotas.take()?;
+
+    Ok(enqueue_transaction(
+        tx_sender,
+        permit,
+        prepared,
+        expiry,
+        max_retries,
+        callback_url,
+        target,
+    ))
+}
+
+/// Queues `raw_tx` in place of the queued transaction `sig`, which is dropped. The
+/// replacement must be paid by the same fee payer at a higher priority fee, and `sig`
+/// must not have been sent yet. It goes to the `target` of `sig` unless it has its own.
+/// Everything the replacement could be refused for is checked, and its place in the
+/// queue claimed, before `sig` is dropped. It's taken from the caller's transaction
+/// quota like a sent transaction
+#[allow(clippy::too_many_arguments)]
+pub fn replace_transaction(
+    block_store: &BlockStore,
+    tx_sender: &TxSender,
+    tx_send: &Sender<QueuedTx>,
+    priority_fees: &PriorityFeeEnforcer,
+    tx_quotas: &TxQuotas,
+    sig: &str,
+    raw_tx: WireTransaction,
+    max_retries: Option<u16>,
+    callback_url: Option<String>,
+    target: Option<SocketAddr>,
+) -> anyhow::Result<Signature> {
+    let prepared = prepare_transaction(tx_sender, priority_fees, raw_tx, callback_url.as_deref())?;
+    let tx = &prepared.tx;
+
+    let Some(unsent) = tx_sender.unsent(sig) else {
+        bail!("Transaction {sig} isn't queued, it was sent already or is unknown");
+    };
+    check_replacement(&unsent, tx)?;
+
+    let replacement = tx.get_signature().to_string();
+    if tx_sender.is_tracked(&replacement) {
+        bail!("The replacement {replacement} was queued already");
+    }
+    let expiry = blockhash_expiry(block_store, tx)?;
+    let permit = reserve_queue(tx_send)?;
+    tx_quotas.take()?;
+
+    // taken off first so the original can't go out alongside the replacement
+    if tx_sender
+        .drop_queued(sig, TxState::Replaced { by: replacement })
+        .is_none()
+    {
+        tx_quotas.refund();
+        bail!("Transaction {sig} was sent already");
+    }
+
+    Ok(enqueue_transaction(
+        tx_sender,
+        permit,
+        prepared,
+        expiry,
+        max_retries,
+        callback_url,
+        target.or(unsent.target),
+    ))
+}
+
+/// Refuses a replacement of the queued transaction `unsent` that isn't paid by its fee
+/// payer at a higher priority fee
+fn check_replacement(unsent: &Unsent, tx: &VersionedTransaction) -> anyhow::Result<()> {
+    if tx.message.static_account_keys().first() != Some(&unsent.fee_payer) {
+        bail!("The replacement must be paid by {}", unsent.fee_payer);
+    }
+    if compute_unit_price(tx) <= unsent.priority_fee {
+        bail!(
+            "The replacement must pay a priority fee above {} micro lamports",
+            unsent.priority_fee
+        );
+    }
+    Ok(())
+}
+
+/// Refuses a transaction that couldn't be queued, with the code telling clients
+/// whether it's worth retrying
+fn queue_error(err: anyhow::Error) -> jsonrpsee::core::Error {
+    let code = if err.is::<TxQueueFull>() {
+        TX_QUEUE_FULL_CODE
+    } else if err.is::<QuotaExhausted>() {
+        RATE_LIMITED_CODE
+    } else if err.is::<MissingPriorityFee>() {
+        TX_MISSING_PRIORITY_FEE_CODE
+    } else {
+        return jsonrpsee::core::Error::Custom(format!("{err:#}"));
+    };
+    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+        code,
+        format!("{err:#}"),
+        None::<()>,
+    )))
+}
+
+/// Checks and queues the transactions clients send, over the json rpc and gRPC alike
+#[derive(Clone)]
+pub struct TxSubmitter {
+    rpc_client: Arc<RpcClient>,
+    tpu_manager: Arc<TpuManager>,
+    block_store: BlockStore,
+    tx_sender: TxSender,
+    tx_send: Sender<QueuedTx>,
+    priority_fees: Arc<PriorityFeeEnforcer>,
+    tx_validator: Arc<TxValidator>,
+    tx_quotas: TxQuotas,
+    method_limits: MethodRateLimits,
+    client_limits: ClientRateLimits,
+}
+
+impl TxSubmitter {
+    /// Simulates the transaction upstream the way the validator's preflight does, a
+    /// failed simulation is returned as the error with the simulation result as data
+    async fn preflight(
+        &self,
+        raw_tx: &[u8],
+        preflight_commitment: Option<CommitmentLevel>,
+        min_context_slot: Option<Slot>,
+    ) -> crate::rpc_wrapper::rpc::Result<()> {
+        let tx = bincode::deserialize::<VersionedTransaction>(raw_tx)
+            .map_err(|err| jsonrpsee::core::Error::Custom(err.to_string()))?;
+        // it was simulated the first time, a second run would only see it processed
+        if self.tx_sender.is_tracked(&tx.get_signature().to_string()) {
+            return Ok(());
+        }
+
+        RPC_SEND_TX_PREFLIGHT.inc();
+        let commitment = CommitmentConfig {
+            commitment: preflight_commitment.unwrap_or(CommitmentLevel::Finalized),
+        };
+        let simulation = self
+            .rpc_client
+            .simulate_transaction_with_config(
+                &tx,
+                RpcSimulateTransactionConfig {
+                    sig_verify: true,
+                    commitment: Some(commitment),
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    min_context_slot,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|err| jsonrpsee::core::Error::Custom(err.to_string()))?
+            .value;
+
+        match &simulation.err {
+            None => Ok(()),
+            Some(err) => {
+                TXS_PREFLIGHT_FAILED.inc();
+                Err(jsonrpsee::core::Error::Call(CallError::Custom(
+                    ErrorObject::owned(
+                        TX_PREFLIGHT_FAILURE_CODE,
+                        format!("Transaction simulation failed: {err}"),
+                        Some(simulation),
+                    ),
+                )))
+            }
+        }
+    }
+
+    /// Rejects malformed transactions before they're preflighted or queued, with the
+    /// validator's error codes
+    async fn validate_transaction(&self, raw_tx: &[u8]) -> crate::rpc_wrapper::rpc::Result<()> {
+        let err = match self.tx_validator.validate(raw_tx).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let code = match &err {
+            InvalidTransaction::InvalidSignature { .. } => TX_SIGNATURE_VERIFICATION_FAILURE_CODE,
+            err if err.is_malformed() => INVALID_PARAMS_CODE as i64,
+            _ => return Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        };
+        Err(jsonrpsee::core::Error::Call(CallError::Custom(
+            ErrorObject::owned(code, err.to_string(), None::<()>),
+        )))
+    }
+
+    /// Tpu of the validator a transaction is targeted at
+    async fn target_tpu(
+        &self,
+        target: Option<String>,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<SocketAddr>> {
+        let Some(target) = target else {
+            return Ok(None);
+        };
+        let identity = Pubkey::from_str(&target)
+            .map_err(|err| jsonrpsee::core::Error::Custom(format!("Invalid target {err}")))?;
+        match self.tpu_manager.tpu_of(&identity).await {
+            Some(tpu) => Ok(Some(tpu)),
+            None => Err(jsonrpsee::core::Error::Custom(format!(
+                "No tpu known for the target validator {target}"
+            ))),
+        }
+    }
+
+    /// Takes a call of `method` from its quota and from its caller's, refusing it once
+    /// either is spent
+    pub fn check_rate_limit(&self, method: &str) -> crate::rpc_wrapper::rpc::Result<()> {
+        exhausted_quota(&self.method_limits, &self.client_limits, method).map_or(Ok(()), |error| {
+            Err(jsonrpsee::core::Error::Call(CallError::Custom(error)))
+        })
+    }
+
+    /// Checks a transaction and queues it, in place of the queued transaction
+    /// `replaces` if there's one. The transaction is already decoded, the `encoding`
+    /// of the config is ignored
+    pub async fn submit(
+        &self,
+        raw_tx: WireTransaction,
+        send_transaction_config: SendTransactionConfig,
+        replaces: Option<&str>,
+    ) -> crate::rpc_wrapper::rpc::Result<Signature> {
+        let SendTransactionConfig {
+            skip_preflight,
+            preflight_commitment,
+            max_retries,
+            min_context_slot,
+            callback_url,
+            target,
+            ..
+        } = send_transaction_config;
+        let max_retries = max_retries.map(|retries| retries.min(u16::MAX as usize) as u16);
+        let target = self.target_tpu(target).await?;
+
+        self.validate_transaction(&raw_tx).await?;
+
+        let context_commitment = if skip_preflight {
+            CommitmentConfig::processed()
+        } else {
+            CommitmentConfig {
+                commitment: preflight_commitment.unwrap_or(CommitmentLevel::Finalized),
+            }
+        };
+        check_min_context_slot(&self.block_store, context_commitment, min_context_slot).await?;
+
+        if !skip_preflight {
+            self.preflight(&raw_tx, preflight_commitment, min_context_slot)
+                .await?;
+        }
+
+        let queued = match replaces {
+            Some(sig) => replace_transaction(
+                &self.block_store,
+                &self.tx_sender,
+                &self.tx_send,
+                &self.priority_fees,
+                &self.tx_quotas,
+                sig,
+                raw_tx,
+                max_retries,
+                callback_url,
+                target,
+            ),
+            None => queue_transaction(
+                &self.block_store,
+                &self.tx_sender,
+                &self.tx_send,
+                &self.priority_fees,
+                &self.tx_quotas,
+                raw_tx,
+                max_retries,
+                callback_url,
+                target,
+            ),
+        };
+        queued.map_err(queue_error)
+    }
+}
+
+/// Refuses the call while the latest slot at `commitment` is below
+/// `min_context_slot`, as the validator does
+async fn check_min_context_slot(
+    block_store: &BlockStore,
+    commitment: CommitmentConfig,
+    min_context_slot: Option<Slot>,
+) -> crate::rpc_wrapper::rpc::Result<()> {
+    if min_context_slot.is_none() {
+        return Ok(());
+    }
+    let slot = block_store.get_latest_block_info(commitment).await.slot;
+    check_context_slot(slot, min_context_slot)
+}
+
+/// Refuses a call answered at `slot` if that's below `min_context_slot`
+fn check_context_slot(
+    slot: Slot,
+    min_context_slot: Option<Slot>,
+) -> crate::rpc_wrapper::rpc::Result<()> {
+    match min_context_slot {
+        Some(min_context_slot) if slot < min_context_slot => Err(jsonrpsee::core::Error::Call(
+            CallError::Custom(ErrorObject::owned(
+                MIN_CONTEXT_SLOT_NOT_REACHED_CODE,
+                "Minimum context slot has not been reached",
+                Some(serde_json::json!({ "contextSlot": slot })),
+            )),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Error of a call over the quota of `method` or of its caller's, the call is taken
+/// from both otherwise
+fn exhausted_quota(
+    method_limits: &MethodRateLimits,
+    client_limits: &ClientRateLimits,
+    method: &str,
+) -> Option<ErrorObjectOwned> {
+    if !method_limits.try_take(method, 1) {
+        return Some(ErrorObject::owned(
+            RATE_LIMITED_CODE,
+            format!("Too many requests for {method}"),
+            None::<()>,
+        ));
+    }
+    if !client_limits.try_take_current() {
+        method_limits.refund(method, 1);
+        return Some(ErrorObject::owned(
+            RATE_LIMITED_CODE,
+            "Too many requests",
+            None::<()>,
+        ));
+    }
+    None
 }
 
 /// A bridge between clients and tpu
 pub struct LiteBridge {
     pub rpc_client: Arc<RpcClient>,
+    /// Upstream rpcs behind `rpc_client`, calls to methods not served here go to them
+    pub upstreams: Upstreams,
     pub tpu_manager: Arc<TpuManager>,
     pub db_instance: Arc<rocksdb::DB>,
     // None if LiteBridge is not executed
-    pub tx_send_channel: Option<UnboundedSender<(String, WireTransaction, u64)>>,
+    pub tx_submitter: Option<TxSubmitter>,
     pub tx_sender: TxSender,
     pub block_listner: BlockListener,
     pub block_store: BlockStore,
+    pub epoch_tracker: EpochTracker,
+    pub prioritization_fees: PrioritizationFeeCache,
+    pub sampling_strategy: SamplingStrategy,
+    pub program_accounts: ProgramAccounts,
+    pub latest_sampled_slot: Arc<AtomicU64>,
+    pub leader_schedule: LeaderScheduleCache,
+    /// None if no block engine is configured
+    pub bundle_forwarder: Option<BundleForwarder>,
+    pub pubsub_multiplexer: PubsubMultiplexer,
+    pub subscriptions: SubscriptionRegistry,
+    /// Priority fee policy applied to sent transactions
+    pub priority_fees: Arc<PriorityFeeEnforcer>,
+    /// Rejects malformed transactions before they're queued
+    pub tx_validator: Arc<TxValidator>,
+    pub access_config: AccessConfig,
+    /// Quotas of the limited methods, taken by the calls of every transport
+    pub method_limits: MethodRateLimits,
+    /// Quotas of each client, taken by the calls of every transport
+    pub client_limits: ClientRateLimits,
+    /// Transaction quotas of the api keys, taken by the calls of every transport
+    pub tx_quotas: TxQuotas,
+    /// Lowest sampled slot and lowest verified one, and when they were looked up
+    lowest_sampled_slots: Arc<tokio::sync::Mutex<[Option<(Instant, Option<Slot>)>; 2]>>,
+    pub health: Health,
 }
 
 impl LiteBridge {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        rpc_url: String,
-        ws_addr: String,
+        upstreams: Upstreams,
         fanout_slots: u64,
         identity: Keypair,
         db_instance: Arc<rocksdb::DB>,
+        sampling_strategy: SamplingStrategy,
+        program_accounts_limits: ProgramAccountsLimits,
+        latest_sampled_slot: Arc<AtomicU64>,
+        leader_schedule: LeaderScheduleCache,
+        block_engine_url: Option<String>,
+        tx_webhook_url: Option<String>,
+        tx_terminal_status: TransactionConfirmationStatus,
+        priority_fee_policy: PriorityFeePolicy,
+        tpu_config: TpuConfig,
+        access_config: AccessConfig,
     ) -> anyhow::Result<Self> {
-        let rpc_client = Arc::new(RpcClient::new(rpc_url.clone()));
+        let rpc_client = Arc::new(upstreams.rpc_client());
 
-        let tpu_manager =
-            Arc::new(TpuManager::new(rpc_client.clone(), ws_addr, fanout_slots, identity).await?);
+        let tpu_manager = Arc::new(
+            TpuManager::new(
+                rpc_client.clone(),
+                upstreams.clone(),
+                fanout_slots,
+                identity,
+                tpu_config,
+            )
+            .await?,
+        );
 
-        let tx_sender = TxSender::new(tpu_manager.clone());
+        let tx_sender = TxSender::new(tpu_manager.clone(), db_instance.clone());
 
         let block_store = BlockStore::new(&rpc_client).await?;
 
-        let block_listner =
-            BlockListener::new(rpc_client.clone(), tx_sender.clone(), block_store.clone());
+        let epoch_tracker = EpochTracker::new(&rpc_client).await?;
+
+        let prioritization_fees = PrioritizationFeeCache::default();
+
+        let priority_fees = Arc::new(PriorityFeeEnforcer::new(priority_fee_policy).await?);
+
+        let tx_validator = Arc::new(TxValidator::new(rpc_client.clone()));
+
+        let subscriptions = SubscriptionRegistry::new(access_config.websocket.max_subscriptions);
+
+        let pubsub_multiplexer = PubsubMultiplexer::new(upstreams.clone(), subscriptions.clone());
+
+        let health = Health::new(
+            block_store.clone(),
+            tpu_manager.clone(),
+            latest_sampled_slot.clone(),
+        );
+
+        let block_listner = BlockListener::new(
+            rpc_client.clone(),
+            upstreams.clone(),
+            tx_sender.clone(),
+            block_store.clone(),
+            prioritization_fees.clone(),
+            db_instance.clone(),
+            subscriptions.clone(),
+            Webhooks::new(tx_webhook_url),
+            tx_terminal_status,
+        );
 
         Ok(Self {
             db_instance,
             rpc_client,
+            upstreams,
             tpu_manager,
-            tx_send_channel: None,
+            tx_submitter: None,
             tx_sender,
             block_listner,
             block_store,
+            epoch_tracker,
+            prioritization_fees,
+            sampling_strategy,
+            program_accounts: ProgramAccounts::new(program_accounts_limits),
+            latest_sampled_slot,
+            leader_schedule,
+            bundle_forwarder: block_engine_url.map(BundleForwarder::new),
+            pubsub_multiplexer,
+            subscriptions,
+            priority_fees,
+            tx_validator,
+            method_limits: MethodRateLimits::new(&access_config),
+            client_limits: ClientRateLimits::new(&access_config),
+            tx_quotas: TxQuotas::new(&access_config.api_keys),
+            lowest_sampled_slots: Default::default(),
+            access_config,
+            health,
+        })
+    }
+
+    /// Takes a call of `method` from its quota and from its caller's, refusing it once
+    /// either is spent
+    fn check_rate_limit(&self, method: &str) -> crate::rpc_wrapper::rpc::Result<()> {
+        self.exhausted_quota(method).map_or(Ok(()), |error| {
+            Err(jsonrpsee::core::Error::Call(CallError::Custom(error)))
+        })
+    }
+
+    fn exhausted_quota(&self, method: &str) -> Option<ErrorObjectOwned> {
+        exhausted_quota(&self.method_limits, &self.client_limits, method)
+    }
+
+    /// Accepts a subscription unless `method` is over its quota or the total
+    /// subscription cap is reached, in which case it's rejected with a JSON-RPC error.
+    /// An accepted sink holds a reserved slot, it must be registered
+    fn accept_subscription(
+        &self,
+        sink: &mut SubscriptionSink,
+        method: &str,
+    ) -> Result<bool, SubscriptionEmptyError> {
+        if let Some(error) = self.exhausted_quota(method) {
+            sink.reject(error)?;
+            return Ok(false);
+        }
+        if !self.subscriptions.try_reserve() {
+            sink.reject(ErrorObject::owned(
+                TOO_MANY_SUBSCRIPTIONS_CODE,
+                format!(
+                    "Exceeded max limit of {} subscriptions",
+                    self.access_config.websocket.max_subscriptions
+                ),
+                None::<()>,
+            ))?;
+            return Ok(false);
+        }
+
+        if let Err(err) = sink.accept() {
+            self.subscriptions.release();
+            return Err(err.into());
+        }
+        Ok(true)
+    }
+
+    /// Runs shred sampling for `slot` against the upstream rpc
+    pub async fn sample_slot(&self, slot: Slot) -> bool {
+        pull_and_verify_shreds(
+            slot as usize,
+            self.rpc_client.url(),
+            DEFAULT_RPC_SAMPLE_QTY,
+            &self.sampling_strategy,
+        )
+        .await
+    }
+
+    /// Proxies a request whose response carries a context upstream, attaching the
+    /// sampling verdict of the context slot
+    pub async fn proxy_with_context<T: DeserializeOwned>(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<T>> {
+        let response: RpcResponse<T> = match self.rpc_client.send(request, params).await {
+            Ok(response) => response,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+
+        Ok(self.with_sampling(response).await)
+    }
+
+    /// Attaches the sampling verdict of the context slot to an upstream response
+    async fn with_sampling<T>(&self, response: RpcResponse<T>) -> LiteResponse<T> {
+        let sampled = self.sample_slot(response.context.slot).await;
+
+        LiteResponse {
+            context: LiteRpcResponseContext {
+                slot: response.context.slot,
+                api_version: response.context.api_version,
+                sampled,
+            },
+            value: response.value,
+        }
+    }
+
+    /// Slots of `slots` with a verified sample record
+    async fn verified_slots(&self, slots: HashSet<Slot>) -> HashSet<Slot> {
+        let db_instance = self.db_instance.clone();
+        let verified = tokio::task::spawn_blocking(move || {
+            slots
+                .into_iter()
+                .filter(|slot| {
+                    matches!(
+                        get_sample_record(&db_instance, *slot),
+                        Ok(Some(record)) if record.verified
+                    )
+                })
+                .collect()
+        })
+        .await;
+
+        verified.unwrap_or_else(|err| {
+            warn!("Couldn't look up sample records {err}");
+            HashSet::new()
+        })
+    }
+
+    /// Decodes a transaction of `sendTransaction` or `replaceTransaction` and queues it,
+    /// in place of the queued transaction `replaces` if there's one
+    async fn submit_transaction(
+        &self,
+        tx: String,
+        send_transaction_config: Option<SendTransactionConfig>,
+        replaces: Option<&str>,
+    ) -> crate::rpc_wrapper::rpc::Result<String> {
+        let send_transaction_config = send_transaction_config.unwrap_or_default();
+        let raw_tx = match send_transaction_config.encoding.decode_transaction(tx) {
+            Ok(raw_tx) => raw_tx,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+
+        let sig = self
+            .tx_submitter
+            .as_ref()
+            .expect("Lite Bridge Not Executed")
+            .submit(raw_tx, send_transaction_config, replaces)
+            .await?;
+        Ok(BinaryEncoding::Base58.encode(sig))
+    }
+
+    /// Lowest slot of our own sampling history, scanned off the rpc's worker threads
+    /// at most every `LOWEST_SAMPLED_SLOT_REFRESH`, callers meanwhile get the last scan
+    async fn lowest_sampled_slot(
+        &self,
+        verified_only: bool,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<Slot>> {
+        let mut lowest_sampled_slots = self.lowest_sampled_slots.lock().await;
+        let cached = &mut lowest_sampled_slots[verified_only as usize];
+        if let Some((scanned_at, slot)) = cached {
+            if scanned_at.elapsed() < LOWEST_SAMPLED_SLOT_REFRESH {
+                return Ok(*slot);
+            }
+        }
+
+        let db_instance = self.db_instance.clone();
+        let slot = match tokio::task::spawn_blocking(move || {
+            lowest_sampled_slot(&db_instance, verified_only)
         })
+        .await
+        {
+            Ok(Ok(slot)) => slot,
+            Ok(Err(err)) => return Err(jsonrpsee::core::Error::Custom(err)),
+            Err(err) => return Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        };
+        *cached = Some((Instant::now(), slot));
+        Ok(slot)
     }
 
     /// List for `JsonRpc` requests
@@ -119,15 +1042,62 @@ impl LiteBridge {
         ws_addr: T,
         tx_batch_size: usize,
         tx_send_interval: Duration,
-        clean_interval: Duration,
+        cleaner_config: CleanerConfig,
+        tls_config: Option<TlsConfig>,
+        grpc_config: Option<LiteGrpcConfig>,
+        identity_file: Option<String>,
     ) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
-        let (tx_send, tx_recv) = mpsc::unbounded_channel();
-        self.tx_send_channel = Some(tx_send);
+        if !self.access_config.api_keys.is_empty() && self.access_config.jwt.is_some() {
+            bail!("configure either api keys or jwt auth, not both");
+        }
+        let api_keys = ApiKeyLayer::new(&self.access_config.api_keys);
+        let jwt = JwtLayer::new(self.access_config.jwt.as_ref())?;
+        let ip_filter = IpFilterLayer::new(&self.access_config);
+        let callers = CallerLayer::new(&self.access_config);
 
-        let tx_sender = self
-            .tx_sender
+        let (tx_send, tx_recv) = mpsc::channel(DEFAULT_TX_QUEUE_CAPACITY);
+        let tx_submitter = TxSubmitter {
+            rpc_client: self.rpc_client.clone(),
+            tpu_manager: self.tpu_manager.clone(),
+            block_store: self.block_store.clone(),
+            tx_sender: self.tx_sender.clone(),
+            tx_send,
+            priority_fees: self.priority_fees.clone(),
+            tx_validator: self.tx_validator.clone(),
+            tx_quotas: self.tx_quotas.clone(),
+            method_limits: self.method_limits.clone(),
+            client_limits: self.client_limits.clone(),
+        };
+        let grpc = grpc_config.map(|config| {
+            let grpc = LiteGrpc::new(self.block_listner.clone(), tx_submitter.clone());
+            let access = GrpcAccessLayer::new(
+                api_keys.clone(),
+                jwt.clone(),
+                ip_filter.clone(),
+                callers.clone(),
+            );
+            grpc.serve(config, access)
+        });
+        self.tx_submitter = Some(tx_submitter);
+
+        self.tx_sender.set_batching(tx_batch_size, tx_send_interval);
+        let tx_sender = self.tx_sender.clone().execute(tx_recv);
+
+        let tuner = Tuner::new(self.tx_sender.clone(), self.tpu_manager.clone()).start();
+
+        let identity_watcher =
+            identity_file.map(|identity_file| self.tpu_manager.watch_identity(identity_file));
+
+        let leader_tracker = self
+            .tpu_manager
+            .as_ref()
             .clone()
-            .execute(tx_recv, tx_batch_size, tx_send_interval);
+            .track_leaders(self.leader_schedule.clone(), DEFAULT_LEADER_FORWARD_COUNT);
+
+        let tx_retrier = self.tx_sender.clone().retry(
+            self.block_store.clone(),
+            Duration::from_millis(DEFAULT_TX_RETRY_INTERVAL_MS),
+        );
 
         let finalized_block_listener = self
             .block_listner
@@ -139,22 +1109,80 @@ impl LiteBridge {
             .clone()
             .listen(CommitmentConfig::confirmed());
 
+        let processed_listener = self.block_listner.clone().listen_processed();
+
+        let received_listener = self.block_listner.clone().listen_received();
+
+        let failed_listener = self.block_listner.clone().listen_failed();
+
         let cleaner = Cleaner::new(
             self.tx_sender.clone(),
             self.block_listner.clone(),
             self.block_store.clone(),
             self.tpu_manager.clone(),
+            self.subscriptions.clone(),
         )
-        .start(clean_interval);
+        .start(cleaner_config);
 
-        let rpc = self.into_rpc();
+        let request_log = RequestLogLayer::new(&self.access_config);
+        let client_ip_header = client_ip_header(&self.access_config);
+        let tx_quotas = self.tx_quotas.clone();
+        let tls = tls_config.as_ref().map(TlsTerminator::new).transpose()?;
+        // behind tls, or if clients are told apart by address, the servers only listen on
+        // loopback. They can't see the address of clients, so the tls terminator or a
+        // proxy reporting it takes their addresses
+        let proxied = tls.is_some() || self.access_config.needs_client_ip();
+        let (ws_listen, http_listen) = if proxied {
+            let loopback: SocketAddr = (Ipv4Addr::LOCALHOST, 0).into();
+            (loopback, loopback)
+        } else {
+            (
+                listen_addr(ws_addr.clone()).await?,
+                listen_addr(http_addr.clone()).await?,
+            )
+        };
+        let health = self.health.clone();
+        let upstreams = self.upstreams.clone();
+        let access_config = self.access_config.clone();
+        let method_limits = self.method_limits.clone();
+        let client_limits = self.client_limits.clone();
+        let rate_limit_eviction = client_limits.evict_idle_clients();
+        let mut rpc = self.into_rpc();
+        // methods served here take their own quotas, on either transport
+        let rate_limit = RateLimitLayer::new(
+            &access_config,
+            method_limits,
+            client_limits,
+            rpc.method_names(),
+        );
+        rpc.merge(tx_quotas.into_rpc())?;
+        // methods not implemented here are forwarded upstream
+        let passthrough = PassthroughLayer::new(upstreams, rpc.method_names());
 
-        let (ws_server, http_server) = {
-            let ws_server_handle = ServerBuilder::default()
-                .ws_only()
-                .build(ws_addr.clone())
-                .await?
-                .start(rpc.clone())?;
+        let mut services = {
+            let ws_server = WsServer::new(
+                rpc.clone(),
+                callers.clone(),
+                WsConfig {
+                    max_message_size: DEFAULT_MAX_REQUEST_SIZE,
+                    max_connections: access_config.websocket.max_connections,
+                    max_subscriptions_per_connection: access_config
+                        .websocket
+                        .max_subscriptions_per_connection,
+                    max_queued_messages: access_config.websocket.max_queued_messages,
+                    max_in_flight_calls: access_config.websocket.max_in_flight_calls,
+                    ping_interval: Duration::from_secs(DEFAULT_WS_PING_INTERVAL_S),
+                },
+            );
+            let (ws_local_addr, ws_server) = ws::listen(
+                ws_listen,
+                tower::ServiceBuilder::new()
+                    .layer(ip_filter.clone())
+                    .layer(api_keys.clone())
+                    .layer(jwt.clone())
+                    .service(ws_server),
+            )
+            .await?;
             let cors = CorsLayer::new()
                 .allow_methods([Method::POST, Method::GET])
                 .allow_origin(Any)
@@ -164,38 +1192,98 @@ impl LiteBridge {
                     header::ACCESS_CONTROL_ALLOW_ORIGIN,
                     header::ACCESS_CONTROL_ALLOW_METHODS,
                 ]);
-            let middleware = tower::ServiceBuilder::new().layer(cors);
-            let http_server_handle = ServerBuilder::default()
+            let middleware = tower::ServiceBuilder::new()
+                .layer(RequestBodyLimitLayer::new(
+                    DEFAULT_MAX_REQUEST_SIZE as usize,
+                ))
+                .layer(cors)
+                .layer(request_log::trace_layer())
+                // the server wants body errors it can box as `std::error::Error`
+                .layer(MapResponseBodyLayer::new(|body: CompressionBody<Body>| {
+                    body.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+                }))
+                .layer(
+                    CompressionLayer::new()
+                        .no_deflate()
+                        .no_zstd()
+                        .compress_when(SizeAbove::new(MIN_COMPRESSED_SIZE)),
+                )
+                .layer(BufferBodyLayer::new(DEFAULT_MAX_REQUEST_SIZE as usize))
+                .layer(HealthLayer::new(health.clone()))
+                .layer(RestLayer)
+                .layer(request_log)
+                .layer(ip_filter)
+                .layer(api_keys)
+                .layer(jwt)
+                .layer(BatchLimitLayer::new(DEFAULT_MAX_BATCH_SIZE))
+                .layer(rate_limit)
+                .layer(callers)
+                .layer(passthrough);
+            let http_server = ServerBuilder::default()
                 .http_only()
+                .max_request_body_size(DEFAULT_MAX_REQUEST_SIZE)
+                .batch_requests_supported(true)
                 .set_middleware(middleware)
                 .set_host_filtering(jsonrpsee::server::AllowHosts::Any)
-                .build(http_addr.clone())
-                .await?
-                .start(rpc)?;
-
-            let ws_server = tokio::spawn(async move {
-                info!("Websocket Server started at {ws_addr:?}");
-                ws_server_handle.stopped().await;
-                bail!("Websocket server stopped");
-            });
+                .build(http_listen)
+                .await?;
+            let http_local_addr = http_server.local_addr()?;
+            let http_server_handle = http_server.start(rpc)?;
 
             let http_server = tokio::spawn(async move {
-                info!("HTTP Server started at {http_addr:?}");
+                info!("HTTP Server started at {http_local_addr}");
                 http_server_handle.stopped().await;
                 bail!("HTTP server stopped");
             });
 
-            (ws_server, http_server)
+            let mut services = vec![
+                health.watch("ws_server", ws_server),
+                health.watch("http_server", http_server),
+                health.watch("rate_limit_eviction", rate_limit_eviction),
+            ];
+            match tls {
+                Some(tls) => {
+                    services.push(health.watch(
+                        "ws_tls",
+                        tls.serve(ws_addr, ws_local_addr, &client_ip_header),
+                    ));
+                    services.push(health.watch(
+                        "http_tls",
+                        tls.serve(http_addr, http_local_addr, &client_ip_header),
+                    ));
+                    if tls_config.map_or(false, |config| config.reload) {
+                        services.push(health.watch("tls_reload", tls.watch()));
+                    }
+                }
+                None if proxied => {
+                    let ws_proxy = PeerProxy::new(ws_local_addr, &client_ip_header)?;
+                    let http_proxy = PeerProxy::new(http_local_addr, &client_ip_header)?;
+                    services.push(health.watch("ws_proxy", ws_proxy.listen(ws_addr)));
+                    services.push(health.watch("http_proxy", http_proxy.listen(http_addr)));
+                }
+                None => {}
+            }
+            services
         };
 
-        let services = vec![
-            ws_server,
-            http_server,
-            tx_sender,
-            finalized_block_listener,
-            confirmed_block_listener,
-            cleaner,
-        ];
+        services.extend([
+            health.watch("tx_sender", tx_sender),
+            health.watch("tx_retrier", tx_retrier),
+            health.watch("leader_tracker", leader_tracker),
+            health.watch("tuner", tuner),
+            health.watch("finalized_block_listener", finalized_block_listener),
+            health.watch("confirmed_block_listener", confirmed_block_listener),
+            health.watch("processed_listener", processed_listener),
+            health.watch("received_listener", received_listener),
+            health.watch("failed_listener", failed_listener),
+            health.watch("cleaner", cleaner),
+        ]);
+        if let Some(grpc) = grpc {
+            services.push(health.watch("grpc", grpc));
+        }
+        if let Some(identity_watcher) = identity_watcher {
+            services.push(health.watch("identity_watcher", identity_watcher));
+        }
 
         Ok(services)
     }
@@ -209,43 +1297,68 @@ impl LiteRpcServer for LiteBridge {
         send_transaction_config: Option<SendTransactionConfig>,
     ) -> crate::rpc_wrapper::rpc::Result<String> {
         RPC_SEND_TX.inc();
+        self.check_rate_limit(method::SEND_TRANSACTION)?;
 
-        let SendTransactionConfig {
-            encoding,
-            max_retries: _,
-        } = send_transaction_config.unwrap_or_default();
+        self.submit_transaction(tx, send_transaction_config, None)
+            .await
+    }
 
-        let raw_tx = match encoding.decode(tx) {
-            Ok(raw_tx) => raw_tx,
-            Err(err) => {
-                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
-            }
-        };
+    async fn drop_transaction(&self, signature: String) -> crate::rpc_wrapper::rpc::Result<bool> {
+        RPC_DROP_TX.inc();
+        self.check_rate_limit(method::DROP_TRANSACTION)?;
 
-        let tx = match bincode::deserialize::<VersionedTransaction>(&raw_tx) {
-            Ok(tx) => tx,
-            Err(err) => {
-                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
-            }
-        };
+        Ok(self
+            .tx_sender
+            .drop_queued(&signature, TxState::Dropped)
+            .is_some())
+    }
 
-        let sig = tx.get_signature();
-        let Some(BlockInformation { slot, .. }) = self
-            .block_store
-            .get_block_info(&tx.get_recent_blockhash().to_string())
-            .await else {
-                warn!("block");
-                return Err(jsonrpsee::core::Error::Custom("Blockhash not found in block store".to_string()));
+    async fn replace_transaction(
+        &self,
+        signature: String,
+        tx: String,
+        send_transaction_config: Option<SendTransactionConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<String> {
+        RPC_REPLACE_TX.inc();
+        self.check_rate_limit(method::REPLACE_TRANSACTION)?;
+
+        self.submit_transaction(tx, send_transaction_config, Some(&signature))
+            .await
+    }
+
+    async fn send_bundle(&self, txs: Vec<String>) -> crate::rpc_wrapper::rpc::Result<String> {
+        RPC_SEND_BUNDLE.inc();
+        self.check_rate_limit(method::SEND_BUNDLE)?;
+
+        let Some(bundle_forwarder) = &self.bundle_forwarder else {
+            return Err(jsonrpsee::core::Error::Custom(String::from(
+                "sendBundle is disabled, no block engine configured",
+            )));
         };
 
-        self.tx_send_channel
-            .as_ref()
-            .expect("Lite Bridge Not Executed")
-            .send((sig.to_string(), raw_tx, slot))
-            .unwrap();
-        TXS_IN_CHANNEL.inc();
+        if txs.is_empty() || txs.len() > MAX_BUNDLE_LEN {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Bundles hold 1 to {MAX_BUNDLE_LEN} transactions"
+            )));
+        }
 
-        Ok(BinaryEncoding::Base58.encode(sig))
+        // reject malformed bundles before they reach the block engine
+        for tx in &txs {
+            let raw_tx = match BinaryEncoding::Base58.decode_transaction(tx) {
+                Ok(raw_tx) => raw_tx,
+                Err(err) => {
+                    return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+                }
+            };
+            if let Err(err) = bincode::deserialize::<VersionedTransaction>(&raw_tx) {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        }
+
+        match bundle_forwarder.send_bundle(txs).await {
+            Ok(bundle_id) => Ok(bundle_id),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
     }
 
     async fn get_latest_blockhash(
@@ -253,6 +1366,7 @@ impl LiteRpcServer for LiteBridge {
         config: Option<RpcContextConfig>,
     ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<RpcBlockhash>> {
         RPC_GET_LATEST_BLOCKHASH.inc();
+        self.check_rate_limit(method::GET_LATEST_BLOCKHASH)?;
 
         let commitment_config = config
             .map(|config| config.commitment.unwrap_or_default())
@@ -265,25 +1379,7 @@ impl LiteRpcServer for LiteBridge {
             },
         ) = self.block_store.get_latest_block(commitment_config).await;
 
-        info!("glb {blockhash} {slot} {block_height}");
-        let mut rpc_url = String::from("http://0.0.0.0:8899");
-        let home_path = std::env::var("HOME").unwrap();
-        let is_existing = home_path.clone() + "/.config/tinydancer/config.json";
-        let path = Path::new(&is_existing);
-        if path.exists() {
-            let file = fs::File::open(home_path.clone() + "/.config/tinydancer/config.json")
-                .expect("Error reading config in bridge");
-            let config: ConfigSchema = serde_json::from_reader(file).unwrap();
-            rpc_url = get_endpoint(config.cluster);
-        } else {
-            println!(
-                "{} {}",
-                "Initialise a config first using:".to_string().yellow(),
-                "tinydancer set config".to_string().green()
-            );
-        }
-        let sampled =
-            pull_and_verify_shreds(slot as usize, String::from(rpc_url), 10 as usize).await;
+        let sampled = self.sample_slot(slot).await;
 
         Ok(LiteResponse {
             context: LiteRpcResponseContext {
@@ -304,6 +1400,7 @@ impl LiteRpcServer for LiteBridge {
         config: Option<IsBlockHashValidConfig>,
     ) -> crate::rpc_wrapper::rpc::Result<RpcResponse<bool>> {
         RPC_IS_BLOCKHASH_VALID.inc();
+        self.check_rate_limit(method::IS_BLOCKHASH_VALID)?;
 
         let commitment = config.unwrap_or_default().commitment.unwrap_or_default();
         let commitment = CommitmentConfig { commitment };
@@ -341,62 +1438,820 @@ impl LiteRpcServer for LiteBridge {
         })
     }
 
-    async fn get_signature_statuses(
+    async fn get_slot(
         &self,
-        sigs: Vec<String>,
-        _config: Option<RpcSignatureStatusConfig>,
-    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Vec<Option<TransactionStatus>>>> {
-        RPC_GET_SIGNATURE_STATUSES.inc();
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Slot> {
+        RPC_GET_SLOT.inc();
+        self.check_rate_limit(method::GET_SLOT)?;
+
+        let RpcContextConfig {
+            commitment,
+            min_context_slot,
+        } = config.unwrap_or_default();
 
-        let sig_statuses = sigs
-            .iter()
-            .map(|sig| {
-                self.tx_sender
-                    .txs_sent_store
-                    .get(sig)
-                    .and_then(|v| v.status.clone())
-            })
-            .collect();
         let slot = self
             .block_store
-            .get_latest_block_info(CommitmentConfig::finalized())
+            .get_latest_block_info(commitment.unwrap_or_default())
             .await
             .slot;
-        let mut rpc_url = String::from("http://0.0.0.0:8899");
-        let home_path = std::env::var("HOME").unwrap();
-        let is_existing = home_path.clone() + "/.config/tinydancer/config.json";
-        let path = Path::new(&is_existing);
-        if path.exists() {
-            let file = fs::File::open(home_path.clone() + "/.config/tinydancer/config.json")
-                .expect("Error reading config in bridge");
-            let config: ConfigSchema = serde_json::from_reader(file).unwrap();
-            rpc_url = get_endpoint(config.cluster);
-        } else {
-            println!(
-                "{} {}",
-                "Initialise a config first using:".to_string().yellow(),
-                "tinydancer set config".to_string().green()
-            );
-        }
-        let sampled =
-            pull_and_verify_shreds(slot as usize, String::from(rpc_url), 10 as usize).await;
-        Ok(LiteResponse {
-            context: LiteRpcResponseContext {
-                slot,
-                api_version: None,
-                sampled,
+        check_context_slot(slot, min_context_slot)?;
+
+        Ok(slot)
+    }
+
+    async fn get_epoch_info(
+        &self,
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<EpochInfo> {
+        RPC_GET_EPOCH_INFO.inc();
+        self.check_rate_limit(method::GET_EPOCH_INFO)?;
+
+        let RpcContextConfig {
+            commitment,
+            min_context_slot,
+        } = config.unwrap_or_default();
+
+        let block_info = self
+            .block_store
+            .get_latest_block_info(commitment.unwrap_or_default())
+            .await;
+        check_context_slot(block_info.slot, min_context_slot)?;
+
+        Ok(self
+            .epoch_tracker
+            .get_epoch_info(&self.rpc_client, block_info)
+            .await)
+    }
+
+    fn get_epoch_schedule(&self) -> crate::rpc_wrapper::rpc::Result<EpochSchedule> {
+        RPC_GET_EPOCH_SCHEDULE.inc();
+        self.check_rate_limit(method::GET_EPOCH_SCHEDULE)?;
+
+        Ok(self.epoch_tracker.epoch_schedule().clone())
+    }
+
+    async fn get_block(
+        &self,
+        slot: Slot,
+        config: Option<RpcBlockConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<LiteResponse<UiConfirmedBlock>>> {
+        RPC_GET_BLOCK.inc();
+        self.check_rate_limit(method::GET_BLOCK)?;
+
+        // null until the block reaches the commitment asked for, like upstream
+        let block: Option<UiConfirmedBlock> = match self
+            .rpc_client
+            .send(
+                RpcRequest::GetBlock,
+                json!([slot, config.unwrap_or_default()]),
+            )
+            .await
+        {
+            Ok(block) => block,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+        let Some(block) = block else {
+            return Ok(None);
+        };
+
+        // availability check the slot before handing out its data
+        let sampled = self.sample_slot(slot).await;
+
+        Ok(Some(LiteResponse {
+            context: LiteRpcResponseContext {
+                slot,
+                api_version: None,
+                sampled,
+            },
+            value: block,
+        }))
+    }
+
+    async fn get_blocks(
+        &self,
+        start_slot: Slot,
+        end_slot: Option<Slot>,
+        commitment: Option<CommitmentConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<Slot>> {
+        RPC_GET_BLOCKS.inc();
+        self.check_rate_limit(method::GET_BLOCKS)?;
+
+        let commitment = commitment.unwrap_or_default();
+        let latest_slot = self
+            .block_store
+            .get_latest_block_info(commitment)
+            .await
+            .slot;
+
+        let end_slot = end_slot.unwrap_or(latest_slot).min(latest_slot);
+        if end_slot < start_slot {
+            return Ok(vec![]);
+        }
+        if end_slot - start_slot > MAX_GET_CONFIRMED_BLOCKS_RANGE {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Slot range too large; max {MAX_GET_CONFIRMED_BLOCKS_RANGE}"
+            )));
+        }
+
+        // ranges the block store doesn't fully cover go upstream
+        if let Some(slots) = self
+            .block_store
+            .get_block_slots(start_slot, end_slot, commitment)
+        {
+            return Ok(slots);
+        }
+
+        match self
+            .rpc_client
+            .get_blocks_with_commitment(start_slot, Some(end_slot), commitment)
+            .await
+        {
+            Ok(slots) => Ok(slots),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_blocks_with_limit(
+        &self,
+        start_slot: Slot,
+        limit: usize,
+        commitment: Option<CommitmentConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<Slot>> {
+        RPC_GET_BLOCKS_WITH_LIMIT.inc();
+        self.check_rate_limit(method::GET_BLOCKS_WITH_LIMIT)?;
+
+        if limit as u64 > MAX_GET_CONFIRMED_BLOCKS_RANGE {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Limit too large; max {MAX_GET_CONFIRMED_BLOCKS_RANGE}"
+            )));
+        }
+
+        let commitment = commitment.unwrap_or_default();
+        let latest_slot = self
+            .block_store
+            .get_latest_block_info(commitment)
+            .await
+            .slot;
+
+        if let Some(mut slots) =
+            self.block_store
+                .get_block_slots(start_slot, latest_slot, commitment)
+        {
+            slots.truncate(limit);
+            return Ok(slots);
+        }
+
+        match self
+            .rpc_client
+            .get_blocks_with_limit_and_commitment(start_slot, limit, commitment)
+            .await
+        {
+            Ok(slots) => Ok(slots),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_transaction(
+        &self,
+        signature_str: String,
+        config: Option<RpcTransactionConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<LiteResponse<VerifiedTransaction>>> {
+        RPC_GET_TRANSACTION.inc();
+        self.check_rate_limit(method::GET_TRANSACTION)?;
+
+        if let Err(err) = Signature::from_str(&signature_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+        let config = config.unwrap_or_default();
+
+        let transaction: Option<EncodedConfirmedTransactionWithStatusMeta> = match self
+            .rpc_client
+            .send(RpcRequest::GetTransaction, json!([signature_str, config]))
+            .await
+        {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+        // unknown or not yet at the commitment asked for
+        let Some(transaction) = transaction else {
+            return Ok(None);
+        };
+        let slot = transaction.slot;
+
+        let sampled = self.sample_slot(slot).await;
+
+        // entries can't be recovered from a sample, so membership is checked against
+        // the signatures of the (sampled) block instead
+        let included_in_block = self
+            .rpc_client
+            .get_block_with_config(
+                slot,
+                RpcBlockConfig {
+                    encoding: None,
+                    transaction_details: Some(TransactionDetails::Signatures),
+                    rewards: Some(false),
+                    commitment: config.commitment,
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await
+            .ok()
+            .and_then(|block| block.signatures)
+            .map(|signatures| signatures.contains(&signature_str));
+
+        Ok(Some(LiteResponse {
+            context: LiteRpcResponseContext {
+                slot,
+                api_version: None,
+                sampled,
+            },
+            value: VerifiedTransaction {
+                transaction,
+                included_in_block,
+            },
+        }))
+    }
+
+    async fn get_account_info(
+        &self,
+        pubkey_str: String,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Option<UiAccount>>> {
+        RPC_GET_ACCOUNT_INFO.inc();
+        self.check_rate_limit(method::GET_ACCOUNT_INFO)?;
+
+        if let Err(err) = Pubkey::from_str(&pubkey_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(RpcRequest::GetAccountInfo, json!([pubkey_str, config]))
+            .await
+    }
+
+    async fn get_balance(
+        &self,
+        pubkey_str: String,
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<u64>> {
+        RPC_GET_BALANCE.inc();
+        self.check_rate_limit(method::GET_BALANCE)?;
+
+        if let Err(err) = Pubkey::from_str(&pubkey_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(RpcRequest::GetBalance, json!([pubkey_str, config]))
+            .await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        pubkey_strs: Vec<String>,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Vec<Option<UiAccount>>>> {
+        RPC_GET_MULTIPLE_ACCOUNTS.inc();
+        self.check_rate_limit(method::GET_MULTIPLE_ACCOUNTS)?;
+
+        if pubkey_strs.len() > DEFAULT_MAX_MULTIPLE_ACCOUNTS {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Too many inputs provided; max {DEFAULT_MAX_MULTIPLE_ACCOUNTS}"
+            )));
+        }
+
+        for pubkey_str in &pubkey_strs {
+            if let Err(err) = Pubkey::from_str(pubkey_str) {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        }
+
+        self.proxy_with_context(
+            RpcRequest::GetMultipleAccounts,
+            json!([pubkey_strs, config]),
+        )
+        .await
+    }
+
+    async fn get_program_accounts(
+        &self,
+        program_id_str: String,
+        config: Option<RpcProgramAccountsConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteOptionalContext<Vec<RpcKeyedAccount>>> {
+        RPC_GET_PROGRAM_ACCOUNTS.inc();
+        self.check_rate_limit(method::GET_PROGRAM_ACCOUNTS)?;
+
+        let limits = &self.program_accounts.limits;
+        if limits.disabled {
+            return Err(jsonrpsee::core::Error::Custom(String::from(
+                "getProgramAccounts is disabled on this rpc",
+            )));
+        }
+
+        if let Err(err) = Pubkey::from_str(&program_id_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        let mut config = config.unwrap_or_default();
+        let has_filters = config
+            .filters
+            .as_ref()
+            .map_or(false, |filters| !filters.is_empty());
+        if limits.require_filters && !has_filters {
+            return Err(jsonrpsee::core::Error::Custom(String::from(
+                "getProgramAccounts requires at least one filter on this rpc",
+            )));
+        }
+
+        // always fetch the context upstream, its slot is the one sampled
+        let with_context = config.with_context.unwrap_or_default();
+        config.with_context = Some(true);
+
+        // streamed, a response over max_accounts is dropped without being read whole
+        let response = self
+            .program_accounts
+            .fetch(&self.rpc_client.url(), json!([program_id_str, config]))
+            .await
+            .map_err(|err| jsonrpsee::core::Error::Custom(err.to_string()))?;
+        let response = self.with_sampling(response).await;
+
+        if with_context {
+            Ok(LiteOptionalContext::Context(response))
+        } else {
+            Ok(LiteOptionalContext::NoContext(response.value))
+        }
+    }
+
+    async fn get_health(&self) -> crate::rpc_wrapper::rpc::Result<String> {
+        RPC_GET_HEALTH.inc();
+        self.check_rate_limit(method::GET_HEALTH)?;
+
+        self.health.readiness().await.map_err(|reason| {
+            jsonrpsee::core::Error::Custom(format!("Node is unhealthy: {reason}"))
+        })?;
+
+        Ok(String::from("ok"))
+    }
+
+    async fn get_recent_prioritization_fees(
+        &self,
+        pubkey_strs: Option<Vec<String>>,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<RpcPrioritizationFee>> {
+        RPC_GET_RECENT_PRIORITIZATION_FEES.inc();
+        self.check_rate_limit(method::GET_RECENT_PRIORITIZATION_FEES)?;
+
+        let pubkey_strs = pubkey_strs.unwrap_or_default();
+        if pubkey_strs.len() > MAX_TX_ACCOUNT_LOCKS {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Too many inputs provided; max {MAX_TX_ACCOUNT_LOCKS}"
+            )));
+        }
+
+        let mut accounts = Vec::with_capacity(pubkey_strs.len());
+        for pubkey_str in pubkey_strs {
+            match Pubkey::from_str(&pubkey_str) {
+                Ok(pubkey) => accounts.push(pubkey),
+                Err(err) => {
+                    return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+                }
+            }
+        }
+
+        Ok(self.prioritization_fees.get_recent_fees(&accounts).await)
+    }
+
+    async fn get_priority_fee_estimate(
+        &self,
+        config: Option<PriorityFeeEstimateConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<PriorityFeeLevels> {
+        RPC_GET_PRIORITY_FEE_ESTIMATE.inc();
+        self.check_rate_limit(method::GET_PRIORITY_FEE_ESTIMATE)?;
+
+        let PriorityFeeEstimateConfig {
+            account_keys,
+            lookback_slots,
+        } = config.unwrap_or_default();
+
+        let account_keys = account_keys.unwrap_or_default();
+        if account_keys.len() > MAX_TX_ACCOUNT_LOCKS {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Too many inputs provided; max {MAX_TX_ACCOUNT_LOCKS}"
+            )));
+        }
+
+        let mut accounts = Vec::with_capacity(account_keys.len());
+        for account_key in account_keys {
+            match Pubkey::from_str(&account_key) {
+                Ok(pubkey) => accounts.push(pubkey),
+                Err(err) => {
+                    return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+                }
+            }
+        }
+
+        Ok(self
+            .prioritization_fees
+            .estimate(&accounts, lookback_slots.unwrap_or(usize::MAX))
+            .await)
+    }
+
+    async fn get_fee_for_message(
+        &self,
+        message: String,
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Option<u64>>> {
+        RPC_GET_FEE_FOR_MESSAGE.inc();
+        self.check_rate_limit(method::GET_FEE_FOR_MESSAGE)?;
+
+        // the cluster only accepts base64 encoded messages
+        let raw_message = match BinaryEncoding::Base64.decode(&message) {
+            Ok(raw_message) => raw_message,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+
+        if let Err(err) = bincode::deserialize::<VersionedMessage>(&raw_message) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(RpcRequest::GetFeeForMessage, json!([message, config]))
+            .await
+    }
+
+    async fn get_token_account_balance(
+        &self,
+        pubkey_str: String,
+        commitment: Option<CommitmentConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<UiTokenAmount>> {
+        RPC_GET_TOKEN_ACCOUNT_BALANCE.inc();
+        self.check_rate_limit(method::GET_TOKEN_ACCOUNT_BALANCE)?;
+
+        if let Err(err) = Pubkey::from_str(&pubkey_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(
+            RpcRequest::GetTokenAccountBalance,
+            json!([pubkey_str, commitment]),
+        )
+        .await
+    }
+
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner_str: String,
+        token_account_filter: RpcTokenAccountsFilter,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Vec<RpcKeyedAccount>>> {
+        RPC_GET_TOKEN_ACCOUNTS_BY_OWNER.inc();
+        self.check_rate_limit(method::GET_TOKEN_ACCOUNTS_BY_OWNER)?;
+
+        if let Err(err) = Pubkey::from_str(&owner_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(
+            RpcRequest::GetTokenAccountsByOwner,
+            json!([owner_str, token_account_filter, config]),
+        )
+        .await
+    }
+
+    async fn get_token_supply(
+        &self,
+        mint_str: String,
+        commitment: Option<CommitmentConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<UiTokenAmount>> {
+        RPC_GET_TOKEN_SUPPLY.inc();
+        self.check_rate_limit(method::GET_TOKEN_SUPPLY)?;
+
+        if let Err(err) = Pubkey::from_str(&mint_str) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        self.proxy_with_context(RpcRequest::GetTokenSupply, json!([mint_str, commitment]))
+            .await
+    }
+
+    async fn minimum_ledger_slot(&self) -> crate::rpc_wrapper::rpc::Result<Slot> {
+        RPC_MINIMUM_LEDGER_SLOT.inc();
+        self.check_rate_limit(method::MINIMUM_LEDGER_SLOT)?;
+
+        let upstream = match self.rpc_client.minimum_ledger_slot().await {
+            Ok(slot) => slot,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+
+        // nothing below what we sampled can be vouched for, nor below what the
+        // upstream retains served
+        let sampled = self.lowest_sampled_slot(false).await?;
+        Ok(sampled.map_or(upstream, |sampled| sampled.max(upstream)))
+    }
+
+    async fn get_first_available_block(&self) -> crate::rpc_wrapper::rpc::Result<Slot> {
+        RPC_GET_FIRST_AVAILABLE_BLOCK.inc();
+        self.check_rate_limit(method::GET_FIRST_AVAILABLE_BLOCK)?;
+
+        let upstream = match self.rpc_client.get_first_available_block().await {
+            Ok(slot) => slot,
+            Err(err) => {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        };
+
+        let verified = self.lowest_sampled_slot(true).await?;
+        Ok(verified.map_or(upstream, |verified| verified.max(upstream)))
+    }
+
+    async fn get_cluster_nodes(&self) -> crate::rpc_wrapper::rpc::Result<Vec<RpcContactInfo>> {
+        RPC_GET_CLUSTER_NODES.inc();
+        self.check_rate_limit(method::GET_CLUSTER_NODES)?;
+
+        // tinydancer doesn't take part in gossip, the upstream's view is all we have
+        match self.rpc_client.get_cluster_nodes().await {
+            Ok(nodes) => Ok(nodes),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_vote_accounts(
+        &self,
+        config: Option<RpcGetVoteAccountsConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<RpcVoteAccountStatus> {
+        RPC_GET_VOTE_ACCOUNTS.inc();
+        self.check_rate_limit(method::GET_VOTE_ACCOUNTS)?;
+
+        // only the unfiltered set is cached, filtered calls go upstream
+        let vote_accounts = match config {
+            Some(config) if config != RpcGetVoteAccountsConfig::default() => self
+                .rpc_client
+                .get_vote_accounts_with_config(config)
+                .await
+                .map_err(anyhow::Error::from),
+            _ => self.leader_schedule.vote_accounts().await,
+        };
+
+        match vote_accounts {
+            Ok(vote_accounts) => Ok(vote_accounts),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_leader_schedule(
+        &self,
+        slot: Option<Slot>,
+        config: Option<RpcLeaderScheduleConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<RpcLeaderSchedule>> {
+        RPC_GET_LEADER_SCHEDULE.inc();
+        self.check_rate_limit(method::GET_LEADER_SCHEDULE)?;
+
+        let RpcLeaderScheduleConfig {
+            identity,
+            commitment,
+        } = config.unwrap_or_default();
+
+        let latest_slot = self
+            .block_store
+            .get_latest_block_info(commitment.unwrap_or_default())
+            .await
+            .slot;
+        let epoch_schedule = self.leader_schedule.epoch_schedule();
+        let current_epoch = epoch_schedule.get_epoch(latest_slot);
+        let epoch = epoch_schedule.get_epoch(slot.unwrap_or(latest_slot));
+        // only the schedules in use are served, others would evict them from the cache
+        if epoch != current_epoch && epoch != current_epoch + 1 {
+            return Ok(None);
+        }
+
+        // derived from the epoch's stakes, the schedule the shreds are verified against
+        let schedule = match self.leader_schedule.get_schedule(epoch).await {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                warn!("No local leader schedule of epoch {}: {}", epoch, err);
+                return Ok(None);
+            }
+        };
+
+        let mut leader_schedule = RpcLeaderSchedule::new();
+        for (slot_index, leader) in schedule.get_slot_leaders().iter().enumerate() {
+            let leader = leader.to_string();
+            if identity
+                .as_ref()
+                .map_or(true, |identity| *identity == leader)
+            {
+                leader_schedule.entry(leader).or_default().push(slot_index);
+            }
+        }
+
+        Ok(Some(leader_schedule))
+    }
+
+    async fn get_block_time(&self, slot: Slot) -> crate::rpc_wrapper::rpc::Result<i64> {
+        RPC_GET_BLOCK_TIME.inc();
+        self.check_rate_limit(method::GET_BLOCK_TIME)?;
+
+        if let Some(block_time) = self.block_store.get_block_time(slot) {
+            return Ok(block_time);
+        }
+
+        match self.rpc_client.get_block_time(slot).await {
+            Ok(block_time) => Ok(block_time),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        address: String,
+        config: Option<RpcSignaturesForAddressConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        RPC_GET_SIGNATURES_FOR_ADDRESS.inc();
+        self.check_rate_limit(method::GET_SIGNATURES_FOR_ADDRESS)?;
+
+        if let Err(err) = Pubkey::from_str(&address) {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+
+        let mut config = config.unwrap_or_default();
+        for signature in config.before.iter().chain(config.until.iter()) {
+            if let Err(err) = Signature::from_str(signature) {
+                return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+            }
+        }
+
+        // pages are capped here rather than at the upstream's own limit
+        match config.limit {
+            Some(limit) if limit == 0 || limit > DEFAULT_MAX_SIGNATURES_FOR_ADDRESS => {
+                return Err(jsonrpsee::core::Error::Custom(format!(
+                    "Invalid limit; max {DEFAULT_MAX_SIGNATURES_FOR_ADDRESS}"
+                )));
+            }
+            Some(_) => {}
+            None => config.limit = Some(DEFAULT_MAX_SIGNATURES_FOR_ADDRESS),
+        }
+
+        match self
+            .rpc_client
+            .send(
+                RpcRequest::GetSignaturesForAddress,
+                json!([address, config]),
+            )
+            .await
+        {
+            Ok(signatures) => Ok(signatures),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_slot_leaders(
+        &self,
+        start_slot: Slot,
+        limit: u64,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<String>> {
+        RPC_GET_SLOT_LEADERS.inc();
+        self.check_rate_limit(method::GET_SLOT_LEADERS)?;
+
+        if limit > MAX_GET_SLOT_LEADERS as u64 {
+            return Err(jsonrpsee::core::Error::Custom(format!(
+                "Invalid limit; max {MAX_GET_SLOT_LEADERS}"
+            )));
+        }
+
+        // only epochs already derived are served, a request never derives or evicts one
+        match self
+            .leader_schedule
+            .cached_slot_leaders(start_slot, limit)
+            .await
+        {
+            Some(leaders) => Ok(leaders.iter().map(|leader| leader.to_string()).collect()),
+            None => Err(jsonrpsee::core::Error::Custom(format!(
+                "Leader schedule for slots {start_slot} to {} isn't available",
+                start_slot.saturating_add(limit.saturating_sub(1))
+            ))),
+        }
+    }
+
+    async fn get_slot_verification(
+        &self,
+        slot: Slot,
+    ) -> crate::rpc_wrapper::rpc::Result<Option<SlotSampleRecord>> {
+        RPC_GET_SLOT_VERIFICATION.inc();
+        self.check_rate_limit(method::GET_SLOT_VERIFICATION)?;
+
+        let db_instance = self.db_instance.clone();
+        match tokio::task::spawn_blocking(move || get_sample_record(&db_instance, slot)).await {
+            Ok(Ok(record)) => Ok(record),
+            Ok(Err(err)) => Err(jsonrpsee::core::Error::Custom(err)),
+            Err(err) => Err(jsonrpsee::core::Error::Custom(err.to_string())),
+        }
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        sigs: Vec<String>,
+        config: Option<RpcSignatureStatusConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Vec<Option<TransactionStatus>>>> {
+        RPC_GET_SIGNATURE_STATUSES.inc();
+        self.check_rate_limit(method::GET_SIGNATURE_STATUSES)?;
+
+        let mut sig_statuses: Vec<Option<TransactionStatus>> = sigs
+            .iter()
+            .map(|sig| {
+                self.tx_sender
+                    .txs_sent_store
+                    .get(sig)
+                    .and_then(|v| v.status.clone())
+            })
+            .collect();
+
+        // transactions we didn't send are looked up upstream
+        let unknown: Vec<(usize, Signature)> = sig_statuses
+            .iter()
+            .zip(&sigs)
+            .enumerate()
+            .filter(|(_, (status, _))| status.is_none())
+            .filter_map(|(index, (_, sig))| Some((index, Signature::from_str(sig).ok()?)))
+            .collect();
+        if DEFAULT_SIGNATURE_STATUS_FALLBACK && !unknown.is_empty() {
+            let search_history = config.map_or(false, |config| config.search_transaction_history);
+            let mut upstream = Vec::with_capacity(unknown.len());
+            for unknown in unknown.chunks(MAX_SIGNATURE_STATUSES_QUERY) {
+                let unknown_sigs: Vec<Signature> = unknown.iter().map(|(_, sig)| *sig).collect();
+                let statuses = if search_history {
+                    self.rpc_client
+                        .get_signature_statuses_with_history(&unknown_sigs)
+                        .await
+                } else {
+                    self.rpc_client.get_signature_statuses(&unknown_sigs).await
+                };
+
+                match statuses {
+                    Ok(statuses) => upstream.extend(unknown.iter().zip(statuses.value)),
+                    Err(err) => {
+                        warn!("Couldn't fetch signature statuses upstream: {err}");
+                        break;
+                    }
+                }
+            }
+
+            // only statuses in slots the sampler verified are vouched for
+            let slots = upstream
+                .iter()
+                .filter_map(|(_, status)| Some(status.as_ref()?.slot))
+                .collect();
+            let verified = self.verified_slots(slots).await;
+            for ((index, _), status) in upstream {
+                sig_statuses[*index] = status.filter(|status| verified.contains(&status.slot));
+            }
+        }
+
+        let slot = self
+            .block_store
+            .get_latest_block_info(CommitmentConfig::finalized())
+            .await
+            .slot;
+        let sampled = self.sample_slot(slot).await;
+        Ok(LiteResponse {
+            context: LiteRpcResponseContext {
+                slot,
+                api_version: None,
+                sampled,
             },
             value: sig_statuses,
         })
     }
 
-    fn get_version(&self) -> crate::rpc_wrapper::rpc::Result<RpcVersionInfo> {
+    fn get_version(&self) -> crate::rpc_wrapper::rpc::Result<LiteVersionInfo> {
         RPC_GET_VERSION.inc();
+        self.check_rate_limit(method::GET_VERSION)?;
 
         let version = solana_version::Version::default();
-        Ok(RpcVersionInfo {
-            solana_core: version.to_string(),
-            feature_set: Some(version.feature_set),
+        let auth = if self.access_config.jwt.is_some() {
+            AuthMode::Jwt
+        } else if !self.access_config.api_keys.is_empty() {
+            AuthMode::ApiKey
+        } else {
+            AuthMode::None
+        };
+
+        Ok(LiteVersionInfo {
+            version: RpcVersionInfo {
+                solana_core: version.to_string(),
+                feature_set: Some(version.feature_set),
+            },
+            tinydancer: LiteExtensions {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                sampling_strategy: self.sampling_strategy.clone(),
+                sampling_transport: String::from("getShreds"),
+                auth,
+                extended_context_version: EXTENDED_CONTEXT_VERSION,
+            },
         })
     }
 
@@ -407,6 +2262,7 @@ impl LiteRpcServer for LiteBridge {
         config: Option<RpcRequestAirdropConfig>,
     ) -> crate::rpc_wrapper::rpc::Result<String> {
         RPC_REQUEST_AIRDROP.inc();
+        self.check_rate_limit(method::REQUEST_AIRDROP)?;
 
         let pubkey = match Pubkey::from_str(&pubkey_str) {
             Ok(pubkey) => pubkey,
@@ -426,9 +2282,7 @@ impl LiteRpcServer for LiteBridge {
             }
         };
 
-        self.tx_sender
-            .txs_sent_store
-            .insert(airdrop_sig.clone(), Default::default());
+        self.tx_sender.mark_sent(airdrop_sig.clone());
 
         Ok(airdrop_sig)
     }
@@ -437,12 +2291,171 @@ impl LiteRpcServer for LiteBridge {
         &self,
         mut sink: SubscriptionSink,
         signature: String,
-        commitment_config: CommitmentConfig,
+        config: Option<RpcSignatureSubscribeConfig>,
     ) -> SubscriptionResult {
         RPC_SIGNATURE_SUBSCRIBE.inc();
-        sink.accept()?;
-        self.block_listner
-            .signature_subscribe(signature, commitment_config, sink);
+        if !self.accept_subscription(&mut sink, method::SIGNATURE_SUBSCRIBE)? {
+            return Ok(());
+        }
+        let RpcSignatureSubscribeConfig {
+            commitment,
+            enable_received_notification,
+        } = config.unwrap_or_default();
+        let commitment_config =
+            commitment.unwrap_or_else(|| self.block_listner.terminal_commitment());
+        self.block_listner.signature_subscribe(
+            signature,
+            commitment_config,
+            enable_received_notification.unwrap_or_default(),
+            sink,
+        );
+        Ok(())
+    }
+
+    fn account_subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        pubkey: String,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> SubscriptionResult {
+        RPC_ACCOUNT_SUBSCRIBE.inc();
+        let pubkey = match Pubkey::from_str(&pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(err) => {
+                sink.reject(ErrorObject::owned(
+                    INVALID_PARAMS_CODE,
+                    err.to_string(),
+                    None::<()>,
+                ))?;
+                return Ok(());
+            }
+        };
+        if !self.accept_subscription(&mut sink, method::ACCOUNT_SUBSCRIBE)? {
+            return Ok(());
+        }
+        self.pubsub_multiplexer
+            .account_subscribe(pubkey, config, sink);
+        Ok(())
+    }
+
+    fn logs_subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        filter: RpcTransactionLogsFilter,
+        config: Option<RpcTransactionLogsConfig>,
+    ) -> SubscriptionResult {
+        RPC_LOGS_SUBSCRIBE.inc();
+        // like the validator, only a single mentioned account is supported
+        if let RpcTransactionLogsFilter::Mentions(mentions) = &filter {
+            let error = match mentions.as_slice() {
+                [pubkey] => Pubkey::from_str(pubkey).err().map(|err| err.to_string()),
+                _ => Some("Invalid Request: Only 1 address supported".to_string()),
+            };
+            if let Some(error) = error {
+                sink.reject(ErrorObject::owned(INVALID_PARAMS_CODE, error, None::<()>))?;
+                return Ok(());
+            }
+        }
+        if !self.accept_subscription(&mut sink, method::LOGS_SUBSCRIBE)? {
+            return Ok(());
+        }
+        self.pubsub_multiplexer.logs_subscribe(
+            filter,
+            config.and_then(|config| config.commitment),
+            sink,
+        );
+        Ok(())
+    }
+
+    fn block_subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        filter: RpcBlockSubscribeFilter,
+        config: Option<RpcBlockSubscribeConfig>,
+    ) -> SubscriptionResult {
+        RPC_BLOCK_SUBSCRIBE.inc();
+        let config = config.unwrap_or_default();
+
+        // blocks are indexed base64 encoded with full transaction details
+        let error = if !matches!(config.encoding, None | Some(UiTransactionEncoding::Base64)) {
+            Some("Invalid Request: only base64 encoding is supported".to_string())
+        } else if matches!(
+            config.transaction_details,
+            Some(TransactionDetails::Accounts)
+        ) {
+            Some("Invalid Request: accounts transaction details are not supported".to_string())
+        } else {
+            None
+        };
+
+        let mentions = match filter {
+            RpcBlockSubscribeFilter::All => Ok(None),
+            RpcBlockSubscribeFilter::MentionsAccountOrProgram(pubkey) => {
+                Pubkey::from_str(&pubkey).map(Some)
+            }
+        };
+
+        let mentions = match (error, mentions) {
+            (None, Ok(mentions)) => mentions,
+            (Some(error), _) => {
+                sink.reject(ErrorObject::owned(INVALID_PARAMS_CODE, error, None::<()>))?;
+                return Ok(());
+            }
+            (None, Err(err)) => {
+                sink.reject(ErrorObject::owned(
+                    INVALID_PARAMS_CODE,
+                    err.to_string(),
+                    None::<()>,
+                ))?;
+                return Ok(());
+            }
+        };
+
+        if !self.accept_subscription(&mut sink, method::BLOCK_SUBSCRIBE)? {
+            return Ok(());
+        }
+        self.block_listner.block_subscribe(mentions, config, sink);
+        Ok(())
+    }
+
+    fn program_subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        program_id_str: String,
+        config: Option<RpcProgramAccountsConfig>,
+    ) -> SubscriptionResult {
+        RPC_PROGRAM_SUBSCRIBE.inc();
+        let program_id = Pubkey::from_str(&program_id_str).map_err(|err| err.to_string());
+
+        let filters = config.as_ref().and_then(|config| config.filters.as_ref());
+        let filters_valid = filters.map_or(Ok(()), |filters| {
+            filters
+                .iter()
+                .try_for_each(|filter| filter.verify().map_err(|err| err.to_string()))
+        });
+
+        let program_id = match filters_valid.and(program_id) {
+            Ok(program_id) => program_id,
+            Err(err) => {
+                sink.reject(ErrorObject::owned(INVALID_PARAMS_CODE, err, None::<()>))?;
+                return Ok(());
+            }
+        };
+
+        if !self.accept_subscription(&mut sink, method::PROGRAM_SUBSCRIBE)? {
+            return Ok(());
+        }
+        self.pubsub_multiplexer
+            .program_subscribe(program_id, config, sink);
+        Ok(())
+    }
+
+    fn slots_updates_subscribe(&self, mut sink: SubscriptionSink) -> SubscriptionResult {
+        RPC_SLOTS_UPDATES_SUBSCRIBE.inc();
+        if !self.accept_subscription(&mut sink, method::SLOTS_UPDATES_SUBSCRIBE)? {
+            return Ok(());
+        }
+        self.block_listner.slots_updates_subscribe(sink);
         Ok(())
     }
 }
@@ -455,6 +2468,9 @@ impl Deref for LiteBridge {
     }
 }
 
+/// Bumped whenever the fields the light client adds to response contexts change
+pub const EXTENDED_CONTEXT_VERSION: u32 = 1;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LiteRpcResponseContext {
@@ -463,8 +2479,132 @@ pub struct LiteRpcResponseContext {
     pub api_version: Option<RpcApiVersion>,
     pub sampled: bool,
 }
+
+/// `getVersion` of the upstream node along with what this light client supports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiteVersionInfo {
+    #[serde(flatten)]
+    pub version: RpcVersionInfo,
+    pub tinydancer: LiteExtensions,
+}
+
+/// Lets clients feature-detect the light client extensions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiteExtensions {
+    /// Version of the tinydancer crate
+    pub version: String,
+    pub sampling_strategy: SamplingStrategy,
+    /// How sampled shreds are fetched
+    pub sampling_transport: String,
+    pub auth: AuthMode,
+    /// Schema of the `sampled` and other fields added to response contexts
+    pub extended_context_version: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthMode {
+    None,
+    ApiKey,
+    Jwt,
+}
+
+/// A transaction along with the proof status of the slot it landed in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiedTransaction {
+    #[serde(flatten)]
+    pub transaction: EncodedConfirmedTransactionWithStatusMeta,
+    /// None if the block couldn't be fetched
+    pub included_in_block: Option<bool>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LiteResponse<T> {
     pub context: LiteRpcResponseContext,
     pub value: T,
 }
+
+/// A response that only carries its context when the client asked for it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LiteOptionalContext<T> {
+    Context(LiteResponse<T>),
+    NoContext(T),
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        compute_budget::ComputeBudgetInstruction, message::Message, system_instruction,
+    };
+
+    use super::*;
+
+    fn tx(payer: &Pubkey, priority_fee: u64) -> VersionedTransaction {
+        let instructions = [
+            ComputeBudgetInstruction::set_compute_unit_price(priority_fee),
+            system_instruction::transfer(payer, &Pubkey::new_unique(), 1),
+        ];
+        VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::Legacy(Message::new(&instructions, Some(payer))),
+        }
+    }
+
+    #[test]
+    fn refuses_transactions_while_the_queue_is_full() {
+        let (tx_send, mut tx_recv) = mpsc::channel(1);
+        let permit = reserve_queue(&tx_send).unwrap();
+        let err = reserve_queue(&tx_send).unwrap_err();
+        assert!(err.is::<TxQueueFull>());
+
+        // a claimed slot holds its place until it's sent to
+        permit.send(QueuedTx {
+            sig: String::from("sig"),
+            tx: vec![],
+            slot: 0,
+            priority_fee: 0,
+            target: None,
+        });
+        assert!(reserve_queue(&tx_send).unwrap_err().is::<TxQueueFull>());
+        assert_eq!(tx_recv.try_recv().unwrap().sig, "sig");
+        assert!(reserve_queue(&tx_send).is_ok());
+
+        drop(tx_recv);
+        assert!(!reserve_queue(&tx_send).unwrap_err().is::<TxQueueFull>());
+    }
+
+    #[test]
+    fn refuses_calls_below_the_min_context_slot() {
+        assert!(check_context_slot(10, None).is_ok());
+        assert!(check_context_slot(10, Some(10)).is_ok());
+
+        let err = check_context_slot(9, Some(10)).unwrap_err();
+        let jsonrpsee::core::Error::Call(CallError::Custom(err)) = err else {
+            panic!("{err}");
+        };
+        assert_eq!(i64::from(err.code()), MIN_CONTEXT_SLOT_NOT_REACHED_CODE);
+        assert_eq!(err.data().unwrap().get(), r#"{"contextSlot":9}"#);
+    }
+
+    #[test]
+    fn replacements_pay_more_from_the_same_fee_payer() {
+        let fee_payer = Pubkey::new_unique();
+        let unsent = Unsent {
+            fee_payer,
+            priority_fee: 1_000,
+            target: None,
+        };
+
+        assert!(check_replacement(&unsent, &tx(&fee_payer, 1_001)).is_ok());
+        for priority_fee in [999, 1_000] {
+            let err = check_replacement(&unsent, &tx(&fee_payer, priority_fee)).unwrap_err();
+            assert!(err.to_string().contains("above 1000"), "{err}");
+        }
+        let err = check_replacement(&unsent, &tx(&Pubkey::new_unique(), 2_000)).unwrap_err();
+        assert!(err.to_string().contains(&fee_payer.to_string()), "{err}");
+    }
+}
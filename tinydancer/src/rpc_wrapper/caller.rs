@@ -0,0 +1,124 @@
+//! Client a call is made by. It's in scope while the call runs, so methods can limit
+//! clients alike over http and websocket
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    auth::api_key,
+    configs::{AccessConfig, ApiKey},
+    ip_filter::ClientIps,
+};
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+/// Client making the calls of an http request or a websocket connection
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// configured api key the client presented
+    pub api_key: Option<ApiKey>,
+    pub ip: Option<IpAddr>,
+}
+
+impl Caller {
+    /// Caller of the running call, `None` outside of one
+    pub fn current() -> Option<Caller> {
+        CALLER.try_with(Caller::clone).ok()
+    }
+
+    /// Runs `future` with the calls it makes attributed to this caller
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CALLER.scope(self, future).await
+    }
+}
+
+/// Tells the caller of http requests and websocket upgrades, and runs the calls of http
+/// requests with it in scope
+#[derive(Clone)]
+pub struct CallerLayer {
+    /// configured api keys by key
+    api_keys: Arc<HashMap<String, ApiKey>>,
+    client_ips: ClientIps,
+}
+
+impl CallerLayer {
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            api_keys: Arc::new(
+                config
+                    .api_keys
+                    .iter()
+                    .map(|api_key| (api_key.key.clone(), api_key.clone()))
+                    .collect(),
+            ),
+            client_ips: ClientIps::new(config),
+        }
+    }
+
+    pub fn identify<B>(&self, request: &Request<B>) -> Caller {
+        Caller {
+            api_key: self.api_key(request),
+            ip: self.client_ips.client_ip(request),
+        }
+    }
+
+    /// Like `identify`, for a request accepted from `peer` directly, not through the
+    /// proxy in front of the servers
+    pub fn identify_from<B>(&self, request: &Request<B>, peer: Option<IpAddr>) -> Caller {
+        Caller {
+            api_key: self.api_key(request),
+            ip: peer.and_then(|peer| self.client_ips.client_ip_from(request, peer)),
+        }
+    }
+
+    fn api_key<B>(&self, request: &Request<B>) -> Option<ApiKey> {
+        api_key(request).and_then(|key| self.api_keys.get(&key).cloned())
+    }
+}
+
+impl<S> Layer<S> for CallerLayer {
+    type Service = CallerScope<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CallerScope {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CallerScope<S> {
+    inner: S,
+    layer: CallerLayer,
+}
+
+impl<S> Service<Request<Body>> for CallerScope<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let caller = self.layer.identify(&request);
+        // the server runs the calls of an http request within its response future
+        Box::pin(caller.scope(self.inner.call(request)))
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use prometheus::{
+    opts, register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec,
+};
+use serde_json::Value;
+use solana_rpc_client::{http_sender::HttpSender, rpc_sender::RpcSender};
+use solana_rpc_client_api::{
+    client_error::{Error as ClientError, ErrorKind, Result as ClientResult},
+    request::RpcRequest,
+};
+use tiny_logger::logs::{info, warn};
+
+use crate::rpc_wrapper::{DEFAULT_UPSTREAM_BREAKER_COOLDOWN_MS, DEFAULT_UPSTREAM_BREAKER_FAILURES};
+
+lazy_static::lazy_static! {
+    static ref UPSTREAM_BREAKER_OPEN: IntGaugeVec =
+    register_int_gauge_vec!(opts!("literpc_upstream_breaker_open", "Whether calls to the upstream rpc are short circuited"), &["upstream"]).unwrap();
+    static ref UPSTREAM_BREAKER_TRIPS: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_upstream_breaker_trips", "Number of times the upstream circuit breaker opened"), &["upstream"]).unwrap();
+    static ref UPSTREAM_BREAKER_CACHED: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_upstream_breaker_cached", "Upstream calls answered from cache by the open circuit breaker"), &["upstream"]).unwrap();
+}
+
+/// Requests whose last response is still useful while the upstream is down
+const CACHED_REQUESTS: [RpcRequest; 8] = [
+    RpcRequest::GetClusterNodes,
+    RpcRequest::GetEpochInfo,
+    RpcRequest::GetEpochSchedule,
+    RpcRequest::GetGenesisHash,
+    RpcRequest::GetLeaderSchedule,
+    RpcRequest::GetMinimumBalanceForRentExemption,
+    RpcRequest::GetVersion,
+    RpcRequest::GetVoteAccounts,
+];
+
+/// Responses kept for the open breaker, some cached requests take arbitrary params
+const MAX_CACHED_RESPONSES: usize = 256;
+
+/// How long a trial call may take before another one is let through, in case it was
+/// dropped before recording its outcome. The http sender's own timeout is 30s
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// a single trial call is in flight after the cooldown, until its deadline
+    HalfOpen {
+        until: Instant,
+    },
+}
+
+/// Sends rpc requests to a single upstream over http and stops calling it once it was
+/// unreachable `DEFAULT_UPSTREAM_BREAKER_FAILURES` times in a row, so callers fail
+/// fast instead of each timing out. After `DEFAULT_UPSTREAM_BREAKER_COOLDOWN_MS` one
+/// call is let through to probe it. While open, a few slow moving requests can still
+/// be served their last response
+pub struct CircuitBreaker {
+    sender: HttpSender,
+    state: Mutex<State>,
+    /// responses with the order they were cached in
+    cache: DashMap<(RpcRequest, String), (u64, Value)>,
+    cached_count: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(url: String) -> Self {
+        Self {
+            sender: HttpSender::new(url),
+            state: Mutex::new(State::Closed { failures: 0 }),
+            cache: Default::default(),
+            cached_count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        self.sender.url()
+    }
+
+    pub fn sender(&self) -> &HttpSender {
+        &self.sender
+    }
+
+    /// Whether calls are failed fast, a breaker past its cooldown isn't
+    pub fn is_open(&self) -> bool {
+        match *self.state.lock().unwrap() {
+            State::Open { until } => Instant::now() < until,
+            State::HalfOpen { .. } => true,
+            State::Closed { .. } => false,
+        }
+    }
+
+    /// Whether a call may go upstream now, the first call past the cooldown is the
+    /// trial, as is the first call past the deadline of a trial that never finished
+    pub fn admit(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { until } if Instant::now() >= until => {
+                *state = State::HalfOpen {
+                    until: Instant::now() + PROBE_TIMEOUT,
+                };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    fn record(&self, reachable: bool) {
+        let url = self.url();
+        let mut state = self.state.lock().unwrap();
+        let next = match (*state, reachable) {
+            (State::Closed { .. }, true) => State::Closed { failures: 0 },
+            (State::HalfOpen { .. } | State::Open { .. }, true) => {
+                info!("Upstream rpc {url} recovered, closing its circuit breaker");
+                UPSTREAM_BREAKER_OPEN.with_label_values(&[&url]).set(0);
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, false)
+                if failures + 1 < DEFAULT_UPSTREAM_BREAKER_FAILURES =>
+            {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (State::Closed { .. } | State::HalfOpen { .. }, false) => tripped(&url),
+            (open @ State::Open { .. }, false) => open,
+        };
+        *state = next;
+    }
+
+    /// Fails calls fast as if the upstream had been unreachable on every call, until
+    /// the cooldown is over
+    pub fn trip(&self) {
+        let url = self.url();
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Open { .. }) {
+            *state = tripped(&url);
+        }
+    }
+
+    /// Sends a call that was admitted
+    pub async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let key = CACHED_REQUESTS
+            .contains(&request)
+            .then(|| (request, params.to_string()));
+
+        let result = self.sender.send(request, params).await;
+        self.record(!matches!(&result, Err(err) if unreachable(err)));
+
+        if let (Ok(response), Some(key)) = (&result, key) {
+            self.cache_response(key, response.clone());
+        }
+        result
+    }
+
+    /// Keeps `response` for the open breaker, in place of the oldest one once the
+    /// cache is full
+    fn cache_response(&self, key: (RpcRequest, String), response: Value) {
+        if self.cache.len() >= MAX_CACHED_RESPONSES && !self.cache.contains_key(&key) {
+            let oldest = self
+                .cache
+                .iter()
+                .min_by_key(|entry| entry.value().0)
+                .map(|entry| entry.key().clone());
+            if let Some(oldest) = oldest {
+                self.cache.remove(&oldest);
+            }
+        }
+        let order = self.cached_count.fetch_add(1, Ordering::Relaxed);
+        self.cache.insert(key, (order, response));
+    }
+
+    /// Last response to the call, if it's one that's cached
+    pub fn cached(&self, request: RpcRequest, params: &Value) -> Option<Value> {
+        let cached = self.cache.get(&(request, params.to_string()))?.1.clone();
+        UPSTREAM_BREAKER_CACHED
+            .with_label_values(&[&self.url()])
+            .inc();
+        Some(cached)
+    }
+}
+
+/// State of a breaker tripping now
+fn tripped(url: &str) -> State {
+    warn!(
+        "Upstream rpc {url} is unreachable, failing calls fast for {DEFAULT_UPSTREAM_BREAKER_COOLDOWN_MS}ms"
+    );
+    UPSTREAM_BREAKER_TRIPS.with_label_values(&[url]).inc();
+    UPSTREAM_BREAKER_OPEN.with_label_values(&[url]).set(1);
+    State::Open {
+        until: Instant::now() + Duration::from_millis(DEFAULT_UPSTREAM_BREAKER_COOLDOWN_MS),
+    }
+}
+
+/// Whether the upstream never answered, rpc errors still mean it did
+pub fn unreachable(err: &ClientError) -> bool {
+    matches!(err.kind(), ErrorKind::Io(_) | ErrorKind::Reqwest(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(String::from("http://upstream.invalid"))
+    }
+
+    /// Ends the cooldown of an open breaker
+    fn cool_down(breaker: &CircuitBreaker) {
+        *breaker.state.lock().unwrap() = State::Open {
+            until: Instant::now(),
+        };
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker();
+        for _ in 1..DEFAULT_UPSTREAM_BREAKER_FAILURES {
+            breaker.record(false);
+            assert!(!breaker.is_open());
+        }
+        // an answer starts the count over
+        breaker.record(true);
+        for _ in 1..DEFAULT_UPSTREAM_BREAKER_FAILURES {
+            breaker.record(false);
+        }
+        assert!(!breaker.is_open());
+
+        breaker.record(false);
+        assert!(breaker.is_open());
+        assert!(!breaker.admit());
+    }
+
+    #[test]
+    fn closes_when_the_trial_call_answers() {
+        let breaker = breaker();
+        breaker.trip();
+        assert!(breaker.is_open());
+        assert!(!breaker.admit());
+
+        cool_down(&breaker);
+        assert!(!breaker.is_open());
+        assert!(breaker.admit());
+        // half open, the trial is the only call let through
+        assert!(breaker.is_open());
+        assert!(!breaker.admit());
+
+        breaker.record(true);
+        assert!(!breaker.is_open());
+        assert!(breaker.admit());
+    }
+
+    #[test]
+    fn reopens_when_the_trial_call_fails() {
+        let breaker = breaker();
+        breaker.trip();
+        cool_down(&breaker);
+        assert!(breaker.admit());
+
+        breaker.record(false);
+        assert!(matches!(*breaker.state.lock().unwrap(), State::Open { .. }));
+        assert!(breaker.is_open());
+        assert!(!breaker.admit());
+    }
+
+    #[test]
+    fn evicts_the_oldest_cached_response() {
+        let breaker = breaker();
+        let request = RpcRequest::GetLeaderSchedule;
+        for slot in 0..MAX_CACHED_RESPONSES {
+            breaker.cache_response((request, Value::from(slot).to_string()), Value::from(slot));
+        }
+        // answered again, so no longer the oldest
+        breaker.cache_response((request, Value::from(0).to_string()), Value::from(0));
+
+        let newest = Value::from(MAX_CACHED_RESPONSES);
+        breaker.cache_response((request, newest.to_string()), newest.clone());
+
+        assert_eq!(breaker.cache.len(), MAX_CACHED_RESPONSES);
+        assert_eq!(breaker.cached(request, &Value::from(1)), None);
+        assert_eq!(
+            breaker.cached(request, &Value::from(0)),
+            Some(Value::from(0))
+        );
+        assert_eq!(breaker.cached(request, &newest), Some(newest));
+    }
+}
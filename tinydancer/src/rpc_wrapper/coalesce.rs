@@ -0,0 +1,184 @@
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{
+    future::{BoxFuture, Shared, WeakShared},
+    FutureExt,
+};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+
+lazy_static::lazy_static! {
+    static ref UPSTREAM_CALLS_COALESCED: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_upstream_calls_coalesced", "Upstream calls that joined an identical call already in flight"), &["call"]).unwrap();
+}
+
+/// A call in flight for longer isn't joined, it may hang or answer with stale data
+const MAX_JOIN_AGE: Duration = Duration::from_secs(1);
+
+struct InFlight<V> {
+    started: Instant,
+    /// tells the call apart from a newer one for the same key
+    generation: u64,
+    /// weak, so the call is dropped once every caller waiting on it gave up
+    call: WeakShared<BoxFuture<'static, V>>,
+}
+
+/// Runs a single upstream call per key at a time, callers asking for a key that's
+/// already in flight wait for that call and get a clone of its result
+pub struct Coalescer<K, V> {
+    name: &'static str,
+    in_flight: Arc<DashMap<K, InFlight<V>>>,
+    generations: AtomicU64,
+}
+
+impl<K, V> Coalescer<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// `name` labels the coalesced calls metric
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            in_flight: Default::default(),
+            generations: AtomicU64::new(0),
+        }
+    }
+
+    /// Result of `call`, or of the call already in flight for `key`
+    pub async fn run<F>(&self, key: K, call: F) -> V
+    where
+        F: Future<Output = V> + Send + 'static,
+    {
+        let shared = match self.in_flight.entry(key.clone()) {
+            Entry::Occupied(mut in_flight) => {
+                let joined = (in_flight.get().started.elapsed() < MAX_JOIN_AGE)
+                    .then(|| in_flight.get().call.upgrade())
+                    .flatten();
+                match joined {
+                    Some(shared) => {
+                        UPSTREAM_CALLS_COALESCED
+                            .with_label_values(&[self.name])
+                            .inc();
+                        shared
+                    }
+                    // the older call keeps running for the callers waiting on it
+                    None => {
+                        let (started, shared) = self.start(key, call);
+                        in_flight.insert(started);
+                        shared
+                    }
+                }
+            }
+            Entry::Vacant(slot) => {
+                let (started, shared) = self.start(key, call);
+                slot.insert(started);
+                shared
+            }
+        };
+
+        shared.await
+    }
+
+    fn start<F>(&self, key: K, call: F) -> (InFlight<V>, Shared<BoxFuture<'static, V>>)
+    where
+        F: Future<Output = V> + Send + 'static,
+    {
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let clear = Clear {
+            in_flight: self.in_flight.clone(),
+            key,
+            generation,
+        };
+        let shared = async move {
+            let _clear = clear;
+            call.await
+        }
+        .boxed()
+        .shared();
+
+        let started = InFlight {
+            started: Instant::now(),
+            generation,
+            call: shared
+                .downgrade()
+                .expect("a call not polled yet is pending"),
+        };
+        (started, shared)
+    }
+}
+
+/// Clears the key of a call once it finished or was dropped with its last caller,
+/// unless a newer call took the key already
+struct Clear<K: Eq + Hash, V> {
+    in_flight: Arc<DashMap<K, InFlight<V>>>,
+    key: K,
+    generation: u64,
+}
+
+impl<K: Eq + Hash, V> Drop for Clear<K, V> {
+    fn drop(&mut self) {
+        self.in_flight
+            .remove_if(&self.key, |_, call| call.generation == self.generation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn joins_calls_in_flight() {
+        let coalescer = Coalescer::new("test");
+        let (first, second) = tokio::join!(
+            coalescer.run(1, async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                1
+            }),
+            coalescer.run(1, async { 2 }),
+        );
+        assert_eq!((first, second), (1, 1));
+        assert!(coalescer.in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgets_calls_every_caller_gave_up_on() {
+        let coalescer = Coalescer::new("test");
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            coalescer.run(1, async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                1
+            }),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert!(coalescer.in_flight.is_empty());
+
+        assert_eq!(coalescer.run(1, async { 2 }).await, 2);
+    }
+
+    #[tokio::test]
+    async fn doesnt_join_calls_past_their_age() {
+        let coalescer = Coalescer::new("test");
+        let (first, second) = tokio::join!(
+            coalescer.run(1, async {
+                tokio::time::sleep(MAX_JOIN_AGE * 2).await;
+                1
+            }),
+            async {
+                tokio::time::sleep(MAX_JOIN_AGE + Duration::from_millis(100)).await;
+                coalescer.run(1, async { 2 }).await
+            },
+        );
+        assert_eq!((first, second), (1, 2));
+    }
+}
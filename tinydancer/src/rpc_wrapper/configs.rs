@@ -1,18 +1,37 @@
-use crate::rpc_wrapper::encoding::BinaryEncoding;
+use std::time::Duration;
+
+use crate::rpc_wrapper::{
+    encoding::BinaryEncoding, DEFAULT_BLOCK_STORE_TTL_S, DEFAULT_CLEAN_INTERVAL_MS,
+    DEFAULT_SIGNATURE_SUBSCRIPTION_TTL_S, DEFAULT_TX_JOURNAL_RETENTION_S, DEFAULT_TX_SENT_TTL_S,
+    DEFAULT_WS_MAX_CONNECTIONS, DEFAULT_WS_MAX_IN_FLIGHT_CALLS, DEFAULT_WS_MAX_QUEUED_MESSAGES,
+    DEFAULT_WS_MAX_SUBSCRIPTIONS, DEFAULT_WS_MAX_SUBSCRIPTIONS_PER_CONNECTION,
+};
+use ipnet::IpNet;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use solana_sdk::commitment_config::CommitmentLevel;
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SendTransactionConfig {
-    //    #[serde(default)]
-    //    pub skip_preflight: bool,
-    //    #[serde(default)]
-    //    pub preflight_commitment: CommitmentLevel,
+    /// Queue without simulating against the upstream first
+    #[serde(default)]
+    pub skip_preflight: bool,
+    /// Commitment the preflight simulation runs at, finalized if unset
+    pub preflight_commitment: Option<CommitmentLevel>,
     #[serde(default)]
     pub encoding: BinaryEncoding,
-    pub max_retries: Option<u16>,
-    //    pub min_context_slot: Option<Slot>,
+    /// Rebroadcasts until it's confirmed, capped at `u16::MAX`
+    pub max_retries: Option<usize>,
+    /// Refuse the transaction while the slot at the preflight commitment, processed
+    /// if preflight is skipped, is below it
+    pub min_context_slot: Option<Slot>,
+    /// Url the transaction's status is posted to once it's confirmed and once it's
+    /// finalized, the configured webhook if unset. Only public addresses are posted to
+    pub callback_url: Option<String>,
+    /// Identity of a validator the transaction is sent to alone, instead of the
+    /// upcoming leaders
+    pub target: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -21,3 +40,454 @@ pub struct IsBlockHashValidConfig {
     pub commitment: Option<CommitmentLevel>,
     //    pub minContextSlot: Option<u64>,
 }
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriorityFeeEstimateConfig {
+    /// Only count transactions writing to any of these accounts
+    pub account_keys: Option<Vec<String>>,
+    /// Recent blocks to estimate from, all of the cached ones if unset
+    pub lookback_slots: Option<usize>,
+}
+
+/// Guardrails for `getProgramAccounts`, which can scan a program's whole account set upstream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProgramAccountsLimits {
+    /// Reject every `getProgramAccounts` call
+    pub disabled: bool,
+    /// Responses holding more accounts than this are rejected
+    pub max_accounts: usize,
+    /// Reject calls without at least one filter
+    pub require_filters: bool,
+}
+
+impl Default for ProgramAccountsLimits {
+    fn default() -> Self {
+        Self {
+            disabled: false,
+            max_accounts: 10_000,
+            require_filters: true,
+        }
+    }
+}
+
+/// What happens to transactions sent without a compute unit price
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PriorityFeeMode {
+    /// They're queued as they are
+    #[default]
+    Off,
+    /// They're refused with a hint to add one
+    Reject,
+    /// The payer's own transactions get `micro_lamports` appended and are re-signed,
+    /// anyone else's are refused
+    Append,
+}
+
+/// Priority fees required of sent transactions during congestion
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PriorityFeePolicy {
+    pub mode: PriorityFeeMode,
+    /// Compute unit price appended in micro lamports
+    pub micro_lamports: u64,
+    /// Keypair file of the payer whose transactions may be re-signed
+    pub payer: Option<String>,
+}
+
+/// How often the cleaner runs and how long what it cleans up is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CleanerConfig {
+    /// Milliseconds between cleanups
+    pub interval_ms: u64,
+    /// Seconds the record of a sent transaction is kept
+    pub tx_ttl_s: u64,
+    /// Seconds a block is kept in the block store
+    pub block_ttl_s: u64,
+    /// Seconds a signature subscription waits for its transaction
+    pub subscription_ttl_s: u64,
+    /// Seconds an accepted transaction is kept in the journal, 0 keeps it for good
+    pub journal_retention_s: u64,
+}
+
+impl Default for CleanerConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: DEFAULT_CLEAN_INTERVAL_MS,
+            tx_ttl_s: DEFAULT_TX_SENT_TTL_S,
+            block_ttl_s: DEFAULT_BLOCK_STORE_TTL_S,
+            subscription_ttl_s: DEFAULT_SIGNATURE_SUBSCRIPTION_TTL_S,
+            journal_retention_s: DEFAULT_TX_JOURNAL_RETENTION_S,
+        }
+    }
+}
+
+/// How the tpu manager connects and sends to leaders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TpuConfig {
+    /// Quic connections kept to each leader
+    pub connection_pool_size: usize,
+    /// Streams open at once on the connections to a leader, each transaction takes one
+    pub max_streams_per_connection: usize,
+    /// Milliseconds a quic send may take, which bounds the handshake of new
+    /// connections to far away leaders
+    pub handshake_timeout_ms: u64,
+    pub quic_retry: QuicRetryConfig,
+    pub congestion: CongestionConfig,
+    pub leader_cache: LeaderCacheConfig,
+}
+
+impl Default for TpuConfig {
+    fn default() -> Self {
+        Self {
+            connection_pool_size: 8,
+            max_streams_per_connection: 128,
+            handshake_timeout_ms: 10_000,
+            quic_retry: QuicRetryConfig::default(),
+            congestion: CongestionConfig::default(),
+            leader_cache: LeaderCacheConfig::default(),
+        }
+    }
+}
+
+/// How failed quic sends of a batch are retried before the leader is given up on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QuicRetryConfig {
+    /// Retries of a failed send, none by default
+    pub max_retries: u32,
+    /// Milliseconds waited before the first retry
+    pub initial_backoff_ms: u64,
+    /// Each retry waits this many times longer than the one before
+    pub backoff_multiplier: f64,
+    /// Milliseconds a retry waits at most
+    pub max_backoff_ms: u64,
+    /// Share of the backoff randomly added or taken off, so retries spread out
+    pub jitter: f64,
+}
+
+impl Default for QuicRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff_ms: 10,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: 200,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl QuicRetryConfig {
+    /// Time waited before retry number `retry`, counted from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = (self.initial_backoff_ms as f64
+            * self.backoff_multiplier.max(1.0).powi(retry as i32))
+        .min(self.max_backoff_ms as f64);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        Duration::from_secs_f64(backoff * factor / 1000.0)
+    }
+}
+
+/// How batches to the tpu are slowed down while leaders push back on our sends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CongestionConfig {
+    pub enabled: bool,
+    /// Share of sends pushed back above which batches are slowed down
+    pub threshold: f64,
+    /// Most the interval between batches is stretched by, when every send is pushed back
+    pub max_slowdown: f64,
+    /// Weight of each send in the smoothed pushback rate
+    pub smoothing: f64,
+}
+
+impl Default for CongestionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 0.1,
+            max_slowdown: 8.0,
+            smoothing: 0.05,
+        }
+    }
+}
+
+/// How the tpus of upcoming leaders are looked up and connected to ahead of their slots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LeaderCacheConfig {
+    /// Leaders past the ones transactions are sent to whose tpus are looked up,
+    /// unconfirmed transactions are rebroadcast to them
+    pub lookahead_leaders: u64,
+    /// Milliseconds between lookups of the upcoming leaders
+    pub refresh_interval_ms: u64,
+    /// Milliseconds after which the tpu addresses of the cluster are fetched again
+    pub cluster_tpus_refresh_interval_ms: u64,
+    /// Upcoming leaders quic connections are established to before their slots
+    pub warm_up_leaders: u64,
+}
+
+impl Default for LeaderCacheConfig {
+    fn default() -> Self {
+        Self {
+            lookahead_leaders: 4,
+            refresh_interval_ms: 400,
+            cluster_tpus_refresh_interval_ms: 60_000,
+            warm_up_leaders: 3,
+        }
+    }
+}
+
+impl LeaderCacheConfig {
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_millis(self.refresh_interval_ms.max(1))
+    }
+
+    pub fn cluster_tpus_refresh_interval(&self) -> Duration {
+        Duration::from_millis(self.cluster_tpus_refresh_interval_ms)
+    }
+}
+
+/// A key clients present to call the lite rpc
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    /// Reported in metrics instead of the key itself
+    pub name: String,
+    pub key: String,
+    /// Transactions the key may send per window over any transport, unlimited if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_quota: Option<TxQuota>,
+    /// May call the admin methods, e.g. `getApiKeyUsage`
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxQuota {
+    pub max_transactions: u64,
+    pub window_s: u64,
+}
+
+impl TxQuota {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_s)
+    }
+}
+
+/// Who may call the lite rpc's http, websocket and gRPC servers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccessConfig {
+    /// Requests must present one of these keys, anyone may call if empty
+    pub api_keys: Vec<ApiKey>,
+    /// Requests must present a valid bearer jwt instead, exclusive with `api_keys`
+    pub jwt: Option<JwtConfig>,
+    /// Only clients in these networks may call, anyone may if empty
+    pub allow: Vec<IpNet>,
+    /// Clients in these networks may not call, even if allowed
+    pub deny: Vec<IpNet>,
+    /// Header the proxy in front of the servers reports the client address in,
+    /// `x-forwarded-for` if unset. The address a connection comes from is always
+    /// appended to it
+    pub client_ip_header: Option<String>,
+    /// Proxies in front of the lite rpc whose entries in the client address header are
+    /// trusted, the client is the right-most address that isn't one of them
+    pub trusted_proxies: Vec<IpNet>,
+    /// Calls per second allowed to each limited method over all clients and transports
+    pub rate_limits: Vec<MethodQuota>,
+    /// Calls per second allowed to each client, identified by api key or else address
+    pub client_rate_limit: Option<Quota>,
+    /// Connections and subscriptions the websocket server takes on
+    pub websocket: WsLimits,
+}
+
+impl AccessConfig {
+    /// Whether clients are told apart by address, which the servers only learn from a
+    /// proxy in front of them
+    pub fn needs_client_ip(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty() || self.client_rate_limit.is_some()
+    }
+}
+
+/// Caps of the websocket server, the `DEFAULT_WS_*` values unless configured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WsLimits {
+    /// Concurrent connections, further connections are refused
+    pub max_connections: u32,
+    /// Subscriptions a single connection may hold
+    pub max_subscriptions_per_connection: u32,
+    /// Subscriptions over all connections
+    pub max_subscriptions: usize,
+    /// Messages queued for a client, a client falling further behind is disconnected
+    pub max_queued_messages: usize,
+    /// Calls of a single connection run at once
+    pub max_in_flight_calls: usize,
+}
+
+impl Default for WsLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_WS_MAX_CONNECTIONS,
+            max_subscriptions_per_connection: DEFAULT_WS_MAX_SUBSCRIPTIONS_PER_CONNECTION,
+            max_subscriptions: DEFAULT_WS_MAX_SUBSCRIPTIONS,
+            max_queued_messages: DEFAULT_WS_MAX_QUEUED_MESSAGES,
+            max_in_flight_calls: DEFAULT_WS_MAX_IN_FLIGHT_CALLS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    pub requests_per_second: u32,
+    /// Calls allowed at once after a quiet period, `requests_per_second` if unset
+    pub burst: Option<u32>,
+}
+
+/// Quota of a JSON-RPC method, e.g. a tight one for `sendTransaction`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodQuota {
+    pub method: String,
+    #[serde(flatten)]
+    pub quota: Quota,
+}
+
+/// Certificate the lite rpc serves tls with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    /// PEM certificate chain
+    pub cert_path: String,
+    /// PEM private key, PKCS8, RSA or EC
+    pub key_path: String,
+    /// Pick up rotated certificates without a restart
+    #[serde(default)]
+    pub reload: bool,
+}
+
+/// Transaction path knobs applied on SIGHUP, unset ones are left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TxTuningConfig {
+    /// Slots the tpu client fans out to
+    pub fanout_slots: Option<u64>,
+    /// Transactions per tpu batch
+    pub batch_size: Option<usize>,
+    /// Milliseconds waited for a batch to fill up
+    pub batch_interval_ms: Option<u64>,
+}
+
+/// How bearer jwts are validated
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JwtConfig {
+    /// Shared secret of HMAC signed tokens
+    pub secret: Option<String>,
+    /// JWKS endpoint the keys of asymmetrically signed tokens are fetched from
+    pub jwks_url: Option<String>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Restricts tokens to the methods of the allowlists matching their claims over
+    /// http, restricted tokens can't open websocket connections. Tokens may call
+    /// anything if empty
+    pub method_allowlists: Vec<MethodAllowlist>,
+}
+
+/// Methods tokens whose `claim` holds `value` may call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodAllowlist {
+    pub claim: String,
+    pub value: String,
+    pub methods: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_send_transaction_configs_as_upstream_does() {
+        let config: SendTransactionConfig = serde_json::from_str(
+            r#"{"encoding":"base64","skipPreflight":false,"preflightCommitment":"confirmed","maxRetries":70000,"minContextSlot":100}"#,
+        )
+        .unwrap();
+        assert!(matches!(config.encoding, BinaryEncoding::Base64));
+        assert!(!config.skip_preflight);
+        assert_eq!(
+            config.preflight_commitment,
+            Some(CommitmentLevel::Confirmed)
+        );
+        // capped when it's used, not refused
+        assert_eq!(config.max_retries, Some(70_000));
+        assert_eq!(config.min_context_slot, Some(100));
+
+        let config: SendTransactionConfig =
+            serde_json::from_str(r#"{"encoding":"binary"}"#).unwrap();
+        assert!(matches!(config.encoding, BinaryEncoding::Base58));
+        assert_eq!(config.preflight_commitment, None);
+        assert_eq!(config.min_context_slot, None);
+    }
+
+    #[test]
+    fn unset_websocket_limits_keep_their_defaults() {
+        let config: AccessConfig =
+            serde_json::from_str(r#"{"websocket":{"maxConnections":10}}"#).unwrap();
+        assert_eq!(config.websocket.max_connections, 10);
+        assert_eq!(
+            config.websocket.max_subscriptions,
+            DEFAULT_WS_MAX_SUBSCRIPTIONS
+        );
+
+        let config: AccessConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.websocket.max_connections, DEFAULT_WS_MAX_CONNECTIONS);
+    }
+
+    #[test]
+    fn backoff_grows_up_to_its_max() {
+        let config = QuicRetryConfig {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let backoffs: Vec<_> = (0..6).map(|retry| config.backoff(retry)).collect();
+        assert_eq!(
+            backoffs,
+            [10, 20, 40, 80, 160, 200].map(Duration::from_millis)
+        );
+
+        // a multiplier below 1 doesn't shrink it
+        let config = QuicRetryConfig {
+            backoff_multiplier: 0.5,
+            ..config
+        };
+        assert_eq!(config.backoff(3), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_its_share() {
+        let config = QuicRetryConfig {
+            initial_backoff_ms: 100,
+            jitter: 0.2,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let backoff = config.backoff(0);
+            assert!(
+                (Duration::from_millis(80)..=Duration::from_millis(120)).contains(&backoff),
+                "{backoff:?}"
+            );
+        }
+    }
+}
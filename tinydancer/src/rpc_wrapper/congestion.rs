@@ -0,0 +1,150 @@
+use std::{sync::Mutex, time::Duration};
+
+use prometheus::{opts, register_gauge, register_int_counter, Gauge, IntCounter};
+use tiny_logger::logs::{info, warn};
+
+use crate::rpc_wrapper::configs::CongestionConfig;
+
+lazy_static::lazy_static! {
+    static ref TPU_PUSHBACK_RATE: Gauge =
+        register_gauge!(opts!("literpc_tpu_pushback_rate", "Smoothed share of quic sends to leaders that were pushed back or dropped")).unwrap();
+    static ref TPU_PUSHBACKS: IntCounter =
+        register_int_counter!(opts!("literpc_tpu_pushbacks", "Quic sends to leaders that waited for streams, failed or timed out")).unwrap();
+    static ref TX_SEND_SLOWDOWN: Gauge =
+        register_gauge!(opts!("literpc_tx_send_slowdown", "Factor the interval between tpu batches is stretched by under congestion")).unwrap();
+}
+
+/// Follows how often leaders push back on our quic sends, by making us wait for
+/// streams, or drop them, and slows batch emission down while the cluster is
+/// congested instead of sending batches that won't get through
+pub struct CongestionMonitor {
+    config: CongestionConfig,
+    /// smoothed share of sends pushed back, from 0 to 1
+    pushback_rate: Mutex<f64>,
+}
+
+impl CongestionMonitor {
+    pub fn new(config: CongestionConfig) -> Self {
+        TX_SEND_SLOWDOWN.set(1.0);
+        Self {
+            config,
+            pushback_rate: Mutex::new(0.0),
+        }
+    }
+
+    /// Records a quic send to a leader
+    pub fn record(&self, pushed_back: bool) {
+        if pushed_back {
+            TPU_PUSHBACKS.inc();
+        }
+        if !self.config.enabled {
+            return;
+        }
+
+        let was_congested = self.is_congested();
+        {
+            let smoothing = self.config.smoothing.clamp(0.0, 1.0);
+            let mut pushback_rate = self.pushback_rate.lock().unwrap();
+            let sample = if pushed_back { 1.0 } else { 0.0 };
+            *pushback_rate += smoothing * (sample - *pushback_rate);
+            TPU_PUSHBACK_RATE.set(*pushback_rate);
+        }
+        TX_SEND_SLOWDOWN.set(self.slowdown());
+
+        match (was_congested, self.is_congested()) {
+            (false, true) => warn!(
+                "Leaders push back on {:.0}% of sends, slowing down tpu batches",
+                self.pushback_rate() * 100.0
+            ),
+            (true, false) => info!("Leaders stopped pushing back, tpu batches back to pace"),
+            _ => {}
+        }
+    }
+
+    pub fn pushback_rate(&self) -> f64 {
+        *self.pushback_rate.lock().unwrap()
+    }
+
+    pub fn is_congested(&self) -> bool {
+        self.slowdown() > 1.0
+    }
+
+    /// Factor the interval between batches is stretched by, 1 until the pushback rate
+    /// passes the threshold and up to `max_slowdown` when every send is pushed back
+    pub fn slowdown(&self) -> f64 {
+        let threshold = self.config.threshold.clamp(0.0, 1.0);
+        let pushback_rate = self.pushback_rate();
+        if !self.config.enabled || pushback_rate <= threshold || threshold >= 1.0 {
+            return 1.0;
+        }
+        let congestion = (pushback_rate - threshold) / (1.0 - threshold);
+        1.0 + (self.config.max_slowdown.max(1.0) - 1.0) * congestion
+    }
+
+    /// Time to leave between batches sent every `interval`, zero while the cluster
+    /// isn't congested
+    pub fn pause(&self, interval: Duration) -> Duration {
+        let slowdown = self.slowdown();
+        if slowdown <= 1.0 {
+            return Duration::ZERO;
+        }
+        // batches are at least a millisecond apart, even at a zero interval
+        interval.max(Duration::from_millis(1)).mul_f64(slowdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(enabled: bool) -> CongestionMonitor {
+        CongestionMonitor::new(CongestionConfig {
+            enabled,
+            threshold: 0.5,
+            max_slowdown: 5.0,
+            smoothing: 1.0,
+        })
+    }
+
+    #[test]
+    fn slows_down_past_the_threshold() {
+        let monitor = monitor(true);
+        monitor.record(false);
+        assert_eq!(monitor.slowdown(), 1.0);
+        assert_eq!(monitor.pause(Duration::from_millis(10)), Duration::ZERO);
+
+        // every send pushed back
+        monitor.record(true);
+        assert!(monitor.is_congested());
+        assert_eq!(monitor.slowdown(), 5.0);
+        assert_eq!(
+            monitor.pause(Duration::from_millis(10)),
+            Duration::from_millis(50)
+        );
+        assert_eq!(monitor.pause(Duration::ZERO), Duration::from_millis(5));
+
+        monitor.record(false);
+        assert!(!monitor.is_congested());
+    }
+
+    #[test]
+    fn smooths_the_pushback_rate() {
+        let monitor = CongestionMonitor::new(CongestionConfig {
+            smoothing: 0.5,
+            ..CongestionConfig::default()
+        });
+        monitor.record(true);
+        monitor.record(true);
+        assert_eq!(monitor.pushback_rate(), 0.75);
+        monitor.record(false);
+        assert_eq!(monitor.pushback_rate(), 0.375);
+    }
+
+    #[test]
+    fn never_slows_down_when_disabled() {
+        let monitor = monitor(false);
+        monitor.record(true);
+        assert_eq!(monitor.pushback_rate(), 0.0);
+        assert_eq!(monitor.pause(Duration::from_millis(10)), Duration::ZERO);
+    }
+}
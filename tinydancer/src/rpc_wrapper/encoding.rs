@@ -1,10 +1,13 @@
 use base64::Engine;
 use serde::{Deserialize, Serialize};
+use solana_sdk::packet::PACKET_DATA_SIZE;
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum BinaryEncoding {
+    /// `binary` is the deprecated name upstream still accepts
     #[default]
+    #[serde(alias = "binary")]
     Base58,
     Base64,
 }
@@ -17,6 +20,8 @@ pub enum BinaryCodecError {
     Base58EncodeError(#[from] bs58::encode::Error),
     #[error("Base64DecodeError {0}")]
     Base64DecodeError(#[from] base64::DecodeError),
+    #[error("TransactionTooLarge {len} bytes, max {max}")]
+    TransactionTooLarge { len: usize, max: usize },
 }
 
 impl BinaryEncoding {
@@ -27,6 +32,36 @@ impl BinaryEncoding {
         }
     }
 
+    /// Decodes a wire transaction, oversized ones are rejected before decoding
+    pub fn decode_transaction<D: AsRef<[u8]>>(
+        &self,
+        to_decode: D,
+    ) -> Result<Vec<u8>, BinaryCodecError> {
+        let max = self.max_encoded_len(PACKET_DATA_SIZE);
+        let len = to_decode.as_ref().len();
+        if len > max {
+            return Err(BinaryCodecError::TransactionTooLarge { len, max });
+        }
+
+        let raw_tx = self.decode(to_decode)?;
+        if raw_tx.len() > PACKET_DATA_SIZE {
+            return Err(BinaryCodecError::TransactionTooLarge {
+                len: raw_tx.len(),
+                max: PACKET_DATA_SIZE,
+            });
+        }
+        Ok(raw_tx)
+    }
+
+    /// Longest encoding of `decoded_len` bytes
+    pub fn max_encoded_len(&self, decoded_len: usize) -> usize {
+        match self {
+            // log(256) / log(58) characters per byte, rounded up
+            Self::Base58 => (decoded_len * 1366 + 999) / 1000,
+            Self::Base64 => (decoded_len + 2) / 3 * 4,
+        }
+    }
+
     pub fn encode<E: AsRef<[u8]>>(&self, to_encode: E) -> String {
         match self {
             Self::Base58 => bs58::encode(to_encode).into_string(),
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{epoch_info::EpochInfo, epoch_schedule::EpochSchedule};
+use tiny_logger::logs::{info, warn};
+use tokio::sync::RwLock;
+
+use crate::rpc_wrapper::block_store::BlockInformation;
+
+/// Serves epoch info from the block store, only going upstream once per epoch
+#[derive(Clone)]
+pub struct EpochTracker {
+    epoch_schedule: EpochSchedule,
+    /// epoch info as reported upstream the last time the epoch rolled over
+    current_epoch: Arc<RwLock<EpochInfo>>,
+}
+
+impl EpochTracker {
+    pub async fn new(rpc_client: &RpcClient) -> anyhow::Result<Self> {
+        let epoch_schedule = rpc_client.get_epoch_schedule().await?;
+        let current_epoch = rpc_client.get_epoch_info().await?;
+
+        Ok(Self {
+            epoch_schedule,
+            current_epoch: Arc::new(RwLock::new(current_epoch)),
+        })
+    }
+
+    pub fn epoch_schedule(&self) -> &EpochSchedule {
+        &self.epoch_schedule
+    }
+
+    /// Epoch info at the given block, the transaction count isn't tracked
+    pub async fn get_epoch_info(
+        &self,
+        rpc_client: &RpcClient,
+        block_info: BlockInformation,
+    ) -> EpochInfo {
+        let (epoch, slot_index) = self
+            .epoch_schedule
+            .get_epoch_and_slot_index(block_info.slot);
+
+        if epoch > self.current_epoch.read().await.epoch {
+            self.refresh(rpc_client, epoch).await;
+        }
+
+        EpochInfo {
+            epoch,
+            slot_index,
+            slots_in_epoch: self.epoch_schedule.get_slots_in_epoch(epoch),
+            absolute_slot: block_info.slot,
+            block_height: block_info.block_height,
+            transaction_count: None,
+        }
+    }
+
+    async fn refresh(&self, rpc_client: &RpcClient, epoch: u64) {
+        let mut current_epoch = self.current_epoch.write().await;
+        // another caller may have refreshed while we waited on the lock
+        if current_epoch.epoch >= epoch {
+            return;
+        }
+
+        match rpc_client.get_epoch_info().await {
+            Ok(epoch_info) => {
+                if epoch_info.epoch != epoch {
+                    warn!(
+                        "upstream reports epoch {} but the block store is at epoch {}",
+                        epoch_info.epoch, epoch
+                    );
+                }
+                info!("Epoch tracker moved to epoch {}", epoch_info.epoch);
+                *current_epoch = epoch_info;
+            }
+            Err(e) => warn!("Couldn't refresh epoch info: {}", e),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! gRPC counterpart of the lite rpc for consumers that can't afford JSON, serves
+//! `sendTransaction`, slot and block streams and the sampler's verification events.
+//! Calls go through the same api key, jwt and client address checks as http
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use futures::{Stream, StreamExt};
+use hyper::Body;
+use jsonrpsee::types::error::CallError;
+use solana_rpc_client_api::response::SlotUpdate;
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use tiny_logger::logs::info;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{
+    body::BoxBody,
+    transport::{server::TcpConnectInfo, Server},
+    Request, Response, Status,
+};
+use tower::{Layer, Service};
+
+use crate::{
+    grpc::{proto::verification_server::VerificationServer, VerificationStreamer},
+    rpc_wrapper::{
+        auth::ApiKeyLayer,
+        bridge::{
+            TxSubmitter, MIN_CONTEXT_SLOT_NOT_REACHED_CODE, TX_MISSING_PRIORITY_FEE_CODE,
+            TX_PREFLIGHT_FAILURE_CODE, TX_QUEUE_FULL_CODE,
+        },
+        caller::CallerLayer,
+        configs::SendTransactionConfig,
+        encoding::BinaryEncoding,
+        ip_filter::IpFilterLayer,
+        jwt::{bearer_token, JwtLayer, Rejection},
+        middleware::take_ready,
+        rate_limit::RATE_LIMITED_CODE,
+        rpc::method,
+        workers::{BlockListener, IndexedBlock},
+    },
+    sampler::VerificationEvent,
+};
+
+pub mod proto {
+    tonic::include_proto!("tinydancer.lite");
+}
+
+use proto::lite_server::{Lite, LiteServer};
+
+pub struct LiteGrpcConfig {
+    pub addr: SocketAddr,
+    pub verification_events: broadcast::Sender<VerificationEvent>,
+}
+
+pub struct LiteGrpc {
+    block_listener: BlockListener,
+    /// Runs the same checks and quotas on sent transactions as the json rpc
+    tx_submitter: TxSubmitter,
+}
+
+impl LiteGrpc {
+    pub fn new(block_listener: BlockListener, tx_submitter: TxSubmitter) -> Self {
+        Self {
+            block_listener,
+            tx_submitter,
+        }
+    }
+
+    /// Serves the lite and verification services on `config.addr`, to the clients
+    /// `access` lets through
+    pub fn serve(
+        self,
+        config: LiteGrpcConfig,
+        access: GrpcAccessLayer,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        let verification = VerificationStreamer::new(config.verification_events);
+
+        tokio::spawn(async move {
+            info!("Lite gRPC Server started at {}", config.addr);
+            Server::builder()
+                .layer(access)
+                .add_service(LiteServer::new(self))
+                .add_service(VerificationServer::new(verification))
+                .serve(config.addr)
+                .await?;
+            anyhow::bail!("Lite gRPC server stopped");
+        })
+    }
+}
+
+/// Applies the api keys, jwt auth and client address filters of the lite rpc to the
+/// gRPC services, and runs the calls with their caller in scope for the transaction
+/// quotas. Clients connect directly, so their address is the peer's unless that's a
+/// trusted proxy. Tokens restricted to methods name gRPC ones in lower camel case,
+/// like `sendTransaction` or `subscribeBlocks`
+#[derive(Clone)]
+pub struct GrpcAccessLayer {
+    api_keys: ApiKeyLayer,
+    jwt: JwtLayer,
+    ip_filter: IpFilterLayer,
+    callers: CallerLayer,
+}
+
+impl GrpcAccessLayer {
+    pub fn new(
+        api_keys: ApiKeyLayer,
+        jwt: JwtLayer,
+        ip_filter: IpFilterLayer,
+        callers: CallerLayer,
+    ) -> Self {
+        Self {
+            api_keys,
+            jwt,
+            ip_filter,
+            callers,
+        }
+    }
+}
+
+impl<S> Layer<S> for GrpcAccessLayer {
+    type Service = GrpcAccess<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcAccess {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcAccess<S> {
+    inner: S,
+    layer: GrpcAccessLayer,
+}
+
+impl<S> Service<hyper::Request<Body>> for GrpcAccess<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+{
+    type Response = hyper::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        let peer = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|addr| addr.ip());
+        if !self.layer.ip_filter.admits_from(&request, peer) {
+            return Box::pin(async {
+                Ok(Status::permission_denied("Client address not allowed").to_http())
+            });
+        }
+        if !self.layer.api_keys.admits(&request) {
+            return Box::pin(async {
+                Ok(Status::unauthenticated("Missing or unknown api key").to_http())
+            });
+        }
+
+        let mut inner = take_ready(&mut self.inner);
+        let jwt = self.layer.jwt.clone();
+        let token = bearer_token(&request);
+        let method = grpc_method(request.uri().path());
+        let caller = self.layer.callers.identify_from(&request, peer);
+
+        Box::pin(async move {
+            match jwt.authorize_method(token, &method).await {
+                Ok(()) => caller.scope(inner.call(request)).await,
+                Err(Rejection::Unauthorized(message)) => {
+                    Ok(Status::unauthenticated(message).to_http())
+                }
+                Err(Rejection::Forbidden(message)) => {
+                    Ok(Status::permission_denied(message).to_http())
+                }
+            }
+        })
+    }
+}
+
+/// Method of a gRPC path like `/tinydancer.lite.Lite/SendTransaction`, in lower camel case
+fn grpc_method(path: &str) -> String {
+    let method = path.rsplit('/').next().unwrap_or_default();
+    let mut chars = method.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Status of a transaction the json rpc refuses with `err`
+fn refused(err: jsonrpsee::core::Error) -> Status {
+    let error = match err {
+        jsonrpsee::core::Error::Call(CallError::Custom(error)) => error,
+        err => return Status::invalid_argument(err.to_string()),
+    };
+    let message = error.message().to_owned();
+    match i64::from(error.code()) {
+        RATE_LIMITED_CODE | TX_QUEUE_FULL_CODE => Status::resource_exhausted(message),
+        TX_MISSING_PRIORITY_FEE_CODE
+        | TX_PREFLIGHT_FAILURE_CODE
+        | MIN_CONTEXT_SLOT_NOT_REACHED_CODE => Status::failed_precondition(message),
+        _ => Status::invalid_argument(message),
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Lite for LiteGrpc {
+    type SubscribeSlotsStream = ResponseStream<proto::SlotUpdate>;
+    type SubscribeBlocksStream = ResponseStream<proto::Block>;
+
+    async fn send_transaction(
+        &self,
+        request: Request<proto::SendTransactionRequest>,
+    ) -> Result<Response<proto::SendTransactionResponse>, Status> {
+        self.tx_submitter
+            .check_rate_limit(method::SEND_TRANSACTION)
+            .map_err(refused)?;
+
+        let proto::SendTransactionRequest {
+            transaction,
+            max_retries,
+            callback_url,
+            skip_preflight,
+            preflight_commitment,
+            min_context_slot,
+        } = request.into_inner();
+        let preflight_commitment = if preflight_commitment.is_empty() {
+            None
+        } else {
+            Some(
+                CommitmentLevel::from_str(&preflight_commitment).map_err(|_| {
+                    Status::invalid_argument(format!("Unknown commitment {preflight_commitment}"))
+                })?,
+            )
+        };
+        let config = SendTransactionConfig {
+            skip_preflight,
+            preflight_commitment,
+            max_retries: max_retries.map(|retries| retries as usize),
+            min_context_slot,
+            callback_url,
+            ..Default::default()
+        };
+
+        let sig = self
+            .tx_submitter
+            .submit(transaction, config, None)
+            .await
+            .map_err(refused)?;
+        Ok(Response::new(proto::SendTransactionResponse {
+            signature: BinaryEncoding::Base58.encode(sig),
+        }))
+    }
+
+    async fn subscribe_slots(
+        &self,
+        _request: Request<proto::SubscribeSlotsRequest>,
+    ) -> Result<Response<Self::SubscribeSlotsStream>, Status> {
+        let stream = BroadcastStream::new(self.block_listener.slot_updates()).filter_map(
+            |update| async move {
+                // lagging subscribers miss the updates that were overwritten
+                update.ok().map(|update| Ok(update.into()))
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn subscribe_blocks(
+        &self,
+        request: Request<proto::SubscribeBlocksRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let commitment = request.into_inner().commitment;
+        let commitment_config = if commitment.is_empty() {
+            CommitmentConfig::confirmed()
+        } else {
+            CommitmentConfig::from_str(&commitment)
+                .map_err(|_| Status::invalid_argument(format!("Unknown commitment {commitment}")))?
+        };
+        let commitment_config = BlockListener::get_supported_commitment_config(commitment_config);
+
+        let stream = BroadcastStream::new(self.block_listener.indexed_blocks()).filter_map(
+            move |indexed| async move {
+                match indexed {
+                    Ok(indexed) if indexed.commitment_config == commitment_config => {
+                        Some(Ok(indexed.into()))
+                    }
+                    // lagging subscribers miss the blocks that were overwritten
+                    _ => None,
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+impl From<SlotUpdate> for proto::SlotUpdate {
+    fn from(update: SlotUpdate) -> Self {
+        let slot = update.slot();
+        let (kind, timestamp) = match update {
+            SlotUpdate::FirstShredReceived { timestamp, .. } => ("firstShredReceived", timestamp),
+            SlotUpdate::Completed { timestamp, .. } => ("completed", timestamp),
+            SlotUpdate::CreatedBank { timestamp, .. } => ("createdBank", timestamp),
+            SlotUpdate::Frozen { timestamp, .. } => ("frozen", timestamp),
+            SlotUpdate::Dead { timestamp, .. } => ("dead", timestamp),
+            SlotUpdate::OptimisticConfirmation { timestamp, .. } => {
+                ("optimisticConfirmation", timestamp)
+            }
+            SlotUpdate::Root { timestamp, .. } => ("root", timestamp),
+        };
+
+        Self {
+            slot,
+            timestamp,
+            kind: kind.to_string(),
+        }
+    }
+}
+
+impl From<IndexedBlock> for proto::Block {
+    fn from(indexed: IndexedBlock) -> Self {
+        let block = &indexed.block;
+        let signatures = block
+            .transactions
+            .iter()
+            .flatten()
+            .filter_map(|tx| tx.transaction.decode())
+            .map(|tx| tx.signatures[0].to_string())
+            .collect();
+
+        Self {
+            slot: indexed.slot,
+            blockhash: block.blockhash.clone(),
+            parent_slot: block.parent_slot,
+            previous_blockhash: block.previous_blockhash.clone(),
+            block_height: block.block_height,
+            block_time: block.block_time,
+            signatures,
+            verified: indexed.verified,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Method, Request, Response, StatusCode,
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use tiny_logger::logs::warn;
+use tokio::task::JoinHandle;
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    block_store::BlockStore, tpu_manager::TpuManager, DEFAULT_HEALTH_MAX_SLOT_LAG,
+};
+
+/// Block listeners are considered dead once no block was added for this long
+const BLOCK_LISTENER_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Path answering whether every service is still running
+pub const LIVENESS_PATH: &str = "/livez";
+
+/// Path answering whether the node is running and caught up enough to serve
+pub const READINESS_PATH: &str = "/readyz";
+
+/// Tracks the services of the bridge and the lag of what they feed, backs `getHealth`
+/// and the `/livez` and `/readyz` probes
+#[derive(Clone)]
+pub struct Health {
+    block_store: BlockStore,
+    tpu_manager: Arc<TpuManager>,
+    latest_sampled_slot: Arc<AtomicU64>,
+    /// service name to whether its task is still running
+    services: Arc<DashMap<&'static str, bool>>,
+}
+
+impl Health {
+    pub fn new(
+        block_store: BlockStore,
+        tpu_manager: Arc<TpuManager>,
+        latest_sampled_slot: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            block_store,
+            tpu_manager,
+            latest_sampled_slot,
+            services: Default::default(),
+        }
+    }
+
+    /// Marks the service stopped once its task finishes, whatever the outcome
+    pub fn watch(
+        &self,
+        name: &'static str,
+        service: JoinHandle<anyhow::Result<()>>,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        self.services.insert(name, true);
+        let services = self.services.clone();
+
+        tokio::spawn(async move {
+            let result = service.await;
+            services.insert(name, false);
+            warn!("Service {name} stopped");
+            result?
+        })
+    }
+
+    /// Err naming the first stopped service
+    pub fn liveness(&self) -> Result<(), String> {
+        match self.services.iter().find(|service| !*service.value()) {
+            Some(service) => Err(format!("{} stopped", service.key())),
+            None => Ok(()),
+        }
+    }
+
+    /// Err describing why the node shouldn't be served from
+    pub async fn readiness(&self) -> Result<(), String> {
+        self.liveness()?;
+
+        if self.block_store.last_block_elapsed().await > BLOCK_LISTENER_STALE_AFTER {
+            return Err(String::from("block listeners stopped receiving blocks"));
+        }
+
+        if !self.tpu_manager.is_connected() {
+            return Err(String::from("tpu connection is down"));
+        }
+
+        let tip = self
+            .block_store
+            .get_latest_block_info(CommitmentConfig::confirmed())
+            .await
+            .slot;
+        let sampled = self.latest_sampled_slot.load(Ordering::Relaxed);
+        let lag = tip.saturating_sub(sampled);
+        if lag > DEFAULT_HEALTH_MAX_SLOT_LAG {
+            return Err(format!("sampler is behind by {lag} slots"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Answers GET `/livez` and `/readyz` with 200 or 503, anything else goes to the server
+#[derive(Clone)]
+pub struct HealthLayer {
+    health: Health,
+}
+
+impl HealthLayer {
+    pub fn new(health: Health) -> Self {
+        Self { health }
+    }
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = HealthProbe<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HealthProbe {
+            inner,
+            health: self.health.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthProbe<S> {
+    inner: S,
+    health: Health,
+}
+
+impl<S> Service<Request<Body>> for HealthProbe<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() != Method::GET {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let health = self.health.clone();
+        match request.uri().path() {
+            LIVENESS_PATH => Box::pin(async move { Ok(probe(health.liveness())) }),
+            READINESS_PATH => Box::pin(async move { Ok(probe(health.readiness().await)) }),
+            _ => Box::pin(self.inner.call(request)),
+        }
+    }
+}
+
+fn probe(status: Result<(), String>) -> Response<Body> {
+    let (status, body) = match status {
+        Ok(()) => (StatusCode::OK, serde_json::json!({ "status": "ok" })),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({ "status": "unhealthy", "reason": reason }),
+        ),
+    };
+
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
@@ -0,0 +1,243 @@
+use std::{
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response, StatusCode};
+use ipnet::IpNet;
+use prometheus::{register_int_counter, IntCounter};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    configs::AccessConfig,
+    middleware::{error_response, ACCESS_DENIED_CODE},
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_IP_REJECTED: IntCounter =
+        register_int_counter!("literpc_rpc_ip_rejected", "Number of requests rejected for their client address").unwrap();
+}
+
+/// Header the client address is read from if none is configured
+pub const DEFAULT_CLIENT_IP_HEADER: &str = "x-forwarded-for";
+
+struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    client_ips: ClientIps,
+}
+
+impl IpFilter {
+    fn is_allowed<B>(&self, request: &Request<B>) -> bool {
+        // the proxy in front of the servers always reports one, a request without is
+        // coming around it
+        self.client_ips
+            .client_ip(request)
+            .map_or(false, |ip| self.allows(ip))
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Rejects http requests and websocket upgrades from denied networks, or from outside
+/// the allowed ones if any are configured, deny wins over allow. Clients whose
+/// address can't be told are rejected
+#[derive(Clone)]
+pub struct IpFilterLayer {
+    filter: Option<Arc<IpFilter>>,
+}
+
+impl IpFilterLayer {
+    pub fn new(config: &AccessConfig) -> Self {
+        if config.allow.is_empty() && config.deny.is_empty() {
+            return Self { filter: None };
+        }
+
+        Self {
+            filter: Some(Arc::new(IpFilter {
+                allow: config.allow.clone(),
+                deny: config.deny.clone(),
+                client_ips: ClientIps::new(config),
+            })),
+        }
+    }
+
+    /// Whether a request accepted from `peer` directly, not through the proxy in front
+    /// of the servers, may call. Anyone may if no filter is configured
+    pub fn admits_from<B>(&self, request: &Request<B>, peer: Option<IpAddr>) -> bool {
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+
+        let allowed = peer
+            .and_then(|peer| filter.client_ips.client_ip_from(request, peer))
+            .map_or(false, |ip| filter.allows(ip));
+        if !allowed {
+            RPC_IP_REJECTED.inc();
+        }
+        allowed
+    }
+}
+
+/// Header the client address is read from
+pub fn client_ip_header(config: &AccessConfig) -> String {
+    config
+        .client_ip_header
+        .clone()
+        .unwrap_or_else(|| DEFAULT_CLIENT_IP_HEADER.to_string())
+}
+
+/// Tells the address of a request's client from the client address header, which
+/// every proxy in front of the servers appends the address it was reached from to
+#[derive(Clone)]
+pub struct ClientIps {
+    header: String,
+    trusted_proxies: Arc<Vec<IpNet>>,
+}
+
+impl ClientIps {
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            header: client_ip_header(config),
+            trusted_proxies: Arc::new(config.trusted_proxies.clone()),
+        }
+    }
+
+    /// Right-most address in the header that isn't a trusted proxy, entries left of it
+    /// were written by the client and can't be trusted. `None` if the header is missing
+    pub fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        self.untrusted_hop(&self.hops(request))
+    }
+
+    /// Like `client_ip`, for a request accepted from `peer` directly, which counts as
+    /// the last hop
+    pub fn client_ip_from<B>(&self, request: &Request<B>, peer: IpAddr) -> Option<IpAddr> {
+        let mut hops = self.hops(request);
+        hops.push(peer);
+        self.untrusted_hop(&hops)
+    }
+
+    /// Addresses in the header, entries that aren't one, like `unknown` or an
+    /// obfuscated identifier some proxies write, are skipped
+    fn hops<B>(&self, request: &Request<B>) -> Vec<IpAddr> {
+        request
+            .headers()
+            .get_all(self.header.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .collect()
+    }
+
+    fn untrusted_hop(&self, hops: &[IpAddr]) -> Option<IpAddr> {
+        let is_trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|net| net.contains(ip));
+        hops.iter()
+            .rev()
+            .find(|ip| !is_trusted(ip))
+            // a request from a trusted proxy itself
+            .or_else(|| hops.first())
+            .copied()
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilterService {
+            inner,
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IpFilterService<S> {
+    inner: S,
+    filter: Option<Arc<IpFilter>>,
+}
+
+impl<S> Service<Request<Body>> for IpFilterService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        match &self.filter {
+            Some(filter) if !filter.is_allowed(&request) => {
+                RPC_IP_REJECTED.inc();
+                Box::pin(async {
+                    Ok(error_response(
+                        StatusCode::FORBIDDEN,
+                        ACCESS_DENIED_CODE,
+                        "Client address not allowed",
+                    ))
+                })
+            }
+            _ => Box::pin(self.inner.call(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_ips(trusted_proxies: &[&str]) -> ClientIps {
+        ClientIps::new(&AccessConfig {
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|net| net.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    fn request(forwarded_for: &str) -> Request<()> {
+        Request::builder()
+            .header(DEFAULT_CLIENT_IP_HEADER, forwarded_for)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn skips_trusted_proxies() {
+        let client_ips = client_ips(&["10.0.0.0/8"]);
+        let request = request("1.1.1.1, 2.2.2.2, 10.0.0.1");
+        assert_eq!(
+            client_ips.client_ip(&request),
+            Some("2.2.2.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn skips_hops_that_are_not_addresses() {
+        let client_ips = client_ips(&["10.0.0.0/8"]);
+        let request = request("unknown, 2.2.2.2, _hidden, 10.0.0.1");
+        assert_eq!(
+            client_ips.client_ip(&request),
+            Some("2.2.2.2".parse().unwrap())
+        );
+        assert_eq!(
+            client_ips.client_ip_from(&Request::new(()), "3.3.3.3".parse().unwrap()),
+            Some("3.3.3.3".parse().unwrap())
+        );
+    }
+}
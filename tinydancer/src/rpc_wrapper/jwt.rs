@@ -0,0 +1,503 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::bail;
+use hyper::{
+    header::{AUTHORIZATION, UPGRADE},
+    Body, Request, Response, StatusCode,
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use prometheus::{register_int_counter, IntCounter};
+use serde_json::Value;
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::Instant,
+};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    configs::{JwtConfig, MethodAllowlist},
+    middleware::{error_response, parse_calls, take_ready, ACCESS_DENIED_CODE},
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_JWT_REJECTED: IntCounter =
+        register_int_counter!("literpc_rpc_jwt_rejected", "Number of requests rejected for a missing, invalid or insufficient jwt").unwrap();
+}
+
+/// Query parameter a token may be presented in, for websocket clients that can't set headers
+pub const ACCESS_TOKEN_QUERY_PARAM: &str = "access-token";
+
+/// Unknown key ids refetch the JWKS at most this often
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// A JWKS fetch taking longer fails, the requests waiting on it are rejected
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum JwtKeys {
+    Secret(DecodingKey),
+    Jwks {
+        url: String,
+        client: reqwest::Client,
+        keys: RwLock<HashMap<String, (Algorithm, DecodingKey)>>,
+        /// held for the length of a fetch, requests for unknown keys wait on it
+        last_fetch: Mutex<Option<Instant>>,
+    },
+}
+
+struct JwtAuth {
+    keys: JwtKeys,
+    issuer: Option<String>,
+    audience: Option<String>,
+    method_allowlists: Vec<MethodAllowlist>,
+}
+
+pub enum Rejection {
+    Unauthorized(&'static str),
+    Forbidden(String),
+}
+
+impl JwtAuth {
+    async fn decoding_key(&self, token: &str) -> Option<(Algorithm, DecodingKey)> {
+        let header = decode_header(token).ok()?;
+
+        match &self.keys {
+            JwtKeys::Secret(key) => matches!(
+                header.alg,
+                Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+            )
+            .then(|| (header.alg, key.clone())),
+            JwtKeys::Jwks {
+                url,
+                client,
+                keys,
+                last_fetch,
+            } => {
+                let kid = header.kid?;
+                if let Some(key) = keys.read().await.get(&kid) {
+                    return Some(key.clone());
+                }
+
+                // the issuer may have rotated its keys, requests arriving during a fetch
+                // wait for its keys rather than being turned away
+                let mut last_fetch = last_fetch.lock().await;
+                if let Some(key) = keys.read().await.get(&kid) {
+                    return Some(key.clone());
+                }
+                if last_fetch.map_or(false, |fetched| fetched.elapsed() < JWKS_REFRESH_INTERVAL) {
+                    return None;
+                }
+                *last_fetch = Some(Instant::now());
+
+                match fetch_jwks(client, url).await {
+                    Ok(fetched) => {
+                        info!("Fetched {} signing key(s) from {url}", fetched.len());
+                        let key = fetched.get(&kid).cloned();
+                        *keys.write().await = fetched;
+                        key
+                    }
+                    Err(err) => {
+                        warn!("Couldn't fetch JWKS from {url}: {err}");
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    async fn claims(&self, token: &str) -> Option<Value> {
+        let (algorithm, key) = self.decoding_key(token).await?;
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+
+        decode::<Value>(token, &key, &validation)
+            .ok()
+            .map(|token| token.claims)
+    }
+
+    /// Methods the claims may call, `None` if unrestricted
+    fn allowed_methods(&self, claims: &Value) -> Option<HashSet<&str>> {
+        if self.method_allowlists.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.method_allowlists
+                .iter()
+                .filter(|allowlist| claim_matches(claims.get(&allowlist.claim), &allowlist.value))
+                .flat_map(|allowlist| allowlist.methods.iter().map(String::as_str))
+                .collect(),
+        )
+    }
+
+    /// Methods the token may call, `None` if unrestricted
+    async fn authenticate(
+        &self,
+        token: Option<String>,
+    ) -> Result<Option<HashSet<&str>>, Rejection> {
+        let token = token.ok_or(Rejection::Unauthorized("Missing jwt"))?;
+        let claims = self
+            .claims(&token)
+            .await
+            .ok_or(Rejection::Unauthorized("Invalid jwt"))?;
+
+        Ok(self.allowed_methods(&claims))
+    }
+
+    async fn authorize(&self, request: Request<Body>) -> Result<Request<Body>, Rejection> {
+        let Some(allowed_methods) = self.authenticate(bearer_token(&request)).await? else {
+            return Ok(request);
+        };
+
+        // websocket messages aren't visible here so their methods couldn't be checked
+        if request.headers().contains_key(UPGRADE) {
+            return Err(Rejection::Forbidden(
+                "Tokens restricted to methods can't open websocket connections".to_string(),
+            ));
+        }
+
+        let (request, calls) = parse_calls(request)
+            .await
+            .map_err(|_| Rejection::Unauthorized("Unreadable request"))?;
+
+        let denied = calls
+            .methods()
+            .find(|method| !allowed_methods.contains(method));
+
+        match denied {
+            Some(method) => Err(Rejection::Forbidden(format!(
+                "Method {method} is not allowed for this token"
+            ))),
+            None => Ok(request),
+        }
+    }
+}
+
+/// Accepts requests presenting a valid bearer jwt, restricting their methods by claim,
+/// everything is let through if jwt auth isn't configured
+#[derive(Clone)]
+pub struct JwtLayer {
+    auth: Option<Arc<JwtAuth>>,
+}
+
+impl JwtLayer {
+    pub fn new(config: Option<&JwtConfig>) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self { auth: None });
+        };
+
+        let keys = match (&config.secret, &config.jwks_url) {
+            (Some(secret), None) => JwtKeys::Secret(DecodingKey::from_secret(secret.as_bytes())),
+            (None, Some(url)) => JwtKeys::Jwks {
+                url: url.clone(),
+                client: reqwest::Client::builder()
+                    .timeout(JWKS_FETCH_TIMEOUT)
+                    .build()?,
+                keys: Default::default(),
+                last_fetch: Default::default(),
+            },
+            _ => bail!("jwt auth needs exactly one of a secret or a JWKS url"),
+        };
+
+        Ok(Self {
+            auth: Some(Arc::new(JwtAuth {
+                keys,
+                issuer: config.issuer.clone(),
+                audience: config.audience.clone(),
+                method_allowlists: config.method_allowlists.clone(),
+            })),
+        })
+    }
+
+    /// Checks the token of a call to `method` made other than over JSON-RPC, like over
+    /// gRPC. Everything is let through if jwt auth isn't configured
+    pub async fn authorize_method(
+        &self,
+        token: Option<String>,
+        method: &str,
+    ) -> Result<(), Rejection> {
+        let Some(auth) = &self.auth else {
+            return Ok(());
+        };
+
+        let result = match auth.authenticate(token).await {
+            Ok(Some(allowed_methods)) if !allowed_methods.contains(method) => Err(
+                Rejection::Forbidden(format!("Method {method} is not allowed for this token")),
+            ),
+            Ok(_) => Ok(()),
+            Err(rejection) => Err(rejection),
+        };
+        if result.is_err() {
+            RPC_JWT_REJECTED.inc();
+        }
+        result
+    }
+}
+
+impl<S> Layer<S> for JwtLayer {
+    type Service = JwtAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        JwtAuthService {
+            inner,
+            auth: self.auth.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JwtAuthService<S> {
+    inner: S,
+    auth: Option<Arc<JwtAuth>>,
+}
+
+impl<S> Service<Request<Body>> for JwtAuthService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let Some(auth) = self.auth.clone() else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let mut inner = take_ready(&mut self.inner);
+
+        Box::pin(async move {
+            match auth.authorize(request).await {
+                Ok(request) => inner.call(request).await,
+                Err(rejection) => {
+                    RPC_JWT_REJECTED.inc();
+                    Ok(rejected(rejection))
+                }
+            }
+        })
+    }
+}
+
+async fn fetch_jwks(
+    client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<HashMap<String, (Algorithm, DecodingKey)>> {
+    let jwks: JwkSet = client.get(url).send().await?.json().await?;
+
+    Ok(jwks
+        .keys
+        .iter()
+        .filter_map(|jwk| {
+            let kid = jwk.common.key_id.clone()?;
+            let algorithm = jwk.common.algorithm?;
+            let key = DecodingKey::from_jwk(jwk).ok()?;
+            Some((kid, (algorithm, key)))
+        })
+        .collect())
+}
+
+/// Token the request presents in the authorization header or the query
+pub fn bearer_token<B>(request: &Request<B>) -> Option<String> {
+    let from_header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(String::from);
+
+    from_header.or_else(|| {
+        request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(param, _)| param == ACCESS_TOKEN_QUERY_PARAM)
+                .map(|(_, token)| token.into_owned())
+        })
+    })
+}
+
+/// Claims may hold a single value or a list of them, like `roles`
+fn claim_matches(claim: Option<&Value>, value: &str) -> bool {
+    match claim {
+        Some(Value::String(claim)) => claim == value,
+        Some(Value::Array(claims)) => claims.iter().any(|claim| claim.as_str() == Some(value)),
+        _ => false,
+    }
+}
+
+fn rejected(rejection: Rejection) -> Response<Body> {
+    let (status, message) = match rejection {
+        Rejection::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message.to_string()),
+        Rejection::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+    };
+    error_response(status, ACCESS_DENIED_CODE, &message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::Mutex as SyncMutex,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use hyper::service::{make_service_fn, service_fn};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"first-signing-key";
+    const ROTATED_SECRET: &[u8] = b"second-signing-key";
+    const ISSUER: &str = "https://issuer.test";
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            secret: Some(String::from_utf8(SECRET.to_vec()).unwrap()),
+            issuer: Some(ISSUER.to_string()),
+            method_allowlists: vec![MethodAllowlist {
+                claim: "role".to_string(),
+                value: "reader".to_string(),
+                methods: vec!["getSlot".to_string()],
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn claims(expires_in: i64) -> Value {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        json!({ "iss": ISSUER, "exp": now + expires_in, "role": ["reader"] })
+    }
+
+    fn token(claims: &Value, kid: Option<&str>, secret: &[u8]) -> Option<String> {
+        let header = Header {
+            kid: kid.map(String::from),
+            ..Header::new(Algorithm::HS256)
+        };
+        Some(encode(&header, claims, &EncodingKey::from_secret(secret)).unwrap())
+    }
+
+    fn unauthorized(result: Result<(), Rejection>) -> bool {
+        matches!(result, Err(Rejection::Unauthorized(_)))
+    }
+
+    /// Url of a JWKS endpoint serving whatever `jwks` holds
+    fn jwks_endpoint(jwks: Arc<SyncMutex<Value>>) -> String {
+        let make_service = make_service_fn(move |_| {
+            let jwks = jwks.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_: Request<Body>| {
+                    let body = jwks.lock().unwrap().to_string();
+                    async move { Ok::<_, Infallible>(Response::new(Body::from(body))) }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    fn jwks(kid: &str, encoded_secret: &str) -> Value {
+        json!({ "keys": [{ "kty": "oct", "kid": kid, "alg": "HS256", "k": encoded_secret }] })
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_tokens() {
+        let jwt = JwtLayer::new(Some(&config())).unwrap();
+
+        let result = jwt
+            .authorize_method(token(&claims(60), None, SECRET), "getSlot")
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_tokens() {
+        let jwt = JwtLayer::new(Some(&config())).unwrap();
+
+        assert!(unauthorized(jwt.authorize_method(None, "getSlot").await));
+
+        // past the default leeway of a minute
+        let expired = token(&claims(-3600), None, SECRET);
+        assert!(unauthorized(jwt.authorize_method(expired, "getSlot").await));
+
+        let mut other_issuer = claims(60);
+        other_issuer["iss"] = json!("https://other.test");
+        let other_issuer = token(&other_issuer, None, SECRET);
+        assert!(unauthorized(
+            jwt.authorize_method(other_issuer, "getSlot").await
+        ));
+
+        let forged = token(&claims(60), None, ROTATED_SECRET);
+        assert!(unauthorized(jwt.authorize_method(forged, "getSlot").await));
+    }
+
+    #[tokio::test]
+    async fn enforces_the_method_allowlists() {
+        let jwt = JwtLayer::new(Some(&config())).unwrap();
+
+        let reader = token(&claims(60), None, SECRET);
+        assert!(matches!(
+            jwt.authorize_method(reader, "sendTransaction").await,
+            Err(Rejection::Forbidden(_))
+        ));
+
+        // no allowlist matches the claims
+        let mut writer = claims(60);
+        writer["role"] = json!("writer");
+        let writer = token(&writer, None, SECRET);
+        assert!(matches!(
+            jwt.authorize_method(writer, "getSlot").await,
+            Err(Rejection::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn picks_up_rotated_jwks_keys() {
+        let served = Arc::new(SyncMutex::new(jwks("first", "Zmlyc3Qtc2lnbmluZy1rZXk")));
+        let config = JwtConfig {
+            secret: None,
+            jwks_url: Some(jwks_endpoint(served.clone())),
+            ..config()
+        };
+        let jwt = JwtLayer::new(Some(&config)).unwrap();
+
+        let first = || token(&claims(60), Some("first"), SECRET);
+        let second = || token(&claims(60), Some("second"), ROTATED_SECRET);
+        assert!(jwt.authorize_method(first(), "getSlot").await.is_ok());
+
+        *served.lock().unwrap() = jwks("second", "c2Vjb25kLXNpZ25pbmcta2V5");
+        // unknown keys refetch the JWKS at most once per interval
+        assert!(unauthorized(
+            jwt.authorize_method(second(), "getSlot").await
+        ));
+
+        let JwtKeys::Jwks { last_fetch, .. } = &jwt.auth.as_ref().unwrap().keys else {
+            unreachable!();
+        };
+        *last_fetch.lock().await = Some(Instant::now() - JWKS_REFRESH_INTERVAL);
+        assert!(jwt.authorize_method(second(), "getSlot").await.is_ok());
+        // the retired key is gone with the refetch
+        assert!(unauthorized(jwt.authorize_method(first(), "getSlot").await));
+    }
+}
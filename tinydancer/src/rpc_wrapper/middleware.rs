@@ -0,0 +1,121 @@
+//! Pieces shared by the http middleware in front of the lite rpc server
+use std::sync::Arc;
+
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Request, Response, StatusCode,
+};
+use serde_json::Value;
+
+/// Error code of requests refused for who is making them
+pub const ACCESS_DENIED_CODE: i64 = -32001;
+
+/// Calls of a JSON-RPC request body
+#[derive(Debug, Default)]
+pub struct JsonRpcCalls {
+    /// a single call is the only one, a malformed body has none and is left to the server
+    pub calls: Vec<Value>,
+    pub is_batch: bool,
+}
+
+impl JsonRpcCalls {
+    fn parse(body: &[u8]) -> Self {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(calls)) => Self {
+                calls,
+                is_batch: true,
+            },
+            Ok(call) => Self {
+                calls: vec![call],
+                is_batch: false,
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Methods called, calls without one are skipped
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        self.calls
+            .iter()
+            .filter_map(|call| call.get("method").and_then(Value::as_str))
+    }
+}
+
+/// Buffers the body of a request and parses its calls. Only the first layer asking
+/// reads the body, the ones after it find the calls in the request's extensions
+pub async fn parse_calls(
+    request: Request<Body>,
+) -> Result<(Request<Body>, Arc<JsonRpcCalls>), hyper::Error> {
+    if let Some(calls) = request.extensions().get::<Arc<JsonRpcCalls>>().cloned() {
+        return Ok((request, calls));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let calls = Arc::new(JsonRpcCalls::parse(&body));
+    parts.extensions.insert(calls.clone());
+    Ok((Request::from_parts(parts, Body::from(body)), calls))
+}
+
+/// JSON-RPC error answering a request refused before any of its calls ran
+pub fn error_response(status: StatusCode, code: i64, message: &str) -> Response<Body> {
+    let error = serde_json::json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": code,
+            "message": message,
+        },
+        "id": null,
+    });
+
+    let mut response = Response::new(Body::from(error.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Takes the service `poll_ready` was called on, to call it from a response future.
+/// A clone isn't ready, it's left in its place for the next request
+pub fn take_ready<S: Clone>(inner: &mut S) -> S {
+    let clone = inner.clone();
+    std::mem::replace(inner, clone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_calls_and_batches() {
+        let single = JsonRpcCalls::parse(br#"{"jsonrpc":"2.0","id":1,"method":"getSlot"}"#);
+        assert!(!single.is_batch);
+        assert_eq!(single.methods().collect::<Vec<_>>(), ["getSlot"]);
+
+        let batch = JsonRpcCalls::parse(
+            br#"[{"jsonrpc":"2.0","id":1,"method":"getSlot"},{"id":2},{"jsonrpc":"2.0","id":3,"method":"getHealth"}]"#,
+        );
+        assert!(batch.is_batch);
+        assert_eq!(batch.calls.len(), 3);
+        assert_eq!(
+            batch.methods().collect::<Vec<_>>(),
+            ["getSlot", "getHealth"]
+        );
+
+        let malformed = JsonRpcCalls::parse(b"{not json");
+        assert!(malformed.calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reads_the_body_once() {
+        let request = Request::new(Body::from(r#"{"jsonrpc":"2.0","id":1,"method":"getSlot"}"#));
+        let (request, calls) = parse_calls(request).await.unwrap();
+        let (request, again) = parse_calls(request).await.unwrap();
+        assert!(Arc::ptr_eq(&calls, &again));
+
+        // the body is still there for the server
+        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        assert_eq!(JsonRpcCalls::parse(&body).calls, calls.calls);
+    }
+}
@@ -8,18 +8,49 @@
 //! The above copyright notice and this permission notice shall be included in all
 //! copies or substantial portions of the Software.
 
+pub mod auth;
+pub mod batch_limit;
+pub mod body_limit;
 pub mod bridge;
+pub mod caller;
+pub mod circuit_breaker;
+pub mod coalesce;
 pub mod configs;
+pub mod congestion;
 pub mod encoding;
+pub mod epoch_tracker;
+pub mod grpc;
+pub mod health;
+pub mod ip_filter;
+pub mod jwt;
+pub mod middleware;
+pub mod passthrough;
+pub mod prioritization_fees;
+pub mod program_accounts;
+pub mod proxy;
+pub mod rate_limit;
+pub mod request_log;
+pub mod rest;
 pub mod rpc;
+pub mod tls;
 pub mod tpu_manager;
+pub mod tx_quota;
+pub mod tx_validation;
+pub mod upstreams;
 pub mod workers;
+pub mod ws;
 // pub mod cli;
 pub mod block_store;
-use crate::convert_to_websocket;
+use crate::leader_schedule::LeaderScheduleCache;
 use crate::rpc_wrapper::bridge::LiteBridge;
-use crate::tinydancer::{endpoint, ClientService, Cluster};
-use anyhow::bail;
+use crate::rpc_wrapper::configs::{
+    AccessConfig, CleanerConfig, PriorityFeePolicy, ProgramAccountsLimits, TlsConfig, TpuConfig,
+};
+use crate::rpc_wrapper::grpc::LiteGrpcConfig;
+use crate::rpc_wrapper::upstreams::Upstreams;
+use crate::sampler::SamplingStrategy;
+use crate::tinydancer::ClientService;
+use anyhow::{bail, Context};
 use async_trait::async_trait;
 use clap::Parser;
 use const_env::from_env;
@@ -28,9 +59,9 @@ use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_ledger::shred::Signer;
 use solana_sdk::signer::keypair::Keypair;
 use solana_transaction_status::TransactionConfirmationStatus;
-use std::sync::Arc;
-use std::{env, time::Duration};
-use tiny_logger::logs::info;
+use std::sync::{atomic::AtomicU64, Arc};
+use std::{env, net::SocketAddr, time::Duration};
+use tiny_logger::logs::{info, warn};
 use tokio::task::JoinHandle;
 
 // use self::cli::Args;
@@ -41,18 +72,112 @@ pub const DEFAULT_RPC_ADDR: &str = "http://0.0.0.0:8899";
 pub const DEFAULT_LITE_RPC_ADDR: &str = "http://0.0.0.0:8890";
 #[from_env]
 pub const DEFAULT_WS_ADDR: &str = "ws://0.0.0.0:8900";
+/// address the lite rpc http server binds unless configured
+#[from_env]
+pub const DEFAULT_LITE_RPC_HTTP_BIND: &str = "[::]:8890";
+/// address the lite rpc websocket server binds unless configured
+#[from_env]
+pub const DEFAULT_LITE_RPC_WS_BIND: &str = "[::]:8891";
+/// rebroadcasts of a transaction sent without maxRetries
 #[from_env]
 pub const DEFAULT_TX_MAX_RETRIES: u16 = 1;
+/// how often unconfirmed transactions are rebroadcast
+#[from_env]
+pub const DEFAULT_TX_RETRY_INTERVAL_MS: u64 = 2000;
+/// slots a transaction goes unconfirmed after a send before it's rebroadcast to the
+/// next leaders
+#[from_env]
+pub const DEFAULT_TX_RETRY_WINDOW_SLOTS: u64 = 8;
 #[from_env]
 pub const DEFAULT_TX_BATCH_SIZE: usize = 128;
+/// transactions queued for the tpu before sendTransaction refuses more
+#[from_env]
+pub const DEFAULT_TX_QUEUE_CAPACITY: usize = 16_384;
+/// slots the tpu client fans out to while the upcoming leaders aren't known yet
 #[from_env]
 pub const DEFAULT_FANOUT_SIZE: u64 = 32;
+/// leaders after the current one transactions are sent to
+#[from_env]
+pub const DEFAULT_LEADER_FORWARD_COUNT: u64 = 2;
 #[from_env]
 pub const DEFAULT_TX_BATCH_INTERVAL_MS: u64 = 1;
+/// batches worth of queued transactions a batch is picked from by priority fee
+#[from_env]
+pub const DEFAULT_TX_PRIORITY_WINDOW: usize = 4;
+/// share of each batch that goes to the oldest transactions whatever their fee
+#[from_env]
+pub const DEFAULT_TX_FAIR_SHARE_PERCENT: usize = 20;
+/// how long queued transactions may take to go out to the tpu on shutdown
+#[from_env]
+pub const DEFAULT_TX_DRAIN_TIMEOUT_MS: u64 = 5000;
 #[from_env]
 pub const DEFAULT_CLEAN_INTERVAL_MS: u64 = 5 * 60 * 1000; // five minute
+/// how long the record of a sent transaction is kept unless configured
+#[from_env]
+pub const DEFAULT_TX_SENT_TTL_S: u64 = 5 * 60;
+/// how long a block is kept in the block store unless configured
+#[from_env]
+pub const DEFAULT_BLOCK_STORE_TTL_S: u64 = 5 * 60;
+/// how long a signature subscription waits for its transaction unless configured
+#[from_env]
+pub const DEFAULT_SIGNATURE_SUBSCRIPTION_TTL_S: u64 = 5 * 60;
+/// how long an accepted transaction is kept in the journal unless configured, 0 keeps
+/// it for good
+#[from_env]
+pub const DEFAULT_TX_JOURNAL_RETENTION_S: u64 = 7 * 24 * 60 * 60;
+/// how long a durable nonce transaction is tracked, it has no blockhash to expire with
+#[from_env]
+pub const DEFAULT_DURABLE_NONCE_TX_TTL_S: u64 = 60 * 60;
+/// shreds sampled per slot when verifying the slot of an rpc response
+#[from_env]
+pub const DEFAULT_RPC_SAMPLE_QTY: usize = 10;
+/// accounts a single getMultipleAccounts call may request
+#[from_env]
+pub const DEFAULT_MAX_MULTIPLE_ACCOUNTS: usize = 100;
+/// signatures a single getSignaturesForAddress page may hold
+#[from_env]
+pub const DEFAULT_MAX_SIGNATURES_FOR_ADDRESS: usize = 1000;
+/// calls a single http JSON-RPC batch may hold
+#[from_env]
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+/// look up the statuses of signatures we didn't send upstream instead of returning null,
+/// statuses in slots the sampler didn't verify are still null
+#[from_env]
+pub const DEFAULT_SIGNATURE_STATUS_FALLBACK: bool = true;
+/// bytes an http request body or a websocket message may hold
 #[from_env]
-pub const DEFAULT_TX_SENT_TTL_S: u64 = 12;
+pub const DEFAULT_MAX_REQUEST_SIZE: u32 = 2 * 1024 * 1024;
+/// log the body of one in this many http requests, 0 to never
+#[from_env]
+pub const DEFAULT_RPC_LOG_BODY_SAMPLE_EVERY: u64 = 0;
+/// slots the sampler may trail the cluster tip by before getHealth reports unhealthy
+#[from_env]
+pub const DEFAULT_HEALTH_MAX_SLOT_LAG: u64 = 150;
+/// consecutive unreachable upstream calls before calls are failed fast
+#[from_env]
+pub const DEFAULT_UPSTREAM_BREAKER_FAILURES: u32 = 5;
+/// milliseconds the upstream is left alone once the breaker tripped
+#[from_env]
+pub const DEFAULT_UPSTREAM_BREAKER_COOLDOWN_MS: u64 = 5000;
+/// seconds between websocket pings, dead connections are noticed when a ping fails
+#[from_env]
+pub const DEFAULT_WS_PING_INTERVAL_S: u64 = 30;
+/// concurrent websocket connections, further connections are refused
+#[from_env]
+pub const DEFAULT_WS_MAX_CONNECTIONS: u32 = 1000;
+/// subscriptions a single websocket connection may hold
+#[from_env]
+pub const DEFAULT_WS_MAX_SUBSCRIPTIONS_PER_CONNECTION: u32 = 1024;
+/// messages queued for a websocket client, a client falling further behind is disconnected
+#[from_env]
+pub const DEFAULT_WS_MAX_QUEUED_MESSAGES: usize = 4096;
+/// calls of a single websocket connection run at once, its next messages wait to be read
+#[from_env]
+pub const DEFAULT_WS_MAX_IN_FLIGHT_CALLS: usize = 64;
+/// subscriptions over all websocket connections
+#[from_env]
+pub const DEFAULT_WS_MAX_SUBSCRIPTIONS: usize = 10_000;
+/// confirmation status sent transactions are done at unless configured
 pub const DEFAULT_TRANSACTION_CONFIRMATION_STATUS: TransactionConfirmationStatus =
     TransactionConfirmationStatus::Finalized;
 
@@ -61,63 +186,116 @@ pub struct TransactionService {
 }
 
 pub struct TransactionServiceConfig {
-    pub cluster: Cluster,
+    pub upstreams: Upstreams,
     pub db_instance: Arc<rocksdb::DB>,
+    pub sampling_strategy: SamplingStrategy,
+    pub program_accounts_limits: ProgramAccountsLimits,
+    pub latest_sampled_slot: Arc<AtomicU64>,
+    pub leader_schedule: LeaderScheduleCache,
+    pub block_engine_url: Option<String>,
+    /// Url the statuses of transactions sent without a callback url are posted to
+    pub tx_webhook_url: Option<String>,
+    /// Confirmation status sent transactions are done at and signature subscriptions
+    /// default to, `DEFAULT_TRANSACTION_CONFIRMATION_STATUS` if `None`
+    pub tx_terminal_status: Option<TransactionConfirmationStatus>,
+    pub priority_fee_policy: PriorityFeePolicy,
+    /// How transactions are sent to the leaders' tpus
+    pub tpu_config: TpuConfig,
+    pub access_config: AccessConfig,
+    pub tls_config: Option<TlsConfig>,
+    pub http_addr: SocketAddr,
+    pub ws_addr: SocketAddr,
+    /// Serve the lite grpc service as well, disabled if `None`
+    pub grpc_config: Option<LiteGrpcConfig>,
+    /// Keypair file of the validator identity the tpu client's quic certificate is
+    /// signed with, an ephemeral unstaked identity if `None`
+    pub tpu_identity: Option<String>,
+    pub cleaner_config: CleanerConfig,
 }
 
-async fn get_identity_keypair(identity_from_cli: &String) -> Keypair {
-    if let Ok(identity_env_var) = env::var("IDENTITY") {
-        if let Ok(identity_bytes) = serde_json::from_str::<Vec<u8>>(identity_env_var.as_str()) {
-            print!("HASIII TO HASU");
-            Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
-        } else {
+/// The `IDENTITY` env var, a keypair or its file, wins over the configured file.
+/// Without either the identity is ephemeral and unstaked
+async fn get_identity_keypair(identity_from_config: Option<&str>) -> anyhow::Result<Keypair> {
+    let identity_bytes: Vec<u8> = if let Ok(identity_env_var) = env::var("IDENTITY") {
+        match serde_json::from_str::<Vec<u8>>(identity_env_var.as_str()) {
+            Ok(identity_bytes) => identity_bytes,
             // must be a file
-            let identity_file = tokio::fs::read_to_string(identity_env_var.as_str())
-                .await
-                .expect("Cannot find the identity file provided");
-            let identity_bytes: Vec<u8> = serde_json::from_str(&identity_file).unwrap();
-            Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
+            Err(_) => read_keypair_bytes(&identity_env_var).await?,
         }
-    } else if identity_from_cli.is_empty() {
-        Keypair::new()
+    } else if let Some(identity_file) = identity_from_config {
+        read_keypair_bytes(identity_file).await?
     } else {
-        let identity_file = tokio::fs::read_to_string(identity_from_cli.as_str())
-            .await
-            .expect("Cannot find the identity file provided");
-        let identity_bytes: Vec<u8> = serde_json::from_str(&identity_file).unwrap();
-        Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
+        return Ok(Keypair::new());
+    };
+
+    Keypair::from_bytes(identity_bytes.as_slice())
+        .map_err(|err| anyhow::anyhow!("Invalid identity keypair {err}"))
+}
+
+/// File the tpu identity is read from, `None` if it's ephemeral or inline in the
+/// `IDENTITY` env var
+fn identity_file(identity_from_config: Option<&str>) -> Option<String> {
+    match env::var("IDENTITY") {
+        Ok(identity) if serde_json::from_str::<Vec<u8>>(&identity).is_ok() => None,
+        Ok(identity_file) => Some(identity_file),
+        Err(_) => identity_from_config.map(String::from),
     }
 }
 
+pub async fn read_keypair(path: &str) -> anyhow::Result<Keypair> {
+    Keypair::from_bytes(&read_keypair_bytes(path).await?)
+        .map_err(|err| anyhow::anyhow!("Invalid identity keypair {err}"))
+}
+
+async fn read_keypair_bytes(path: &str) -> anyhow::Result<Vec<u8>> {
+    let identity_file = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Cannot read the identity file {path}"))?;
+    Ok(serde_json::from_str(&identity_file)?)
+}
+
 #[async_trait]
 impl ClientService<TransactionServiceConfig> for TransactionService {
     type ServiceError = tokio::io::Error;
     fn new(config: TransactionServiceConfig) -> Self {
         let transaction_handle = tokio::spawn(async {
             dotenv().ok();
-            let rpc_url = endpoint(config.cluster);
-
-            let payer = Keypair::new();
+            let identity = get_identity_keypair(config.tpu_identity.as_deref()).await?;
+            let identity_file = identity_file(config.tpu_identity.as_deref());
 
             let tx_batch_interval_ms = Duration::from_millis(DEFAULT_TX_BATCH_INTERVAL_MS);
-            let clean_interval_ms = Duration::from_millis(DEFAULT_CLEAN_INTERVAL_MS);
 
             let light_bridge = LiteBridge::new(
-                String::from(rpc_url.clone()),
-                String::from(convert_to_websocket!(rpc_url)),
+                config.upstreams,
                 DEFAULT_FANOUT_SIZE,
-                payer,
+                identity,
                 config.db_instance,
+                config.sampling_strategy,
+                config.program_accounts_limits,
+                config.latest_sampled_slot,
+                config.leader_schedule,
+                config.block_engine_url,
+                config.tx_webhook_url,
+                config
+                    .tx_terminal_status
+                    .unwrap_or(DEFAULT_TRANSACTION_CONFIRMATION_STATUS),
+                config.priority_fee_policy,
+                config.tpu_config,
+                config.access_config,
             )
             .await?;
+            let tx_sender = light_bridge.tx_sender.clone();
 
             let services = light_bridge
                 .start_services(
-                    String::from("[::]:8890"),
-                    String::from("[::]:8891"),
+                    config.http_addr,
+                    config.ws_addr,
                     DEFAULT_TX_BATCH_SIZE,
                     tx_batch_interval_ms,
-                    clean_interval_ms,
+                    config.cleaner_config,
+                    config.tls_config,
+                    config.grpc_config,
+                    identity_file,
                 )
                 .await?;
 
@@ -130,7 +308,13 @@ impl ClientService<TransactionServiceConfig> for TransactionService {
                     bail!("Services quit unexpectedly");
                 }
                 _ = ctrl_c_signal => {
-                    info!("Received ctrl+c signal");
+                    info!("Received ctrl+c signal, draining queued transactions");
+                    let unsent = tx_sender
+                        .drain(Duration::from_millis(DEFAULT_TX_DRAIN_TIMEOUT_MS))
+                        .await;
+                    if unsent > 0 {
+                        warn!("Exiting with {unsent} queued transaction(s) unsent");
+                    }
                     Ok(())
                 }
             }
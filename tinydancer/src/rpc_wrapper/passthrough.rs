@@ -0,0 +1,313 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper::{
+    header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    Body, Request, Response,
+};
+use prometheus::{register_int_counter, IntCounter};
+use serde_json::{json, Value};
+use tiny_logger::logs::warn;
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    middleware::{parse_calls, take_ready},
+    upstreams::Upstreams,
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_CALLS_PASSED_THROUGH: IntCounter =
+        register_int_counter!("literpc_rpc_calls_passed_through", "Number of calls to unimplemented methods forwarded upstream").unwrap();
+}
+
+/// How long a forwarded call may take, same as the rpc client's own calls
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Forwards http JSON-RPC calls to methods `LiteBridge` doesn't implement to the
+/// upstream rpc verbatim, batches are split between the two. Calls go to the upstream
+/// the rpc client's calls currently go to
+#[derive(Clone)]
+pub struct PassthroughLayer {
+    upstreams: Upstreams,
+    methods: Arc<HashSet<&'static str>>,
+    client: reqwest::Client,
+}
+
+impl PassthroughLayer {
+    pub fn new(upstreams: Upstreams, methods: impl IntoIterator<Item = &'static str>) -> Self {
+        Self {
+            upstreams,
+            methods: Arc::new(methods.into_iter().collect()),
+            client: reqwest::Client::builder()
+                .timeout(UPSTREAM_TIMEOUT)
+                .build()
+                .expect("the passthrough client builds"),
+        }
+    }
+
+    /// calls without a method are left to the server to reject
+    fn is_local(&self, call: &Value) -> bool {
+        call.get("method")
+            .and_then(Value::as_str)
+            .map_or(true, |method| self.methods.contains(method))
+    }
+
+    async fn forward(&self, calls: &Value) -> Value {
+        RPC_CALLS_PASSED_THROUGH.inc_by(calls.as_array().map_or(1, Vec::len) as u64);
+
+        let url = self.upstreams.url();
+        let response = match self.client.post(&url).json(calls).send().await {
+            Ok(response) => response.json::<Value>().await,
+            Err(err) => {
+                // the next calls go elsewhere while it's unreachable
+                if err.is_connect() || err.is_timeout() {
+                    self.upstreams.mark_unreachable(&url);
+                }
+                Err(err)
+            }
+        };
+
+        match response {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Couldn't forward calls to upstream rpc {url}: {err}");
+                let message = format!("Upstream error: {err}");
+                match calls {
+                    Value::Array(calls) => calls
+                        .iter()
+                        .map(|call| call_error(call, &message))
+                        .collect(),
+                    call => call_error(call, &message),
+                }
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for PassthroughLayer {
+    type Service = Passthrough<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Passthrough {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Passthrough<S> {
+    inner: S,
+    layer: PassthroughLayer,
+}
+
+impl<S> Service<Request<Body>> for Passthrough<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error> + Send,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let mut inner = take_ready(&mut self.inner);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let (request, calls) = parse_calls(request).await?;
+            if calls.calls.iter().all(|call| layer.is_local(call)) {
+                return inner.call(request).await;
+            }
+            if !calls.is_batch {
+                return Ok(json_response(layer.forward(&calls.calls[0]).await));
+            }
+
+            let (mut parts, _) = request.into_parts();
+            let (local, upstream): (Vec<Value>, Vec<Value>) = calls
+                .calls
+                .iter()
+                .cloned()
+                .partition(|call| layer.is_local(call));
+
+            let mut responses = Vec::with_capacity(local.len() + upstream.len());
+            if !local.is_empty() {
+                // the body is rewritten, its length no longer matches
+                parts.headers.remove(CONTENT_LENGTH);
+                let local_request =
+                    Request::from_parts(parts, Body::from(Value::Array(local.clone()).to_string()));
+                let response = inner.call(local_request).await?;
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+                match serde_json::from_slice::<Value>(&body) {
+                    Ok(Value::Array(local_responses)) => responses.extend(local_responses),
+                    // a batch of notifications only isn't answered
+                    _ if body.is_empty() => {}
+                    // anything but a batch, like a single error, answers none of the calls
+                    _ => responses
+                        .extend(local.iter().map(|call| call_error(call, "Internal error"))),
+                }
+            }
+
+            let upstream = Value::Array(upstream);
+            match layer.forward(&upstream).await {
+                Value::Array(upstream_responses) => responses.extend(upstream_responses),
+                reply => {
+                    warn!("Upstream answered a batch with {reply}");
+                    let message = "Upstream error: invalid batch response";
+                    responses.extend(
+                        upstream
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .map(|call| call_error(call, message)),
+                    );
+                }
+            }
+
+            Ok(json_response(Value::Array(responses)))
+        })
+    }
+}
+
+/// Internal error answering `call`, which couldn't be answered otherwise
+fn call_error(call: &Value, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": -32603,
+            "message": message,
+        },
+        "id": call.get("id").cloned().unwrap_or(Value::Null),
+    })
+}
+
+fn json_response(body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::future::poll_fn;
+    use hyper::service::{make_service_fn, service_fn};
+
+    use super::*;
+
+    /// Answers every call of `body` with who answered it and the method called
+    fn answer(who: &str, body: &[u8]) -> Response<Body> {
+        let answer = |call: &Value| {
+            let method = call["method"].as_str().unwrap_or_default();
+            json!({
+                "jsonrpc": "2.0",
+                "result": format!("{who} {method}"),
+                "id": call["id"],
+            })
+        };
+        json_response(match serde_json::from_slice(body).unwrap() {
+            Value::Array(calls) => calls.iter().map(answer).collect(),
+            call => answer(&call),
+        })
+    }
+
+    async fn local(request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        Ok(answer("local", &body))
+    }
+
+    /// Url of an upstream rpc answering every call
+    fn upstream() -> String {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let body = hyper::body::to_bytes(request.into_body()).await?;
+                Ok::<_, hyper::Error>(answer("upstream", &body))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    async fn call<S>(service: &mut S, calls: Value) -> Value
+    where
+        S: Service<Request<Body>, Response = Response<Body>>,
+        S::Error: std::fmt::Debug,
+    {
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        let response = service
+            .call(Request::new(Body::from(calls.to_string())))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwards_unknown_methods_upstream() {
+        let upstreams = Upstreams::new(vec![upstream()]);
+        let mut service = PassthroughLayer::new(upstreams, ["getSlot"]).layer(service_fn(local));
+
+        let response = call(
+            &mut service,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "getInflationRate"}),
+        )
+        .await;
+        assert_eq!(response["result"], "upstream getInflationRate");
+
+        // a batch is split, the answers of the local calls come first
+        let response = call(
+            &mut service,
+            json!([
+                {"jsonrpc": "2.0", "id": 1, "method": "getInflationRate"},
+                {"jsonrpc": "2.0", "id": 2, "method": "getSlot"},
+            ]),
+        )
+        .await;
+        assert_eq!(response[0]["result"], "local getSlot");
+        assert_eq!(response[0]["id"], 2);
+        assert_eq!(response[1]["result"], "upstream getInflationRate");
+        assert_eq!(response[1]["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn leaves_native_methods_alone() {
+        // nothing listens there, a forwarded call would fail
+        let upstreams = Upstreams::new(vec![String::from("http://127.0.0.1:1")]);
+        let mut service =
+            PassthroughLayer::new(upstreams, ["getSlot", "getHealth"]).layer(service_fn(local));
+
+        let response = call(
+            &mut service,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "getSlot"}),
+        )
+        .await;
+        assert_eq!(response["result"], "local getSlot");
+
+        let response = call(
+            &mut service,
+            json!([
+                {"jsonrpc": "2.0", "id": 1, "method": "getSlot"},
+                {"jsonrpc": "2.0", "id": 2, "method": "getHealth"},
+            ]),
+        )
+        .await;
+        assert_eq!(response[0]["result"], "local getSlot");
+        assert_eq!(response[1]["result"], "local getHealth");
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use anyhow::bail;
+use prometheus::{opts, register_int_counter, IntCounter};
+use serde::{Deserialize, Serialize};
+use solana_rpc_client_api::response::RpcPrioritizationFee;
+use solana_sdk::{
+    borsh::try_from_slice_unchecked,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::CompiledInstruction,
+    message::VersionedMessage,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    slot_history::Slot,
+    transaction::VersionedTransaction,
+};
+use tiny_logger::logs::warn;
+use tokio::sync::RwLock;
+
+use crate::rpc_wrapper::{
+    configs::{PriorityFeeMode, PriorityFeePolicy},
+    read_keypair,
+    workers::WireTransaction,
+};
+
+lazy_static::lazy_static! {
+    static ref TXS_PRIORITY_FEE_APPENDED: IntCounter =
+    register_int_counter!(opts!("literpc_txs_priority_fee_appended", "Transactions re-signed with the configured compute unit price appended")).unwrap();
+    static ref TXS_PRIORITY_FEE_REJECTED: IntCounter =
+    register_int_counter!(opts!("literpc_txs_priority_fee_rejected", "Transactions refused because they had no compute unit price")).unwrap();
+}
+
+/// Slots of fees kept around, same as the validator's cache
+const MAX_NUM_RECENT_BLOCKS: usize = 150;
+
+/// Fees paid in a single block
+#[derive(Default, Clone, Debug)]
+struct BlockPrioritizationFees {
+    min_transaction_fee: Option<u64>,
+    min_writable_account_fees: HashMap<Pubkey, u64>,
+    /// compute unit price of every transaction
+    transaction_fees: Vec<u64>,
+    /// transactions writing to each account, as indexes into `transaction_fees`
+    writable_account_txs: HashMap<Pubkey, Vec<usize>>,
+}
+
+/// Compute unit prices in micro lamports at increasing percentiles of recent transactions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriorityFeeLevels {
+    pub min: u64,
+    /// 25th percentile
+    pub low: u64,
+    /// 50th percentile
+    pub medium: u64,
+    /// 75th percentile
+    pub high: u64,
+    /// 95th percentile
+    pub very_high: u64,
+    pub unsafe_max: u64,
+}
+
+/// Prioritization fees of the blocks seen by the block listener
+#[derive(Default, Clone)]
+pub struct PrioritizationFeeCache {
+    blocks: Arc<RwLock<BTreeMap<Slot, BlockPrioritizationFees>>>,
+}
+
+impl PrioritizationFeeCache {
+    /// Records the fees paid by the non vote transactions of a block
+    pub async fn add_block(&self, slot: Slot, transactions: &[VersionedTransaction]) {
+        let mut fees = BlockPrioritizationFees::default();
+
+        for tx in transactions {
+            let account_keys = tx.message.static_account_keys();
+            if account_keys
+                .iter()
+                .any(|key| *key == solana_sdk::vote::program::id())
+            {
+                continue;
+            }
+
+            let fee = compute_unit_price(tx);
+            fees.min_transaction_fee =
+                Some(fees.min_transaction_fee.map_or(fee, |min| min.min(fee)));
+            let tx_index = fees.transaction_fees.len();
+            fees.transaction_fees.push(fee);
+
+            // accounts loaded from lookup tables aren't resolved
+            for (index, key) in account_keys.iter().enumerate() {
+                if tx.message.is_maybe_writable(index) {
+                    fees.min_writable_account_fees
+                        .entry(*key)
+                        .and_modify(|min| *min = (*min).min(fee))
+                        .or_insert(fee);
+                    fees.writable_account_txs
+                        .entry(*key)
+                        .or_default()
+                        .push(tx_index);
+                }
+            }
+        }
+
+        let mut blocks = self.blocks.write().await;
+        blocks.insert(slot, fees);
+        while blocks.len() > MAX_NUM_RECENT_BLOCKS {
+            blocks.pop_first();
+        }
+    }
+
+    /// Fee a transaction locking all of `accounts` had to pay to land in each recent block
+    pub async fn get_recent_fees(&self, accounts: &[Pubkey]) -> Vec<RpcPrioritizationFee> {
+        self.blocks
+            .read()
+            .await
+            .iter()
+            .map(|(slot, fees)| {
+                let transaction_fee = fees.min_transaction_fee.unwrap_or_default();
+                let prioritization_fee = accounts
+                    .iter()
+                    .filter_map(|account| fees.min_writable_account_fees.get(account))
+                    .copied()
+                    .fold(transaction_fee, u64::max);
+
+                RpcPrioritizationFee {
+                    slot: *slot,
+                    prioritization_fee,
+                }
+            })
+            .collect()
+    }
+
+    /// Percentiles of the fees paid in the last `lookback_slots` blocks, by the
+    /// transactions writing to any of `accounts` if given. A transaction writing to
+    /// several of them counts once
+    pub async fn estimate(&self, accounts: &[Pubkey], lookback_slots: usize) -> PriorityFeeLevels {
+        let blocks = self.blocks.read().await;
+
+        let mut fees: Vec<u64> = blocks
+            .values()
+            .rev()
+            .take(lookback_slots)
+            .flat_map(|block| {
+                if accounts.is_empty() {
+                    return block.transaction_fees.clone();
+                }
+                accounts
+                    .iter()
+                    .filter_map(|account| block.writable_account_txs.get(account))
+                    .flatten()
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .map(|&tx_index| block.transaction_fees[tx_index])
+                    .collect()
+            })
+            .collect();
+        drop(blocks);
+
+        if fees.is_empty() {
+            return PriorityFeeLevels::default();
+        }
+        fees.sort_unstable();
+
+        let percentile = |percent: usize| fees[(fees.len() - 1) * percent / 100];
+        PriorityFeeLevels {
+            min: percentile(0),
+            low: percentile(25),
+            medium: percentile(50),
+            high: percentile(75),
+            very_high: percentile(95),
+            unsafe_max: percentile(100),
+        }
+    }
+}
+
+/// Compute unit price in micro lamports requested by the transaction, 0 if none
+pub fn compute_unit_price(tx: &VersionedTransaction) -> u64 {
+    requested_compute_unit_price(tx).unwrap_or_default()
+}
+
+/// Compute unit price of the transaction's `SetComputeUnitPrice` instruction, `None`
+/// if it has none, an explicit price of 0 is still one
+fn requested_compute_unit_price(tx: &VersionedTransaction) -> Option<u64> {
+    let account_keys = tx.message.static_account_keys();
+
+    tx.message
+        .instructions()
+        .iter()
+        .filter(|ix| {
+            account_keys.get(ix.program_id_index as usize)
+                == Some(&solana_sdk::compute_budget::id())
+        })
+        .find_map(|ix| match try_from_slice_unchecked(&ix.data) {
+            Ok(ComputeBudgetInstruction::SetComputeUnitPrice(price)) => Some(price),
+            _ => None,
+        })
+}
+
+/// The transaction doesn't pay a compute unit price and the policy requires one
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Transaction has no compute unit price, add a SetComputeUnitPrice compute budget \
+     instruction, getPriorityFeeEstimate suggests one for its writable accounts"
+)]
+pub struct MissingPriorityFee;
+
+/// Applies the `PriorityFeePolicy` to transactions as they're queued
+pub struct PriorityFeeEnforcer {
+    mode: PriorityFeeMode,
+    micro_lamports: u64,
+    /// transactions it alone signs are re-signed with the appended price
+    payer: Option<Keypair>,
+}
+
+impl PriorityFeeEnforcer {
+    pub async fn new(policy: PriorityFeePolicy) -> anyhow::Result<Self> {
+        let payer = match &policy.payer {
+            Some(path) => Some(read_keypair(path).await?),
+            None => None,
+        };
+        if policy.mode == PriorityFeeMode::Append && payer.is_none() {
+            warn!(
+                "Priority fees are appended without a payer, transactions without one are rejected"
+            );
+        }
+
+        Ok(Self {
+            mode: policy.mode,
+            micro_lamports: policy.micro_lamports,
+            payer,
+        })
+    }
+
+    /// Returns `raw_tx` as it should be queued, with a compute unit price appended if
+    /// it was missing and the payer can re-sign it
+    pub fn enforce(&self, raw_tx: WireTransaction) -> anyhow::Result<WireTransaction> {
+        if self.mode == PriorityFeeMode::Off {
+            return Ok(raw_tx);
+        }
+        let mut tx = bincode::deserialize::<VersionedTransaction>(&raw_tx)?;
+        // a second price instruction would fail the transaction as a duplicate
+        if requested_compute_unit_price(&tx).is_some() {
+            return Ok(raw_tx);
+        }
+
+        let payer = match (&self.mode, &self.payer) {
+            (PriorityFeeMode::Append, Some(payer))
+                if tx.message.header().num_required_signatures == 1
+                    && tx.message.static_account_keys().first() == Some(&payer.pubkey()) =>
+            {
+                payer
+            }
+            _ => {
+                TXS_PRIORITY_FEE_REJECTED.inc();
+                return Err(MissingPriorityFee.into());
+            }
+        };
+
+        // only what the payer already signed is re-signed, or anyone could have the
+        // payer sign for them
+        if tx.verify_with_results().first() != Some(&true) {
+            TXS_PRIORITY_FEE_REJECTED.inc();
+            bail!("Signature of the payer doesn't verify, the transaction isn't re-signed");
+        }
+
+        append_compute_unit_price(&mut tx.message, self.micro_lamports)?;
+        tx.signatures[0] = payer.sign_message(&tx.message.serialize());
+        let raw_tx = bincode::serialize(&tx)?;
+        if raw_tx.len() > PACKET_DATA_SIZE {
+            bail!("Transaction is too large to append a compute unit price to");
+        }
+
+        TXS_PRIORITY_FEE_APPENDED.inc();
+        Ok(raw_tx)
+    }
+}
+
+/// Appends a `SetComputeUnitPrice` instruction, adding the compute budget program to
+/// the static keys if it isn't one already
+fn append_compute_unit_price(
+    message: &mut VersionedMessage,
+    micro_lamports: u64,
+) -> anyhow::Result<()> {
+    let (header, account_keys, instructions) = match message {
+        VersionedMessage::Legacy(message) => (
+            &mut message.header,
+            &mut message.account_keys,
+            &mut message.instructions,
+        ),
+        VersionedMessage::V0(message) => (
+            &mut message.header,
+            &mut message.account_keys,
+            &mut message.instructions,
+        ),
+    };
+
+    let program_id = solana_sdk::compute_budget::id();
+    let program_id_index = match account_keys.iter().position(|key| *key == program_id) {
+        Some(index) => index as u8,
+        None => {
+            // read only unsigned keys go last, addresses loaded from lookup tables
+            // are indexed right after them so they shift by one
+            let Ok(index) = u8::try_from(account_keys.len()) else {
+                bail!("Transaction has too many accounts to append a compute unit price to");
+            };
+            for ix in instructions.iter_mut() {
+                let indexes =
+                    std::iter::once(&mut ix.program_id_index).chain(ix.accounts.iter_mut());
+                for account in indexes.filter(|account| **account >= index) {
+                    let Some(shifted) = account.checked_add(1) else {
+                        bail!(
+                            "Transaction has too many accounts to append a compute unit price to"
+                        );
+                    };
+                    *account = shifted;
+                }
+            }
+            account_keys.push(program_id);
+            header.num_readonly_unsigned_accounts += 1;
+            index
+        }
+    };
+
+    instructions.push(CompiledInstruction {
+        program_id_index,
+        accounts: vec![],
+        data: ComputeBudgetInstruction::set_compute_unit_price(micro_lamports).data,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_compute_unit_price, requested_compute_unit_price, PrioritizationFeeCache,
+        PriorityFeeEnforcer,
+    };
+    use crate::rpc_wrapper::configs::PriorityFeeMode;
+    use solana_sdk::{
+        compute_budget::{self, ComputeBudgetInstruction},
+        hash::Hash,
+        instruction::CompiledInstruction,
+        message::{
+            v0::{self, MessageAddressTableLookup},
+            Message, MessageHeader, VersionedMessage,
+        },
+        pubkey::Pubkey,
+        sanitize::Sanitize,
+        signature::{Keypair, Signature, Signer},
+        system_instruction,
+        transaction::VersionedTransaction,
+    };
+
+    #[test]
+    fn append_to_legacy_message() {
+        let payer = Pubkey::new_unique();
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+        let mut message = VersionedMessage::Legacy(Message::new(&[transfer.clone()], Some(&payer)));
+
+        append_compute_unit_price(&mut message, 1_000).unwrap();
+
+        let VersionedMessage::Legacy(message) = &message else {
+            unreachable!()
+        };
+        assert!(message.sanitize().is_ok());
+        assert_eq!(message.header.num_readonly_unsigned_accounts, 2);
+        assert_eq!(message.account_keys.len(), 4);
+        assert_eq!(message.account_keys[3], compute_budget::id());
+        assert_eq!(message.program_id(0), Some(&transfer.program_id));
+        assert_eq!(message.instructions[0].accounts, vec![0, 1]);
+        assert_eq!(message.instructions[1].program_id_index, 3);
+        assert_eq!(
+            message.instructions[1].data,
+            ComputeBudgetInstruction::set_compute_unit_price(1_000).data
+        );
+    }
+
+    #[test]
+    fn append_to_v0_message_with_lookup_tables() {
+        let payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut message = VersionedMessage::V0(v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![payer, program],
+            recent_blockhash: Hash::new_unique(),
+            // loads a writable address at index 2 and a readonly one at index 3
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                1,
+                vec![],
+                vec![0, 2, 3],
+            )],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![1],
+            }],
+        });
+
+        append_compute_unit_price(&mut message, 1_000).unwrap();
+
+        let VersionedMessage::V0(message) = &message else {
+            unreachable!()
+        };
+        assert!(message.sanitize().is_ok());
+        assert_eq!(
+            message.account_keys,
+            vec![payer, program, compute_budget::id()]
+        );
+        assert_eq!(message.header.num_readonly_unsigned_accounts, 2);
+        // loaded addresses come after the static keys and shift with them
+        assert_eq!(message.instructions[0].program_id_index, 1);
+        assert_eq!(message.instructions[0].accounts, vec![0, 3, 4]);
+        assert_eq!(message.instructions[1].program_id_index, 2);
+    }
+
+    #[test]
+    fn append_reuses_compute_budget_key() {
+        let payer = Pubkey::new_unique();
+        let limit = ComputeBudgetInstruction::set_compute_unit_limit(200_000);
+        let mut message = VersionedMessage::Legacy(Message::new(&[limit], Some(&payer)));
+
+        append_compute_unit_price(&mut message, 1_000).unwrap();
+
+        assert_eq!(message.static_account_keys().len(), 2);
+        assert_eq!(message.instructions()[1].program_id_index, 1);
+    }
+
+    fn enforcer(payer: &Keypair) -> PriorityFeeEnforcer {
+        PriorityFeeEnforcer {
+            mode: PriorityFeeMode::Append,
+            micro_lamports: 1_000,
+            payer: Some(Keypair::from_bytes(&payer.to_bytes()).unwrap()),
+        }
+    }
+
+    fn transfer_from(payer: &Keypair) -> VersionedTransaction {
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let message = VersionedMessage::Legacy(Message::new(&[transfer], Some(&payer.pubkey())));
+        VersionedTransaction::try_new(message, &[payer]).unwrap()
+    }
+
+    #[test]
+    fn enforce_refuses_forged_payer_signature() {
+        let payer = Keypair::new();
+        let mut tx = transfer_from(&payer);
+        tx.signatures[0] = Signature::default();
+
+        let raw_tx = bincode::serialize(&tx).unwrap();
+        assert!(enforcer(&payer).enforce(raw_tx).is_err());
+    }
+
+    #[test]
+    fn enforce_keeps_explicit_zero_price() {
+        let payer = Keypair::new();
+        let price = ComputeBudgetInstruction::set_compute_unit_price(0);
+        let message = VersionedMessage::Legacy(Message::new(&[price], Some(&payer.pubkey())));
+        let tx = VersionedTransaction::try_new(message, &[&payer]).unwrap();
+
+        let raw_tx = bincode::serialize(&tx).unwrap();
+        assert_eq!(enforcer(&payer).enforce(raw_tx.clone()).unwrap(), raw_tx);
+        assert_eq!(requested_compute_unit_price(&tx), Some(0));
+    }
+
+    #[test]
+    fn enforce_resigns_payer_transactions() {
+        let payer = Keypair::new();
+        let tx = transfer_from(&payer);
+
+        let raw_tx = bincode::serialize(&tx).unwrap();
+        let enforced = enforcer(&payer).enforce(raw_tx).unwrap();
+        let enforced: VersionedTransaction = bincode::deserialize(&enforced).unwrap();
+        assert!(enforced
+            .verify_with_results()
+            .iter()
+            .all(|verified| *verified));
+        assert_eq!(requested_compute_unit_price(&enforced), Some(1_000));
+    }
+
+    fn writing_to(accounts: &[Pubkey], micro_lamports: u64) -> VersionedTransaction {
+        let payer = Keypair::new();
+        let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_price(
+            micro_lamports,
+        )];
+        instructions.extend(
+            accounts
+                .iter()
+                .map(|account| system_instruction::transfer(&payer.pubkey(), account, 1)),
+        );
+        let message = VersionedMessage::Legacy(Message::new(&instructions, Some(&payer.pubkey())));
+        VersionedTransaction::try_new(message, &[&payer]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn estimate_counts_a_transaction_once() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let cache = PrioritizationFeeCache::default();
+        cache
+            .add_block(
+                1,
+                &[
+                    writing_to(&[first, second], 100),
+                    writing_to(&[first, second], 100),
+                    writing_to(&[first], 1),
+                ],
+            )
+            .await;
+
+        // counted per account, the high fee transactions would crowd out the low one
+        let levels = cache.estimate(&[first, second], 10).await;
+        assert_eq!((levels.min, levels.low, levels.medium), (1, 1, 100));
+        let levels = cache.estimate(&[second], 10).await;
+        assert_eq!((levels.min, levels.unsafe_max), (100, 100));
+    }
+}
@@ -0,0 +1,295 @@
+//! `getProgramAccounts` fetched from the upstream as a stream, a response holding more
+//! accounts than allowed is abandoned as soon as that's clear instead of being
+//! buffered whole first
+use std::{fmt, io::Read};
+
+use hyper::body::{Buf, Bytes};
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserializer,
+};
+use serde_json::{json, Value};
+use solana_rpc_client_api::response::{
+    Response as RpcResponse, RpcKeyedAccount, RpcResponseContext,
+};
+use tokio::sync::mpsc;
+
+use crate::rpc_wrapper::configs::ProgramAccountsLimits;
+
+/// Chunks of the response buffered ahead of the parser
+const CHUNK_BUFFER: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProgramAccountsError {
+    #[error("getProgramAccounts returned more than {0} accounts, narrow the filters")]
+    TooMany(usize),
+    #[error("{0}")]
+    Upstream(String),
+}
+
+#[derive(Clone)]
+pub struct ProgramAccounts {
+    pub limits: ProgramAccountsLimits,
+    client: reqwest::Client,
+}
+
+impl ProgramAccounts {
+    pub fn new(limits: ProgramAccountsLimits) -> Self {
+        Self {
+            limits,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Calls `getProgramAccounts` on `url` with `params`, whose config must ask for the
+    /// context
+    pub async fn fetch(
+        &self,
+        url: &str,
+        params: Value,
+    ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ProgramAccountsError> {
+        let upstream_error = |err: reqwest::Error| ProgramAccountsError::Upstream(err.to_string());
+        let mut response = self
+            .client
+            .post(url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "getProgramAccounts",
+                "params": params,
+            }))
+            .send()
+            .await
+            .map_err(upstream_error)?;
+
+        let max_accounts = self.limits.max_accounts;
+        let (chunks, received) = mpsc::channel(CHUNK_BUFFER);
+        let parse = tokio::task::spawn_blocking(move || {
+            let reader = ChunkReader {
+                chunks: received,
+                chunk: Bytes::new(),
+            };
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            Envelope { max_accounts }.deserialize(&mut deserializer)
+        });
+
+        let read = async move {
+            while let Some(chunk) = response.chunk().await? {
+                // the parser gave up, dropping the response closes the connection
+                if chunks.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+            Ok::<_, reqwest::Error>(())
+        };
+
+        let (read, parsed) = tokio::join!(read, parse);
+        let parsed = parsed.map_err(|err| ProgramAccountsError::Upstream(err.to_string()))?;
+        match parsed {
+            Ok(response) => response,
+            Err(err) if err.to_string().starts_with(TOO_MANY_ACCOUNTS) => {
+                Err(ProgramAccountsError::TooMany(max_accounts))
+            }
+            Err(err) => {
+                read.map_err(upstream_error)?;
+                Err(ProgramAccountsError::Upstream(format!(
+                    "Malformed getProgramAccounts response: {err}"
+                )))
+            }
+        }
+    }
+}
+
+/// Error message the parser stops with once over the limit
+const TOO_MANY_ACCOUNTS: &str = "too many accounts";
+
+/// Blocking reader over the chunks of a response read on the runtime
+struct ChunkReader {
+    chunks: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.chunk.has_remaining() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.remaining());
+        self.chunk.copy_to_slice(&mut buf[..len]);
+        Ok(len)
+    }
+}
+
+/// JSON-RPC response envelope, the upstream's error if it answered with one
+struct Envelope {
+    max_accounts: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for Envelope {
+    type Value = Result<RpcResponse<Vec<RpcKeyedAccount>>, ProgramAccountsError>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Envelope {
+    type Value = Result<RpcResponse<Vec<RpcKeyedAccount>>, ProgramAccountsError>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON-RPC response")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut result = None;
+        let mut error = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "result" => {
+                    result = Some(map.next_value_seed(ContextAccounts {
+                        max_accounts: self.max_accounts,
+                    })?)
+                }
+                "error" => error = Some(map.next_value::<Value>()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(match (result, error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(ProgramAccountsError::Upstream(
+                error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map_or_else(|| error.to_string(), String::from),
+            )),
+            (None, None) => Err(ProgramAccountsError::Upstream(String::from(
+                "Upstream answered without a result",
+            ))),
+        })
+    }
+}
+
+/// Result of a call asking for the context
+struct ContextAccounts {
+    max_accounts: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for ContextAccounts {
+    type Value = RpcResponse<Vec<RpcKeyedAccount>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ContextAccounts {
+    type Value = RpcResponse<Vec<RpcKeyedAccount>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("accounts with their context")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut context = None;
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "context" => context = Some(map.next_value::<RpcResponseContext>()?),
+                "value" => {
+                    value = Some(map.next_value_seed(BoundedAccounts {
+                        max_accounts: self.max_accounts,
+                    })?)
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(RpcResponse {
+            context: context.ok_or_else(|| de::Error::missing_field("context"))?,
+            value: value.ok_or_else(|| de::Error::missing_field("value"))?,
+        })
+    }
+}
+
+/// Accounts, failing as soon as there are more than `max_accounts`
+struct BoundedAccounts {
+    max_accounts: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for BoundedAccounts {
+    type Value = Vec<RpcKeyedAccount>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BoundedAccounts {
+    type Value = Vec<RpcKeyedAccount>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "at most {} accounts", self.max_accounts)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut accounts = Vec::new();
+        while let Some(account) = seq.next_element::<RpcKeyedAccount>()? {
+            if accounts.len() == self.max_accounts {
+                return Err(de::Error::custom(TOO_MANY_ACCOUNTS));
+            }
+            accounts.push(account);
+        }
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(
+        body: &str,
+        max_accounts: usize,
+    ) -> serde_json::Result<Result<RpcResponse<Vec<RpcKeyedAccount>>, ProgramAccountsError>> {
+        Envelope { max_accounts }.deserialize(&mut serde_json::Deserializer::from_str(body))
+    }
+
+    const ACCOUNT: &str = r#"{"pubkey":"11111111111111111111111111111111","account":{"lamports":1,"data":["","base64"],"owner":"11111111111111111111111111111111","executable":false,"rentEpoch":0}}"#;
+
+    #[test]
+    fn parses_accounts_with_their_context() {
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","result":{{"context":{{"slot":5}},"value":[{ACCOUNT},{ACCOUNT}]}},"id":1}}"#
+        );
+        let response = parse(&body, 2).unwrap().unwrap();
+        assert_eq!(response.context.slot, 5);
+        assert_eq!(response.value.len(), 2);
+    }
+
+    #[test]
+    fn stops_past_the_limit() {
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","result":{{"context":{{"slot":5}},"value":[{ACCOUNT},{ACCOUNT},{ACCOUNT}"#
+        );
+        let err = parse(&body, 2).unwrap_err();
+        assert!(err.to_string().starts_with(TOO_MANY_ACCOUNTS));
+    }
+
+    #[test]
+    fn passes_on_upstream_errors() {
+        let body = r#"{"jsonrpc":"2.0","error":{"code":-32010,"message":"excluded from account secondary indexes"},"id":1}"#;
+        assert!(matches!(
+            parse(body, 2).unwrap(),
+            Err(ProgramAccountsError::Upstream(message)) if message.contains("secondary indexes")
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, UPGRADE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Client, Request, Response, StatusCode, Uri,
+};
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, ToSocketAddrs},
+    task::JoinHandle,
+};
+
+/// Pause after a failed accept, e.g. out of file descriptors, before the next one
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Forwards http requests and websocket upgrades to a server on loopback, appending the
+/// address of the peer to the client address header, which the server can't see
+#[derive(Clone)]
+pub struct PeerProxy {
+    upstream: SocketAddr,
+    client_ip_header: HeaderName,
+    client: Client<HttpConnector>,
+}
+
+impl PeerProxy {
+    pub fn new(upstream: SocketAddr, client_ip_header: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            upstream,
+            client_ip_header: HeaderName::from_bytes(client_ip_header.as_bytes())?,
+            client: Client::new(),
+        })
+    }
+
+    /// Accepts plaintext connections on `addr` and serves their requests
+    pub fn listen(
+        self,
+        addr: impl ToSocketAddrs + Send + 'static,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(addr).await?;
+            info!("Serving {} for {}", listener.local_addr()?, self.upstream);
+
+            accept_loop(listener, |stream, peer| {
+                let proxy = self.clone();
+                async move { proxy.serve_connection(stream, peer).await }
+            })
+            .await
+        })
+    }
+
+    /// Serves the requests of a connection accepted from `peer`
+    pub async fn serve_connection<S>(&self, stream: S, peer: SocketAddr)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let proxy = self.clone();
+        let service = service_fn(move |request| {
+            let proxy = proxy.clone();
+            async move { Ok::<_, Infallible>(proxy.forward(request, peer).await) }
+        });
+
+        if let Err(err) = Http::new()
+            .http1_only(true)
+            .serve_connection(stream, service)
+            .with_upgrades()
+            .await
+        {
+            warn!("Connection from {peer} failed: {err}");
+        }
+    }
+
+    async fn forward(&self, mut request: Request<Body>, peer: SocketAddr) -> Response<Body> {
+        let forwarded = match request.headers().get(&self.client_ip_header) {
+            Some(value) => format!("{}, {}", value.to_str().unwrap_or_default(), peer.ip()),
+            None => peer.ip().to_string(),
+        };
+        let Ok(forwarded) = HeaderValue::from_str(&forwarded) else {
+            return bad_gateway();
+        };
+        request
+            .headers_mut()
+            .insert(self.client_ip_header.clone(), forwarded);
+
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        let Ok(uri) = format!("http://{}{path}", self.upstream).parse::<Uri>() else {
+            return bad_gateway();
+        };
+        *request.uri_mut() = uri;
+
+        let upgrade = request
+            .headers()
+            .contains_key(UPGRADE)
+            .then(|| hyper::upgrade::on(&mut request));
+        let mut response = match self.client.request(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Couldn't reach {} for {peer}: {err}", self.upstream);
+                return bad_gateway();
+            }
+        };
+
+        if let Some(upgrade) = upgrade {
+            if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+                let upstream_upgrade = hyper::upgrade::on(&mut response);
+                tokio::spawn(async move {
+                    let (mut client, mut upstream) = match (upgrade.await, upstream_upgrade.await) {
+                        (Ok(client), Ok(upstream)) => (client, upstream),
+                        (Err(err), _) | (_, Err(err)) => {
+                            warn!("Upgrade of the connection from {peer} failed: {err}");
+                            return;
+                        }
+                    };
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        }
+        response
+    }
+}
+
+/// Accepts connections on `listener` and hands each to `serve` along with its peer,
+/// failed accepts are logged and the listener keeps going
+pub async fn accept_loop<F, Fut>(listener: TcpListener, serve: F) -> anyhow::Result<()>
+where
+    F: Fn(tokio::net::TcpStream, SocketAddr) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(serve(stream, peer));
+            }
+            Err(err) => {
+                warn!("Couldn't accept a connection: {err}");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+fn bad_gateway() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::BAD_GATEWAY;
+    response
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use hyper::{Body, Request, Response, StatusCode};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use tokio::{task::JoinHandle, time::Instant};
+use tower::{Layer, Service};
+
+use crate::rpc_wrapper::{
+    caller::{Caller, CallerLayer},
+    configs::{AccessConfig, Quota},
+    middleware::{error_response, parse_calls, take_ready, JsonRpcCalls},
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_RATE_LIMITED: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_rpc_rate_limited", "Number of requests rejected for exceeding the quota of a method"), &["method"]).unwrap();
+    static ref RPC_CLIENT_RATE_LIMITED: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_rpc_client_rate_limited", "Number of requests rejected for exceeding the quota of their client"), &["by"]).unwrap();
+}
+
+/// Error code of calls over their method's quota
+pub const RATE_LIMITED_CODE: i64 = -32005;
+
+/// How often the buckets of clients gone quiet are dropped
+const CLIENT_EVICTION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Token bucket refilling `rate` calls per second up to `burst`
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(quota: &Quota) -> Self {
+        let burst = quota.burst.unwrap_or(quota.requests_per_second).max(1) as f64;
+        Self {
+            rate: quota.requests_per_second as f64,
+            burst,
+            tokens: burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+
+    fn has(&mut self, calls: usize) -> bool {
+        self.refill();
+        self.tokens >= calls as f64
+    }
+
+    fn try_take(&mut self, calls: usize) -> bool {
+        if !self.has(calls) {
+            return false;
+        }
+        self.tokens -= calls as f64;
+        true
+    }
+
+    /// Gives back tokens taken for calls that were refused after all
+    fn refund(&mut self, calls: usize) {
+        self.tokens = (self.tokens + calls as f64).min(self.burst);
+    }
+
+    /// Whether a bucket left alone would be full again, so dropping it changes nothing
+    fn is_full(&self) -> bool {
+        self.tokens + self.refilled.elapsed().as_secs_f64() * self.rate >= self.burst
+    }
+}
+
+/// Quotas of the limited methods over all clients, shared by every transport
+#[derive(Clone, Default)]
+pub struct MethodRateLimits {
+    buckets: Arc<HashMap<String, Mutex<Bucket>>>,
+}
+
+impl MethodRateLimits {
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            buckets: Arc::new(
+                config
+                    .rate_limits
+                    .iter()
+                    .map(|limit| (limit.method.clone(), Mutex::new(Bucket::new(&limit.quota))))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Takes `calls` tokens from the method's bucket, false if it's out of them.
+    /// Methods without a quota are unlimited
+    pub fn try_take(&self, method: &str, calls: usize) -> bool {
+        let Some(bucket) = self.buckets.get(method) else {
+            return true;
+        };
+        if bucket.lock().unwrap().try_take(calls) {
+            return true;
+        }
+        RPC_RATE_LIMITED.with_label_values(&[method]).inc();
+        false
+    }
+
+    /// Takes the calls of every method from its bucket, or none of them if a method
+    /// is out of tokens, which is then returned
+    pub fn try_take_all<'a>(&self, calls: &BTreeMap<&'a str, usize>) -> Result<(), &'a str> {
+        // locked in method order, so concurrent batches can't deadlock
+        let mut buckets: Vec<_> = calls
+            .iter()
+            .filter_map(|(method, calls)| {
+                let bucket = self.buckets.get(*method)?;
+                Some((*method, *calls, bucket.lock().unwrap()))
+            })
+            .collect();
+
+        for (method, calls, bucket) in &mut buckets {
+            if !bucket.has(*calls) {
+                RPC_RATE_LIMITED.with_label_values(&[*method]).inc();
+                return Err(*method);
+            }
+        }
+        for (_, calls, bucket) in &mut buckets {
+            bucket.tokens -= *calls as f64;
+        }
+        Ok(())
+    }
+
+    /// Gives back the tokens of calls refused after they were taken
+    pub fn refund(&self, method: &str, calls: usize) {
+        if let Some(bucket) = self.buckets.get(method) {
+            bucket.lock().unwrap().refund(calls);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// Quotas of each client, told apart by configured api key or else by address, shared
+/// by every transport
+#[derive(Clone, Default)]
+pub struct ClientRateLimits {
+    quota: Option<Quota>,
+    clients: Arc<DashMap<String, Bucket>>,
+}
+
+impl ClientRateLimits {
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            quota: config.client_rate_limit.clone(),
+            clients: Default::default(),
+        }
+    }
+
+    /// Takes `calls` tokens from the bucket of `caller`, false if it's out of them.
+    /// Callers whose address can't be told are refused
+    pub fn try_take(&self, caller: &Caller, calls: usize) -> bool {
+        let Some(quota) = &self.quota else {
+            return true;
+        };
+
+        let (by, client) = match (&caller.api_key, caller.ip) {
+            (Some(api_key), _) => ("api_key", format!("key:{}", api_key.name)),
+            (None, Some(ip)) => ("ip", format!("ip:{ip}")),
+            (None, None) => {
+                RPC_CLIENT_RATE_LIMITED
+                    .with_label_values(&["unknown"])
+                    .inc();
+                return false;
+            }
+        };
+
+        let allowed = self
+            .clients
+            .entry(client)
+            .or_insert_with(|| Bucket::new(quota))
+            .try_take(calls);
+        if !allowed {
+            RPC_CLIENT_RATE_LIMITED.with_label_values(&[by]).inc();
+        }
+        allowed
+    }
+
+    /// Takes a token from the bucket of the running call's caller. Calls made outside
+    /// of a client's request aren't limited
+    pub fn try_take_current(&self) -> bool {
+        Caller::current().map_or(true, |caller| self.try_take(&caller, 1))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.quota.is_none()
+    }
+
+    /// Drops the buckets of clients gone quiet in the background, a full bucket is
+    /// what a new client gets anyway
+    pub fn evict_idle_clients(&self) -> JoinHandle<anyhow::Result<()>> {
+        let clients = self.clients.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLIENT_EVICTION_INTERVAL);
+            loop {
+                interval.tick().await;
+                clients.retain(|_, bucket| !bucket.is_full());
+            }
+        })
+    }
+}
+
+/// Rejects http requests calling methods forwarded upstream more often than their
+/// quota or their client's allows. Methods served here take their tokens in the rpc
+/// module, so websocket calls count too
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    method_limits: MethodRateLimits,
+    client_limits: ClientRateLimits,
+    /// methods checking their own quotas, skipped here
+    served_methods: Arc<HashSet<String>>,
+    callers: CallerLayer,
+}
+
+impl RateLimitLayer {
+    pub fn new<'a>(
+        config: &AccessConfig,
+        method_limits: MethodRateLimits,
+        client_limits: ClientRateLimits,
+        served_methods: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        Self {
+            method_limits,
+            client_limits,
+            served_methods: Arc::new(served_methods.into_iter().map(String::from).collect()),
+            callers: CallerLayer::new(config),
+        }
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.method_limits.is_empty() && self.client_limits.is_empty()
+    }
+
+    /// Methods of the calls that are forwarded upstream
+    fn forwarded<'a>(&self, calls: &'a JsonRpcCalls) -> Vec<&'a str> {
+        calls
+            .methods()
+            .filter(|method| !self.served_methods.contains(*method))
+            .collect()
+    }
+
+    /// Takes a token per forwarded call from its method's quota and from the caller's,
+    /// or none at all and the reason if either is spent
+    fn exhausted(&self, caller: &Caller, forwarded: &[&str]) -> Option<String> {
+        let mut per_method: BTreeMap<&str, usize> = BTreeMap::new();
+        for method in forwarded {
+            *per_method.entry(method).or_default() += 1;
+        }
+
+        if let Err(method) = self.method_limits.try_take_all(&per_method) {
+            return Some(format!("Too many requests for {method}"));
+        }
+        if !self.client_limits.try_take(caller, forwarded.len()) {
+            for (method, calls) in per_method {
+                self.method_limits.refund(method, calls);
+            }
+            return Some(String::from("Too many requests"));
+        }
+        None
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error> + Send,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if self.layer.is_disabled() {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let mut inner = take_ready(&mut self.inner);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let (request, calls) = parse_calls(request).await?;
+
+            let forwarded = layer.forwarded(&calls);
+            if forwarded.is_empty() {
+                return inner.call(request).await;
+            }
+            let caller = layer.callers.identify(&request);
+            if let Some(message) = layer.exhausted(&caller, &forwarded) {
+                return Ok(rate_limited(&message));
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+fn rate_limited(message: &str) -> Response<Body> {
+    error_response(StatusCode::TOO_MANY_REQUESTS, RATE_LIMITED_CODE, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::rpc_wrapper::configs::{ApiKey, MethodQuota};
+
+    use super::*;
+
+    fn quota(requests_per_second: u32, burst: u32) -> Quota {
+        Quota {
+            requests_per_second,
+            burst: Some(burst),
+        }
+    }
+
+    fn config(rate_limits: &[(&str, Quota)], client_rate_limit: Option<Quota>) -> AccessConfig {
+        AccessConfig {
+            rate_limits: rate_limits
+                .iter()
+                .map(|(method, quota)| MethodQuota {
+                    method: method.to_string(),
+                    quota: quota.clone(),
+                })
+                .collect(),
+            client_rate_limit,
+            ..Default::default()
+        }
+    }
+
+    fn layer(config: &AccessConfig) -> RateLimitLayer {
+        RateLimitLayer::new(
+            config,
+            MethodRateLimits::new(config),
+            ClientRateLimits::new(config),
+            [],
+        )
+    }
+
+    fn from_ip(last: u8) -> Caller {
+        Caller {
+            api_key: None,
+            ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))),
+        }
+    }
+
+    fn with_key(name: &str, last: u8) -> Caller {
+        Caller {
+            api_key: Some(ApiKey {
+                name: name.to_string(),
+                key: format!("{name}-secret"),
+                tx_quota: None,
+                admin: false,
+            }),
+            ..from_ip(last)
+        }
+    }
+
+    #[test]
+    fn buckets_refill_up_to_their_burst() {
+        let mut bucket = Bucket::new(&quota(2, 4));
+        assert!(bucket.try_take(4));
+        assert!(!bucket.try_take(1));
+
+        bucket.refilled -= Duration::from_secs(1);
+        assert!(bucket.try_take(2));
+        assert!(!bucket.try_take(1));
+
+        // a long quiet period refills the burst and no more
+        bucket.refilled -= Duration::from_secs(60);
+        assert!(!bucket.try_take(5));
+        assert!(bucket.try_take(4));
+    }
+
+    #[test]
+    fn refused_batches_take_no_tokens() {
+        let config = config(
+            &[("getBalance", quota(0, 2)), ("getSlot", quota(0, 1))],
+            None,
+        );
+        let layer = layer(&config);
+        let caller = from_ip(1);
+
+        // getSlot is over its quota, so getBalance isn't charged
+        assert_eq!(
+            layer.exhausted(&caller, &["getBalance", "getSlot", "getSlot"]),
+            Some(String::from("Too many requests for getSlot"))
+        );
+        assert_eq!(
+            layer.exhausted(&caller, &["getBalance", "getBalance"]),
+            None
+        );
+        assert_eq!(layer.exhausted(&caller, &["getSlot"]), None);
+    }
+
+    #[test]
+    fn method_limits_are_checked_before_the_client_is_charged() {
+        let config = config(
+            &[("getBalance", quota(0, 1)), ("getSlot", quota(0, 1))],
+            Some(quota(0, 2)),
+        );
+        let layer = layer(&config);
+        let caller = from_ip(2);
+
+        assert_eq!(layer.exhausted(&caller, &["getBalance"]), None);
+        // refused by the method's quota, the client keeps its token
+        assert!(layer.exhausted(&caller, &["getBalance"]).is_some());
+        assert_eq!(layer.exhausted(&caller, &["getSlot"]), None);
+    }
+
+    #[test]
+    fn client_refusals_give_the_method_tokens_back() {
+        let config = config(&[("getSlot", quota(0, 1))], Some(quota(0, 1)));
+        let layer = layer(&config);
+        let caller = from_ip(3);
+
+        assert_eq!(layer.exhausted(&caller, &["getBlockHeight"]), None);
+        assert_eq!(
+            layer.exhausted(&caller, &["getSlot"]),
+            Some(String::from("Too many requests"))
+        );
+        assert_eq!(layer.exhausted(&from_ip(4), &["getSlot"]), None);
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_api_key_then_address() {
+        let limits = ClientRateLimits::new(&config(&[], Some(quota(0, 1))));
+        let throttled = |by: &str| RPC_CLIENT_RATE_LIMITED.with_label_values(&[by]).get();
+        let (by_key, unknown) = (throttled("api_key"), throttled("unknown"));
+
+        // one key from two addresses is one client
+        assert!(limits.try_take(&with_key("alice", 10), 1));
+        assert!(!limits.try_take(&with_key("alice", 11), 1));
+        assert!(limits.try_take(&with_key("bob", 10), 1));
+
+        // without a key each address is its own client
+        assert!(limits.try_take(&from_ip(10), 1));
+        assert!(limits.try_take(&from_ip(11), 1));
+
+        assert!(!limits.try_take(&Caller::default(), 1));
+
+        assert_eq!(throttled("api_key"), by_key + 1);
+        assert_eq!(throttled("unknown"), unknown + 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Request log of the http server. tower-http's `TraceLayer` times the requests and
+//! logs them once answered, `RequestLogLayer` further in tells it what each request
+//! called and who called it
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper::{Body, Request, Response};
+use serde_json::Value;
+use tiny_logger::logs::{info, warn};
+use tower::{Layer, Service};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, ServerErrorsFailureClass, SharedClassifier},
+    trace::{DefaultMakeSpan, OnFailure, OnResponse, TraceLayer},
+};
+use tracing::Span;
+
+use crate::rpc_wrapper::{
+    configs::AccessConfig,
+    ip_filter::ClientIps,
+    middleware::{parse_calls, take_ready, JsonRpcCalls},
+    DEFAULT_RPC_LOG_BODY_SAMPLE_EVERY,
+};
+
+/// Characters of a sampled request body logged at most
+const MAX_LOGGED_BODY_LEN: usize = 1024;
+
+/// Logs the requests tagged by `RequestLogLayer` with their latency and status
+pub type RequestTraceLayer = TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    DefaultMakeSpan,
+    (),
+    LogResponse,
+    (),
+    (),
+    LogFailure,
+>;
+
+pub fn trace_layer() -> RequestTraceLayer {
+    TraceLayer::new_for_http()
+        .on_request(())
+        .on_response(LogResponse)
+        .on_body_chunk(())
+        .on_eos(())
+        .on_failure(LogFailure)
+}
+
+/// What the log line of a request says about it, carried out on its response
+#[derive(Debug, Clone)]
+struct LoggedRequest {
+    /// comma separated for batches
+    methods: String,
+    client: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogResponse;
+
+impl<B> OnResponse<B> for LogResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, _span: &Span) {
+        // requests that aren't calls, like health checks, aren't tagged
+        if let Some(request) = response.extensions().get::<LoggedRequest>() {
+            info!(
+                "rpc {} from {} took {latency:?}: {}",
+                request.methods,
+                request.client,
+                response.status()
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogFailure;
+
+impl OnFailure<ServerErrorsFailureClass> for LogFailure {
+    fn on_failure(&mut self, failure: ServerErrorsFailureClass, latency: Duration, _span: &Span) {
+        // error statuses are logged with their response
+        if let ServerErrorsFailureClass::Error(err) = failure {
+            warn!("rpc failed after {latency:?}: {err}");
+        }
+    }
+}
+
+/// Tags the responses of JSON-RPC requests with their methods and client for the
+/// trace layer, and logs the body of one in `DEFAULT_RPC_LOG_BODY_SAMPLE_EVERY`
+#[derive(Clone)]
+pub struct RequestLogLayer {
+    client_ips: ClientIps,
+    requests: Arc<AtomicU64>,
+}
+
+impl RequestLogLayer {
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            client_ips: ClientIps::new(config),
+            requests: Default::default(),
+        }
+    }
+
+    fn sample_body(&self) -> bool {
+        let request = self.requests.fetch_add(1, Ordering::Relaxed);
+        DEFAULT_RPC_LOG_BODY_SAMPLE_EVERY != 0 && request % DEFAULT_RPC_LOG_BODY_SAMPLE_EVERY == 0
+    }
+}
+
+impl<S> Layer<S> for RequestLogLayer {
+    type Service = RequestLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestLog {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestLog<S> {
+    inner: S,
+    layer: RequestLogLayer,
+}
+
+impl<S> Service<Request<Body>> for RequestLog<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error> + Send,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let mut inner = take_ready(&mut self.inner);
+
+        let client = self
+            .layer
+            .client_ips
+            .client_ip(&request)
+            .map_or_else(|| String::from("unknown"), |ip| ip.to_string());
+        let sample_body = self.layer.sample_body();
+
+        Box::pin(async move {
+            let (request, calls) = parse_calls(request).await?;
+            let methods = methods(&calls);
+            if sample_body && !calls.calls.is_empty() {
+                let logged: String = body(&calls).chars().take(MAX_LOGGED_BODY_LEN).collect();
+                info!("rpc {methods} from {client} body {logged}");
+            }
+
+            let mut response = inner.call(request).await?;
+            response
+                .extensions_mut()
+                .insert(LoggedRequest { methods, client });
+            Ok(response)
+        })
+    }
+}
+
+/// Methods called, comma separated for batches
+fn methods(calls: &JsonRpcCalls) -> String {
+    if calls.calls.is_empty() {
+        return String::from("<malformed>");
+    }
+
+    calls
+        .calls
+        .iter()
+        .map(|call| call.get("method").and_then(Value::as_str).unwrap_or("?"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The calls as they were sent, give or take whitespace
+fn body(calls: &JsonRpcCalls) -> String {
+    match calls.is_batch {
+        true => Value::from(calls.calls.clone()).to_string(),
+        false => calls.calls.iter().map(Value::to_string).collect(),
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    Body, Method, Request, Response, StatusCode, Uri,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tower::{Layer, Service};
+
+/// Prefix of the rest routes, anything else goes to the server untouched
+pub const REST_PREFIX: &str = "/v1/";
+
+/// Query parameters of the rest routes, others like the api key are left to the
+/// layers reading them
+#[derive(Deserialize)]
+struct RestQuery {
+    commitment: Option<String>,
+}
+
+/// Json rpc call a rest route maps onto
+struct RestCall {
+    method: &'static str,
+    params: Value,
+}
+
+impl RestCall {
+    /// `None` if the path isn't a known route
+    fn route(path: &str, query: RestQuery) -> Option<Self> {
+        let config = query
+            .commitment
+            .map(|commitment| json!({ "commitment": commitment }));
+
+        let segments: Vec<&str> = path.strip_prefix(REST_PREFIX)?.split('/').collect();
+        let (method, params) = match segments[..] {
+            ["slot"] => ("getSlot", json!([config])),
+            ["blockhash"] => ("getLatestBlockhash", json!([config])),
+            ["tx", signature] => {
+                let mut tx_config = json!({
+                    "encoding": "json",
+                    "maxSupportedTransactionVersion": 0,
+                });
+                if let Some(Value::Object(config)) = config {
+                    tx_config.as_object_mut()?.extend(config);
+                }
+                ("getTransaction", json!([signature, tx_config]))
+            }
+            ["verify", slot] => ("getSlotVerification", json!([slot.parse::<u64>().ok()?])),
+            _ => return None,
+        };
+
+        Some(Self { method, params })
+    }
+}
+
+/// Serves `GET /v1/slot`, `/v1/blockhash`, `/v1/tx/{signature}` and `/v1/verify/{slot}`
+/// by rewriting them into json rpc calls, replies with the bare result instead of an
+/// envelope. `?commitment=` is passed on as the call's commitment
+#[derive(Clone, Default)]
+pub struct RestLayer;
+
+impl<S> Layer<S> for RestLayer {
+    type Service = Rest<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Rest { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rest<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for Rest<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+    S::Error: From<hyper::Error>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() != Method::GET || !request.uri().path().starts_with(REST_PREFIX) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let query =
+            serde_urlencoded::from_str::<RestQuery>(request.uri().query().unwrap_or_default());
+        let Ok(query) = query else {
+            return Box::pin(async {
+                Ok(reply(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "error": "Invalid query" }),
+                ))
+            });
+        };
+        let Some(call) = RestCall::route(request.uri().path(), query) else {
+            return Box::pin(async {
+                Ok(reply(
+                    StatusCode::NOT_FOUND,
+                    json!({ "error": "Unknown route" }),
+                ))
+            });
+        };
+
+        let (mut parts, _) = request.into_parts();
+        // the query may carry the api key, keep it
+        parts.uri = match parts.uri.query() {
+            Some(query) => format!("/?{query}"),
+            None => String::from("/"),
+        }
+        .parse::<Uri>()
+        .expect("path and query of a valid uri");
+        parts.method = Method::POST;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": call.method,
+            "params": call.params,
+        });
+        let response = self
+            .inner
+            .call(Request::from_parts(parts, Body::from(body.to_string())));
+
+        Box::pin(async move {
+            let response = response.await?;
+            // auth and rate limit rejections are passed on as they are
+            if response.status() != StatusCode::OK {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let envelope = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+
+            let mut response = match (envelope.get("result"), envelope.get("error")) {
+                (Some(Value::Null), _) => {
+                    reply(StatusCode::NOT_FOUND, json!({ "error": "Not found" }))
+                }
+                (Some(result), _) => reply(StatusCode::OK, result.clone()),
+                (None, Some(error)) => reply(
+                    StatusCode::BAD_REQUEST,
+                    json!({
+                        "error": error.get("message").cloned().unwrap_or_default(),
+                        "code": error.get("code").cloned().unwrap_or_default(),
+                    }),
+                ),
+                (None, None) => reply(
+                    StatusCode::BAD_GATEWAY,
+                    json!({ "error": "Malformed rpc response" }),
+                ),
+            };
+            // what the layers before learned about the call, the request log's tag
+            *response.extensions_mut() = parts.extensions;
+            Ok(response)
+        })
+    }
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::poll_fn;
+    use hyper::service::service_fn;
+
+    use super::*;
+
+    /// Answers with the call it got, or `null` for `getTransaction`
+    async fn rpc(request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+        let uri = request.uri().to_string();
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        let call: Value = serde_json::from_slice(&body).unwrap();
+        let result = match call["method"].as_str() {
+            Some("getTransaction") if call["params"][0] == "unknown" => Value::Null,
+            _ => json!({ "uri": uri, "method": call["method"], "params": call["params"] }),
+        };
+        Ok(reply(
+            StatusCode::OK,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": result }),
+        ))
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Value) {
+        let mut service = RestLayer.layer(service_fn(rpc));
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = service.call(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_the_slot() {
+        let (status, call) = get("/v1/slot").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(call["method"], "getSlot");
+        assert_eq!(call["params"], json!([null]));
+
+        // decoded, and the other parameters are passed on
+        let (_, call) = get("/v1/slot?api-key=secret&commitment=%63onfirmed").await;
+        assert_eq!(call["params"], json!([{ "commitment": "confirmed" }]));
+        assert_eq!(call["uri"], "/?api-key=secret&commitment=%63onfirmed");
+    }
+
+    #[tokio::test]
+    async fn serves_the_latest_blockhash() {
+        let (status, call) = get("/v1/blockhash?commitment=finalized").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(call["method"], "getLatestBlockhash");
+        assert_eq!(call["params"], json!([{ "commitment": "finalized" }]));
+    }
+
+    #[tokio::test]
+    async fn serves_transactions() {
+        let (status, call) = get("/v1/tx/5sig?commitment=confirmed").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(call["method"], "getTransaction");
+        assert_eq!(
+            call["params"],
+            json!([
+                "5sig",
+                {
+                    "encoding": "json",
+                    "maxSupportedTransactionVersion": 0,
+                    "commitment": "confirmed",
+                },
+            ])
+        );
+
+        let (status, _) = get("/v1/tx/unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn serves_slot_verifications() {
+        let (status, call) = get("/v1/verify/42").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(call["method"], "getSlotVerification");
+        assert_eq!(call["params"], json!([42]));
+
+        let (status, _) = get("/v1/verify/latest").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn refuses_unknown_routes_and_queries() {
+        let (status, _) = get("/v1/blocks").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get("/v1/slot?commitment=confirmed&commitment=finalized").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
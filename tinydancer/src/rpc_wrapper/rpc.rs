@@ -1,17 +1,94 @@
 use jsonrpsee::proc_macros::rpc;
+use solana_account_decoder::{parse_token::UiTokenAmount, UiAccount};
 use solana_rpc_client_api::config::{
-    RpcContextConfig, RpcRequestAirdropConfig, RpcSignatureStatusConfig,
+    RpcAccountInfoConfig, RpcBlockConfig, RpcBlockSubscribeConfig, RpcBlockSubscribeFilter,
+    RpcContextConfig, RpcGetVoteAccountsConfig, RpcLeaderScheduleConfig, RpcProgramAccountsConfig,
+    RpcRequestAirdropConfig, RpcSignatureStatusConfig, RpcSignatureSubscribeConfig,
+    RpcSignaturesForAddressConfig, RpcTokenAccountsFilter, RpcTransactionConfig,
+    RpcTransactionLogsConfig, RpcTransactionLogsFilter,
 };
-use solana_rpc_client_api::response::{Response as RpcResponse, RpcBlockhash, RpcVersionInfo};
-use solana_sdk::commitment_config::CommitmentConfig;
-use solana_transaction_status::TransactionStatus;
+use solana_rpc_client_api::response::{
+    Response as RpcResponse, RpcBlockhash, RpcConfirmedTransactionStatusWithSignature,
+    RpcContactInfo, RpcKeyedAccount, RpcLeaderSchedule, RpcLogsResponse, RpcPrioritizationFee,
+    RpcVoteAccountStatus, SlotUpdate,
+};
+use solana_sdk::{
+    clock::Slot, commitment_config::CommitmentConfig, epoch_info::EpochInfo,
+    epoch_schedule::EpochSchedule,
+};
+use solana_transaction_status::{TransactionStatus, UiConfirmedBlock};
 
-use crate::rpc_wrapper::configs::{IsBlockHashValidConfig, SendTransactionConfig};
+use crate::{
+    rpc_wrapper::{
+        configs::{IsBlockHashValidConfig, PriorityFeeEstimateConfig, SendTransactionConfig},
+        prioritization_fees::PriorityFeeLevels,
+        workers::VerifiedBlockUpdate,
+    },
+    sampler::SlotSampleRecord,
+};
 
-use super::bridge::LiteResponse;
+use super::bridge::{LiteOptionalContext, LiteResponse, LiteVersionInfo, VerifiedTransaction};
 
 pub type Result<T> = std::result::Result<T, jsonrpsee::core::Error>;
 
+/// Defines `method` with a const per name and `ALL` of them
+macro_rules! method_names {
+    ($($name:ident = $method:literal,)*) => {
+        /// Names of the methods and subscriptions served, for the limits keyed by them
+        pub mod method {
+            $(pub const $name: &str = $method;)*
+
+            pub const ALL: &[&str] = &[$($name),*];
+        }
+    };
+}
+
+// kept in step with the names of `LiteRpc` by the test below
+method_names! {
+    SEND_TRANSACTION = "sendTransaction",
+    DROP_TRANSACTION = "dropTransaction",
+    REPLACE_TRANSACTION = "replaceTransaction",
+    SEND_BUNDLE = "sendBundle",
+    GET_LATEST_BLOCKHASH = "getLatestBlockhash",
+    IS_BLOCKHASH_VALID = "isBlockhashValid",
+    GET_SLOT = "getSlot",
+    GET_EPOCH_INFO = "getEpochInfo",
+    GET_EPOCH_SCHEDULE = "getEpochSchedule",
+    GET_BLOCK = "getBlock",
+    GET_BLOCKS = "getBlocks",
+    GET_BLOCKS_WITH_LIMIT = "getBlocksWithLimit",
+    GET_TRANSACTION = "getTransaction",
+    GET_ACCOUNT_INFO = "getAccountInfo",
+    GET_BALANCE = "getBalance",
+    GET_MULTIPLE_ACCOUNTS = "getMultipleAccounts",
+    GET_PROGRAM_ACCOUNTS = "getProgramAccounts",
+    GET_HEALTH = "getHealth",
+    GET_RECENT_PRIORITIZATION_FEES = "getRecentPrioritizationFees",
+    GET_PRIORITY_FEE_ESTIMATE = "getPriorityFeeEstimate",
+    GET_FEE_FOR_MESSAGE = "getFeeForMessage",
+    GET_TOKEN_ACCOUNT_BALANCE = "getTokenAccountBalance",
+    GET_TOKEN_ACCOUNTS_BY_OWNER = "getTokenAccountsByOwner",
+    GET_TOKEN_SUPPLY = "getTokenSupply",
+    MINIMUM_LEDGER_SLOT = "minimumLedgerSlot",
+    GET_FIRST_AVAILABLE_BLOCK = "getFirstAvailableBlock",
+    GET_CLUSTER_NODES = "getClusterNodes",
+    GET_VOTE_ACCOUNTS = "getVoteAccounts",
+    GET_LEADER_SCHEDULE = "getLeaderSchedule",
+    GET_BLOCK_TIME = "getBlockTime",
+    GET_SIGNATURES_FOR_ADDRESS = "getSignaturesForAddress",
+    GET_SLOT_LEADERS = "getSlotLeaders",
+    GET_SLOT_VERIFICATION = "getSlotVerification",
+    GET_SIGNATURE_STATUSES = "getSignatureStatuses",
+    GET_VERSION = "getVersion",
+    REQUEST_AIRDROP = "requestAirdrop",
+    SIGNATURE_SUBSCRIBE = "signatureSubscribe",
+    ACCOUNT_SUBSCRIBE = "accountSubscribe",
+    LOGS_SUBSCRIBE = "logsSubscribe",
+    BLOCK_SUBSCRIBE = "blockSubscribe",
+    PROGRAM_SUBSCRIBE = "programSubscribe",
+    SLOTS_UPDATES_SUBSCRIBE = "slotsUpdatesSubscribe",
+}
+
 #[rpc(server)]
 pub trait LiteRpc {
     #[method(name = "sendTransaction")]
@@ -21,6 +98,23 @@ pub trait LiteRpc {
         send_transaction_config: Option<SendTransactionConfig>,
     ) -> Result<String>;
 
+    /// Drops a queued transaction that wasn't sent yet, false if it was
+    #[method(name = "dropTransaction")]
+    async fn drop_transaction(&self, signature: String) -> Result<bool>;
+
+    /// Queues a re-signed, higher priority fee version of a transaction that wasn't
+    /// sent yet in its place
+    #[method(name = "replaceTransaction")]
+    async fn replace_transaction(
+        &self,
+        signature: String,
+        tx: String,
+        send_transaction_config: Option<SendTransactionConfig>,
+    ) -> Result<String>;
+
+    #[method(name = "sendBundle")]
+    async fn send_bundle(&self, txs: Vec<String>) -> Result<String>;
+
     #[method(name = "getLatestBlockhash")]
     async fn get_latest_blockhash(
         &self,
@@ -34,6 +128,156 @@ pub trait LiteRpc {
         config: Option<IsBlockHashValidConfig>,
     ) -> Result<RpcResponse<bool>>;
 
+    #[method(name = "getSlot")]
+    async fn get_slot(&self, config: Option<RpcContextConfig>) -> Result<Slot>;
+
+    #[method(name = "getEpochInfo")]
+    async fn get_epoch_info(&self, config: Option<RpcContextConfig>) -> Result<EpochInfo>;
+
+    #[method(name = "getEpochSchedule")]
+    fn get_epoch_schedule(&self) -> Result<EpochSchedule>;
+
+    #[method(name = "getBlock")]
+    async fn get_block(
+        &self,
+        slot: Slot,
+        config: Option<RpcBlockConfig>,
+    ) -> Result<Option<LiteResponse<UiConfirmedBlock>>>;
+
+    #[method(name = "getBlocks")]
+    async fn get_blocks(
+        &self,
+        start_slot: Slot,
+        end_slot: Option<Slot>,
+        commitment: Option<CommitmentConfig>,
+    ) -> Result<Vec<Slot>>;
+
+    #[method(name = "getBlocksWithLimit")]
+    async fn get_blocks_with_limit(
+        &self,
+        start_slot: Slot,
+        limit: usize,
+        commitment: Option<CommitmentConfig>,
+    ) -> Result<Vec<Slot>>;
+
+    #[method(name = "getTransaction")]
+    async fn get_transaction(
+        &self,
+        signature_str: String,
+        config: Option<RpcTransactionConfig>,
+    ) -> Result<Option<LiteResponse<VerifiedTransaction>>>;
+
+    #[method(name = "getAccountInfo")]
+    async fn get_account_info(
+        &self,
+        pubkey_str: String,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> Result<LiteResponse<Option<UiAccount>>>;
+
+    #[method(name = "getBalance")]
+    async fn get_balance(
+        &self,
+        pubkey_str: String,
+        config: Option<RpcContextConfig>,
+    ) -> Result<LiteResponse<u64>>;
+
+    #[method(name = "getMultipleAccounts")]
+    async fn get_multiple_accounts(
+        &self,
+        pubkey_strs: Vec<String>,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> Result<LiteResponse<Vec<Option<UiAccount>>>>;
+
+    #[method(name = "getProgramAccounts")]
+    async fn get_program_accounts(
+        &self,
+        program_id_str: String,
+        config: Option<RpcProgramAccountsConfig>,
+    ) -> Result<LiteOptionalContext<Vec<RpcKeyedAccount>>>;
+
+    #[method(name = "getHealth")]
+    async fn get_health(&self) -> Result<String>;
+
+    #[method(name = "getRecentPrioritizationFees")]
+    async fn get_recent_prioritization_fees(
+        &self,
+        pubkey_strs: Option<Vec<String>>,
+    ) -> Result<Vec<RpcPrioritizationFee>>;
+
+    #[method(name = "getPriorityFeeEstimate")]
+    async fn get_priority_fee_estimate(
+        &self,
+        config: Option<PriorityFeeEstimateConfig>,
+    ) -> Result<PriorityFeeLevels>;
+
+    #[method(name = "getFeeForMessage")]
+    async fn get_fee_for_message(
+        &self,
+        message: String,
+        config: Option<RpcContextConfig>,
+    ) -> Result<LiteResponse<Option<u64>>>;
+
+    #[method(name = "getTokenAccountBalance")]
+    async fn get_token_account_balance(
+        &self,
+        pubkey_str: String,
+        commitment: Option<CommitmentConfig>,
+    ) -> Result<LiteResponse<UiTokenAmount>>;
+
+    #[method(name = "getTokenAccountsByOwner")]
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner_str: String,
+        token_account_filter: RpcTokenAccountsFilter,
+        config: Option<RpcAccountInfoConfig>,
+    ) -> Result<LiteResponse<Vec<RpcKeyedAccount>>>;
+
+    #[method(name = "getTokenSupply")]
+    async fn get_token_supply(
+        &self,
+        mint_str: String,
+        commitment: Option<CommitmentConfig>,
+    ) -> Result<LiteResponse<UiTokenAmount>>;
+
+    #[method(name = "minimumLedgerSlot")]
+    async fn minimum_ledger_slot(&self) -> Result<Slot>;
+
+    #[method(name = "getFirstAvailableBlock")]
+    async fn get_first_available_block(&self) -> Result<Slot>;
+
+    #[method(name = "getClusterNodes")]
+    async fn get_cluster_nodes(&self) -> Result<Vec<RpcContactInfo>>;
+
+    #[method(name = "getVoteAccounts")]
+    async fn get_vote_accounts(
+        &self,
+        config: Option<RpcGetVoteAccountsConfig>,
+    ) -> Result<RpcVoteAccountStatus>;
+
+    #[method(name = "getLeaderSchedule")]
+    async fn get_leader_schedule(
+        &self,
+        slot: Option<Slot>,
+        config: Option<RpcLeaderScheduleConfig>,
+    ) -> Result<Option<RpcLeaderSchedule>>;
+
+    #[method(name = "getBlockTime")]
+    async fn get_block_time(&self, slot: Slot) -> Result<i64>;
+
+    #[method(name = "getSignaturesForAddress")]
+    async fn get_signatures_for_address(
+        &self,
+        address: String,
+        config: Option<RpcSignaturesForAddressConfig>,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>>;
+
+    #[method(name = "getSlotLeaders")]
+    async fn get_slot_leaders(&self, start_slot: Slot, limit: u64) -> Result<Vec<String>>;
+
+    /// The sampler's record of a slot, `None` if it wasn't sampled
+    #[method(name = "getSlotVerification")]
+    async fn get_slot_verification(&self, slot: Slot) -> Result<Option<SlotSampleRecord>>;
+
     #[method(name = "getSignatureStatuses")]
     async fn get_signature_statuses(
         &self,
@@ -42,7 +286,7 @@ pub trait LiteRpc {
     ) -> Result<LiteResponse<Vec<Option<TransactionStatus>>>>;
 
     #[method(name = "getVersion")]
-    fn get_version(&self) -> Result<RpcVersionInfo>;
+    fn get_version(&self) -> Result<LiteVersionInfo>;
 
     #[method(name = "requestAirdrop")]
     async fn request_airdrop(
@@ -53,5 +297,49 @@ pub trait LiteRpc {
     ) -> Result<String>;
 
     #[subscription(name = "signatureSubscribe" => "signatureNotification", unsubscribe="signatureUnsubscribe", item=RpcResponse<serde_json::Value>)]
-    fn signature_subscribe(&self, signature: String, commitment_config: CommitmentConfig);
+    /// Notifies once the signature reaches the commitment, the deployment's terminal
+    /// confirmation status if unset. With `enableReceivedNotification` the subscriber
+    /// is told first when the transaction went out to the tpu
+    fn signature_subscribe(&self, signature: String, config: Option<RpcSignatureSubscribeConfig>);
+
+    #[subscription(name = "accountSubscribe" => "accountNotification", unsubscribe="accountUnsubscribe", item=RpcResponse<UiAccount>)]
+    fn account_subscribe(&self, pubkey: String, config: Option<RpcAccountInfoConfig>);
+
+    #[subscription(name = "logsSubscribe" => "logsNotification", unsubscribe="logsUnsubscribe", item=RpcResponse<RpcLogsResponse>)]
+    fn logs_subscribe(
+        &self,
+        filter: RpcTransactionLogsFilter,
+        config: Option<RpcTransactionLogsConfig>,
+    );
+
+    #[subscription(name = "blockSubscribe" => "blockNotification", unsubscribe="blockUnsubscribe", item=RpcResponse<VerifiedBlockUpdate>)]
+    fn block_subscribe(
+        &self,
+        filter: RpcBlockSubscribeFilter,
+        config: Option<RpcBlockSubscribeConfig>,
+    );
+
+    #[subscription(name = "programSubscribe" => "programNotification", unsubscribe="programUnsubscribe", item=RpcResponse<RpcKeyedAccount>)]
+    fn program_subscribe(&self, program_id_str: String, config: Option<RpcProgramAccountsConfig>);
+
+    #[subscription(name = "slotsUpdatesSubscribe" => "slotsUpdatesNotification", unsubscribe="slotsUpdatesUnsubscribe", item=SlotUpdate)]
+    fn slots_updates_subscribe(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn method_names_match_the_served_ones() {
+        let served: HashSet<&str> = include_str!("rpc.rs")
+            .split("(name = \"")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        assert_eq!(served.len(), method::ALL.len());
+        assert_eq!(served, method::ALL.iter().copied().collect());
+    }
 }
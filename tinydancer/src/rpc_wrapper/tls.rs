@@ -0,0 +1,159 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+use crate::rpc_wrapper::{
+    configs::TlsConfig,
+    proxy::{accept_loop, PeerProxy},
+};
+
+/// How often the certificate and key files are checked for changes
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// Time a client has to complete the tls handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Terminates tls on a public address and forwards the decrypted requests to a
+/// plaintext server listening on loopback, with the client's address
+#[derive(Clone)]
+pub struct TlsTerminator {
+    config: TlsConfig,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl TlsTerminator {
+    pub fn new(config: &TlsConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            acceptor: Arc::new(RwLock::new(load_acceptor(config)?)),
+            config: config.clone(),
+        })
+    }
+
+    /// Accepts tls connections on `addr`, forwarding their requests to the server at
+    /// `upstream` with the peer appended to `client_ip_header`
+    pub fn serve(
+        &self,
+        addr: impl ToSocketAddrs + Send + 'static,
+        upstream: SocketAddr,
+        client_ip_header: &str,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        let acceptor = self.acceptor.clone();
+        let proxy = PeerProxy::new(upstream, client_ip_header);
+
+        tokio::spawn(async move {
+            let proxy = proxy?;
+            let listener = TcpListener::bind(addr).await?;
+            info!(
+                "Terminating tls at {} for {upstream}",
+                listener.local_addr()?
+            );
+
+            accept_loop(listener, |stream, peer| {
+                let acceptor = acceptor.read().unwrap().clone();
+                let proxy = proxy.clone();
+                async move {
+                    let stream =
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(err)) => {
+                                warn!("Tls handshake with {peer} failed: {err}");
+                                return;
+                            }
+                            Err(_) => {
+                                warn!("Tls handshake with {peer} timed out");
+                                return;
+                            }
+                        };
+                    proxy.serve_connection(stream, peer).await;
+                }
+            })
+            .await
+        })
+    }
+
+    /// Swaps in the certificate and key whenever their files change, a rotation that
+    /// fails to load keeps the previous ones
+    pub fn watch(&self) -> JoinHandle<anyhow::Result<()>> {
+        let terminator = self.clone();
+
+        tokio::spawn(async move {
+            let mut modified = terminator.modified();
+            let mut reload = tokio::time::interval(TLS_RELOAD_INTERVAL);
+
+            loop {
+                reload.tick().await;
+
+                let current = terminator.modified();
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+
+                match load_acceptor(&terminator.config) {
+                    Ok(acceptor) => {
+                        *terminator.acceptor.write().unwrap() = acceptor;
+                        info!("Reloaded tls certificate {}", terminator.config.cert_path);
+                    }
+                    Err(err) => warn!("Couldn't reload tls certificate: {err:#}"),
+                }
+            }
+        })
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &str| std::fs::metadata(path).and_then(|meta| meta.modified());
+        Some((
+            modified(&self.config.cert_path).ok()?,
+            modified(&self.config.key_path).ok()?,
+        ))
+    }
+}
+
+fn load_acceptor(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let mut certs = BufReader::new(
+        File::open(&config.cert_path).with_context(|| format!("opening {}", config.cert_path))?,
+    );
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut certs)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        bail!("no certificate in {}", config.cert_path);
+    }
+
+    let mut keys = BufReader::new(
+        File::open(&config.key_path).with_context(|| format!("opening {}", config.key_path))?,
+    );
+    let key = rustls_pemfile::read_all(&mut keys)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("no private key in {}", config.key_path))?;
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
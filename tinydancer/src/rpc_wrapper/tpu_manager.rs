@@ -1,50 +1,204 @@
 use std::{
-    net::{IpAddr, Ipv4Addr},
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicU32, AtomicU64, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
-use prometheus::{opts, register_int_counter, IntCounter};
+use dashmap::DashMap;
+use itertools::Itertools;
+
+use prometheus::{
+    histogram_opts, opts, register_histogram, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Histogram, IntCounter, IntCounterVec, IntGauge,
+};
 use solana_quic_client::{QuicConfig, QuicPool};
 use solana_rpc_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::signature::Keypair;
+use solana_sdk::{
+    clock::NUM_CONSECUTIVE_LEADER_SLOTS,
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
 use solana_tpu_client::{
-    nonblocking::tpu_client::TpuClient,
+    nonblocking::{tpu_client::TpuClient, tpu_connection::TpuConnection},
     tpu_client::TpuClientConfig,
     tpu_connection_cache::{NewTpuConfig, TpuConnectionCache},
 };
-use tiny_logger::logs::info;
-use tokio::sync::RwLock;
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    net::UdpSocket,
+    sync::{RwLock, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::{
+    leader_schedule::LeaderScheduleCache,
+    rpc_wrapper::{
+        configs::TpuConfig, congestion::CongestionMonitor, read_keypair, upstreams::Upstreams,
+    },
+};
 
 pub type QuicTpuClient = TpuClient<QuicPool>;
 pub type QuicConnectionCache = TpuConnectionCache<QuicPool>;
 
-const TPU_CONNECTION_CACHE_SIZE: usize = 8;
+/// consecutive send errors after which the tpu client is reset
+const TPU_ERRORS_BEFORE_RESET: u32 = 5;
+/// consecutive failed quic sends to a leader after which it's sent to over udp
+const QUIC_FAILURES_BEFORE_UDP: u32 = 3;
+/// how long a leader is sent to over udp before quic is tried again
+const UDP_FALLBACK_PERIOD: Duration = Duration::from_secs(30);
+/// age of the last send over a leader's connection after which it's warmed up again
+/// before the leader's slots, idle quic connections may have been closed meanwhile
+const STALE_CONNECTION_AGE: Duration = Duration::from_secs(5);
+/// how often the identity keypair file is checked for changes
+const IDENTITY_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// how often the estimated slot is compared to the slot of the upstream
+const SLOT_SKEW_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 lazy_static::lazy_static! {
 static ref TPU_CONNECTION_RESET: IntCounter =
     register_int_counter!(opts!("literpc_tpu_connection_reset", "Number of times tpu connection was reseted")).unwrap();
+static ref TPU_IDENTITY_STAKE: IntGauge =
+    register_int_gauge!(opts!("literpc_tpu_identity_stake", "Lamports activated on the identity of the tpu client's quic certificate")).unwrap();
+static ref TPU_LEADERS_TARGETED: IntGauge =
+    register_int_gauge!(opts!("literpc_tpu_leaders_targeted", "Leader tpus transactions are currently sent to")).unwrap();
+static ref TPU_LEADERS_ON_UDP: IntGauge =
+    register_int_gauge!(opts!("literpc_tpu_leaders_on_udp", "Targeted leader tpus sent to over udp as quic kept failing")).unwrap();
+static ref TPU_BATCHES_QUIC: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_batches_quic", "Batches sent to a leader tpu over quic")).unwrap();
+static ref TPU_IDENTITY_SWAPS: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_identity_swaps", "Number of times the identity of the tpu client was swapped")).unwrap();
+static ref TPU_BATCHES_UDP: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_batches_udp", "Batches sent to a leader tpu over its legacy udp port")).unwrap();
+static ref TPU_LEADER_SENDS: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_tpu_leader_sends", "Batches sent to each leader tpu by outcome"), &["tpu", "outcome"]).unwrap();
+static ref TPU_BATCH_RETRIES: Histogram = register_histogram!(histogram_opts!(
+    "literpc_tpu_batch_retries",
+    "Quic send retries it took to get a batch to the leaders",
+    vec![0.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0],
+))
+.unwrap();
+static ref TPU_LEADERS_CONNECTED: IntGauge =
+    register_int_gauge!(opts!("literpc_tpu_leaders_connected", "Upcoming leader tpus with a fresh quic connection")).unwrap();
+static ref TPU_LEADER_RECONNECTS: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_leader_reconnects", "Stale quic connections to upcoming leaders reconnected ahead of their slots")).unwrap();
+static ref TPU_LEADER_RECONNECT_ERRORS: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_leader_reconnect_errors", "Quic connections to upcoming leaders that couldn't be reconnected")).unwrap();
+static ref TPU_SENDS_TO_PAST_LEADERS: IntCounter =
+    register_int_counter!(opts!("literpc_tpu_sends_to_past_leaders", "Batches sent to a leader tpu after the leader's last slot already passed")).unwrap();
+static ref SLOT_SKEW: IntGauge =
+    register_int_gauge!(opts!("literpc_slot_skew", "Slots the locally estimated slot is ahead of the upstream's processed slot, negative if behind")).unwrap();
+static ref TPU_SEND_LATENCY: Histogram = register_histogram!(histogram_opts!(
+    "literpc_tpu_send_latency",
+    "Time taken to send a batch to a leader tpu",
+))
+.unwrap();
+}
+
+/// How quic sends to a leader's tpu have been going
+#[derive(Default)]
+struct QuicHealth {
+    /// consecutive failed sends
+    failures: u32,
+    /// sent to over udp until then
+    udp_until: Option<Instant>,
+}
+
+impl QuicHealth {
+    /// Whether it's sent to over udp, once the fallback period is over quic gets
+    /// another chance
+    fn is_on_udp(&mut self) -> bool {
+        match self.udp_until {
+            Some(udp_until) if udp_until > Instant::now() => true,
+            Some(_) => {
+                *self = Self::default();
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Records a failed send, true once it's to be sent to over udp
+    fn failed(&mut self) -> bool {
+        self.failures += 1;
+        if self.failures < QUIC_FAILURES_BEFORE_UDP {
+            return false;
+        }
+        self.udp_until = Some(Instant::now() + UDP_FALLBACK_PERIOD);
+        true
+    }
+}
+
+/// How the quic connection to a leader's tpu is doing
+#[derive(Default)]
+struct ConnectionHealth {
+    /// last time a send or warm up over it went through
+    last_used: Option<Instant>,
+    /// a warm up is in flight
+    warming: bool,
+}
+
+impl ConnectionHealth {
+    fn is_fresh(&self) -> bool {
+        self.last_used.map_or(false, |last_used| {
+            last_used.elapsed() < STALE_CONNECTION_AGE
+        })
+    }
 }
 
+/// Forwards transactions to the upcoming leaders over quic. Leaders give connections
+/// of a staked identity stream bandwidth weighted by its stake, an unstaked identity
+/// (the default, an ephemeral keypair) still gets through but shares the small quota
+/// of unstaked connections and is the first to be throttled under load
 #[derive(Clone)]
 pub struct TpuManager {
     error_count: Arc<AtomicU32>,
     rpc_client: Arc<RpcClient>,
     // why arc twice / one is so that we clone rwlock and other so that we can clone tpu client
     tpu_client: Arc<RwLock<Arc<QuicTpuClient>>>,
-    pub ws_addr: String,
-    fanout_slots: u64,
-    identity: Arc<Keypair>,
+    /// connections of the tpu client, shared with sends to the tracked leaders
+    connection_cache: Arc<RwLock<Arc<QuicConnectionCache>>>,
+    /// tpus of the current and next leaders, empty until the leaders are tracked
+    leader_tpus: Arc<RwLock<Vec<SocketAddr>>>,
+    /// tpus of the targeted leaders and the ones after them, in schedule order
+    upcoming_tpus: Arc<RwLock<Vec<SocketAddr>>>,
+    /// last slot of each upcoming leader tpu within the looked up schedule
+    leader_last_slots: Arc<DashMap<SocketAddr, u64>>,
+    /// tpus of the cluster's validators by identity, fetched by the leader tracker
+    cluster_tpus: Arc<RwLock<HashMap<String, SocketAddr>>>,
+    /// leader tpus quic sends failed to lately
+    quic_health: Arc<DashMap<SocketAddr, QuicHealth>>,
+    /// connections to the targeted leader tpus
+    connection_health: Arc<DashMap<SocketAddr, ConnectionHealth>>,
+    /// sends to the legacy udp tpu of leaders quic keeps failing to
+    udp_socket: Arc<UdpSocket>,
+    /// the tpu client follows the leader schedule over the pubsub of the best upstream
+    upstreams: Upstreams,
+    /// tuned at runtime, the tpu client picks it up when it's reset
+    fanout_slots: Arc<AtomicU64>,
+    /// swapped at runtime, connections are rebuilt with it
+    identity: Arc<RwLock<Arc<Keypair>>>,
+    /// held by batches being sent, an identity swap waits for them to drain
+    in_flight: Arc<RwLock<()>>,
+    /// streams left to open to each leader tpu
+    stream_permits: Arc<DashMap<SocketAddr, Arc<Semaphore>>>,
+    /// connection pool, stream limits, timeouts and retries of quic sends
+    config: TpuConfig,
+    /// pushback of leaders on our quic sends
+    congestion: Arc<CongestionMonitor>,
 }
 
 impl TpuManager {
     pub async fn new(
         rpc_client: Arc<RpcClient>,
-        ws_addr: String,
+        upstreams: Upstreams,
         fanout_slots: u64,
         identity: Keypair,
+        config: TpuConfig,
     ) -> anyhow::Result<Self> {
         let mut tpu_config = QuicConfig::new().unwrap();
         tpu_config
@@ -52,23 +206,73 @@ impl TpuManager {
             .unwrap();
 
         let connection_cache =
-            QuicConnectionCache::new_with_config(TPU_CONNECTION_CACHE_SIZE, tpu_config);
+            QuicConnectionCache::new_with_config(config.connection_pool_size.max(1), tpu_config);
         let connection_cache = Arc::new(connection_cache);
-        let tpu_client =
-            Self::new_tpu_client(rpc_client.clone(), &ws_addr, fanout_slots, connection_cache)
-                .await?;
+        let tpu_client = Self::new_tpu_client(
+            rpc_client.clone(),
+            &upstreams.ws_url(),
+            fanout_slots,
+            connection_cache.clone(),
+        )
+        .await?;
         let tpu_client = Arc::new(RwLock::new(Arc::new(tpu_client)));
+        let connection_cache = Arc::new(RwLock::new(connection_cache));
+
+        Self::check_identity_stake(&rpc_client, &identity.pubkey()).await;
+        let udp_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
 
         Ok(Self {
             rpc_client,
             tpu_client,
-            ws_addr,
-            fanout_slots,
+            connection_cache,
+            leader_tpus: Default::default(),
+            upcoming_tpus: Default::default(),
+            leader_last_slots: Default::default(),
+            cluster_tpus: Default::default(),
+            quic_health: Default::default(),
+            connection_health: Default::default(),
+            udp_socket: Arc::new(udp_socket),
+            upstreams,
+            fanout_slots: Arc::new(AtomicU64::new(fanout_slots)),
             error_count: Default::default(),
-            identity: Arc::new(identity),
+            identity: Arc::new(RwLock::new(Arc::new(identity))),
+            in_flight: Default::default(),
+            stream_permits: Default::default(),
+            congestion: Arc::new(CongestionMonitor::new(config.congestion.clone())),
+            config,
         })
     }
 
+    /// Logs whether leaders will treat the identity as staked, never fails as an
+    /// unstaked identity still works
+    async fn check_identity_stake(rpc_client: &RpcClient, identity: &Pubkey) {
+        let vote_accounts = match rpc_client.get_vote_accounts().await {
+            Ok(vote_accounts) => vote_accounts,
+            Err(err) => {
+                warn!("Couldn't look up the stake of tpu identity {identity} {err}");
+                return;
+            }
+        };
+
+        let identity = identity.to_string();
+        let stake: u64 = vote_accounts
+            .current
+            .iter()
+            .chain(&vote_accounts.delinquent)
+            .filter(|vote_account| vote_account.node_pubkey == identity)
+            .map(|vote_account| vote_account.activated_stake)
+            .sum();
+        TPU_IDENTITY_STAKE.set(stake as i64);
+
+        if stake == 0 {
+            warn!(
+                "Tpu identity {identity} isn't staked, leaders treat its connections as unstaked"
+            );
+        } else {
+            info!("Tpu identity {identity} has {stake} lamports staked, leaders weight its connections by stake");
+        }
+    }
+
     pub async fn new_tpu_client(
         rpc_client: Arc<RpcClient>,
         ws_addr: &str,
@@ -84,34 +288,116 @@ impl TpuManager {
         .await?)
     }
 
-    pub async fn reset_tpu_client(&self) -> anyhow::Result<()> {
+    /// A tpu client and its connections with a quic certificate of `identity`
+    async fn connect(
+        &self,
+        identity: &Keypair,
+    ) -> anyhow::Result<(QuicTpuClient, Arc<QuicConnectionCache>)> {
         let mut tpu_config = QuicConfig::new().unwrap();
         tpu_config
-            .update_client_certificate(&self.identity, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)))
+            .update_client_certificate(identity, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)))
             .unwrap();
-        let connection_cache =
-            QuicConnectionCache::new_with_config(TPU_CONNECTION_CACHE_SIZE, tpu_config);
+        let connection_cache = QuicConnectionCache::new_with_config(
+            self.config.connection_pool_size.max(1),
+            tpu_config,
+        );
         let connection_cache = Arc::new(connection_cache);
 
         let tpu_client = Self::new_tpu_client(
             self.rpc_client.clone(),
-            &self.ws_addr,
-            self.fanout_slots,
-            connection_cache,
+            &self.upstreams.ws_url(),
+            self.fanout_slots(),
+            connection_cache.clone(),
         )
         .await?;
+        Ok((tpu_client, connection_cache))
+    }
+
+    pub async fn reset_tpu_client(&self) -> anyhow::Result<()> {
+        let identity = self.identity.read().await.clone();
+        let (tpu_client, connection_cache) = self.connect(&identity).await?;
+        // the identity was swapped meanwhile, its connections are the newer ones
+        if !Arc::ptr_eq(&identity, &*self.identity.read().await) {
+            return Ok(());
+        }
         self.error_count.store(0, Ordering::Relaxed);
         *self.tpu_client.write().await = Arc::new(tpu_client);
+        *self.connection_cache.write().await = connection_cache;
+        self.connection_health.clear();
         TPU_CONNECTION_RESET.inc();
         Ok(())
     }
 
+    pub async fn identity(&self) -> Pubkey {
+        self.identity.read().await.pubkey()
+    }
+
+    /// Swaps the identity of the quic certificate. The new connections are set up first,
+    /// then batches in flight are drained on the old ones before they're replaced
+    pub async fn set_identity(&self, identity: Keypair) -> anyhow::Result<()> {
+        Self::check_identity_stake(&self.rpc_client, &identity.pubkey()).await;
+        let (tpu_client, connection_cache) = self.connect(&identity).await?;
+
+        let _drained = self.in_flight.write().await;
+        *self.identity.write().await = Arc::new(identity);
+        *self.tpu_client.write().await = Arc::new(tpu_client);
+        *self.connection_cache.write().await = connection_cache;
+        self.error_count.store(0, Ordering::Relaxed);
+        // leaders may take the new identity's connections where they refused the old
+        self.quic_health.clear();
+        self.connection_health.clear();
+        TPU_IDENTITY_SWAPS.inc();
+        Ok(())
+    }
+
+    /// Swaps the identity whenever its keypair file changes, e.g. when the identity of
+    /// a validator is moved to this node on failover
+    pub fn watch_identity(&self, identity_file: String) -> JoinHandle<anyhow::Result<()>> {
+        let tpu_manager = self.clone();
+
+        tokio::spawn(async move {
+            let modified = || {
+                std::fs::metadata(&identity_file)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            };
+            let mut last_modified = modified();
+            let mut watch = tokio::time::interval(IDENTITY_WATCH_INTERVAL);
+
+            loop {
+                watch.tick().await;
+
+                let current = modified();
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+
+                let identity = match read_keypair(&identity_file).await {
+                    Ok(identity) => identity,
+                    Err(err) => {
+                        warn!("Couldn't read the tpu identity {identity_file} {err:#}");
+                        continue;
+                    }
+                };
+                let pubkey = identity.pubkey();
+                if pubkey == tpu_manager.identity().await {
+                    continue;
+                }
+                match tpu_manager.set_identity(identity).await {
+                    Ok(()) => info!("Tpu identity swapped to {pubkey}"),
+                    Err(err) => warn!("Couldn't swap the tpu identity to {pubkey} {err}"),
+                }
+            }
+        })
+    }
+
     pub async fn reset(&self) -> anyhow::Result<()> {
         self.error_count.fetch_add(1, Ordering::Relaxed);
 
-        if self.error_count.load(Ordering::Relaxed) > 5 {
+        if self.error_count.load(Ordering::Relaxed) > TPU_ERRORS_BEFORE_RESET {
             self.reset_tpu_client().await?;
-            info!("TPU Reset after 5 errors");
+            info!("TPU Reset after {} errors", TPU_ERRORS_BEFORE_RESET);
         }
 
         Ok(())
@@ -125,21 +411,474 @@ impl TpuManager {
         &self,
         wire_transactions: Vec<Vec<u8>>,
     ) -> anyhow::Result<()> {
+        let _in_flight = self.in_flight.read().await;
         let tpu_client = self.get_tpu_client().await;
-        match tpu_client
-            .try_send_wire_transaction_batch(wire_transactions)
-            .await
-        {
-            Ok(_) => Ok(()),
+        let retry_config = &self.config.quic_retry;
+        let mut retries = 0;
+        let result = loop {
+            let send = tpu_client.try_send_wire_transaction_batch(wire_transactions.clone());
+            let result = match tokio::time::timeout(self.handshake_timeout(), send).await {
+                Ok(result) => result.map_err(anyhow::Error::from),
+                Err(_) => Err(anyhow::anyhow!(
+                    "Timed out sending to the tpu client's leaders"
+                )),
+            };
+            self.congestion.record(result.is_err());
+            match result {
+                Err(_) if retries < retry_config.max_retries => {
+                    tokio::time::sleep(retry_config.backoff(retries)).await;
+                    retries += 1;
+                }
+                result => break result,
+            }
+        };
+        TPU_BATCH_RETRIES.observe(retries as f64);
+
+        match result {
+            Ok(_) => {
+                self.error_count.store(0, Ordering::Relaxed);
+                Ok(())
+            }
             Err(err) => {
                 self.reset().await?;
-                Err(err.into())
+                Err(err)
             }
         }
     }
 
+    /// How leaders have been pushing back on our sends
+    pub fn congestion(&self) -> &CongestionMonitor {
+        &self.congestion
+    }
+
+    fn handshake_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.handshake_timeout_ms)
+    }
+
+    /// Sends the batch to every tpu in `tpus`, succeeds if any of them took it
+    pub async fn send_wire_transaction_batch_to(
+        &self,
+        tpus: &[SocketAddr],
+        wire_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let _in_flight = self.in_flight.read().await;
+        let connection_cache = self.connection_cache.read().await.clone();
+        let retries = AtomicU32::new(0);
+        let sends = tpus
+            .iter()
+            .map(|tpu| self.send_to_tpu(&connection_cache, *tpu, wire_transactions, &retries));
+        let results = futures::future::join_all(sends).await;
+        TPU_BATCH_RETRIES.observe(retries.into_inner() as f64);
+
+        if results.iter().any(Result::is_ok) {
+            self.error_count.store(0, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        self.reset().await?;
+        match results.into_iter().find_map(Result::err) {
+            Some(err) => Err(err),
+            None => anyhow::bail!("No leader tpu to send to"),
+        }
+    }
+
+    /// Sends the batch to `tpu` alone, a validator that won't take it doesn't count
+    /// against the tpu client
+    pub async fn send_wire_transaction_batch_to_target(
+        &self,
+        tpu: SocketAddr,
+        wire_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let _in_flight = self.in_flight.read().await;
+        let connection_cache = self.connection_cache.read().await.clone();
+        let retries = AtomicU32::new(0);
+        let result = self
+            .send_to_tpu(&connection_cache, tpu, wire_transactions, &retries)
+            .await;
+        TPU_BATCH_RETRIES.observe(retries.into_inner() as f64);
+        result
+    }
+
+    /// Sends over quic, or over the legacy udp port of `tpu` while quic keeps failing to it
+    async fn send_to_tpu(
+        &self,
+        connection_cache: &QuicConnectionCache,
+        tpu: SocketAddr,
+        wire_transactions: &[Vec<u8>],
+        retries: &AtomicU32,
+    ) -> anyhow::Result<()> {
+        if let Some(last_slot) = self.leader_last_slots.get(&tpu).map(|slot| *slot) {
+            if self.estimated_current_slot().await > last_slot {
+                TPU_SENDS_TO_PAST_LEADERS.inc();
+            }
+        }
+
+        let timer = TPU_SEND_LATENCY.start_timer();
+        let result = self
+            .send_to_tpu_over_any(connection_cache, tpu, wire_transactions, retries)
+            .await;
+        timer.observe_duration();
+
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        TPU_LEADER_SENDS
+            .with_label_values(&[&tpu.to_string(), outcome])
+            .inc();
+        result
+    }
+
+    /// Sends over quic, retried with backoff on failure, or over udp if quic kept
+    /// failing for `tpu`. Retries are added to `retries`
+    async fn send_to_tpu_over_any(
+        &self,
+        connection_cache: &QuicConnectionCache,
+        tpu: SocketAddr,
+        wire_transactions: &[Vec<u8>],
+        retries: &AtomicU32,
+    ) -> anyhow::Result<()> {
+        if self.is_on_udp(&tpu) {
+            return self.send_over_udp(tpu, wire_transactions).await;
+        }
+
+        let retry_config = &self.config.quic_retry;
+        let mut retry = 0;
+        let result = loop {
+            match self
+                .send_over_quic(connection_cache, tpu, wire_transactions)
+                .await
+            {
+                Err(_) if retry < retry_config.max_retries => {
+                    tokio::time::sleep(retry_config.backoff(retry)).await;
+                    retry += 1;
+                    retries.fetch_add(1, Ordering::Relaxed);
+                }
+                result => break result,
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.quic_health.remove(&tpu);
+                self.connection_used(tpu);
+                TPU_BATCHES_QUIC.inc();
+                Ok(())
+            }
+            Err(err) => {
+                if !self.quic_failed(tpu) {
+                    return Err(err);
+                }
+                warn!("Quic sends to {tpu} keep failing, falling back to udp {err}");
+                self.send_over_udp(tpu, wire_transactions).await
+            }
+        }
+    }
+
+    /// Sends the batch in chunks of at most `max_streams_per_connection`, each waits
+    /// for as many streams to `tpu` to be free. Chunks that had to wait for streams,
+    /// failed or timed out count as pushback of the leader
+    async fn send_over_quic(
+        &self,
+        connection_cache: &QuicConnectionCache,
+        tpu: SocketAddr,
+        wire_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let max_streams = self.config.max_streams_per_connection.max(1);
+        let stream_permits = self
+            .stream_permits
+            .entry(tpu)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_streams)))
+            .clone();
+        let connection = connection_cache.get_nonblocking_connection(&tpu);
+
+        for chunk in wire_transactions.chunks(max_streams) {
+            let streams = chunk.len() as u32;
+            let (_streams, waited) = match stream_permits.clone().try_acquire_many_owned(streams) {
+                Ok(permits) => (permits, false),
+                Err(_) => (
+                    stream_permits.clone().acquire_many_owned(streams).await?,
+                    true,
+                ),
+            };
+            let result = match tokio::time::timeout(
+                self.handshake_timeout(),
+                connection.send_wire_transaction_batch(chunk),
+            )
+            .await
+            {
+                Ok(result) => result.map_err(anyhow::Error::from),
+                Err(_) => Err(anyhow::anyhow!("Timed out sending to {tpu}")),
+            };
+            self.congestion.record(waited || result.is_err());
+            result?;
+        }
+        Ok(())
+    }
+
+    fn connection_used(&self, tpu: SocketAddr) {
+        self.connection_health.entry(tpu).or_default().last_used = Some(Instant::now());
+    }
+
+    /// Reconnects the stale quic connections to `tpus` in the background so the
+    /// handshake is done before their leaders' slots come, leaders on udp are skipped
+    fn warm_up_connections(&self, tpus: &[SocketAddr]) {
+        self.connection_health.retain(|tpu, _| tpus.contains(tpu));
+
+        let mut connected = 0;
+        for tpu in tpus {
+            if self.is_on_udp(tpu) {
+                continue;
+            }
+            let mut health = self.connection_health.entry(*tpu).or_default();
+            if health.is_fresh() {
+                connected += 1;
+                continue;
+            }
+            if health.warming {
+                continue;
+            }
+            health.warming = true;
+            drop(health);
+
+            let tpu_manager = self.clone();
+            let tpu = *tpu;
+            tokio::spawn(async move {
+                tpu_manager.warm_up_connection(tpu).await;
+            });
+        }
+        TPU_LEADERS_CONNECTED.set(connected);
+    }
+
+    /// Sends an empty stream over the connection to `tpu`, which connects it if it
+    /// was closed
+    async fn warm_up_connection(&self, tpu: SocketAddr) {
+        let connection_cache = self.connection_cache.read().await.clone();
+        let connection = connection_cache.get_nonblocking_connection(&tpu);
+        let warm_up = connection.send_wire_transaction(Vec::new());
+
+        let result = match tokio::time::timeout(self.handshake_timeout(), warm_up).await {
+            Ok(result) => result.map_err(anyhow::Error::from),
+            Err(_) => Err(anyhow::anyhow!("timed out")),
+        };
+        match result {
+            Ok(()) => {
+                TPU_LEADER_RECONNECTS.inc();
+                self.connection_used(tpu);
+            }
+            Err(err) => {
+                TPU_LEADER_RECONNECT_ERRORS.inc();
+                warn!("Couldn't reconnect to the leader tpu {tpu} {err}");
+            }
+        }
+        if let Some(mut health) = self.connection_health.get_mut(&tpu) {
+            health.warming = false;
+        }
+    }
+
+    /// Whether `tpu` is sent to over udp
+    fn is_on_udp(&self, tpu: &SocketAddr) -> bool {
+        self.quic_health
+            .get_mut(tpu)
+            .map_or(false, |mut health| health.is_on_udp())
+    }
+
+    /// Records a failed quic send to `tpu`, true once it's to be sent to over udp
+    fn quic_failed(&self, tpu: SocketAddr) -> bool {
+        self.quic_health.entry(tpu).or_default().failed()
+    }
+
+    /// One datagram per transaction, udp tpus take no batches
+    async fn send_over_udp(
+        &self,
+        tpu: SocketAddr,
+        wire_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        for wire_transaction in wire_transactions {
+            self.udp_socket.send_to(wire_transaction, tpu).await?;
+        }
+        TPU_BATCHES_UDP.inc();
+        Ok(())
+    }
+
+    /// Tpus of the current and next leaders, empty until they're known
+    pub async fn leader_tpus(&self) -> Vec<SocketAddr> {
+        self.leader_tpus.read().await.clone()
+    }
+
+    /// Tpus of the current and next leaders followed by the ones after them, in
+    /// schedule order
+    pub async fn upcoming_leader_tpus(&self) -> Vec<SocketAddr> {
+        self.upcoming_tpus.read().await.clone()
+    }
+
+    /// Tpu of the validator with `identity`, `None` if it has none or the cluster's
+    /// tpus weren't fetched yet
+    pub async fn tpu_of(&self, identity: &Pubkey) -> Option<SocketAddr> {
+        self.cluster_tpus
+            .read()
+            .await
+            .get(&identity.to_string())
+            .copied()
+    }
+
+    /// Keeps the tpus of the current and next `leader_count` leaders of the locally
+    /// derived schedule up to date, along with the configured lookahead, and warms up
+    /// the connections to the first of them
+    pub fn track_leaders(
+        self,
+        leader_schedule: LeaderScheduleCache,
+        leader_count: u64,
+    ) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let mut cluster_tpus_fetched: Option<Instant> = None;
+            let mut slot_skew_checked: Option<Instant> = None;
+            // the fallback to fanout is logged once per outage of the local schedule
+            let mut schedule_ready = true;
+            let cache_config = self.config.leader_cache.clone();
+            let mut interval = tokio::time::interval(cache_config.refresh_interval());
+
+            loop {
+                interval.tick().await;
+
+                if cluster_tpus_fetched.map_or(true, |fetched| {
+                    fetched.elapsed() > cache_config.cluster_tpus_refresh_interval()
+                }) {
+                    match self.rpc_client.get_cluster_nodes().await {
+                        Ok(nodes) => {
+                            *self.cluster_tpus.write().await = nodes
+                                .into_iter()
+                                .filter_map(|node| Some((node.pubkey, node.tpu?)))
+                                .collect();
+                            cluster_tpus_fetched = Some(Instant::now());
+                        }
+                        Err(err) => warn!("Couldn't fetch the tpus of the cluster {err}"),
+                    }
+                }
+
+                let slot = self.estimated_current_slot().await;
+                if slot_skew_checked
+                    .map_or(true, |checked| checked.elapsed() > SLOT_SKEW_CHECK_INTERVAL)
+                {
+                    match self
+                        .rpc_client
+                        .get_slot_with_commitment(CommitmentConfig::processed())
+                        .await
+                    {
+                        Ok(cluster_slot) => {
+                            SLOT_SKEW.set(slot as i64 - cluster_slot as i64);
+                            slot_skew_checked = Some(Instant::now());
+                        }
+                        Err(err) => warn!("Couldn't fetch the slot of the upstream {err}"),
+                    }
+                }
+
+                // the current leader may be partway through its slots
+                let upcoming_count = leader_count + 1 + cache_config.lookahead_leaders;
+                let slots = upcoming_count * NUM_CONSECUTIVE_LEADER_SLOTS;
+                let leaders = match leader_schedule.cached_slot_leaders(slot, slots).await {
+                    Some(leaders) => {
+                        if !schedule_ready {
+                            info!("Local leader schedule is ready, targeting its leaders");
+                            schedule_ready = true;
+                        }
+                        leaders
+                    }
+                    None => {
+                        if schedule_ready {
+                            warn!("Local leader schedule isn't ready for slot {slot}, fanning out");
+                            schedule_ready = false;
+                        }
+                        // stale leaders are past, the tpu client's fanout takes over
+                        self.leader_tpus.write().await.clear();
+                        self.upcoming_tpus.write().await.clear();
+                        TPU_LEADERS_TARGETED.set(0);
+                        continue;
+                    }
+                };
+
+                let cluster_tpus = self.cluster_tpus.read().await;
+                let mut last_slots = HashMap::new();
+                for (offset, leader) in leaders.iter().enumerate() {
+                    if let Some(tpu) = cluster_tpus.get(&leader.to_string()) {
+                        last_slots.insert(*tpu, slot + offset as u64);
+                    }
+                }
+                let upcoming: Vec<Option<SocketAddr>> = leaders
+                    .into_iter()
+                    .unique()
+                    .take(upcoming_count as usize)
+                    .map(|leader| cluster_tpus.get(&leader.to_string()).copied())
+                    .collect();
+                drop(cluster_tpus);
+                let tpus: Vec<SocketAddr> = upcoming
+                    .iter()
+                    .take(leader_count as usize + 1)
+                    .flatten()
+                    .copied()
+                    .collect();
+                let upcoming: Vec<SocketAddr> = upcoming.into_iter().flatten().collect();
+                TPU_LEADERS_TARGETED.set(tpus.len() as i64);
+                TPU_LEADERS_ON_UDP
+                    .set(tpus.iter().filter(|tpu| self.is_on_udp(tpu)).count() as i64);
+                // past leaders' permits are dropped once no send holds them
+                self.stream_permits.retain(|tpu, permits| {
+                    upcoming.contains(tpu) || Arc::strong_count(permits) > 1
+                });
+                let warm_up: Vec<SocketAddr> = upcoming
+                    .iter()
+                    .take(cache_config.warm_up_leaders as usize)
+                    .copied()
+                    .collect();
+                self.warm_up_connections(&warm_up);
+                *self.leader_tpus.write().await = tpus;
+                *self.upcoming_tpus.write().await = upcoming;
+                // past leaders keep their last slot, sends to them are counted
+                for (tpu, last_slot) in last_slots {
+                    self.leader_last_slots.insert(tpu, last_slot);
+                }
+                self.leader_last_slots
+                    .retain(|_, last_slot| *last_slot + slots >= slot);
+            }
+        })
+    }
+
+    pub fn fanout_slots(&self) -> u64 {
+        self.fanout_slots.load(Ordering::Relaxed)
+    }
+
+    /// Fans out to `fanout_slots` from now on, the tpu client is rebuilt to pick it up
+    pub async fn set_fanout_slots(&self, fanout_slots: u64) -> anyhow::Result<()> {
+        self.fanout_slots.store(fanout_slots, Ordering::Relaxed);
+        self.reset_tpu_client().await
+    }
+
+    /// false while sends keep failing and the client is due for a reset
+    pub fn is_connected(&self) -> bool {
+        self.error_count.load(Ordering::Relaxed) < TPU_ERRORS_BEFORE_RESET
+    }
+
     pub async fn estimated_current_slot(&self) -> u64 {
         let tpu_client = self.get_tpu_client().await;
         tpu_client.estimated_current_slot()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_udp_after_repeated_quic_failures() {
+        let mut health = QuicHealth::default();
+        for _ in 1..QUIC_FAILURES_BEFORE_UDP {
+            assert!(!health.failed());
+            assert!(!health.is_on_udp());
+        }
+        assert!(health.failed());
+        assert!(health.is_on_udp());
+
+        // quic gets another chance once the fallback period is over
+        health.udp_until = Some(Instant::now() - Duration::from_millis(1));
+        assert!(!health.is_on_udp());
+        assert_eq!(health.failures, 0);
+        assert!(!health.failed());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use jsonrpsee::{
+    types::{error::CallError, ErrorObject},
+    RpcModule,
+};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::rpc_wrapper::{
+    caller::Caller,
+    configs::{ApiKey, TxQuota},
+    middleware::ACCESS_DENIED_CODE,
+};
+
+lazy_static::lazy_static! {
+    static ref RPC_TX_QUOTA_EXHAUSTED: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_rpc_tx_quota_exhausted", "Number of requests rejected for exceeding the transaction quota of their api key"), &["key"]).unwrap();
+}
+
+pub const QUOTA_EXHAUSTED: &str =
+    "Transaction quota of the api key is exhausted until its window resets";
+
+/// The calling api key sent all the transactions its quota allows in this window
+#[derive(Debug, thiserror::Error)]
+#[error("{}", QUOTA_EXHAUSTED)]
+pub struct QuotaExhausted;
+
+/// Transactions sent with an api key in the current window and overall
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyUsage {
+    pub name: String,
+    /// quota of the key, unlimited if `None`
+    pub quota: Option<TxQuota>,
+    pub window_sent: u64,
+    /// seconds until the window resets
+    pub window_resets_in_s: u64,
+    pub total_sent: u64,
+    pub total_rejected: u64,
+}
+
+struct Usage {
+    window_started: Instant,
+    window_sent: u64,
+    total_sent: u64,
+    total_rejected: u64,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self {
+            window_started: Instant::now(),
+            window_sent: 0,
+            total_sent: 0,
+            total_rejected: 0,
+        }
+    }
+}
+
+impl Usage {
+    /// Starts a new window once the current one is over
+    fn roll_window(&mut self, window: Duration) {
+        if self.window_started.elapsed() >= window {
+            self.window_started = Instant::now();
+            self.window_sent = 0;
+        }
+    }
+}
+
+/// Per api key quotas on the transactions sent, in fixed windows
+#[derive(Clone)]
+pub struct TxQuotas {
+    /// key to the key's config
+    keys: Arc<HashMap<String, ApiKey>>,
+    /// usage by key name
+    usage: Arc<DashMap<String, Usage>>,
+}
+
+impl TxQuotas {
+    pub fn new(api_keys: &[ApiKey]) -> Self {
+        Self {
+            keys: Arc::new(
+                api_keys
+                    .iter()
+                    .map(|api_key| (api_key.key.clone(), api_key.clone()))
+                    .collect(),
+            ),
+            usage: Default::default(),
+        }
+    }
+
+    /// Counts `transactions` against the quota of `api_key`, false if they'd exceed it
+    fn try_send(&self, api_key: &ApiKey, transactions: u64) -> bool {
+        let mut usage = self.usage.entry(api_key.name.clone()).or_default();
+        if let Some(quota) = &api_key.tx_quota {
+            usage.roll_window(quota.window());
+            if usage.window_sent + transactions > quota.max_transactions {
+                usage.total_rejected += transactions;
+                return false;
+            }
+        }
+        usage.window_sent += transactions;
+        usage.total_sent += transactions;
+        true
+    }
+
+    /// Counts a transaction against the quota of the calling api key, refusing it once
+    /// the quota is spent. Callers without a configured key aren't limited
+    pub fn take(&self) -> Result<(), QuotaExhausted> {
+        let Some(api_key) = Caller::current().and_then(|caller| caller.api_key) else {
+            return Ok(());
+        };
+        if self.try_send(&api_key, 1) {
+            return Ok(());
+        }
+
+        RPC_TX_QUOTA_EXHAUSTED
+            .with_label_values(&[&api_key.name])
+            .inc();
+        Err(QuotaExhausted)
+    }
+
+    /// Gives a transaction taken from the quota of the calling api key back, it was
+    /// refused after all
+    pub fn refund(&self) {
+        let Some(api_key) = Caller::current().and_then(|caller| caller.api_key) else {
+            return;
+        };
+        if let Some(mut usage) = self.usage.get_mut(&api_key.name) {
+            usage.window_sent = usage.window_sent.saturating_sub(1);
+            usage.total_sent = usage.total_sent.saturating_sub(1);
+        }
+    }
+
+    /// Usage of every configured api key
+    pub fn usage(&self) -> Vec<ApiKeyUsage> {
+        self.keys
+            .values()
+            .map(|api_key| {
+                let mut usage = self.usage.entry(api_key.name.clone()).or_default();
+                let window_resets_in_s = match &api_key.tx_quota {
+                    Some(quota) => {
+                        usage.roll_window(quota.window());
+                        quota
+                            .window()
+                            .saturating_sub(usage.window_started.elapsed())
+                            .as_secs()
+                    }
+                    None => 0,
+                };
+                ApiKeyUsage {
+                    name: api_key.name.clone(),
+                    quota: api_key.tx_quota.clone(),
+                    window_sent: usage.window_sent,
+                    window_resets_in_s,
+                    total_sent: usage.total_sent,
+                    total_rejected: usage.total_rejected,
+                }
+            })
+            .collect()
+    }
+
+    /// Admin methods, only admin api keys may call them once any key is configured
+    pub fn into_rpc(self) -> RpcModule<TxQuotas> {
+        let mut module = RpcModule::new(self);
+        module
+            .register_method("getApiKeyUsage", |_params, quotas| {
+                quotas.check_admin("getApiKeyUsage")?;
+                Ok(quotas.usage())
+            })
+            .expect("getApiKeyUsage is registered once");
+        module
+    }
+
+    fn check_admin(&self, method: &str) -> Result<(), jsonrpsee::core::Error> {
+        let is_admin = Caller::current()
+            .and_then(|caller| caller.api_key)
+            .map_or(false, |api_key| api_key.admin);
+        if self.keys.is_empty() || is_admin {
+            return Ok(());
+        }
+        Err(jsonrpsee::core::Error::Call(CallError::Custom(
+            ErrorObject::owned(
+                ACCESS_DENIED_CODE,
+                format!("{method} needs an admin api key"),
+                None::<()>,
+            ),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_key(name: &str, tx_quota: Option<TxQuota>) -> ApiKey {
+        ApiKey {
+            name: name.to_owned(),
+            key: format!("{name}-key"),
+            tx_quota,
+            admin: false,
+        }
+    }
+
+    fn quota(max_transactions: u64, window_s: u64) -> Option<TxQuota> {
+        Some(TxQuota {
+            max_transactions,
+            window_s,
+        })
+    }
+
+    fn usage(quotas: &TxQuotas, name: &str) -> ApiKeyUsage {
+        quotas
+            .usage()
+            .into_iter()
+            .find(|usage| usage.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn refuses_transactions_past_the_quota() {
+        let limited = api_key("limited", quota(3, 3600));
+        let unlimited = api_key("unlimited", None);
+        let quotas = TxQuotas::new(&[limited.clone(), unlimited.clone()]);
+
+        assert!(quotas.try_send(&limited, 2));
+        // a batch goes whole or not at all
+        assert!(!quotas.try_send(&limited, 2));
+        assert!(quotas.try_send(&limited, 1));
+        assert!(!quotas.try_send(&limited, 1));
+        for _ in 0..10 {
+            assert!(quotas.try_send(&unlimited, 1));
+        }
+
+        let limited = usage(&quotas, "limited");
+        assert_eq!(limited.window_sent, 3);
+        assert_eq!((limited.total_sent, limited.total_rejected), (3, 3));
+        assert!(limited.window_resets_in_s > 3590);
+        let unlimited = usage(&quotas, "unlimited");
+        assert_eq!((unlimited.total_sent, unlimited.total_rejected), (10, 0));
+    }
+
+    #[test]
+    fn windows_reset_the_quota() {
+        let api_key = api_key("key", quota(1, 0));
+        let quotas = TxQuotas::new(&[api_key.clone()]);
+        for _ in 0..3 {
+            assert!(quotas.try_send(&api_key, 1));
+        }
+        let usage = usage(&quotas, "key");
+        assert_eq!((usage.window_sent, usage.total_sent), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn limits_the_calling_api_key() {
+        let api_key = api_key("key", quota(1, 3600));
+        let quotas = TxQuotas::new(&[api_key.clone()]);
+        let caller = Caller {
+            api_key: Some(api_key),
+            ip: None,
+        };
+
+        assert!(caller.clone().scope(async { quotas.take() }).await.is_ok());
+        assert!(caller.clone().scope(async { quotas.take() }).await.is_err());
+        // a refunded transaction can be sent again
+        caller.clone().scope(async { quotas.refund() }).await;
+        assert_eq!(usage(&quotas, "key").total_sent, 0);
+        assert!(caller.clone().scope(async { quotas.take() }).await.is_ok());
+        assert!(caller.scope(async { quotas.take() }).await.is_err());
+        // callers without a configured key aren't limited
+        assert!(quotas.take().is_ok());
+        assert!(Caller::default()
+            .scope(async { quotas.take() })
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn admin_methods_need_an_admin_key() {
+        let admin = ApiKey {
+            admin: true,
+            ..api_key("admin", None)
+        };
+        let quotas = TxQuotas::new(&[admin.clone(), api_key("key", None)]);
+        let caller = |api_key| Caller { api_key, ip: None };
+
+        assert!(quotas.check_admin("getApiKeyUsage").is_err());
+        let key = Some(api_key("key", None));
+        assert!(caller(key)
+            .scope(async { quotas.check_admin("getApiKeyUsage") })
+            .await
+            .is_err());
+        assert!(caller(Some(admin))
+            .scope(async { quotas.check_admin("getApiKeyUsage") })
+            .await
+            .is_ok());
+
+        // anyone may while no key is configured
+        assert!(TxQuotas::new(&[]).check_admin("getApiKeyUsage").is_ok());
+    }
+}
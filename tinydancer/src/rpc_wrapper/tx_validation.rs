@@ -0,0 +1,349 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    account::Account,
+    message::{v0::MessageAddressTableLookup, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::{Pubkey, PUBKEY_BYTES},
+    sanitize::{Sanitize, SanitizeError},
+    transaction::{VersionedTransaction, MAX_TX_ACCOUNT_LOCKS},
+};
+
+lazy_static::lazy_static! {
+    static ref TXS_INVALID: IntCounterVec =
+        register_int_counter_vec!(opts!("literpc_txs_invalid", "Transactions rejected on ingest by reason"), &["reason"]).unwrap();
+}
+
+/// Size of the lookup table metadata ahead of its addresses
+const LOOKUP_TABLE_META_SIZE: usize = 56;
+/// Discriminator of an initialized lookup table's state
+const LOOKUP_TABLE_DISCRIMINATOR: u32 = 1;
+/// Deactivation slot of a table that's still active
+const LOOKUP_TABLE_ACTIVE: u64 = u64::MAX;
+/// How long a fetched lookup table is reused, tables only grow so a stale one can
+/// only miss addresses, which fetches it again. Accounts that aren't active tables
+/// are remembered as long, missing ones aren't as they may be created any time
+const LOOKUP_TABLE_CACHE_TTL: Duration = Duration::from_secs(10);
+/// Lookup tables cached at most, fetches past it aren't cached until entries expire
+const MAX_CACHED_LOOKUP_TABLES: usize = 4096;
+
+/// A fetched lookup table account
+#[derive(Clone)]
+enum LookupTable {
+    Addresses(Arc<Vec<Pubkey>>),
+    NotFound,
+    Invalid,
+    Deactivated,
+}
+
+/// Why a transaction was rejected on ingest
+#[derive(thiserror::Error, Debug)]
+pub enum InvalidTransaction {
+    #[error("Transaction is {len} bytes, max {max}")]
+    TooLarge { len: usize, max: usize },
+    #[error("Failed to deserialize the transaction {0}")]
+    Malformed(#[from] bincode::Error),
+    #[error("Transaction has {actual} signature(s), its message requires {expected}")]
+    SignatureCount { expected: usize, actual: usize },
+    #[error("Transaction failed to sanitize {0:?}")]
+    Sanitize(SanitizeError),
+    #[error("Signature {index} of the transaction doesn't verify")]
+    InvalidSignature { index: usize },
+    #[error("Address lookup table {0} not found")]
+    LookupTableNotFound(Pubkey),
+    #[error("Account {0} is not an address lookup table")]
+    InvalidLookupTable(Pubkey),
+    #[error("Address lookup table {0} is deactivated")]
+    LookupTableDeactivated(Pubkey),
+    #[error(
+        "Index {index} is out of bounds of address lookup table {table} with {len} address(es)"
+    )]
+    LookupTableIndexOutOfBounds {
+        table: Pubkey,
+        index: u8,
+        len: usize,
+    },
+    #[error("Transaction loads {accounts} accounts, max {max}")]
+    TooManyAccounts { accounts: usize, max: usize },
+    #[error("Account {0} is loaded more than once")]
+    DuplicateAccount(Pubkey),
+    #[error("Couldn't fetch the address lookup tables {0}")]
+    Upstream(String),
+}
+
+impl InvalidTransaction {
+    fn reason(&self) -> &'static str {
+        match self {
+            Self::TooLarge { .. } => "too_large",
+            Self::Malformed(_) => "malformed",
+            Self::SignatureCount { .. } => "signature_count",
+            Self::Sanitize(_) => "sanitize",
+            Self::InvalidSignature { .. } => "invalid_signature",
+            Self::LookupTableNotFound(_) => "lookup_table_not_found",
+            Self::InvalidLookupTable(_) => "invalid_lookup_table",
+            Self::LookupTableDeactivated(_) => "lookup_table_deactivated",
+            Self::LookupTableIndexOutOfBounds { .. } => "lookup_table_index",
+            Self::TooManyAccounts { .. } => "too_many_accounts",
+            Self::DuplicateAccount(_) => "duplicate_account",
+            Self::Upstream(_) => "upstream",
+        }
+    }
+
+    /// Whether the transaction itself is at fault rather than the upstream
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, Self::Upstream(_))
+    }
+}
+
+/// Checks transactions before they're queued so malformed ones don't take up tpu
+/// bandwidth. The address lookup tables of versioned transactions are fetched from
+/// the upstream and resolved
+pub struct TxValidator {
+    rpc_client: Arc<RpcClient>,
+    /// recently fetched lookup tables, and accounts that turned out not to be one
+    lookup_tables: DashMap<Pubkey, (Instant, LookupTable)>,
+}
+
+impl TxValidator {
+    pub fn new(rpc_client: Arc<RpcClient>) -> Self {
+        Self {
+            rpc_client,
+            lookup_tables: Default::default(),
+        }
+    }
+
+    pub async fn validate(&self, raw_tx: &[u8]) -> Result<(), InvalidTransaction> {
+        let result = self.check(raw_tx).await;
+        if let Err(err) = &result {
+            TXS_INVALID.with_label_values(&[err.reason()]).inc();
+        }
+        result
+    }
+
+    async fn check(&self, raw_tx: &[u8]) -> Result<(), InvalidTransaction> {
+        if raw_tx.len() > PACKET_DATA_SIZE {
+            return Err(InvalidTransaction::TooLarge {
+                len: raw_tx.len(),
+                max: PACKET_DATA_SIZE,
+            });
+        }
+        let tx = bincode::deserialize::<VersionedTransaction>(raw_tx)?;
+
+        let expected = tx.message.header().num_required_signatures as usize;
+        if tx.signatures.len() != expected {
+            return Err(InvalidTransaction::SignatureCount {
+                expected,
+                actual: tx.signatures.len(),
+            });
+        }
+        tx.sanitize().map_err(InvalidTransaction::Sanitize)?;
+        if let Some(index) = tx
+            .verify_with_results()
+            .iter()
+            .position(|verified| !verified)
+        {
+            return Err(InvalidTransaction::InvalidSignature { index });
+        }
+
+        // the count is known from the indexes, no need to fetch the tables to refuse it
+        let loads = tx.message.static_account_keys().len()
+            + tx.message.address_table_lookups().map_or(0, |lookups| {
+                lookups
+                    .iter()
+                    .map(|lookup| lookup.writable_indexes.len() + lookup.readonly_indexes.len())
+                    .sum()
+            });
+        if loads > MAX_TX_ACCOUNT_LOCKS {
+            return Err(InvalidTransaction::TooManyAccounts {
+                accounts: loads,
+                max: MAX_TX_ACCOUNT_LOCKS,
+            });
+        }
+
+        let mut accounts: Vec<Pubkey> = tx.message.static_account_keys().to_vec();
+        if let VersionedMessage::V0(message) = &tx.message {
+            for lookup in &message.address_table_lookups {
+                accounts.extend(self.resolve(lookup).await?);
+            }
+        }
+
+        let mut loaded = HashSet::with_capacity(accounts.len());
+        if let Some(duplicate) = accounts
+            .into_iter()
+            .find(|account| !loaded.insert(*account))
+        {
+            return Err(InvalidTransaction::DuplicateAccount(duplicate));
+        }
+        Ok(())
+    }
+
+    /// Addresses `lookup` loads, the cached table is fetched again if it's too short
+    async fn resolve(
+        &self,
+        lookup: &MessageAddressTableLookup,
+    ) -> Result<Vec<Pubkey>, InvalidTransaction> {
+        let highest = lookup
+            .writable_indexes
+            .iter()
+            .chain(&lookup.readonly_indexes)
+            .max()
+            .copied();
+        let cached = self
+            .lookup_tables
+            .get(&lookup.account_key)
+            .filter(|cached| cached.0.elapsed() < LOOKUP_TABLE_CACHE_TTL)
+            .map(|cached| cached.1.clone())
+            .filter(|table| match table {
+                LookupTable::Addresses(addresses) => {
+                    highest.map_or(true, |highest| (highest as usize) < addresses.len())
+                }
+                LookupTable::NotFound | LookupTable::Invalid | LookupTable::Deactivated => true,
+            });
+        let table = match cached {
+            Some(table) => table,
+            None => self.fetch_lookup_table(lookup.account_key).await?,
+        };
+        let addresses = match table {
+            LookupTable::Addresses(addresses) => addresses,
+            LookupTable::NotFound => {
+                return Err(InvalidTransaction::LookupTableNotFound(lookup.account_key))
+            }
+            LookupTable::Invalid => {
+                return Err(InvalidTransaction::InvalidLookupTable(lookup.account_key))
+            }
+            LookupTable::Deactivated => {
+                return Err(InvalidTransaction::LookupTableDeactivated(
+                    lookup.account_key,
+                ))
+            }
+        };
+
+        lookup
+            .writable_indexes
+            .iter()
+            .chain(&lookup.readonly_indexes)
+            .map(|&index| {
+                addresses.get(index as usize).copied().ok_or(
+                    InvalidTransaction::LookupTableIndexOutOfBounds {
+                        table: lookup.account_key,
+                        index,
+                        len: addresses.len(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Fetches the lookup table `key` and caches what was found unless it was missing,
+    /// only upstream failures are errors
+    async fn fetch_lookup_table(&self, key: Pubkey) -> Result<LookupTable, InvalidTransaction> {
+        let account = self
+            .rpc_client
+            .get_account_with_commitment(&key, self.rpc_client.commitment())
+            .await
+            .map_err(|err| InvalidTransaction::Upstream(err.to_string()))?
+            .value;
+
+        let table = match account {
+            None => return Ok(LookupTable::NotFound),
+            Some(account) => parse_lookup_table(&account),
+        };
+
+        // expired tables would only be fetched again
+        self.lookup_tables
+            .retain(|_, (fetched, _)| fetched.elapsed() < LOOKUP_TABLE_CACHE_TTL);
+        if self.lookup_tables.len() < MAX_CACHED_LOOKUP_TABLES {
+            self.lookup_tables
+                .insert(key, (Instant::now(), table.clone()));
+        }
+        Ok(table)
+    }
+}
+
+/// Addresses of a lookup table account, unless it isn't an initialized table or is
+/// deactivated. A deactivating table still resolves for a while, the transaction
+/// would likely land after it stopped
+fn parse_lookup_table(account: &Account) -> LookupTable {
+    if account.owner != solana_sdk::address_lookup_table_program::id() {
+        return LookupTable::Invalid;
+    }
+    let Some(meta) = account.data.get(..LOOKUP_TABLE_META_SIZE) else {
+        return LookupTable::Invalid;
+    };
+    let discriminator = u32::from_le_bytes(meta[..4].try_into().expect("4 bytes"));
+    if discriminator != LOOKUP_TABLE_DISCRIMINATOR {
+        return LookupTable::Invalid;
+    }
+    let deactivation_slot = u64::from_le_bytes(meta[4..12].try_into().expect("8 bytes"));
+    if deactivation_slot != LOOKUP_TABLE_ACTIVE {
+        return LookupTable::Deactivated;
+    }
+
+    let addresses = &account.data[LOOKUP_TABLE_META_SIZE..];
+    if addresses.len() % PUBKEY_BYTES != 0 {
+        return LookupTable::Invalid;
+    }
+    let addresses = addresses
+        .chunks_exact(PUBKEY_BYTES)
+        .map(|address| Pubkey::try_from(address).ok())
+        .collect::<Option<Vec<_>>>();
+    match addresses {
+        Some(addresses) => LookupTable::Addresses(Arc::new(addresses)),
+        None => LookupTable::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(discriminator: u32, deactivation_slot: u64, addresses: &[Pubkey]) -> Account {
+        let mut data = vec![0; LOOKUP_TABLE_META_SIZE];
+        data[..4].copy_from_slice(&discriminator.to_le_bytes());
+        data[4..12].copy_from_slice(&deactivation_slot.to_le_bytes());
+        for address in addresses {
+            data.extend_from_slice(address.as_ref());
+        }
+        Account {
+            data,
+            owner: solana_sdk::address_lookup_table_program::id(),
+            ..Account::default()
+        }
+    }
+
+    #[test]
+    fn parses_active_tables() {
+        let addresses = [Pubkey::new_unique(), Pubkey::new_unique()];
+        assert!(matches!(
+            parse_lookup_table(&table(LOOKUP_TABLE_DISCRIMINATOR, LOOKUP_TABLE_ACTIVE, &addresses)),
+            LookupTable::Addresses(parsed) if parsed[..] == addresses
+        ));
+    }
+
+    #[test]
+    fn rejects_deactivated_and_uninitialized_tables() {
+        let addresses = [Pubkey::new_unique()];
+        assert!(matches!(
+            parse_lookup_table(&table(LOOKUP_TABLE_DISCRIMINATOR, 100, &addresses)),
+            LookupTable::Deactivated
+        ));
+        assert!(matches!(
+            parse_lookup_table(&table(0, LOOKUP_TABLE_ACTIVE, &[])),
+            LookupTable::Invalid
+        ));
+
+        let mut not_owned = table(LOOKUP_TABLE_DISCRIMINATOR, LOOKUP_TABLE_ACTIVE, &addresses);
+        not_owned.owner = Pubkey::new_unique();
+        assert!(matches!(
+            parse_lookup_table(&not_owned),
+            LookupTable::Invalid
+        ));
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use prometheus::{
+    opts, register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec,
+};
+use serde_json::Value;
+use solana_rpc_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client_api::{
+    client_error::{Error as ClientError, ErrorKind, Result as ClientResult},
+    request::{RpcError, RpcRequest, RpcResponseErrorData},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, signer::SignerError};
+use tiny_logger::logs::warn;
+
+use crate::{
+    convert_to_websocket,
+    rpc_wrapper::{
+        circuit_breaker::{unreachable, CircuitBreaker},
+        coalesce::Coalescer,
+    },
+};
+
+lazy_static::lazy_static! {
+    static ref UPSTREAM_REQUESTS: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_upstream_requests", "Calls to an upstream rpc by outcome"), &["upstream", "outcome"]).unwrap();
+    static ref UPSTREAM_LATENCY_MS: IntGaugeVec =
+    register_int_gauge_vec!(opts!("literpc_upstream_latency_ms", "Moving average of an upstream rpc's latency"), &["upstream"]).unwrap();
+    static ref UPSTREAM_FAILOVERS: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_upstream_failovers", "Calls moved off an unreachable upstream rpc"), &["upstream"]).unwrap();
+}
+
+struct Upstream {
+    breaker: CircuitBreaker,
+    /// moving average, 0 until the first call returned
+    latency_ms: AtomicU64,
+}
+
+impl Upstream {
+    fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_millis().max(1) as u64;
+        let average = match self.latency_ms.load(Ordering::Relaxed) {
+            0 => sample,
+            average => (average * 7 + sample) / 8,
+        };
+        self.latency_ms.store(average, Ordering::Relaxed);
+        UPSTREAM_LATENCY_MS
+            .with_label_values(&[&self.breaker.url()])
+            .set(average as i64);
+    }
+
+    /// Sort key, reachable before unreachable then fastest first, unmeasured
+    /// upstreams only once the measured ones are worse off
+    fn rank(&self) -> (bool, u64) {
+        let latency_ms = match self.latency_ms.load(Ordering::Relaxed) {
+            0 => u64::MAX,
+            latency_ms => latency_ms,
+        };
+        (self.breaker.is_open(), latency_ms)
+    }
+}
+
+/// The upstream rpcs calls can go to, in order of preference. Calls go to the
+/// reachable upstream with the lowest latency and fail over to the next one when an
+/// upstream doesn't answer
+#[derive(Clone)]
+pub struct Upstreams {
+    upstreams: Arc<Vec<Upstream>>,
+}
+
+impl Upstreams {
+    /// `urls` is never empty, the first one is preferred until latencies are known
+    pub fn new(urls: Vec<String>) -> Self {
+        assert!(!urls.is_empty(), "at least one upstream rpc is required");
+
+        let upstreams = urls
+            .into_iter()
+            .map(|url| Upstream {
+                breaker: CircuitBreaker::new(url),
+                latency_ms: AtomicU64::new(0),
+            })
+            .collect();
+
+        Self {
+            upstreams: Arc::new(upstreams),
+        }
+    }
+
+    fn ranked(&self) -> Vec<&Upstream> {
+        let mut ranked: Vec<&Upstream> = self.upstreams.iter().collect();
+        // stable, ties keep the configured order
+        ranked.sort_by_key(|upstream| upstream.rank());
+        ranked
+    }
+
+    /// Every upstream's url in order of preference
+    pub fn urls(&self) -> Vec<String> {
+        self.upstreams
+            .iter()
+            .map(|upstream| upstream.breaker.url())
+            .collect()
+    }
+
+    /// Moves calls off the upstream at `url` until its breaker's cooldown is over, as
+    /// if it hadn't answered them
+    pub fn mark_unreachable(&self, url: &str) {
+        if let Some(upstream) = self
+            .upstreams
+            .iter()
+            .find(|upstream| upstream.breaker.url() == url)
+        {
+            upstream.breaker.trip();
+        }
+    }
+
+    /// Url of the upstream calls currently go to
+    pub fn url(&self) -> String {
+        self.ranked()[0].breaker.url()
+    }
+
+    /// Pubsub url of the upstream calls currently go to
+    pub fn ws_url(&self) -> String {
+        convert_to_websocket!(self.url())
+    }
+
+    /// Every upstream's pubsub url, the one calls currently go to first
+    pub fn ws_urls(&self) -> Vec<String> {
+        self.ranked()
+            .into_iter()
+            .map(|upstream| convert_to_websocket!(upstream.breaker.url()))
+            .collect()
+    }
+
+    /// Nonblocking client sending through the upstreams, identical reads in flight at
+    /// the same time go upstream once
+    pub fn rpc_client(&self) -> RpcClient {
+        RpcClient::new_sender(
+            UpstreamSender {
+                upstreams: self.clone(),
+                in_flight: Coalescer::new("rpc_client"),
+            },
+            RpcClientConfig::with_commitment(CommitmentConfig::default()),
+        )
+    }
+
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut last_err = None;
+
+        for upstream in self.ranked() {
+            if !upstream.breaker.admit() {
+                continue;
+            }
+
+            let url = upstream.breaker.url();
+            let started = Instant::now();
+            let result = upstream.breaker.send(request, params.clone()).await;
+
+            if matches!(&result, Err(err) if unreachable(err)) {
+                UPSTREAM_REQUESTS
+                    .with_label_values(&[&url, "unreachable"])
+                    .inc();
+                UPSTREAM_FAILOVERS.with_label_values(&[&url]).inc();
+                warn!("Upstream rpc {url} didn't answer {request}, failing over");
+                last_err = result.err();
+                continue;
+            }
+
+            let outcome = if result.is_ok() { "ok" } else { "error" };
+            UPSTREAM_REQUESTS.with_label_values(&[&url, outcome]).inc();
+            upstream.record_latency(started.elapsed());
+            return result;
+        }
+
+        if let Some(cached) = self
+            .upstreams
+            .iter()
+            .find_map(|upstream| upstream.breaker.cached(request, &params))
+        {
+            return Ok(cached);
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            ErrorKind::Custom(format!(
+                "No upstream rpc is available, {request} not attempted"
+            ))
+            .into()
+        }))
+    }
+}
+
+struct UpstreamSender {
+    upstreams: Upstreams,
+    in_flight: Coalescer<(RpcRequest, String), Result<Value, Arc<ClientError>>>,
+}
+
+#[async_trait]
+impl RpcSender for UpstreamSender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        // sending twice is the caller's intent
+        if matches!(
+            request,
+            RpcRequest::SendTransaction | RpcRequest::RequestAirdrop
+        ) {
+            return self.upstreams.send(request, params).await;
+        }
+
+        let key = (request, params.to_string());
+        let upstreams = self.upstreams.clone();
+        self.in_flight
+            .run(key, async move {
+                upstreams.send(request, params).await.map_err(Arc::new)
+            })
+            .await
+            .map_err(|err| Arc::try_unwrap(err).unwrap_or_else(|err| copy_error(&err)))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        let mut stats = RpcTransportStats::default();
+        for upstream in self.upstreams.upstreams.iter() {
+            let upstream = upstream.breaker.sender().get_transport_stats();
+            stats.request_count += upstream.request_count;
+            stats.elapsed_time += upstream.elapsed_time;
+            stats.rate_limited_time += upstream.rate_limited_time;
+        }
+        stats
+    }
+
+    fn url(&self) -> String {
+        self.upstreams.url()
+    }
+}
+
+/// Copy of an error shared by the callers of a coalesced call, client errors aren't
+/// clone. Keeps the kind callers match on, errors only their crate builds become an
+/// alike kind carrying the message
+fn copy_error(err: &ClientError) -> ClientError {
+    let kind = match err.kind() {
+        ErrorKind::Io(err) => ErrorKind::Io(io::Error::new(err.kind(), err.to_string())),
+        // still unreachable to whoever checks
+        ErrorKind::Reqwest(err) => {
+            ErrorKind::Io(io::Error::new(io::ErrorKind::Other, err.to_string()))
+        }
+        ErrorKind::RpcError(err) => ErrorKind::RpcError(copy_rpc_error(err)),
+        ErrorKind::SerdeJson(err) => ErrorKind::SerdeJson(serde::de::Error::custom(err)),
+        ErrorKind::SigningError(err) => {
+            ErrorKind::SigningError(SignerError::Custom(err.to_string()))
+        }
+        ErrorKind::TransactionError(err) => ErrorKind::TransactionError(err.clone()),
+        ErrorKind::Custom(message) => ErrorKind::Custom(message.clone()),
+    };
+
+    ClientError {
+        request: err.request,
+        kind,
+    }
+}
+
+fn copy_rpc_error(err: &RpcError) -> RpcError {
+    match err {
+        RpcError::RpcRequestError(message) => RpcError::RpcRequestError(message.clone()),
+        RpcError::RpcResponseError {
+            code,
+            message,
+            data,
+        } => RpcError::RpcResponseError {
+            code: *code,
+            message: message.clone(),
+            data: match data {
+                RpcResponseErrorData::Empty => RpcResponseErrorData::Empty,
+                RpcResponseErrorData::SendTransactionPreflightFailure(result) => {
+                    RpcResponseErrorData::SendTransactionPreflightFailure(result.clone())
+                }
+                RpcResponseErrorData::NodeUnhealthy { num_slots_behind } => {
+                    RpcResponseErrorData::NodeUnhealthy {
+                        num_slots_behind: *num_slots_behind,
+                    }
+                }
+            },
+        },
+        RpcError::ParseError(message) => RpcError::ParseError(message.clone()),
+        RpcError::ForUser(message) => RpcError::ForUser(message.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_keep_the_error_kind() {
+        let err = ClientError::new_with_request(
+            ErrorKind::RpcError(RpcError::RpcResponseError {
+                code: -32005,
+                message: String::from("Node is behind"),
+                data: RpcResponseErrorData::NodeUnhealthy {
+                    num_slots_behind: Some(10),
+                },
+            }),
+            RpcRequest::GetSlot,
+        );
+        let copy = copy_error(&err);
+        assert_eq!(copy.request, Some(RpcRequest::GetSlot));
+        assert!(matches!(
+            copy.kind(),
+            ErrorKind::RpcError(RpcError::RpcResponseError {
+                code: -32005,
+                data: RpcResponseErrorData::NodeUnhealthy {
+                    num_slots_behind: Some(10)
+                },
+                ..
+            })
+        ));
+
+        let err = ClientError::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert!(unreachable(&copy_error(&err)));
+    }
+
+    #[test]
+    fn calls_move_off_unreachable_upstreams() {
+        let urls = vec![
+            String::from("http://first.invalid"),
+            String::from("http://second.invalid"),
+        ];
+        let upstreams = Upstreams::new(urls.clone());
+        assert_eq!(upstreams.url(), urls[0]);
+
+        upstreams.mark_unreachable(&urls[0]);
+        assert_eq!(upstreams.url(), urls[1]);
+        // every upstream is still known
+        assert_eq!(upstreams.urls(), urls);
+    }
+}
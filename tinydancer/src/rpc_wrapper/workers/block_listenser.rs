@@ -1,10 +1,13 @@
 use std::{
     collections::VecDeque,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use jsonrpsee::SubscriptionSink;
 use prometheus::{
     core::GenericGauge, histogram_opts, opts, register_histogram, register_int_counter,
@@ -12,31 +15,54 @@ use prometheus::{
 };
 use tiny_logger::logs::{info, warn};
 
+use serde::Serialize;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
 use solana_rpc_client::nonblocking::rpc_client::RpcClient;
 use solana_rpc_client_api::{
-    config::RpcBlockConfig,
-    response::{Response as RpcResponse, RpcResponseContext},
+    config::{RpcBlockConfig, RpcBlockSubscribeConfig, RpcBlockSubscribeFilter},
+    response::{
+        Response as RpcResponse, RpcBlockUpdate, RpcBlockUpdateError, RpcResponseContext,
+        SlotUpdate,
+    },
 };
 
 use solana_sdk::{
     commitment_config::{CommitmentConfig, CommitmentLevel},
+    pubkey::Pubkey,
+    signature::Signature,
     slot_history::Slot,
+    timing::timestamp,
+    transaction::{TransactionError, TransactionVersion},
 };
 
 use solana_transaction_status::{
-    option_serializer::OptionSerializer, RewardType, TransactionConfirmationStatus,
-    TransactionDetails, TransactionStatus, UiConfirmedBlock, UiTransactionEncoding,
-    UiTransactionStatusMeta,
+    option_serializer::OptionSerializer, EncodedTransactionWithStatusMeta, RewardType,
+    TransactionConfirmationStatus, TransactionDetails, TransactionStatus, UiConfirmedBlock,
+    UiTransactionEncoding, UiTransactionStatusMeta,
 };
 use tokio::{
-    sync::{mpsc::Sender, Mutex},
+    sync::{
+        broadcast,
+        mpsc::{Sender, UnboundedSender},
+        Mutex,
+    },
     task::JoinHandle,
     time::Instant,
 };
+use tokio_stream::StreamExt;
 
-use crate::rpc_wrapper::block_store::{BlockInformation, BlockStore};
+use crate::{
+    rpc_wrapper::{
+        block_store::{BlockInformation, BlockStore},
+        prioritization_fees::PrioritizationFeeCache,
+        upstreams::Upstreams,
+    },
+    sampler::get_sample_record,
+};
 
-use super::{TxProps, TxSender};
+use super::{
+    SubscriptionId, SubscriptionRegistry, TxFailure, TxProps, TxSender, TxState, Webhooks,
+};
 
 lazy_static::lazy_static! {
     static ref TT_RECV_CON_BLOCK: Histogram = register_histogram!(histogram_opts!(
@@ -49,6 +75,18 @@ lazy_static::lazy_static! {
         "Time to receive finalized block from block subscribe",
     ))
     .unwrap();
+    static ref TT_CONFIRM_TX: Histogram = register_histogram!(histogram_opts!(
+        "literpc_tt_confirm_tx",
+        "Time from a transaction's first send to its confirmation",
+        vec![0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0],
+    ))
+    .unwrap();
+    static ref TT_FINALIZE_TX: Histogram = register_histogram!(histogram_opts!(
+        "literpc_tt_finalize_tx",
+        "Time from a transaction's first send to its finalization",
+        vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0],
+    ))
+    .unwrap();
     static ref FIN_BLOCKS_RECV: IntCounter =
     register_int_counter!(opts!("literpc_fin_blocks_recv", "Number of Finalized Blocks Received")).unwrap();
     static ref CON_BLOCKS_RECV: IntCounter =
@@ -59,23 +97,127 @@ lazy_static::lazy_static! {
     register_int_counter!(opts!("literpc_incomplete_con_blocks_recv", "Number of Incomplete Confirmed Blocks Received")).unwrap();
     static ref TXS_CONFIRMED: IntCounter =
     register_int_counter!(opts!("literpc_txs_confirmed", "Number of Transactions Confirmed")).unwrap();
+    static ref TXS_PROCESSED: IntCounter =
+    register_int_counter!(opts!("literpc_txs_processed", "Number of Transactions seen processed before they were confirmed")).unwrap();
     static ref TXS_FINALIZED: IntCounter =
     register_int_counter!(opts!("literpc_txs_finalized", "Number of Transactions Finalized")).unwrap();
     static ref ERRORS_WHILE_FETCHING_SLOTS: IntCounter =
     register_int_counter!(opts!("literpc_txs_finalized", "Number of Transactions Finalized")).unwrap();
     static ref BLOCKS_IN_QUEUE: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_blocks_in_queue", "Number of blocks waiting to deque")).unwrap();
     static ref BLOCKS_IN_RETRY_QUEUE: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_blocks_in_retry_queue", "Number of blocks waiting in retry")).unwrap();
+    static ref BLOCKS_BACKFILLED: IntCounter =
+    register_int_counter!(opts!("literpc_blocks_backfilled", "Number of blocks the listeners missed and queued again")).unwrap();
+    static ref BLOCKS_STREAMED: IntCounter =
+    register_int_counter!(opts!("literpc_blocks_streamed", "Number of blocks received over the upstream block subscription")).unwrap();
+    static ref BLOCK_SUBSCRIPTIONS: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_block_subscriptions", "Upstream block subscriptions streaming blocks, the other commitments are polled")).unwrap();
     static ref NUMBER_OF_SIGNATURE_SUBSCRIBERS: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_number_of_signature_sub", "Number of signature subscriber")).unwrap();
 }
 
+/// Blocks buffered per block subscriber, slower ones skip what they missed
+const BLOCK_NOTIFICATION_BUFFER: usize = 32;
+
+/// Slot updates buffered per slots updates subscriber
+const SLOT_UPDATE_BUFFER: usize = 256;
+
+/// Time after which a failed or closed upstream block subscription is opened again,
+/// blocks are polled meanwhile
+const BLOCK_SUBSCRIBE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How far the upstream block subscription got, the poller only fills in around it
+#[derive(Default)]
+struct StreamProgress {
+    /// whether blocks are coming in over the subscription
+    streaming: AtomicBool,
+    /// first slot received since the subscription was opened
+    first_slot: AtomicU64,
+    /// latest slot received over the subscription
+    last_slot: AtomicU64,
+}
+
+type SlotRetry = (u64, u8, Instant);
+
+/// How far each listener checked for missed blocks, keyed by commitment
+pub const LISTENER_PROGRESS_CF: &str = "listener_progress";
+
+/// How often the slots behind a listener are checked for blocks it missed
+const GAP_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Recent slots left to the workers and their retries before they're checked
+const GAP_CHECK_LAG: u64 = 64;
+
+/// Slots of missed history backfilled at most, e.g. after a long downtime
+const MAX_BACKFILL_SLOTS: u64 = 10_000;
+
+/// How often sent transactions that didn't land yet are looked up at processed
+const PROCESSED_POLL_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Longest wait between lookups while the upstream keeps failing them
+const MAX_PROCESSED_POLL_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Sent transactions looked up per poll at most, the most recently sent first
+const MAX_PROCESSED_POLL_SIGNATURES: usize = 4 * MAX_SIGNATURE_STATUSES_QUERY;
+
+/// How long after it was sent a transaction is looked up, past its blockhash's
+/// lifetime it won't be processed anymore
+const PROCESSED_POLL_WINDOW: Duration = Duration::from_secs(90);
+
+/// Signatures a single getSignatureStatuses call may hold
+pub const MAX_SIGNATURE_STATUSES_QUERY: usize = 256;
+
+/// Orders confirmation statuses from processed to finalized
+fn confirmation_rank(status: &TransactionConfirmationStatus) -> u8 {
+    match status {
+        TransactionConfirmationStatus::Processed => 0,
+        TransactionConfirmationStatus::Confirmed => 1,
+        TransactionConfirmationStatus::Finalized => 2,
+    }
+}
+
+/// `blockNotification` payload, the block as seen by the listener and the sampler's
+/// verdict on its slot
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiedBlockUpdate {
+    #[serde(flatten)]
+    pub update: RpcBlockUpdate,
+    /// `None` if the sampler hasn't got to the slot yet
+    pub verified: Option<bool>,
+}
+
+/// A block indexed by the listener, shared between block subscribers
+#[derive(Clone)]
+pub struct IndexedBlock {
+    pub commitment_config: CommitmentConfig,
+    pub slot: Slot,
+    pub block: Arc<UiConfirmedBlock>,
+    pub verified: Option<bool>,
+}
+
 /// Background worker which listen's to new blocks
 /// and keeps a track of confirmed txs
 #[derive(Clone)]
 pub struct BlockListener {
     tx_sender: TxSender,
     block_store: BlockStore,
+    prioritization_fees: PrioritizationFeeCache,
     rpc_client: Arc<RpcClient>,
-    signature_subscribers: Arc<DashMap<(String, CommitmentConfig), (SubscriptionSink, Instant)>>,
+    /// blocks are streamed over the pubsub of the best upstream
+    upstreams: Upstreams,
+    db_instance: Arc<rocksdb::DB>,
+    subscriptions: SubscriptionRegistry,
+    /// posts the statuses of tracked transactions to their callback urls
+    webhooks: Webhooks,
+    /// every subscriber of a signature, notified once and then unsubscribed
+    signature_subscribers: Arc<DashMap<(String, CommitmentConfig), Vec<(SubscriptionId, Instant)>>>,
+    /// signature subscribers told when the transaction goes out to the tpu, they stay
+    /// subscribed for its commitment
+    received_subscribers: Arc<DashMap<String, Vec<SubscriptionId>>>,
+    indexed_blocks: broadcast::Sender<IndexedBlock>,
+    slot_updates: broadcast::Sender<SlotUpdate>,
+    /// slots indexed at each commitment that weren't checked for gaps yet
+    indexed_slots: Arc<DashSet<(Slot, CommitmentConfig)>>,
+    /// confirmation status a sent transaction is done at
+    terminal_status: TransactionConfirmationStatus,
 }
 
 pub struct BlockListnerNotificatons {
@@ -84,12 +226,61 @@ pub struct BlockListnerNotificatons {
 }
 
 impl BlockListener {
-    pub fn new(rpc_client: Arc<RpcClient>, tx_sender: TxSender, block_store: BlockStore) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        rpc_client: Arc<RpcClient>,
+        upstreams: Upstreams,
+        tx_sender: TxSender,
+        block_store: BlockStore,
+        prioritization_fees: PrioritizationFeeCache,
+        db_instance: Arc<rocksdb::DB>,
+        subscriptions: SubscriptionRegistry,
+        webhooks: Webhooks,
+        terminal_status: TransactionConfirmationStatus,
+    ) -> Self {
         Self {
             rpc_client,
+            upstreams,
             tx_sender,
             block_store,
+            prioritization_fees,
+            db_instance,
+            subscriptions,
+            webhooks,
             signature_subscribers: Default::default(),
+            received_subscribers: Default::default(),
+            indexed_blocks: broadcast::channel(BLOCK_NOTIFICATION_BUFFER).0,
+            slot_updates: broadcast::channel(SLOT_UPDATE_BUFFER).0,
+            indexed_slots: Default::default(),
+            terminal_status,
+        }
+    }
+
+    /// Commitment of the terminal confirmation status
+    pub fn terminal_commitment(&self) -> CommitmentConfig {
+        match self.terminal_status {
+            TransactionConfirmationStatus::Processed => CommitmentConfig::processed(),
+            TransactionConfirmationStatus::Confirmed => CommitmentConfig::confirmed(),
+            TransactionConfirmationStatus::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+
+    /// Whether a transaction with `status` reached the terminal confirmation status
+    pub fn is_terminal(&self, status: &TransactionStatus) -> bool {
+        status
+            .confirmation_status
+            .as_ref()
+            .map_or(false, |confirmation_status| {
+                confirmation_rank(confirmation_status) >= confirmation_rank(&self.terminal_status)
+            })
+    }
+
+    /// Signatures can be followed at processed as well, blocks only from confirmed on
+    fn get_signature_commitment_config(commitment_config: CommitmentConfig) -> CommitmentConfig {
+        if commitment_config.is_at_least_confirmed() {
+            Self::get_supported_commitment_config(commitment_config)
+        } else {
+            CommitmentConfig::processed()
         }
     }
 
@@ -104,7 +295,9 @@ impl BlockListener {
     }
 
     #[allow(deprecated)]
-    fn get_supported_commitment_config(commitment_config: CommitmentConfig) -> CommitmentConfig {
+    pub fn get_supported_commitment_config(
+        commitment_config: CommitmentConfig,
+    ) -> CommitmentConfig {
         match commitment_config.commitment {
             CommitmentLevel::Finalized | CommitmentLevel::Root | CommitmentLevel::Max => {
                 CommitmentConfig {
@@ -121,19 +314,135 @@ impl BlockListener {
         &self,
         signature: String,
         commitment_config: CommitmentConfig,
+        enable_received_notification: bool,
         sink: SubscriptionSink,
     ) {
-        let commitment_config = Self::get_supported_commitment_config(commitment_config);
+        let commitment_config = Self::get_signature_commitment_config(commitment_config);
+        let id = self.subscriptions.register("signature", sink);
+        if enable_received_notification {
+            let sent = self
+                .tx_sender
+                .txs_sent_store
+                .get(&signature)
+                .map_or(false, |props| props.state == TxState::Sent);
+            if sent {
+                // it went out before the subscription
+                let block_listener = self.clone();
+                tokio::spawn(async move {
+                    let slot = block_listener
+                        .block_store
+                        .get_latest_block_info(CommitmentConfig::confirmed())
+                        .await
+                        .slot;
+                    block_listener.notify_received(id, slot);
+                });
+            } else {
+                self.received_subscribers
+                    .entry(signature.clone())
+                    .or_default()
+                    .push(id);
+            }
+        }
         self.signature_subscribers
-            .insert((signature, commitment_config), (sink, Instant::now()));
+            .entry((signature.clone(), commitment_config))
+            .or_default()
+            .push((id, Instant::now()));
         NUMBER_OF_SIGNATURE_SUBSCRIBERS.inc();
+
+        // it won't land and did so before the subscription, ones that failed on chain
+        // are told at the commitment they reach
+        let failure = self
+            .tx_sender
+            .txs_sent_store
+            .get(&signature)
+            .and_then(|props| props.failure.clone())
+            .filter(|failure| !matches!(failure, TxFailure::OnChain { .. }));
+        if let Some(failure) = failure {
+            let slot = self
+                .block_store
+                .latest_block_info(CommitmentConfig::confirmed())
+                .slot;
+            self.notify_failure(&signature, slot, &failure);
+        }
+    }
+
+    /// Streams the blocks of the given commitment as they get indexed, `mentions`
+    /// only keeps the transactions referencing that account
+    pub fn block_subscribe(
+        &self,
+        mentions: Option<Pubkey>,
+        config: RpcBlockSubscribeConfig,
+        sink: SubscriptionSink,
+    ) {
+        let commitment_config =
+            Self::get_supported_commitment_config(config.commitment.unwrap_or_default());
+
+        self.subscriptions.fan_out(
+            "block",
+            sink,
+            self.indexed_blocks.subscribe(),
+            move |indexed: IndexedBlock| {
+                if indexed.commitment_config != commitment_config {
+                    return None;
+                }
+
+                let (block, err) =
+                    match block_for_subscriber(&indexed.block, mentions.as_ref(), &config)? {
+                        Ok(block) => (Some(block), None),
+                        Err(err) => (None, Some(err)),
+                    };
+                Some(RpcResponse {
+                    context: RpcResponseContext {
+                        slot: indexed.slot,
+                        api_version: None,
+                    },
+                    value: VerifiedBlockUpdate {
+                        update: RpcBlockUpdate {
+                            slot: indexed.slot,
+                            block,
+                            err,
+                        },
+                        verified: indexed.verified,
+                    },
+                })
+            },
+        );
+    }
+
+    pub fn slots_updates_subscribe(&self, sink: SubscriptionSink) {
+        self.subscriptions
+            .fan_out("slots_updates", sink, self.slot_updates.subscribe(), Some);
+    }
+
+    /// Receives every block indexed from now on, of any commitment
+    pub fn indexed_blocks(&self) -> broadcast::Receiver<IndexedBlock> {
+        self.indexed_blocks.subscribe()
+    }
+
+    /// Receives every slot update published from now on
+    pub fn slot_updates(&self) -> broadcast::Receiver<SlotUpdate> {
+        self.slot_updates.subscribe()
+    }
+
+    /// Publishes a slot update to the slots updates subscribers, the block listener
+    /// only knows about confirmations and roots, earlier stages (first shred, completed,
+    /// frozen) have to come from a shred listener
+    pub fn publish_slot_update(&self, update: SlotUpdate) {
+        // no receivers left is not an error
+        let _ = self.slot_updates.send(update);
     }
 
     pub fn signature_un_subscribe(&self, signature: String, commitment_config: CommitmentConfig) {
-        let commitment_config = Self::get_supported_commitment_config(commitment_config);
-        self.signature_subscribers
-            .remove(&(signature, commitment_config));
-        NUMBER_OF_SIGNATURE_SUBSCRIBERS.dec();
+        let commitment_config = Self::get_signature_commitment_config(commitment_config);
+        if let Some((_, subscribers)) = self
+            .signature_subscribers
+            .remove(&(signature, commitment_config))
+        {
+            for (id, _) in &subscribers {
+                self.subscriptions.unsubscribe(*id);
+            }
+            NUMBER_OF_SIGNATURE_SUBSCRIBERS.sub(subscribers.len() as i64);
+        }
     }
 
     fn increment_invalid_block_metric(commitment_config: CommitmentConfig) {
@@ -150,19 +459,12 @@ impl BlockListener {
         commitment_config: CommitmentConfig,
     ) -> anyhow::Result<()> {
         //info!("indexing slot {} commitment {}", slot, commitment_config.commitment);
-        let comfirmation_status = match commitment_config.commitment {
-            CommitmentLevel::Finalized => TransactionConfirmationStatus::Finalized,
-            _ => TransactionConfirmationStatus::Confirmed,
-        };
-
         let timer = if commitment_config.is_finalized() {
             TT_RECV_FIN_BLOCK.start_timer()
         } else {
             TT_RECV_CON_BLOCK.start_timer()
         };
 
-        let start = Instant::now();
-
         let block = self
             .rpc_client
             .get_block_with_config(
@@ -178,6 +480,25 @@ impl BlockListener {
             .await?;
         timer.observe_duration();
 
+        self.index_block(slot, block, commitment_config).await
+    }
+
+    /// Records the block of `slot` and the statuses of the transactions we sent in it,
+    /// then notifies their subscribers
+    pub async fn index_block(
+        &self,
+        slot: Slot,
+        block: UiConfirmedBlock,
+        commitment_config: CommitmentConfig,
+    ) -> anyhow::Result<()> {
+        let comfirmation_status = match commitment_config.commitment {
+            CommitmentLevel::Finalized => TransactionConfirmationStatus::Finalized,
+            _ => TransactionConfirmationStatus::Confirmed,
+        };
+
+        let start = Instant::now();
+        self.indexed_slots.insert((slot, commitment_config));
+
         if commitment_config.is_finalized() {
             FIN_BLOCKS_RECV.inc();
         } else {
@@ -189,27 +510,44 @@ impl BlockListener {
             return Ok(());
         };
 
+        // only cloned when someone listens
+        let indexed_block = (self.indexed_blocks.receiver_count() > 0).then(|| block.clone());
+
         let Some(transactions) = block.transactions else {
                 Self::increment_invalid_block_metric(commitment_config);
                 return Ok(());
          };
 
         let blockhash = block.blockhash;
-        //let parent_slot = block.parent_slot;
 
         self.block_store
             .add_block(
                 blockhash.clone(),
                 BlockInformation {
                     slot,
+                    parent_slot: block.parent_slot,
                     block_height,
+                    block_time: block.block_time,
                     instant: Instant::now(),
                 },
                 commitment_config,
             )
             .await;
 
+        self.publish_slot_update(if commitment_config.is_finalized() {
+            SlotUpdate::Root {
+                slot,
+                timestamp: timestamp(),
+            }
+        } else {
+            SlotUpdate::OptimisticConfirmation {
+                slot,
+                timestamp: timestamp(),
+            }
+        });
+
         let mut transactions_processed = 0;
+        let mut decoded_transactions = Vec::with_capacity(transactions.len());
         for tx in transactions {
             let Some(UiTransactionStatusMeta { err, status, compute_units_consumed: _ ,.. }) = tx.meta else {
                 info!("tx with no meta");
@@ -225,6 +563,7 @@ impl BlockListener {
             };
             transactions_processed += 1;
             let sig = tx.signatures[0].to_string();
+            decoded_transactions.push(tx);
 
             if let Some(mut tx_status) = self.tx_sender.txs_sent_store.get_mut(&sig) {
                 //
@@ -237,30 +576,72 @@ impl BlockListener {
                         TXS_CONFIRMED.inc();
                     }
                 }
+                // only the first time it reaches the commitment, blocks may be indexed twice
+                let reached = tx_status
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.confirmation_status.as_ref())
+                    .map_or(true, |current| {
+                        confirmation_rank(current) < confirmation_rank(&comfirmation_status)
+                    });
+                if reached {
+                    let elapsed = tx_status.sent_at.elapsed().as_secs_f64();
+                    if commitment_config.is_finalized() {
+                        TT_FINALIZE_TX.observe(elapsed);
+                    } else {
+                        TT_CONFIRM_TX.observe(elapsed);
+                    }
+                }
 
-                tx_status.value_mut().status = Some(TransactionStatus {
+                let new_status = TransactionStatus {
                     slot,
                     confirmations: None,
                     status,
                     err: err.clone(),
                     confirmation_status: Some(comfirmation_status.clone()),
-                });
+                };
+                tx_status.value_mut().status = Some(new_status.clone());
+                // landed in a block indexed late, after it was taken for expired
+                if tx_status.state == TxState::Expired {
+                    tx_status.state = TxState::Sent;
+                }
+                tx_status.failure = err.clone().map(|err| TxFailure::OnChain { err });
+                let callback_url = tx_status.callback_url.clone();
+                drop(tx_status);
+                self.tx_sender.persist_tx(&sig);
+                self.webhooks
+                    .notify(callback_url.as_deref(), sig.clone(), new_status);
             };
 
-            // subscribers
-            if let Some((_sig, (mut sink, _))) =
-                self.signature_subscribers.remove(&(sig, commitment_config))
-            {
-                // none if transaction succeeded
-                sink.send(&RpcResponse {
-                    context: RpcResponseContext {
-                        slot,
-                        api_version: None,
-                    },
-                    value: serde_json::json!({ "err": err }),
-                })?;
-                NUMBER_OF_SIGNATURE_SUBSCRIBERS.dec();
-            }
+            self.notify_signature_subscribers(sig, commitment_config, slot, err);
+        }
+
+        // confirmed blocks are recorded first, finalization doesn't change the fees
+        if !commitment_config.is_finalized() {
+            self.prioritization_fees
+                .add_block(slot, &decoded_transactions)
+                .await;
+        } else {
+            // confirmed blocks are indexed out of order, one holding the transaction may
+            // still be on its way, but by finalization its ancestors were long indexed
+            self.expire_transactions(block_height, slot);
+        }
+
+        if let Some(block) = indexed_block {
+            let verified = match get_sample_record(&self.db_instance, slot) {
+                Ok(record) => record.map(|record| record.verified),
+                Err(err) => {
+                    warn!("Couldn't read the sample record of slot {slot}: {err}");
+                    None
+                }
+            };
+            // no receivers left is not an error
+            let _ = self.indexed_blocks.send(IndexedBlock {
+                commitment_config,
+                slot,
+                block: Arc::new(block),
+                verified,
+            });
         }
 
         info!(
@@ -273,6 +654,138 @@ impl BlockListener {
 
         Ok(())
     }
+    /// Notifies the subscribers of `sig` at `commitment_config` and unsubscribes them,
+    /// `err` is none if the transaction succeeded
+    fn notify_signature_subscribers(
+        &self,
+        sig: String,
+        commitment_config: CommitmentConfig,
+        slot: Slot,
+        err: Option<TransactionError>,
+    ) {
+        let value = match err {
+            Some(err) => TxFailure::OnChain { err }.notification(),
+            None => serde_json::json!({ "err": null }),
+        };
+        self.send_signature_notification(sig, commitment_config, slot, value);
+    }
+
+    /// Tells the subscribers of `sig` at every commitment why it won't land
+    fn notify_failure(&self, sig: &str, slot: Slot, failure: &TxFailure) {
+        for commitment_config in [
+            CommitmentConfig::processed(),
+            CommitmentConfig::confirmed(),
+            CommitmentConfig::finalized(),
+        ] {
+            self.send_signature_notification(
+                sig.to_owned(),
+                commitment_config,
+                slot,
+                failure.notification(),
+            );
+        }
+        // it won't go out to the tpu anymore
+        self.received_subscribers.remove(sig);
+    }
+
+    fn send_signature_notification(
+        &self,
+        sig: String,
+        commitment_config: CommitmentConfig,
+        slot: Slot,
+        value: serde_json::Value,
+    ) {
+        let Some((_sig, subscribers)) = self.signature_subscribers.remove(&(sig, commitment_config)) else {
+            return;
+        };
+
+        let notification = RpcResponse {
+            context: RpcResponseContext {
+                slot,
+                api_version: None,
+            },
+            value,
+        };
+        for (id, _) in &subscribers {
+            self.subscriptions.notify(*id, &notification);
+            self.subscriptions.unsubscribe(*id);
+        }
+        NUMBER_OF_SIGNATURE_SUBSCRIBERS.sub(subscribers.len() as i64);
+    }
+
+    fn notify_received(&self, id: SubscriptionId, slot: Slot) {
+        let notification = RpcResponse {
+            context: RpcResponseContext {
+                slot,
+                api_version: None,
+            },
+            value: serde_json::json!("receivedSignature"),
+        };
+        self.subscriptions.notify(id, &notification);
+    }
+
+    /// Tells the received subscribers of the transactions going out to the tpu
+    pub fn listen_received(self) -> JoinHandle<anyhow::Result<()>> {
+        let mut sent_batches = self.tx_sender.sent_batches();
+        tokio::spawn(async move {
+            loop {
+                let batch = match sent_batches.recv().await {
+                    Ok(batch) => batch,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Missed {missed} sent batches, their received subscribers aren't notified");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        anyhow::bail!("Tx sender stopped");
+                    }
+                };
+                for (sig, slot) in batch {
+                    let Some((_, subscribers)) = self.received_subscribers.remove(&sig) else {
+                        continue;
+                    };
+                    for id in subscribers {
+                        self.notify_received(id, slot);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Tells the signature subscribers of the transactions the tx sender failed
+    pub fn listen_failed(self) -> JoinHandle<anyhow::Result<()>> {
+        let mut failed_txs = self.tx_sender.failed_txs();
+        tokio::spawn(async move {
+            loop {
+                let failed = match failed_txs.recv().await {
+                    Ok(failed) => failed,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Missed {missed} failed batches, their signature subscribers aren't notified");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        anyhow::bail!("Tx sender stopped");
+                    }
+                };
+                let slot = self
+                    .block_store
+                    .get_latest_block_info(CommitmentConfig::confirmed())
+                    .await
+                    .slot;
+                for (sig, failure) in failed {
+                    self.notify_failure(&sig, slot, &failure);
+                }
+            }
+        })
+    }
+
+    /// Expires the transactions whose blockhash is too old for the finalized block at
+    /// `block_height`, their subscribers are told the blockhash wasn't found
+    fn expire_transactions(&self, block_height: u64, slot: Slot) {
+        for (sig, failure) in self.tx_sender.expire(block_height) {
+            self.notify_failure(&sig, slot, &failure);
+        }
+    }
+
     pub fn listen(self, commitment_config: CommitmentConfig) -> JoinHandle<anyhow::Result<()>> {
         let slots_task_queue = Arc::new(Mutex::new(VecDeque::<(u64, u8)>::new()));
         let (slot_retry_queue_sx, mut slot_retry_queue_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -322,6 +835,14 @@ impl BlockListener {
             });
         }
 
+        // blocks come in over the upstream subscription when it's up, polled otherwise
+        let progress = Arc::new(StreamProgress::default());
+        self.clone()
+            .stream_blocks(commitment_config, progress.clone(), slot_retry_queue_sx);
+
+        self.clone()
+            .backfill(commitment_config, slots_task_queue.clone());
+
         // a task that will queue back the slots to be retried after a certain delay
         let recent_slot = Arc::new(AtomicU64::new(0));
         {
@@ -371,6 +892,23 @@ impl BlockListener {
             // storage for recent slots processed
             let rpc_client = rpc_client.clone();
             loop {
+                if progress.streaming.load(Ordering::Relaxed) {
+                    // the slots between the last poll and the subscription's first block
+                    let first_slot = progress.first_slot.load(Ordering::Relaxed);
+                    if last_latest_slot < first_slot {
+                        let mut lock = slots_task_queue.lock().await;
+                        for slot in last_latest_slot..first_slot {
+                            lock.push_back((slot, 0));
+                        }
+                        BLOCKS_IN_QUEUE.set(lock.len() as i64);
+                    }
+                    last_latest_slot =
+                        last_latest_slot.max(progress.last_slot.load(Ordering::Relaxed) + 1);
+                    recent_slot.store(last_latest_slot, Ordering::Relaxed);
+                    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+                    continue;
+                }
+
                 let new_slot = match rpc_client.get_slot_with_commitment(commitment_config).await {
                     Ok(new_slot) => new_slot,
                     Err(err) => {
@@ -405,15 +943,372 @@ impl BlockListener {
         })
     }
 
+    /// Polls the upstream for the sent transactions that didn't land yet, those it
+    /// processed are reported processed until the block listener sees them confirmed
+    pub fn listen_processed(self) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let mut delay = PROCESSED_POLL_INTERVAL;
+
+            loop {
+                tokio::time::sleep(delay).await;
+                let mut failed = false;
+
+                let mut pending: Vec<(std::time::Instant, String)> = self
+                    .tx_sender
+                    .txs_sent_store
+                    .iter()
+                    .filter(|props| {
+                        props.status.is_none()
+                            && props.state == TxState::Sent
+                            && props.sent_at.elapsed() < PROCESSED_POLL_WINDOW
+                    })
+                    .map(|props| (props.sent_at, props.key().clone()))
+                    .collect();
+                pending.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+                pending.truncate(MAX_PROCESSED_POLL_SIGNATURES);
+                let pending: Vec<String> = pending.into_iter().map(|(_, sig)| sig).collect();
+
+                for sigs in pending.chunks(MAX_SIGNATURE_STATUSES_QUERY) {
+                    let (sigs, signatures): (Vec<&String>, Vec<Signature>) = sigs
+                        .iter()
+                        .filter_map(|sig| Some((sig, sig.parse().ok()?)))
+                        .unzip();
+
+                    let statuses = match self.rpc_client.get_signature_statuses(&signatures).await {
+                        Ok(statuses) => statuses.value,
+                        Err(err) => {
+                            warn!("Couldn't look up the processed transactions {err}");
+                            failed = true;
+                            break;
+                        }
+                    };
+
+                    for (sig, status) in sigs.into_iter().zip(statuses) {
+                        if let Some(status) = status {
+                            self.mark_processed(sig, status);
+                        }
+                    }
+                }
+
+                // the upstream struggles, don't pile on
+                delay = match failed {
+                    true => (delay * 2).min(MAX_PROCESSED_POLL_BACKOFF),
+                    false => PROCESSED_POLL_INTERVAL,
+                };
+            }
+        })
+    }
+
+    fn mark_processed(&self, sig: &str, status: TransactionStatus) {
+        let Some(mut props) = self.tx_sender.txs_sent_store.get_mut(sig) else {
+            return;
+        };
+        // the block listener got to it first
+        if props.status.is_some() {
+            return;
+        }
+
+        let slot = status.slot;
+        let err = status.err.clone();
+        props.status = Some(TransactionStatus {
+            confirmation_status: Some(TransactionConfirmationStatus::Processed),
+            ..status
+        });
+        props.failure = err.clone().map(|err| TxFailure::OnChain { err });
+        drop(props);
+        self.tx_sender.persist_tx(sig);
+        TXS_PROCESSED.inc();
+
+        self.notify_signature_subscribers(sig.to_owned(), CommitmentConfig::processed(), slot, err);
+    }
+
+    /// Checks the slots behind the listener for blocks it didn't index, missed while
+    /// the upstream hiccuped or before a restart, and queues them. How far it checked
+    /// is persisted so a restart picks up where it left off
+    fn backfill(
+        self,
+        commitment_config: CommitmentConfig,
+        slots_task_queue: Arc<Mutex<VecDeque<(u64, u8)>>>,
+    ) {
+        tokio::spawn(async move {
+            let mut checked_up_to = self.read_progress(commitment_config);
+            let mut interval = tokio::time::interval(GAP_CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let latest_slot = match self
+                    .rpc_client
+                    .get_slot_with_commitment(commitment_config)
+                    .await
+                {
+                    Ok(latest_slot) => latest_slot,
+                    Err(err) => {
+                        warn!("Couldn't fetch the latest slot to check for gaps {err}");
+                        continue;
+                    }
+                };
+                let end = latest_slot.saturating_sub(GAP_CHECK_LAG);
+                // nothing indexed before this run to check against
+                let Some(start) = checked_up_to else {
+                    checked_up_to = Some(end + 1);
+                    continue;
+                };
+                let start = start.max(end.saturating_sub(MAX_BACKFILL_SLOTS));
+                if start > end {
+                    continue;
+                }
+
+                // skipped slots have no block to miss
+                let slots = match self
+                    .rpc_client
+                    .get_blocks_with_commitment(start, Some(end), commitment_config)
+                    .await
+                {
+                    Ok(slots) => slots,
+                    Err(err) => {
+                        warn!("Couldn't fetch the blocks of slots {start} to {end} {err}");
+                        continue;
+                    }
+                };
+                let missed: Vec<Slot> = slots
+                    .into_iter()
+                    .filter(|slot| !self.indexed_slots.contains(&(*slot, commitment_config)))
+                    .collect();
+
+                if !missed.is_empty() {
+                    warn!(
+                        "Backfilling {} block(s) missed between slots {start} and {end} at commitment {}",
+                        missed.len(),
+                        commitment_config.commitment
+                    );
+                    BLOCKS_BACKFILLED.inc_by(missed.len() as u64);
+                    let mut queue = slots_task_queue.lock().await;
+                    for slot in missed {
+                        queue.push_back((slot, 0));
+                    }
+                    BLOCKS_IN_QUEUE.set(queue.len() as i64);
+                }
+
+                self.indexed_slots
+                    .retain(|(slot, commitment)| *commitment != commitment_config || *slot > end);
+                checked_up_to = Some(end + 1);
+                self.write_progress(commitment_config, end + 1);
+            }
+        });
+    }
+
+    fn read_progress(&self, commitment_config: CommitmentConfig) -> Option<Slot> {
+        let cf = self.db_instance.cf_handle(LISTENER_PROGRESS_CF)?;
+        let progress = self
+            .db_instance
+            .get_cf(cf, commitment_config.commitment.to_string())
+            .ok()??;
+        Some(Slot::from_be_bytes(progress.try_into().ok()?))
+    }
+
+    fn write_progress(&self, commitment_config: CommitmentConfig, slot: Slot) {
+        let Some(cf) = self.db_instance.cf_handle(LISTENER_PROGRESS_CF) else {
+            return;
+        };
+        if let Err(err) = self.db_instance.put_cf(
+            cf,
+            commitment_config.commitment.to_string(),
+            slot.to_be_bytes(),
+        ) {
+            warn!("Couldn't persist the listener progress {err}");
+        }
+    }
+
+    /// Keeps a block subscription open on the upstream and indexes the blocks it streams,
+    /// slots it couldn't index go to the retry queue of the poller
+    fn stream_blocks(
+        self,
+        commitment_config: CommitmentConfig,
+        progress: Arc<StreamProgress>,
+        slot_retry_queue: UnboundedSender<SlotRetry>,
+    ) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self
+                    .subscribe_blocks(commitment_config, &progress, &slot_retry_queue)
+                    .await
+                {
+                    warn!(
+                        "Block subscription at commitment {} ended, polling blocks {err}",
+                        commitment_config.commitment
+                    );
+                }
+                if progress.streaming.swap(false, Ordering::Relaxed) {
+                    BLOCK_SUBSCRIPTIONS.dec();
+                }
+                tokio::time::sleep(BLOCK_SUBSCRIBE_RETRY_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn subscribe_blocks(
+        &self,
+        commitment_config: CommitmentConfig,
+        progress: &StreamProgress,
+        slot_retry_queue: &UnboundedSender<SlotRetry>,
+    ) -> anyhow::Result<()> {
+        let pubsub = PubsubClient::new(&self.upstreams.ws_url()).await?;
+        let (mut blocks, _unsubscribe) = pubsub
+            .block_subscribe(
+                RpcBlockSubscribeFilter::All,
+                Some(RpcBlockSubscribeConfig {
+                    commitment: Some(commitment_config),
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    transaction_details: Some(TransactionDetails::Full),
+                    show_rewards: Some(true),
+                    max_supported_transaction_version: Some(0),
+                }),
+            )
+            .await?;
+
+        while let Some(update) = blocks.next().await {
+            let RpcBlockUpdate { slot, block, err } = update.value;
+            if !progress.streaming.load(Ordering::Relaxed) {
+                progress.first_slot.store(slot, Ordering::Relaxed);
+                progress.streaming.store(true, Ordering::Relaxed);
+                BLOCK_SUBSCRIPTIONS.inc();
+                info!(
+                    "Streaming blocks at commitment {} from slot {slot}",
+                    commitment_config.commitment
+                );
+            }
+            progress.last_slot.fetch_max(slot, Ordering::Relaxed);
+            BLOCKS_STREAMED.inc();
+
+            let indexed = match (block, err) {
+                (Some(block), None) => self.index_block(slot, block, commitment_config).await,
+                (_, err) => Err(anyhow::anyhow!("no block streamed for slot {slot} {err:?}")),
+            };
+            if indexed.is_err() {
+                let _ = slot_retry_queue.send((slot, 0, Instant::now()));
+                BLOCKS_IN_RETRY_QUEUE.inc();
+            }
+        }
+
+        anyhow::bail!("stream closed")
+    }
+
     pub fn clean(&self, ttl_duration: Duration) {
-        let length_before = self.signature_subscribers.len();
-        self.signature_subscribers
-            .retain(|_k, (sink, instant)| !sink.is_closed() && instant.elapsed() < ttl_duration);
+        let mut cleaned = 0;
+        self.signature_subscribers.retain(|_k, subscribers| {
+            subscribers.retain(|(id, instant)| {
+                let keep = self.subscriptions.is_active(*id) && instant.elapsed() < ttl_duration;
+                if !keep {
+                    self.subscriptions.unsubscribe(*id);
+                    cleaned += 1;
+                }
+                keep
+            });
+            !subscribers.is_empty()
+        });
+        // they're gone once notified at their commitment or cleaned up above
+        self.received_subscribers.retain(|_sig, subscribers| {
+            subscribers.retain(|id| self.subscriptions.is_active(*id));
+            !subscribers.is_empty()
+        });
 
-        NUMBER_OF_SIGNATURE_SUBSCRIBERS.set(self.signature_subscribers.len() as i64);
-        info!(
-            "Cleaned {} Signature Subscribers",
-            length_before - self.signature_subscribers.len()
+        NUMBER_OF_SIGNATURE_SUBSCRIBERS.set(
+            self.signature_subscribers
+                .iter()
+                .map(|subscribers| subscribers.len() as i64)
+                .sum(),
         );
+        info!("Cleaned {} Signature Subscribers", cleaned);
     }
 }
+
+/// Shapes an indexed block the way a subscriber asked for it, `None` if none of its
+/// transactions reference `mentions`. A block holding transactions of a version the
+/// subscriber doesn't support is an error, like upstream
+fn block_for_subscriber(
+    block: &UiConfirmedBlock,
+    mentions: Option<&Pubkey>,
+    config: &RpcBlockSubscribeConfig,
+) -> Option<Result<UiConfirmedBlock, RpcBlockUpdateError>> {
+    let mut block = block.clone();
+
+    if let Some(mentions) = mentions {
+        let mentioned = mentions.to_string();
+        let transactions = block
+            .transactions
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|tx| loads_account(tx, mentions, &mentioned))
+            .collect::<Vec<_>>();
+
+        if transactions.is_empty() {
+            return None;
+        }
+        block.transactions = Some(transactions);
+    }
+
+    let unsupported = block
+        .transactions
+        .iter()
+        .flatten()
+        .filter_map(|tx| match tx.transaction.decode()?.version() {
+            TransactionVersion::Legacy(_) => None,
+            TransactionVersion::Number(version) => Some(version),
+        })
+        .find(|&version| {
+            config
+                .max_supported_transaction_version
+                .map_or(true, |max| version > max)
+        });
+    if let Some(version) = unsupported {
+        return Some(Err(RpcBlockUpdateError::UnsupportedTransactionVersion(
+            version,
+        )));
+    }
+    // only told to subscribers that know about versions
+    if config.max_supported_transaction_version.is_none() {
+        for tx in block.transactions.iter_mut().flatten() {
+            tx.version = None;
+        }
+    }
+
+    match config.transaction_details.unwrap_or_default() {
+        TransactionDetails::Signatures => {
+            block.signatures = block.transactions.take().map(|transactions| {
+                transactions
+                    .iter()
+                    .filter_map(|tx| tx.transaction.decode())
+                    .map(|tx| tx.signatures[0].to_string())
+                    .collect()
+            });
+        }
+        TransactionDetails::None => block.transactions = None,
+        // other details are rejected on subscribe
+        _ => {}
+    }
+
+    if !config.show_rewards.unwrap_or(true) {
+        block.rewards = None;
+    }
+
+    Some(Ok(block))
+}
+
+/// Whether `tx` loads `account`, `mentioned` as a string, from its message or from an
+/// address lookup table
+fn loads_account(tx: &EncodedTransactionWithStatusMeta, account: &Pubkey, mentioned: &str) -> bool {
+    let static_keys = tx.transaction.decode().map_or(false, |tx| {
+        tx.message.static_account_keys().contains(account)
+    });
+    let loaded = match tx.meta.as_ref().map(|meta| &meta.loaded_addresses) {
+        Some(OptionSerializer::Some(loaded)) => loaded
+            .writable
+            .iter()
+            .chain(&loaded.readonly)
+            .any(|key| key == mentioned),
+        _ => false,
+    };
+    static_keys || loaded
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, bail};
+use prometheus::{register_int_counter, IntCounter};
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+use tiny_logger::logs::info;
+
+lazy_static::lazy_static! {
+    static ref BUNDLES_SENT: IntCounter =
+        register_int_counter!("literpc_bundles_sent", "Number of bundles forwarded to the block engine").unwrap();
+    static ref BUNDLES_SENT_ERRORS: IntCounter =
+    register_int_counter!("literpc_bundles_sent_errors", "Number of errors while forwarding bundles to the block engine").unwrap();
+}
+
+/// Transactions a block engine accepts in a single bundle
+pub const MAX_BUNDLE_LEN: usize = 5;
+
+/// Forwards bundles to a Jito block engine
+#[derive(Clone)]
+pub struct BundleForwarder {
+    block_engine_url: String,
+    client: reqwest::Client,
+}
+
+impl BundleForwarder {
+    pub fn new(block_engine_url: String) -> Self {
+        Self {
+            block_engine_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Submits base58 encoded transactions as one bundle, returns the bundle id
+    pub async fn send_bundle(&self, txs: Vec<String>) -> anyhow::Result<String> {
+        let num_txs = txs.len();
+        match self.forward(txs).await {
+            Ok(bundle_id) => {
+                BUNDLES_SENT.inc();
+                info!("Forwarded bundle {bundle_id} of {num_txs} transaction(s)");
+                Ok(bundle_id)
+            }
+            Err(err) => {
+                BUNDLES_SENT_ERRORS.inc();
+                Err(err)
+            }
+        }
+    }
+
+    async fn forward(&self, txs: Vec<String>) -> anyhow::Result<String> {
+        let response: Value = self
+            .client
+            .post(format!("{}/api/v1/bundles", self.block_engine_url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({"jsonrpc": "2.0", "id": 1, "method": "sendBundle", "params": [txs]})
+                    .to_string(),
+            )
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            bail!("block engine rejected bundle: {error}");
+        }
+
+        response
+            .get("result")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| anyhow!("block engine returned no bundle id"))
+    }
+}
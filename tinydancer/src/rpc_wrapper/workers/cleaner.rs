@@ -1,11 +1,14 @@
 use std::{sync::Arc, time::Duration};
 
-use tiny_logger::logs::info;
+use tiny_logger::logs::{info, warn};
 use tokio::task::JoinHandle;
 
-use crate::rpc_wrapper::{block_store::BlockStore, tpu_manager::TpuManager};
+use crate::rpc_wrapper::{
+    block_store::BlockStore, configs::CleanerConfig, tpu_manager::TpuManager,
+    DEFAULT_DURABLE_NONCE_TX_TTL_S,
+};
 
-use super::{BlockListener, TxSender};
+use super::{BlockListener, SubscriptionRegistry, TxSender};
 
 /// Background worker which cleans up memory  
 #[derive(Clone)]
@@ -14,6 +17,7 @@ pub struct Cleaner {
     block_listenser: BlockListener,
     block_store: BlockStore,
     tpu_manager: Arc<TpuManager>,
+    subscriptions: SubscriptionRegistry,
 }
 
 impl Cleaner {
@@ -22,20 +26,45 @@ impl Cleaner {
         block_listenser: BlockListener,
         block_store: BlockStore,
         tpu_manager: Arc<TpuManager>,
+        subscriptions: SubscriptionRegistry,
     ) -> Self {
         Self {
             tx_sender,
             block_listenser,
             block_store,
             tpu_manager,
+            subscriptions,
         }
     }
 
     pub fn clean_tx_sender(&self, ttl_duration: Duration) {
+        let durable_nonce_ttl = Duration::from_secs(DEFAULT_DURABLE_NONCE_TX_TTL_S);
+        let mut evicted = Vec::new();
+        self.tx_sender.durable_nonce_txs.retain(|sig, queued_at| {
+            let landed = self
+                .tx_sender
+                .txs_sent_store
+                .get(sig)
+                .map_or(false, |props| {
+                    props
+                        .status
+                        .as_ref()
+                        .map_or(false, |status| self.block_listenser.is_terminal(status))
+                });
+            !landed && queued_at.elapsed() < durable_nonce_ttl
+        });
+
         let length_before = self.tx_sender.txs_sent_store.len();
-        self.tx_sender
-            .txs_sent_store
-            .retain(|_k, v| v.sent_at.elapsed() < ttl_duration);
+        // durable nonce transactions may land long after the ttl
+        self.tx_sender.txs_sent_store.retain(|k, v| {
+            let keep = v.sent_at.elapsed() < ttl_duration || self.tx_sender.is_durable_nonce(k);
+            if !keep {
+                evicted.push(k.clone());
+            }
+            keep
+        });
+        // the persisted copies go with them
+        self.tx_sender.unpersist_txs(&evicted);
         info!(
             "Cleaned {} transactions",
             length_before - self.tx_sender.txs_sent_store.len()
@@ -47,22 +76,46 @@ impl Cleaner {
         self.block_listenser.clean(ttl_duration);
     }
 
+    /// Drop the sinks of websocket subscribers that went away or stayed idle
+    pub fn clean_subscriptions(&self) {
+        self.subscriptions.clean();
+    }
+
     pub async fn clean_block_store(&self, ttl_duration: Duration) {
         self.block_store.clean(ttl_duration).await;
     }
 
-    pub fn start(self, ttl_duration: Duration) -> JoinHandle<anyhow::Result<()>> {
-        let mut ttl = tokio::time::interval(ttl_duration);
+    /// Drop the journal entries older than `retention`, scanned off the executor
+    pub async fn clean_journal(&self, retention: Duration) {
+        let tx_sender = self.tx_sender.clone();
+        match tokio::task::spawn_blocking(move || tx_sender.prune_journal(retention)).await {
+            Ok(Ok(pruned)) => info!("Pruned {pruned} journaled transactions"),
+            Ok(Err(err)) => warn!("Couldn't prune the transaction journal {err}"),
+            Err(err) => warn!("Pruning the transaction journal panicked {err}"),
+        }
+    }
+
+    pub fn start(self, config: CleanerConfig) -> JoinHandle<anyhow::Result<()>> {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms));
+        let tx_ttl = Duration::from_secs(config.tx_ttl_s);
+        let block_ttl = Duration::from_secs(config.block_ttl_s);
+        let subscription_ttl = Duration::from_secs(config.subscription_ttl_s);
+        let journal_retention = (config.journal_retention_s > 0)
+            .then(|| Duration::from_secs(config.journal_retention_s));
 
         tokio::spawn(async move {
             info!("Cleaning memory");
 
             loop {
-                ttl.tick().await;
+                interval.tick().await;
 
-                self.clean_tx_sender(ttl_duration);
-                self.clean_block_listeners(ttl_duration);
-                self.clean_block_store(ttl_duration).await;
+                self.clean_tx_sender(tx_ttl);
+                self.clean_block_listeners(subscription_ttl);
+                self.clean_subscriptions();
+                self.clean_block_store(block_ttl).await;
+                if let Some(journal_retention) = journal_retention {
+                    self.clean_journal(journal_retention).await;
+                }
                 let _ = self.tpu_manager.reset_tpu_client().await;
             }
         })
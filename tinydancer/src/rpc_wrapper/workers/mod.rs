@@ -1,7 +1,19 @@
 mod block_listenser;
+mod bundle_forwarder;
 mod cleaner;
+mod pubsub_multiplexer;
+mod subscriptions;
+mod tuner;
+mod tx_journal;
 mod tx_sender;
+mod webhooks;
 
 pub use block_listenser::*;
+pub use bundle_forwarder::*;
 pub use cleaner::*;
+pub use pubsub_multiplexer::*;
+pub use subscriptions::*;
+pub use tuner::*;
+pub use tx_journal::*;
 pub use tx_sender::*;
+pub use webhooks::*;
@@ -0,0 +1,340 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{future::BoxFuture, stream::BoxStream};
+use jsonrpsee::SubscriptionSink;
+use prometheus::{opts, register_int_gauge, IntGauge};
+use solana_account_decoder::UiAccount;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_rpc_client_api::{
+    config::{
+        RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionLogsConfig,
+        RpcTransactionLogsFilter,
+    },
+    response::{Response as RpcResponse, RpcKeyedAccount, RpcLogsResponse},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use tiny_logger::logs::{info, warn};
+use tokio::sync::{broadcast, Mutex};
+use tokio_stream::StreamExt;
+
+use super::SubscriptionRegistry;
+use crate::rpc_wrapper::upstreams::Upstreams;
+
+lazy_static::lazy_static! {
+    static ref UPSTREAM_SUBSCRIPTIONS: IntGauge =
+        register_int_gauge!(opts!("literpc_upstream_subscriptions", "Number of subscriptions open on the upstream websocket")).unwrap();
+}
+
+/// Notifications buffered per local subscriber, slower ones skip what they missed
+const NOTIFICATION_BUFFER: usize = 64;
+
+/// How often an upstream subscription checks whether it still has local subscribers
+const ABANDONED_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Wait before the first retry of a failed connect or subscribe, doubled up to
+/// `RECONNECT_BACKOFF_MAX` on every further failure
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+type Subscriptions<T> = Arc<DashMap<String, Arc<broadcast::Sender<T>>>>;
+
+/// Shares a single upstream websocket between local subscribers, every distinct
+/// subscription is opened upstream once and fanned out to the sinks asking for it.
+/// A dropped websocket is reconnected and the subscriptions still listened to are
+/// opened again on it
+#[derive(Clone)]
+pub struct PubsubMultiplexer {
+    pubsub: Arc<UpstreamPubsub>,
+    subscriptions: SubscriptionRegistry,
+    accounts: Subscriptions<RpcResponse<UiAccount>>,
+    logs: Subscriptions<RpcResponse<RpcLogsResponse>>,
+    programs: Subscriptions<RpcResponse<RpcKeyedAccount>>,
+}
+
+impl PubsubMultiplexer {
+    /// Connects in the background, subscribers wait for the connection rather than the
+    /// lite rpc for an upstream websocket to start
+    pub fn new(upstreams: Upstreams, subscriptions: SubscriptionRegistry) -> Self {
+        let pubsub = Arc::new(UpstreamPubsub {
+            upstreams,
+            client: Mutex::new(None),
+        });
+
+        tokio::spawn({
+            let pubsub = pubsub.clone();
+            async move {
+                pubsub.client().await;
+            }
+        });
+
+        Self {
+            pubsub,
+            subscriptions,
+            accounts: Default::default(),
+            logs: Default::default(),
+            programs: Default::default(),
+        }
+    }
+
+    pub fn account_subscribe(
+        &self,
+        pubkey: Pubkey,
+        config: Option<RpcAccountInfoConfig>,
+        sink: SubscriptionSink,
+    ) {
+        // subscribers asking for another encoding or commitment get their own upstream subscription
+        let key = format!(
+            "{pubkey}:{}",
+            serde_json::to_string(&config).unwrap_or_default()
+        );
+
+        let notifications = multiplex(&self.accounts, key, &self.pubsub, move |client| {
+            let config = config.clone();
+            Box::pin(async move { client.account_subscribe(&pubkey, config).await })
+        });
+
+        self.subscriptions
+            .fan_out("account", sink, notifications, Some);
+    }
+
+    /// `all` and `allWithVotes` subscribers share one upstream subscription per commitment
+    /// and drop votes locally, `mentions` are subscribed upstream per account since logs
+    /// don't carry the transaction's accounts
+    pub fn logs_subscribe(
+        &self,
+        filter: RpcTransactionLogsFilter,
+        commitment: Option<CommitmentConfig>,
+        sink: SubscriptionSink,
+    ) {
+        let commitment = commitment.unwrap_or_default();
+        let (upstream_filter, with_votes) = match filter {
+            RpcTransactionLogsFilter::All => (RpcTransactionLogsFilter::AllWithVotes, false),
+            RpcTransactionLogsFilter::AllWithVotes => {
+                (RpcTransactionLogsFilter::AllWithVotes, true)
+            }
+            mentions @ RpcTransactionLogsFilter::Mentions(_) => (mentions, true),
+        };
+
+        let key = format!(
+            "{}:{:?}",
+            serde_json::to_string(&upstream_filter).unwrap_or_default(),
+            commitment.commitment
+        );
+
+        let notifications = multiplex(&self.logs, key, &self.pubsub, move |client| {
+            let filter = upstream_filter.clone();
+            let config = RpcTransactionLogsConfig {
+                commitment: Some(commitment),
+            };
+            Box::pin(async move { client.logs_subscribe(filter, config).await })
+        });
+
+        self.subscriptions
+            .fan_out("logs", sink, notifications, move |notification| {
+                (with_votes || !is_vote(&notification.value)).then_some(notification)
+            });
+    }
+
+    /// Filters are applied upstream, subscribers only share a subscription with the
+    /// exact same filters
+    pub fn program_subscribe(
+        &self,
+        program_id: Pubkey,
+        config: Option<RpcProgramAccountsConfig>,
+        sink: SubscriptionSink,
+    ) {
+        let key = format!(
+            "{program_id}:{}",
+            serde_json::to_string(&config).unwrap_or_default()
+        );
+
+        let notifications = multiplex(&self.programs, key, &self.pubsub, move |client| {
+            let config = config.clone();
+            Box::pin(async move { client.program_subscribe(&program_id, config).await })
+        });
+
+        self.subscriptions
+            .fan_out("program", sink, notifications, Some);
+    }
+}
+
+/// Whether every top level instruction is a vote, the same transactions the
+/// validator leaves out of `all`
+fn is_vote(logs: &RpcLogsResponse) -> bool {
+    let vote_invoke = format!("Program {} invoke [1]", solana_sdk::vote::program::id());
+    let mut invocations = logs
+        .logs
+        .iter()
+        .filter(|log| log.ends_with(" invoke [1]"))
+        .peekable();
+
+    invocations.peek().is_some() && invocations.all(|log| *log == vote_invoke)
+}
+
+/// Upstream websocket the subscriptions share, connected to the first upstream that
+/// accepts
+struct UpstreamPubsub {
+    upstreams: Upstreams,
+    client: Mutex<Option<Arc<PubsubClient>>>,
+}
+
+impl UpstreamPubsub {
+    /// Current connection, connecting first if there's none. Tries the upstreams in
+    /// turn and backs off between rounds until one accepts
+    async fn client(&self) -> Arc<PubsubClient> {
+        // held while connecting, so subscriptions losing the connection at once
+        // share the reconnect
+        let mut client = self.client.lock().await;
+        if let Some(client) = &*client {
+            return client.clone();
+        }
+
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        loop {
+            for url in self.upstreams.ws_urls() {
+                match PubsubClient::new(&url).await {
+                    Ok(connected) => {
+                        info!("Connected to upstream pubsub at {url}");
+                        let connected = Arc::new(connected);
+                        *client = Some(connected.clone());
+                        return connected;
+                    }
+                    Err(err) => warn!("Couldn't connect to upstream pubsub at {url}: {err}"),
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        }
+    }
+
+    /// Drops `failed` so the next subscription reconnects, unless another one did already
+    async fn reset(&self, failed: &Arc<PubsubClient>) {
+        let mut client = self.client.lock().await;
+        if matches!(&*client, Some(client) if Arc::ptr_eq(client, failed)) {
+            *client = None;
+        }
+    }
+}
+
+/// Publishing end of an upstream subscription, unregisters it when dropped
+struct Fanout<T> {
+    key: String,
+    sender: Arc<broadcast::Sender<T>>,
+    subscriptions: Subscriptions<T>,
+}
+
+impl<T: Clone> Fanout<T> {
+    /// Publishes upstream notifications until the stream ends or every local subscriber
+    /// left, returns whether they did
+    async fn forward(&self, mut notifications: BoxStream<'_, T>) -> bool {
+        let mut abandoned_check = tokio::time::interval(ABANDONED_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                notification = notifications.next() => match notification {
+                    // a send without receivers is picked up by the next check
+                    Some(notification) => {
+                        let _ = self.sender.send(notification);
+                    }
+                    None => return false,
+                },
+                _ = abandoned_check.tick() => {
+                    if self.is_abandoned() {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Unregisters the subscription if nobody listens, holding the map entry so no
+    /// subscriber can join in between
+    fn is_abandoned(&self) -> bool {
+        self.subscriptions
+            .remove_if(&self.key, |_, sender| {
+                Arc::ptr_eq(sender, &self.sender) && sender.receiver_count() == 0
+            })
+            .is_some()
+    }
+}
+
+impl<T> Drop for Fanout<T> {
+    fn drop(&mut self) {
+        UPSTREAM_SUBSCRIPTIONS.dec();
+        self.subscriptions
+            .remove_if(&self.key, |_, sender| Arc::ptr_eq(sender, &self.sender));
+    }
+}
+
+/// Keeps a subscription open upstream while it has local subscribers, opening it
+/// again through `subscribe` whenever the upstream one ends or fails
+async fn run_upstream<T, F, U, E>(fanout: Fanout<T>, pubsub: Arc<UpstreamPubsub>, subscribe: F)
+where
+    T: Clone,
+    F: for<'a> Fn(&'a PubsubClient) -> BoxFuture<'a, Result<(BoxStream<'a, T>, U), E>>,
+    U: FnOnce() -> BoxFuture<'static, ()>,
+    E: Display,
+{
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+    loop {
+        let client = pubsub.client().await;
+        match subscribe(client.as_ref()).await {
+            Ok((notifications, unsubscribe)) => {
+                backoff = RECONNECT_BACKOFF_MIN;
+                if fanout.forward(notifications).await {
+                    unsubscribe().await;
+                    return;
+                }
+                warn!("Upstream subscription {} ended, resubscribing", fanout.key);
+            }
+            Err(err) => warn!("Couldn't subscribe to {} upstream: {err}", fanout.key),
+        }
+
+        // an ended subscription most likely means a dropped websocket
+        pubsub.reset(&client).await;
+        drop(client);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        if fanout.is_abandoned() {
+            return;
+        }
+    }
+}
+
+/// Joins the subscription registered under `key`, opening it upstream through
+/// `subscribe` if there's none yet
+fn multiplex<T, F, U, E>(
+    subscriptions: &Subscriptions<T>,
+    key: String,
+    pubsub: &Arc<UpstreamPubsub>,
+    subscribe: F,
+) -> broadcast::Receiver<T>
+where
+    T: Clone + Send + Sync + 'static,
+    F: for<'a> Fn(&'a PubsubClient) -> BoxFuture<'a, Result<(BoxStream<'a, T>, U), E>>
+        + Send
+        + Sync
+        + 'static,
+    U: FnOnce() -> BoxFuture<'static, ()> + Send + 'static,
+    E: Display + Send + 'static,
+{
+    match subscriptions.entry(key.clone()) {
+        Entry::Occupied(entry) => entry.get().subscribe(),
+        Entry::Vacant(entry) => {
+            let (sender, receiver) = broadcast::channel(NOTIFICATION_BUFFER);
+            let sender = Arc::new(sender);
+            entry.insert(sender.clone());
+
+            UPSTREAM_SUBSCRIPTIONS.inc();
+            let fanout = Fanout {
+                key,
+                sender,
+                subscriptions: subscriptions.clone(),
+            };
+            tokio::spawn(run_upstream(fanout, pubsub.clone(), subscribe));
+
+            receiver
+        }
+    }
+}
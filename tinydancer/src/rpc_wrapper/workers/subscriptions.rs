@@ -0,0 +1,183 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use jsonrpsee::SubscriptionSink;
+use prometheus::{opts, register_int_gauge_vec, IntGaugeVec};
+use serde::Serialize;
+use tiny_logger::logs::info;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+lazy_static::lazy_static! {
+    static ref ACTIVE_SUBSCRIPTIONS: IntGaugeVec =
+        register_int_gauge_vec!(opts!("literpc_active_subscriptions", "Number of active websocket subscriptions"), &["kind"]).unwrap();
+}
+
+/// How often a fan out task checks whether its subscriber is still there
+const CLOSED_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+pub type SubscriptionId = u64;
+
+struct Subscription {
+    kind: &'static str,
+    sink: SubscriptionSink,
+}
+
+/// Owns the sinks of every websocket subscription, notifications are sent through it
+/// and sinks closed by a `*Unsubscribe` call or a dropped connection are removed
+/// on the next notification or clean
+#[derive(Clone)]
+pub struct SubscriptionRegistry {
+    subscriptions: Arc<DashMap<SubscriptionId, Subscription>>,
+    next_id: Arc<AtomicU64>,
+    /// slots taken by registered subscriptions and ones about to be
+    reserved: Arc<AtomicUsize>,
+    max_subscriptions: usize,
+}
+
+impl SubscriptionRegistry {
+    pub fn new(max_subscriptions: usize) -> Self {
+        Self {
+            subscriptions: Default::default(),
+            next_id: Default::default(),
+            reserved: Default::default(),
+            max_subscriptions,
+        }
+    }
+
+    /// Takes a slot for a subscription about to be registered, false if the cap on
+    /// subscriptions over all connections is reached
+    pub fn try_reserve(&self) -> bool {
+        self.reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |reserved| {
+                (reserved < self.max_subscriptions).then_some(reserved + 1)
+            })
+            .is_ok()
+    }
+
+    /// Gives back a slot reserved for a subscription that won't be registered
+    pub fn release(&self) {
+        self.reserved.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Takes ownership of an accepted sink, in the slot reserved for it by `try_reserve`
+    pub fn register(&self, kind: &'static str, sink: SubscriptionSink) -> SubscriptionId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscriptions.insert(id, Subscription { kind, sink });
+        ACTIVE_SUBSCRIPTIONS.with_label_values(&[kind]).inc();
+        id
+    }
+
+    /// Sends a notification, false once the subscriber is gone and its sink was dropped
+    pub fn notify<T: Serialize>(&self, id: SubscriptionId, notification: &T) -> bool {
+        let delivered = match self.subscriptions.get_mut(&id) {
+            Some(mut subscription) => matches!(subscription.sink.send(notification), Ok(true)),
+            None => false,
+        };
+
+        if !delivered {
+            self.unsubscribe(id);
+        }
+        delivered
+    }
+
+    pub fn is_active(&self, id: SubscriptionId) -> bool {
+        self.subscriptions
+            .get(&id)
+            .map_or(false, |subscription| !subscription.sink.is_closed())
+    }
+
+    /// Drops the sink of a subscription, a no-op if it's already gone
+    pub fn unsubscribe(&self, id: SubscriptionId) {
+        if let Some((_, subscription)) = self.subscriptions.remove(&id) {
+            self.release();
+            ACTIVE_SUBSCRIPTIONS
+                .with_label_values(&[subscription.kind])
+                .dec();
+        }
+    }
+
+    /// Registers a sink and forwards it the notifications `notify` maps to something,
+    /// until the subscriber or the notifications are gone
+    pub fn fan_out<T, U, F>(
+        &self,
+        kind: &'static str,
+        sink: SubscriptionSink,
+        mut notifications: broadcast::Receiver<T>,
+        mut notify: F,
+    ) where
+        T: Clone + Send + 'static,
+        U: Serialize + Send,
+        F: FnMut(T) -> Option<U> + Send + 'static,
+    {
+        let id = self.register(kind, sink);
+        let subscriptions = self.clone();
+
+        tokio::spawn(async move {
+            let mut closed_check = tokio::time::interval(CLOSED_CHECK_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    notification = notifications.recv() => match notification {
+                        Ok(notification) => {
+                            let Some(notification) = notify(notification) else {
+                                continue;
+                            };
+                            if !subscriptions.notify(id, &notification) {
+                                break;
+                            }
+                        }
+                        // slow subscribers skip what they missed
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = closed_check.tick() => {
+                        if !subscriptions.is_active(id) {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            subscriptions.unsubscribe(id);
+        });
+    }
+
+    /// Drops the sinks of subscribers that went away. A dead connection closes its
+    /// sinks once a ping to it fails, quiet subscriptions are left alone
+    pub fn clean(&self) {
+        let closed: Vec<SubscriptionId> = self
+            .subscriptions
+            .iter()
+            .filter(|subscription| subscription.sink.is_closed())
+            .map(|subscription| *subscription.key())
+            .collect();
+
+        for id in &closed {
+            self.unsubscribe(*id);
+        }
+
+        info!("Cleaned {} closed subscriptions", closed.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_up_to_the_cap() {
+        let subscriptions = SubscriptionRegistry::new(2);
+        assert!(subscriptions.try_reserve());
+        assert!(subscriptions.try_reserve());
+        assert!(!subscriptions.try_reserve());
+
+        subscriptions.release();
+        assert!(subscriptions.try_reserve());
+    }
+}
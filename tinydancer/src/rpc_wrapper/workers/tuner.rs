@@ -0,0 +1,75 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+
+use crate::{
+    get_config_file,
+    rpc_wrapper::{configs::TxTuningConfig, tpu_manager::TpuManager},
+};
+
+use super::TxSender;
+
+/// Applies the `txTuning` section of the config at startup and again on every SIGHUP,
+/// so fanout and batching can follow cluster conditions without restarting the bridge
+pub struct Tuner {
+    tx_sender: TxSender,
+    tpu_manager: Arc<TpuManager>,
+}
+
+impl Tuner {
+    pub fn new(tx_sender: TxSender, tpu_manager: Arc<TpuManager>) -> Self {
+        Self {
+            tx_sender,
+            tpu_manager,
+        }
+    }
+
+    pub async fn apply(&self, tuning: TxTuningConfig) -> anyhow::Result<()> {
+        let (batch_size, batch_interval) = self.tx_sender.batching();
+        let batch_size = tuning.batch_size.unwrap_or(batch_size);
+        let batch_interval = tuning
+            .batch_interval_ms
+            .map_or(batch_interval, Duration::from_millis);
+        self.tx_sender.set_batching(batch_size, batch_interval);
+        info!(
+            "Batching tx(s) with batch size of {batch_size} every {}ms",
+            batch_interval.as_millis()
+        );
+
+        match tuning.fanout_slots {
+            Some(fanout_slots) if fanout_slots != self.tpu_manager.fanout_slots() => {
+                self.tpu_manager.set_fanout_slots(fanout_slots).await?;
+                info!("Tpu client fans out to {fanout_slots} slots");
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn start(self) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let mut hangups = signal(SignalKind::hangup())?;
+
+            loop {
+                match get_config_file() {
+                    Ok(config) => {
+                        if let Err(err) = self.apply(config.tx_tuning).await {
+                            warn!("Couldn't apply the tx tuning {err}");
+                        }
+                    }
+                    Err(err) => warn!("Couldn't read the config, tuning left as it is {err}"),
+                }
+
+                if hangups.recv().await.is_none() {
+                    bail!("SIGHUP stream closed");
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,160 @@
+use rocksdb::{IteratorMode, Options as RocksOptions, DB};
+use serde::{Deserialize, Serialize};
+use solana_sdk::transaction::TransactionError;
+
+use super::{TxProps, TxState, WireTransaction};
+
+/// Column family every accepted transaction is journaled in, entries outlive the
+/// tracked statuses so they can be exported or rebroadcast later
+pub const TX_JOURNAL_CF: &str = "tx_journal";
+
+/// What became of a journaled transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JournalOutcome {
+    Queued,
+    Sent,
+    /// landed in `slot`, `err` if it failed
+    Landed {
+        slot: u64,
+        err: Option<TransactionError>,
+    },
+    /// dropped from the queue before it was sent
+    Dropped,
+    /// dropped from the queue for the transaction with signature `by`
+    Replaced {
+        by: String,
+    },
+    /// its blockhash expired before it landed
+    Expired,
+}
+
+impl JournalOutcome {
+    pub fn of(props: &TxProps) -> Self {
+        if let Some(status) = &props.status {
+            return Self::Landed {
+                slot: status.slot,
+                err: status.err.clone(),
+            };
+        }
+        match &props.state {
+            TxState::Queued => Self::Queued,
+            TxState::Sent => Self::Sent,
+            TxState::Dropped => Self::Dropped,
+            TxState::Replaced { by } => Self::Replaced { by: by.clone() },
+            TxState::Expired => Self::Expired,
+        }
+    }
+}
+
+/// An accepted transaction as journaled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub signature: String,
+    pub tx: WireTransaction,
+    /// unix millis it was accepted at
+    pub received_at_ms: u64,
+    pub outcome: JournalOutcome,
+}
+
+/// Reads the journal from the archive of a (possibly running) client, entries received
+/// at `since_ms` or later in the order they were received
+pub fn read_journal(archive_path: &str, since_ms: u64) -> anyhow::Result<Vec<JournalEntry>> {
+    let opts = RocksOptions::default();
+    let instance = DB::open_cf_for_read_only(&opts, archive_path, vec![TX_JOURNAL_CF], false)?;
+    let cf = instance
+        .cf_handle(TX_JOURNAL_CF)
+        .ok_or_else(|| anyhow::anyhow!("ColumnFamily {} not found", TX_JOURNAL_CF))?;
+
+    let mut entries = Vec::new();
+    for entry in instance.iterator_cf(cf, IteratorMode::Start) {
+        let (_, value) = entry?;
+        let entry: JournalEntry = serde_json::from_slice(&value)?;
+        if entry.received_at_ms >= since_ms {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|entry| entry.received_at_ms);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_transaction_status::TransactionStatus;
+
+    use super::*;
+
+    #[test]
+    fn outcomes_follow_the_tracked_state() {
+        let outcome = |state| {
+            JournalOutcome::of(&TxProps {
+                state,
+                ..Default::default()
+            })
+        };
+        assert_eq!(outcome(TxState::Queued), JournalOutcome::Queued);
+        assert_eq!(outcome(TxState::Sent), JournalOutcome::Sent);
+        assert_eq!(outcome(TxState::Dropped), JournalOutcome::Dropped);
+        assert_eq!(outcome(TxState::Expired), JournalOutcome::Expired);
+        assert_eq!(
+            outcome(TxState::Replaced {
+                by: String::from("other")
+            }),
+            JournalOutcome::Replaced {
+                by: String::from("other")
+            }
+        );
+
+        // a status means it landed, whatever the state
+        let landed = TxProps {
+            status: Some(TransactionStatus {
+                slot: 9,
+                confirmations: None,
+                status: Err(TransactionError::AccountInUse),
+                err: Some(TransactionError::AccountInUse),
+                confirmation_status: None,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            JournalOutcome::of(&landed),
+            JournalOutcome::Landed {
+                slot: 9,
+                err: Some(TransactionError::AccountInUse)
+            }
+        );
+    }
+
+    #[test]
+    fn reads_entries_received_since_in_order() {
+        let archive_path =
+            std::env::temp_dir().join(format!("tx_journal_test_{}", std::process::id()));
+        {
+            let mut opts = RocksOptions::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            let db = DB::open_cf(&opts, &archive_path, [TX_JOURNAL_CF]).unwrap();
+            let cf = db.cf_handle(TX_JOURNAL_CF).unwrap();
+            for (signature, received_at_ms) in [("c", 30), ("a", 10), ("b", 20)] {
+                let entry = JournalEntry {
+                    signature: signature.to_owned(),
+                    tx: vec![],
+                    received_at_ms,
+                    outcome: JournalOutcome::Sent,
+                };
+                db.put_cf(cf, signature, serde_json::to_vec(&entry).unwrap())
+                    .unwrap();
+            }
+        }
+
+        let entries = read_journal(archive_path.to_str().unwrap(), 20);
+        std::fs::remove_dir_all(&archive_path).unwrap();
+        let signatures: Vec<_> = entries
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.signature)
+            .collect();
+        assert_eq!(signatures, ["b", "c"]);
+    }
+}
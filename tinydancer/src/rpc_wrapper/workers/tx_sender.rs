@@ -1,40 +1,106 @@
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    cmp::Reverse,
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::bail;
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
+use itertools::Itertools;
+use rocksdb::IteratorMode;
+use serde::{Deserialize, Serialize};
 use tiny_logger::logs::{info, warn};
 
 use prometheus::{
-    core::GenericGauge, histogram_opts, opts, register_histogram, register_int_counter,
-    register_int_gauge, Histogram, IntCounter,
+    core::GenericGauge, exponential_buckets, histogram_opts, opts, register_histogram,
+    register_int_counter, register_int_gauge, Histogram, IntCounter,
 };
-use solana_transaction_status::TransactionStatus;
+use solana_sdk::{
+    blake3::hashv, commitment_config::CommitmentConfig, pubkey::Pubkey,
+    transaction::TransactionError,
+};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
 use tokio::{
     sync::Semaphore,
-    sync::{mpsc::UnboundedReceiver, OwnedSemaphorePermit},
+    sync::{broadcast, mpsc::Receiver},
     task::JoinHandle,
 };
 
-use crate::rpc_wrapper::{bridge::TXS_IN_CHANNEL, tpu_manager::TpuManager};
+use crate::{
+    rpc_wrapper::{
+        block_store::BlockStore, bridge::TXS_IN_CHANNEL, tpu_manager::TpuManager,
+        DEFAULT_TX_BATCH_INTERVAL_MS, DEFAULT_TX_BATCH_SIZE, DEFAULT_TX_FAIR_SHARE_PERCENT,
+        DEFAULT_TX_PRIORITY_WINDOW, DEFAULT_TX_RETRY_WINDOW_SLOTS,
+    },
+    sampler::{get_serialized, put_serialized},
+};
+
+use super::{JournalEntry, JournalOutcome, TX_JOURNAL_CF};
 
 lazy_static::lazy_static! {
     static ref TXS_SENT: IntCounter =
         register_int_counter!("literpc_txs_sent", "Number of transactions forwarded to tpu").unwrap();
     static ref TXS_SENT_ERRORS: IntCounter =
     register_int_counter!("literpc_txs_sent_errors", "Number of errors while transactions forwarded to tpu").unwrap();
+    static ref TXS_RETRIED: IntCounter =
+    register_int_counter!("literpc_txs_retried", "Number of unconfirmed transactions rebroadcast to tpu").unwrap();
+    static ref TXS_RETRY_ROTATIONS: IntCounter =
+    register_int_counter!("literpc_txs_retry_rotations", "Unconfirmed transactions rebroadcast to leaders they weren't sent to yet").unwrap();
+    static ref TXS_DROPPED: IntCounter =
+    register_int_counter!("literpc_txs_dropped", "Number of queued transactions dropped or replaced before they were sent").unwrap();
+    static ref TXS_EXPIRED: IntCounter =
+    register_int_counter!("literpc_txs_expired", "Number of transactions whose blockhash expired before they landed").unwrap();
+    static ref TXS_DURABLE_NONCE: IntCounter =
+    register_int_counter!("literpc_txs_durable_nonce", "Number of durable nonce transactions sent").unwrap();
+    static ref TXS_PENDING_RETRY: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_txs_pending_retry", "Transactions rebroadcast until they're confirmed")).unwrap();
+    static ref TXS_BACKLOG: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_txs_backlog", "Transactions taken off the queue that wait for a batch")).unwrap();
     static ref TX_BATCH_SIZES: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_tx_batch_size", "batchsize of tx sent by literpc")).unwrap();
     static ref TT_SENT_TIMER: Histogram = register_histogram!(histogram_opts!(
         "literpc_txs_send_timer",
         "Time to send transaction batch",
     ))
     .unwrap();
+    static ref TX_BATCH_SIZE_HISTOGRAM: Histogram = register_histogram!(histogram_opts!(
+        "literpc_tx_batch_size_histogram",
+        "Transactions in each batch sent to the tpu",
+        exponential_buckets(1.0, 2.0, 12).unwrap(),
+    ))
+    .unwrap();
+    static ref TX_QUEUE_DEPTH: Histogram = register_histogram!(histogram_opts!(
+        "literpc_tx_queue_depth",
+        "Transactions left queued each time a batch is taken",
+        exponential_buckets(1.0, 2.0, 16).unwrap(),
+    ))
+    .unwrap();
 }
 
 pub type WireTransaction = Vec<u8>;
+
+/// A transaction queued for the tpu
+pub struct QueuedTx {
+    pub sig: String,
+    pub tx: WireTransaction,
+    pub slot: u64,
+    /// compute unit price in micro lamports, batches go out highest first
+    pub priority_fee: u64,
+    /// tpu of the validator it's sent to alone, the upcoming leaders if `None`
+    pub target: Option<SocketAddr>,
+}
 const NUMBER_OF_TX_SENDERS: usize = 5;
+/// Sent batches buffered for slow listeners
+const SENT_NOTIFICATION_BUFFER: usize = 256;
+/// Failed transactions buffered for slow listeners
+const FAILED_NOTIFICATION_BUFFER: usize = 256;
+
+/// Column family sent transactions and their statuses are persisted in
+pub const TX_STATUS_CF: &str = "tx_statuses";
+/// Column family pending retries are persisted in
+pub const TX_RETRY_CF: &str = "tx_retries";
 
 /// Retry transactions to a maximum of `u16` times, keep a track of confirmed transactions
 #[derive(Clone)]
@@ -43,29 +109,706 @@ pub struct TxSender {
     pub txs_sent_store: Arc<DashMap<String, TxProps>>,
     /// TpuClient to call the tpu port
     pub tpu_manager: Arc<TpuManager>,
+    /// Tx(s) queued but not sent yet, they may still be dropped or replaced
+    unsent: Arc<DashMap<String, Unsent>>,
+    /// Tx(s) rebroadcast until they're confirmed
+    pending_retries: Arc<DashMap<String, PendingRetry>>,
+    /// Durable nonce tx(s) and when they were queued, they don't expire with a
+    /// blockhash so they're tracked for `DEFAULT_DURABLE_NONCE_TX_TTL_S` instead
+    pub durable_nonce_txs: Arc<DashMap<String, Instant>>,
+    /// Statuses and pending retries are written through, so they survive restarts
+    db_instance: Arc<rocksdb::DB>,
+    /// Tx(s) per batch, tuned at runtime
+    batch_size: Arc<AtomicUsize>,
+    /// Time waited for a batch to fill up, tuned at runtime
+    batch_interval_ms: Arc<AtomicU64>,
+    /// Batches being forwarded hold a permit each
+    senders: Arc<Semaphore>,
+    /// Set on shutdown, no more transactions are queued
+    draining: Arc<AtomicBool>,
+    /// Signatures and slots of each batch that went out to the tpu
+    sent: broadcast::Sender<Vec<(String, u64)>>,
+    /// Transactions that won't land and why, failed on chain ones are told by the
+    /// block listeners
+    failed: broadcast::Sender<Vec<(String, TxFailure)>>,
+    /// Held while a journal entry is written, so an outcome recorded while the
+    /// transaction is journaled isn't lost
+    journal_lock: Arc<Mutex<()>>,
+}
+
+/// A transaction that's rebroadcast while it's unconfirmed
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingRetry {
+    tx: WireTransaction,
+    retries_left: u16,
+    /// block height past which its blockhash expired, `None` for durable nonce
+    /// transactions which are valid until their nonce advances
+    last_valid_block_height: Option<u64>,
+    /// tpu it's rebroadcast to alone, the upcoming leaders if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<SocketAddr>,
+    /// slot it was last sent at, `None` until the retries saw it go out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sent_slot: Option<u64>,
+    /// leader tpus it was sent to, it moves on to the ones after them
+    #[serde(skip)]
+    tried: HashSet<SocketAddr>,
+}
+
+/// What a round of retries knows of the cluster
+struct RetryRound<'a> {
+    block_height: u64,
+    slot: u64,
+    leader_tpus: &'a [SocketAddr],
+    upcoming_tpus: &'a [SocketAddr],
+}
+
+/// What a round of retries does with a pending transaction
+#[derive(Debug, PartialEq, Eq)]
+enum RetryStep {
+    /// confirmed, expired or out of retries, it's no longer rebroadcast
+    Done,
+    /// not due yet, `first_send` if its first send was just seen
+    Wait { first_send: bool },
+    /// rebroadcast to `leaders`, or its target alone if they're empty. `rotated` if
+    /// they include upcoming leaders
+    Send {
+        leaders: Vec<SocketAddr>,
+        rotated: bool,
+    },
+}
+
+impl PendingRetry {
+    /// Takes a retry if one is due, `confirmed`, `durable_nonce` and `unsent` are
+    /// what's tracked of the transaction
+    fn step(
+        &mut self,
+        round: &RetryRound,
+        confirmed: bool,
+        durable_nonce: bool,
+        unsent: bool,
+    ) -> RetryStep {
+        let expired = match self.last_valid_block_height {
+            Some(last_valid_block_height) => round.block_height > last_valid_block_height,
+            None => !durable_nonce,
+        };
+        if confirmed || expired || self.retries_left == 0 {
+            return RetryStep::Done;
+        }
+        // still queued, it may yet be dropped or replaced
+        if unsent {
+            return RetryStep::Wait { first_send: false };
+        }
+        // the window starts from the first send, which recorded its leaders
+        let Some(sent_slot) = self.sent_slot else {
+            self.sent_slot = Some(round.slot);
+            return RetryStep::Wait { first_send: true };
+        };
+        if round.slot < sent_slot + DEFAULT_TX_RETRY_WINDOW_SLOTS {
+            return RetryStep::Wait { first_send: false };
+        }
+
+        self.retries_left -= 1;
+        self.sent_slot = Some(round.slot);
+        if self.target.is_some() {
+            return RetryStep::Send {
+                leaders: vec![],
+                rotated: false,
+            };
+        }
+
+        let (leader_tpus, upcoming_tpus) = (round.leader_tpus, round.upcoming_tpus);
+        let fanout = leader_tpus.len().max(1);
+        let mut next = next_leaders(leader_tpus, upcoming_tpus, &self.tried, fanout);
+        if next.len() == leader_tpus.len() && !self.tried.is_empty() {
+            // every upcoming leader had it, go around again
+            self.tried.clear();
+            next = next_leaders(leader_tpus, upcoming_tpus, &self.tried, fanout);
+        }
+        self.tried.extend(next.iter().copied());
+        RetryStep::Send {
+            rotated: next.len() > leader_tpus.len(),
+            leaders: next,
+        }
+    }
+}
+
+/// A queued transaction that wasn't sent yet
+#[derive(Debug, Clone, Copy)]
+pub struct Unsent {
+    pub fee_payer: Pubkey,
+    /// compute unit price in micro lamports
+    pub priority_fee: u64,
+    /// tpu it's sent to alone, a replacement goes there too
+    pub target: Option<SocketAddr>,
+}
+
+/// Where a transaction is on its way to the tpu
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TxState {
+    Queued,
+    #[default]
+    Sent,
+    /// dropped from the queue before it was sent
+    Dropped,
+    /// dropped from the queue for the transaction with signature `by`
+    Replaced {
+        by: String,
+    },
+    /// its blockhash expired before it landed
+    Expired,
+}
+
+/// Why a transaction failed or won't land
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum TxFailure {
+    /// its blockhash expired before it landed
+    #[serde(rename_all = "camelCase")]
+    Expired { last_valid_block_height: u64 },
+    /// it couldn't be sent to the tpu and wasn't to be retried
+    SendFailed { reason: String },
+    /// dropped from the queue before it was sent
+    Dropped,
+    /// dropped from the queue for the transaction with signature `by`
+    Replaced { by: String },
+    /// it landed and failed
+    OnChain { err: TransactionError },
+}
+
+impl TxFailure {
+    /// Failure a transaction in `state` is left with, if any
+    fn of_state(state: &TxState) -> Option<Self> {
+        match state {
+            TxState::Dropped => Some(Self::Dropped),
+            TxState::Replaced { by } => Some(Self::Replaced { by: by.clone() }),
+            TxState::Queued | TxState::Sent | TxState::Expired => None,
+        }
+    }
+
+    /// The upstream `err` of a signature notification. Transactions that didn't land
+    /// get `BlockhashNotFound`, which clients take for never landed and safe to resend
+    pub fn err(&self) -> TransactionError {
+        match self {
+            Self::OnChain { err } => err.clone(),
+            _ => TransactionError::BlockhashNotFound,
+        }
+    }
+
+    /// Signature notification value, the upstream `err` with the `reason` next to it
+    pub fn notification(&self) -> serde_json::Value {
+        serde_json::json!({ "err": self.err(), "reason": self })
+    }
 }
 
 /// Transaction Properties
 pub struct TxProps {
     pub status: Option<TransactionStatus>,
+    pub state: TxState,
+    /// Why it failed or won't land, `None` while it may still land or if it succeeded
+    pub failure: Option<TxFailure>,
     /// Time at which transaction was forwarded
     pub sent_at: Instant,
+    /// Where its confirmation is posted, the configured webhook if `None`
+    pub callback_url: Option<String>,
+    /// block height past which its blockhash expired, `None` for durable nonce
+    /// transactions and transactions we didn't queue
+    pub last_valid_block_height: Option<u64>,
+}
+
+impl TxProps {
+    /// Whether it landed in a confirmed block, a processed one may still be forked out
+    pub fn is_confirmed(&self) -> bool {
+        self.status.as_ref().map_or(false, |status| {
+            status.confirmation_status != Some(TransactionConfirmationStatus::Processed)
+        })
+    }
 }
 
 impl Default for TxProps {
     fn default() -> Self {
         Self {
             status: Default::default(),
+            state: Default::default(),
+            failure: None,
             sent_at: Instant::now(),
+            callback_url: None,
+            last_valid_block_height: None,
         }
     }
 }
 
+/// `TxProps` as persisted, instants don't survive restarts so times are unix millis
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedTx {
+    signature: String,
+    status: Option<TransactionStatus>,
+    state: TxState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failure: Option<TxFailure>,
+    sent_at_ms: u64,
+    /// queue time of a durable nonce transaction
+    durable_nonce_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_valid_block_height: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedRetry {
+    signature: String,
+    #[serde(flatten)]
+    retry: PendingRetry,
+}
+
+fn tx_key(cf: &str, sig: &str) -> [u8; 32] {
+    hashv(&[cf.as_bytes(), sig.as_bytes()]).to_bytes()
+}
+
+fn to_unix_ms(instant: Instant) -> u64 {
+    SystemTime::now()
+        .checked_sub(instant.elapsed())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+}
+
+fn from_unix_ms(unix_ms: u64) -> Instant {
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_millis(unix_ms))
+        .unwrap_or_default();
+    Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+}
+
 impl TxSender {
-    pub fn new(tpu_manager: Arc<TpuManager>) -> Self {
-        Self {
+    /// Picks up the statuses and pending retries persisted before a restart
+    pub fn new(tpu_manager: Arc<TpuManager>, db_instance: Arc<rocksdb::DB>) -> Self {
+        let tx_sender = Self {
             tpu_manager,
             txs_sent_store: Default::default(),
+            unsent: Default::default(),
+            pending_retries: Default::default(),
+            durable_nonce_txs: Default::default(),
+            db_instance,
+            batch_size: Arc::new(AtomicUsize::new(DEFAULT_TX_BATCH_SIZE)),
+            batch_interval_ms: Arc::new(AtomicU64::new(DEFAULT_TX_BATCH_INTERVAL_MS)),
+            senders: Arc::new(Semaphore::new(NUMBER_OF_TX_SENDERS)),
+            draining: Arc::new(AtomicBool::new(false)),
+            sent: broadcast::channel(SENT_NOTIFICATION_BUFFER).0,
+            failed: broadcast::channel(FAILED_NOTIFICATION_BUFFER).0,
+            journal_lock: Default::default(),
+        };
+
+        if let Err(err) = tx_sender.restore() {
+            warn!("Couldn't restore persisted transactions {err}");
+        }
+        info!(
+            "Restored {} transaction(s) and {} pending retries",
+            tx_sender.txs_sent_store.len(),
+            tx_sender.pending_retries.len()
+        );
+
+        tx_sender
+    }
+
+    fn restore(&self) -> anyhow::Result<()> {
+        if let Some(cf) = self.db_instance.cf_handle(TX_RETRY_CF) {
+            for entry in self.db_instance.iterator_cf(cf, IteratorMode::Start) {
+                let (_, value) = entry?;
+                let PersistedRetry { signature, retry } = serde_json::from_slice(&value)?;
+                self.pending_retries.insert(signature, retry);
+            }
+        }
+
+        if let Some(cf) = self.db_instance.cf_handle(TX_STATUS_CF) {
+            for entry in self.db_instance.iterator_cf(cf, IteratorMode::Start) {
+                let (_, value) = entry?;
+                let PersistedTx {
+                    signature,
+                    status,
+                    mut state,
+                    mut failure,
+                    sent_at_ms,
+                    durable_nonce_at_ms,
+                    callback_url,
+                    last_valid_block_height,
+                } = serde_json::from_slice(&value)?;
+
+                // the queue didn't survive, the retries send what was left in it
+                if state == TxState::Queued {
+                    state = if self.pending_retries.contains_key(&signature) {
+                        TxState::Sent
+                    } else {
+                        failure = Some(TxFailure::Dropped);
+                        TxState::Dropped
+                    };
+                }
+                if let Some(queued_at_ms) = durable_nonce_at_ms {
+                    self.durable_nonce_txs
+                        .insert(signature.clone(), from_unix_ms(queued_at_ms));
+                }
+                self.txs_sent_store.insert(
+                    signature,
+                    TxProps {
+                        status,
+                        state,
+                        failure,
+                        sent_at: from_unix_ms(sent_at_ms),
+                        callback_url,
+                        last_valid_block_height,
+                    },
+                );
+            }
+        }
+
+        TXS_PENDING_RETRY.set(self.pending_retries.len() as i64);
+        Ok(())
+    }
+
+    /// Writes the tracked state of `sig` through to the db, if it's tracked
+    pub fn persist_tx(&self, sig: &str) {
+        let Some(props) = self.txs_sent_store.get(sig) else {
+            return;
+        };
+        let record = PersistedTx {
+            signature: sig.to_owned(),
+            status: props.status.clone(),
+            state: props.state.clone(),
+            failure: props.failure.clone(),
+            sent_at_ms: to_unix_ms(props.sent_at),
+            durable_nonce_at_ms: self
+                .durable_nonce_txs
+                .get(sig)
+                .map(|queued_at| to_unix_ms(*queued_at)),
+            callback_url: props.callback_url.clone(),
+            last_valid_block_height: props.last_valid_block_height,
+        };
+        let outcome = JournalOutcome::of(&props);
+        drop(props);
+        self.persist(TX_STATUS_CF, sig, &record);
+        self.journal_outcome(sig, outcome);
+    }
+
+    /// Journals an accepted transaction once it's queued, with what became of it so
+    /// far. The entry is kept after its status is evicted, until the retention ends
+    pub fn journal(&self, sig: &str, tx: &[u8]) {
+        let _journal = self.journal_lock.lock().unwrap();
+        let outcome = self
+            .txs_sent_store
+            .get(sig)
+            .map_or(JournalOutcome::Queued, |props| JournalOutcome::of(&props));
+        let entry = JournalEntry {
+            signature: sig.to_owned(),
+            tx: tx.to_vec(),
+            received_at_ms: to_unix_ms(Instant::now()),
+            outcome,
+        };
+        self.persist(TX_JOURNAL_CF, sig, &entry);
+    }
+
+    /// Deletes the journal entries received more than `retention` ago, the number
+    /// deleted. Unreadable entries are left alone
+    pub fn prune_journal(&self, retention: Duration) -> anyhow::Result<usize> {
+        let Some(cf) = self.db_instance.cf_handle(TX_JOURNAL_CF) else {
+            return Ok(0);
+        };
+        let cutoff_ms = to_unix_ms(Instant::now()).saturating_sub(retention.as_millis() as u64);
+
+        let mut pruned = 0;
+        for entry in self.db_instance.iterator_cf(cf, IteratorMode::Start) {
+            let (key, value) = entry?;
+            let Ok(entry) = serde_json::from_slice::<JournalEntry>(&value) else {
+                continue;
+            };
+            if entry.received_at_ms < cutoff_ms {
+                self.db_instance.delete_cf(cf, key)?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Records what became of a journaled transaction, ones we didn't accept aren't
+    /// journaled
+    fn journal_outcome(&self, sig: &str, outcome: JournalOutcome) {
+        let Some(cf) = self.db_instance.cf_handle(TX_JOURNAL_CF) else {
+            return;
+        };
+        let _journal = self.journal_lock.lock().unwrap();
+        match get_serialized::<JournalEntry>(&self.db_instance, cf, tx_key(TX_JOURNAL_CF, sig)) {
+            Ok(Some(mut entry)) if entry.outcome != outcome => {
+                entry.outcome = outcome;
+                self.persist(TX_JOURNAL_CF, sig, &entry);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Couldn't read the journal entry of {sig} {err}"),
+        }
+    }
+
+    fn persist_retry(&self, sig: &str) {
+        match self.pending_retries.get(sig) {
+            Some(retry) => {
+                let record = PersistedRetry {
+                    signature: sig.to_owned(),
+                    retry: PendingRetry {
+                        tx: retry.tx.clone(),
+                        retries_left: retry.retries_left,
+                        last_valid_block_height: retry.last_valid_block_height,
+                        target: retry.target,
+                        sent_slot: retry.sent_slot,
+                        tried: HashSet::new(),
+                    },
+                };
+                drop(retry);
+                self.persist(TX_RETRY_CF, sig, &record);
+            }
+            None => self.unpersist(TX_RETRY_CF, sig),
+        }
+    }
+
+    fn persist<T: Serialize + std::fmt::Debug>(&self, cf: &str, sig: &str, record: &T) {
+        let Some(cf_handle) = self.db_instance.cf_handle(cf) else {
+            return;
+        };
+        if let Err(err) = put_serialized(&self.db_instance, cf_handle, tx_key(cf, sig), record) {
+            warn!("Couldn't persist transaction {sig} {err}");
+        }
+    }
+
+    fn unpersist(&self, cf: &str, sig: &str) {
+        let Some(cf_handle) = self.db_instance.cf_handle(cf) else {
+            return;
+        };
+        if let Err(err) = self.db_instance.delete_cf(cf_handle, tx_key(cf, sig)) {
+            warn!("Couldn't delete persisted transaction {sig} {err}");
+        }
+    }
+
+    /// Deletes the persisted statuses of transactions evicted from memory
+    pub fn unpersist_txs(&self, sigs: &[String]) {
+        for sig in sigs {
+            self.unpersist(TX_STATUS_CF, sig);
+        }
+    }
+
+    /// Tracks `sig` as sent, whatever it was sent through
+    pub fn mark_sent(&self, sig: String) {
+        self.txs_sent_store.insert(sig.clone(), TxProps::default());
+        self.persist_tx(&sig);
+    }
+
+    /// Batches as they go out to the tpu
+    pub fn sent_batches(&self) -> broadcast::Receiver<Vec<(String, u64)>> {
+        self.sent.subscribe()
+    }
+
+    /// Transactions as they fail to get to the tpu or are dropped from the queue
+    pub fn failed_txs(&self) -> broadcast::Receiver<Vec<(String, TxFailure)>> {
+        self.failed.subscribe()
+    }
+
+    /// Records why the transactions won't land and tells the listeners
+    fn fail(&self, failed: Vec<(String, TxFailure)>) {
+        for (sig, failure) in &failed {
+            if let Some(mut props) = self.txs_sent_store.get_mut(sig) {
+                props.failure = Some(failure.clone());
+            }
+            self.persist_tx(sig);
+        }
+        if !failed.is_empty() && self.failed.receiver_count() > 0 {
+            let _ = self.failed.send(failed);
+        }
+    }
+
+    /// Whether the tx sender is shutting down and refuses new transactions
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Stops queueing new transactions and waits until the queued ones went out to
+    /// the tpu, or `timeout` passed. Returns the transactions left unsent
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, Ordering::Relaxed);
+
+        let flushed = async {
+            while !self.unsent.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            // the last batches are still being forwarded
+            let _ = self.senders.acquire_many(NUMBER_OF_TX_SENDERS as u32).await;
+        };
+        if tokio::time::timeout(timeout, flushed).await.is_err() {
+            warn!("Timed out draining the transaction queue");
+        }
+        self.unsent.len()
+    }
+
+    /// Whether `sig` was queued or sent and is still tracked, dropped and replaced
+    /// transactions may be sent again
+    pub fn is_tracked(&self, sig: &str) -> bool {
+        self.txs_sent_store.get(sig).map_or(false, |props| {
+            matches!(props.state, TxState::Queued | TxState::Sent)
+        })
+    }
+
+    /// Marks the queued and sent transactions whose blockhash expired at `block_height`
+    /// before they were confirmed as expired and returns their signatures and failures, queued
+    /// ones won't be sent anymore
+    pub fn expire(&self, block_height: u64) -> Vec<(String, TxFailure)> {
+        let expired = expire_tracked(&self.txs_sent_store, block_height);
+        for (sig, _) in &expired {
+            self.unsent.remove(sig);
+            self.persist_tx(sig);
+        }
+        TXS_EXPIRED.inc_by(expired.len() as u64);
+        expired
+    }
+
+    /// Records a state change of a tracked transaction, dropped and replaced ones are
+    /// failed
+    pub fn set_state(&self, sig: &str, state: TxState) {
+        let failure = TxFailure::of_state(&state);
+        if let Some(mut props) = self.txs_sent_store.get_mut(sig) {
+            props.state = state;
+        }
+        match failure {
+            Some(failure) => self.fail(vec![(sig.to_owned(), failure)]),
+            None => self.persist_tx(sig),
+        }
+    }
+
+    /// Tx(s) per batch and the time waited for a batch to fill up
+    pub fn batching(&self) -> (usize, Duration) {
+        (
+            self.batch_size.load(Ordering::Relaxed),
+            Duration::from_millis(self.batch_interval_ms.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Takes effect from the next batch on
+    pub fn set_batching(&self, batch_size: usize, batch_interval: Duration) {
+        self.batch_size.store(batch_size.max(1), Ordering::Relaxed);
+        self.batch_interval_ms
+            .store(batch_interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Marks `sig` queued, until it's sent it can be dropped or replaced. False if it
+    /// was queued or sent already, checked and marked at once so it's queued only once
+    pub fn mark_queued(
+        &self,
+        sig: String,
+        unsent: Unsent,
+        last_valid_block_height: Option<u64>,
+        callback_url: Option<String>,
+    ) -> bool {
+        let props = TxProps {
+            state: TxState::Queued,
+            callback_url,
+            last_valid_block_height,
+            ..Default::default()
+        };
+        if !track_queued(&self.txs_sent_store, sig.clone(), props) {
+            return false;
+        }
+        self.persist_tx(&sig);
+        self.unsent.insert(sig, unsent);
+        true
+    }
+
+    /// The queued transaction `sig` if it wasn't sent yet
+    pub fn unsent(&self, sig: &str) -> Option<Unsent> {
+        self.unsent.get(sig).map(|unsent| *unsent)
+    }
+
+    /// Takes `sig` off the queue if it wasn't sent yet, `state` says why. Returns
+    /// `None` if it was sent already or was never queued
+    pub fn drop_queued(&self, sig: &str, state: TxState) -> Option<Unsent> {
+        let (_, unsent) = self.unsent.remove(sig)?;
+        self.pending_retries.remove(sig);
+        self.persist_retry(sig);
+        self.durable_nonce_txs.remove(sig);
+        self.set_state(sig, state);
+        TXS_DROPPED.inc();
+        Some(unsent)
+    }
+
+    /// Keeps track of a durable nonce transaction past the blockhash expiry
+    pub fn track_durable_nonce(&self, sig: String) {
+        TXS_DURABLE_NONCE.inc();
+        self.durable_nonce_txs.insert(sig.clone(), Instant::now());
+        self.persist_tx(&sig);
+    }
+
+    /// Whether `sig` is a durable nonce transaction that's still tracked
+    pub fn is_durable_nonce(&self, sig: &str) -> bool {
+        self.durable_nonce_txs.contains_key(sig)
+    }
+
+    /// Rebroadcasts `tx` up to `max_retries` times until it's confirmed or its
+    /// blockhash expired, durable nonce transactions until they're no longer tracked
+    pub fn retry_until_confirmed(
+        &self,
+        sig: String,
+        tx: WireTransaction,
+        max_retries: u16,
+        last_valid_block_height: Option<u64>,
+        target: Option<SocketAddr>,
+    ) {
+        if max_retries == 0 {
+            return;
+        }
+
+        self.pending_retries.insert(
+            sig.clone(),
+            PendingRetry {
+                tx,
+                retries_left: max_retries,
+                last_valid_block_height,
+                target,
+                sent_slot: None,
+                tried: HashSet::new(),
+            },
+        );
+        self.persist_retry(&sig);
+        TXS_PENDING_RETRY.set(self.pending_retries.len() as i64);
+    }
+
+    /// Sends the batch to the tpus of the current and next leaders, falls back to the
+    /// tpu client's fanout until the leaders are tracked. The tpus sent to, none for
+    /// the fanout
+    async fn send_to_leaders(&self, txs: Vec<WireTransaction>) -> anyhow::Result<Vec<SocketAddr>> {
+        let leader_tpus = self.tpu_manager.leader_tpus().await;
+        if leader_tpus.is_empty() {
+            self.tpu_manager
+                .try_send_wire_transaction_batch(txs)
+                .await?;
+            return Ok(vec![]);
+        }
+
+        self.tpu_manager
+            .send_wire_transaction_batch_to(&leader_tpus, &txs)
+            .await?;
+        Ok(leader_tpus)
+    }
+
+    /// Sends the batch to `target` alone, or to the leaders if `None`, the tpus sent to
+    async fn send_to(
+        &self,
+        target: Option<SocketAddr>,
+        txs: Vec<WireTransaction>,
+    ) -> anyhow::Result<Vec<SocketAddr>> {
+        match target {
+            Some(tpu) => {
+                self.tpu_manager
+                    .send_wire_transaction_batch_to_target(tpu, &txs)
+                    .await?;
+                Ok(vec![tpu])
+            }
+            None => self.send_to_leaders(txs).await,
         }
     }
 
@@ -74,7 +817,7 @@ impl TxSender {
         &self,
         sigs_and_slots: Vec<(String, u64)>,
         txs: Vec<WireTransaction>,
-        permit: OwnedSemaphorePermit,
+        target: Option<SocketAddr>,
     ) {
         assert_eq!(sigs_and_slots.len(), txs.len());
 
@@ -85,26 +828,54 @@ impl TxSender {
         let histo_timer = TT_SENT_TIMER.start_timer();
         let start = Instant::now();
 
-        let tpu_client = self.tpu_manager.clone();
         let txs_sent = self.txs_sent_store.clone();
 
         for (sig, _) in &sigs_and_slots {
-            txs_sent.insert(sig.to_owned(), TxProps::default());
+            // what it was queued with is kept
+            txs_sent
+                .entry(sig.to_owned())
+                .and_modify(|props| {
+                    props.state = TxState::Sent;
+                    props.sent_at = Instant::now();
+                })
+                .or_default();
+            self.persist_tx(sig);
         }
 
-        let _quic_response = match tpu_client.try_send_wire_transaction_batch(txs).await {
-            Ok(_) => {
+        let _quic_response = match self.send_to(target, txs).await {
+            Ok(sent_to) => {
                 // metrics
                 TXS_SENT.inc_by(sigs_and_slots.len() as u64);
+                // rebroadcasts move on to the leaders after these
+                for (sig, _) in &sigs_and_slots {
+                    if let Some(mut pending) = self.pending_retries.get_mut(sig) {
+                        pending.tried.extend(sent_to.iter().copied());
+                    }
+                }
+                // only cloned when someone listens
+                if self.sent.receiver_count() > 0 {
+                    let _ = self.sent.send(sigs_and_slots.clone());
+                }
                 1
             }
             Err(err) => {
                 TXS_SENT_ERRORS.inc_by(sigs_and_slots.len() as u64);
                 warn!("{err}");
+                // the ones rebroadcast may still get through
+                let failed = sigs_and_slots
+                    .iter()
+                    .filter(|(sig, _)| !self.pending_retries.contains_key(sig))
+                    .map(|(sig, _)| {
+                        let failure = TxFailure::SendFailed {
+                            reason: err.to_string(),
+                        };
+                        (sig.clone(), failure)
+                    })
+                    .collect();
+                self.fail(failed);
                 0
             }
         };
-        drop(permit);
         histo_timer.observe_duration();
         info!(
             "It took {} ms to send a batch of {} transaction(s)",
@@ -113,31 +884,128 @@ impl TxSender {
         );
     }
 
-    /// retry and confirm transactions every 2ms (avg time to confirm tx)
-    pub fn execute(
+    /// rebroadcast unconfirmed transactions every `retry_interval`, the tpu client
+    /// sends them to the upcoming leaders
+    pub fn retry(
         self,
-        mut recv: UnboundedReceiver<(String, WireTransaction, u64)>,
-        tx_batch_size: usize,
-        tx_send_interval: Duration,
+        block_store: BlockStore,
+        retry_interval: Duration,
     ) -> JoinHandle<anyhow::Result<()>> {
         tokio::spawn(async move {
+            let mut interval = tokio::time::interval(retry_interval);
+            loop {
+                interval.tick().await;
+
+                let block_height = block_store
+                    .get_latest_block_info(CommitmentConfig::confirmed())
+                    .await
+                    .block_height;
+                let slot = self.tpu_manager.estimated_current_slot().await;
+                let leader_tpus: Vec<SocketAddr> = self
+                    .tpu_manager
+                    .leader_tpus()
+                    .await
+                    .into_iter()
+                    .unique()
+                    .collect();
+                let upcoming_tpus = self.tpu_manager.upcoming_leader_tpus().await;
+                let round = RetryRound {
+                    block_height,
+                    slot,
+                    leader_tpus: &leader_tpus,
+                    upcoming_tpus: &upcoming_tpus,
+                };
+
+                let mut txs = Vec::new();
+                let mut changed = Vec::new();
+                let mut rotated = 0;
+                self.pending_retries.retain(|sig, pending| {
+                    // not in the store yet means it wasn't forwarded yet, not that it landed
+                    let confirmed = self
+                        .txs_sent_store
+                        .get(sig)
+                        .map_or(false, |props| props.is_confirmed());
+                    let step = pending.step(
+                        &round,
+                        confirmed,
+                        self.is_durable_nonce(sig),
+                        self.unsent.contains_key(sig),
+                    );
+                    match step {
+                        RetryStep::Done => {
+                            changed.push(sig.clone());
+                            false
+                        }
+                        RetryStep::Wait { first_send } => {
+                            if first_send {
+                                changed.push(sig.clone());
+                            }
+                            true
+                        }
+                        RetryStep::Send {
+                            leaders,
+                            rotated: to_untried,
+                        } => {
+                            rotated += u64::from(to_untried);
+                            txs.push(((pending.target, leaders), pending.tx.clone()));
+                            changed.push(sig.clone());
+                            true
+                        }
+                    }
+                });
+                TXS_RETRY_ROTATIONS.inc_by(rotated);
+                for sig in &changed {
+                    self.persist_retry(sig);
+                }
+                TXS_PENDING_RETRY.set(self.pending_retries.len() as i64);
+
+                if txs.is_empty() {
+                    continue;
+                }
+
+                for ((target, leaders), txs) in txs.into_iter().into_group_map() {
+                    let retried = txs.len() as u64;
+                    let sent = if target.is_some() || leaders.is_empty() {
+                        self.send_to(target, txs).await.map(|_| ())
+                    } else {
+                        self.tpu_manager
+                            .send_wire_transaction_batch_to(&leaders, &txs)
+                            .await
+                    };
+                    match sent {
+                        Ok(_) => TXS_RETRIED.inc_by(retried),
+                        Err(err) => {
+                            TXS_SENT_ERRORS.inc_by(retried);
+                            warn!("Rebroadcasting {retried} transaction(s) failed {err}");
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// retry and confirm transactions every 2ms (avg time to confirm tx)
+    pub fn execute(self, mut recv: Receiver<QueuedTx>) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(async move {
+            let (tx_batch_size, tx_send_interval) = self.batching();
             info!(
                 "Batching tx(s) with batch size of {tx_batch_size} every {}ms",
                 tx_send_interval.as_millis()
             );
-            let semaphore = Arc::new(Semaphore::new(NUMBER_OF_TX_SENDERS));
+            let semaphore = self.senders.clone();
+            let mut backlog = VecDeque::new();
+            let mut last_batch = Instant::now();
             loop {
-                let mut sigs_and_slots = Vec::with_capacity(tx_batch_size);
-                let mut txs = Vec::with_capacity(tx_batch_size);
+                let (tx_batch_size, tx_send_interval) = self.batching();
+                let backlog_capacity = tx_batch_size * DEFAULT_TX_PRIORITY_WINDOW;
                 let mut permit = None;
 
-                while txs.len() <= tx_batch_size {
+                while backlog.len() <= tx_batch_size {
                     match tokio::time::timeout(tx_send_interval, recv.recv()).await {
                         Ok(value) => match value {
-                            Some((sig, tx, slot)) => {
+                            Some(queued) => {
                                 TXS_IN_CHANNEL.dec();
-                                sigs_and_slots.push((sig, slot));
-                                txs.push(tx);
+                                backlog.push_back(queued);
                             }
                             None => {
                                 bail!("Channel Disconnected");
@@ -152,9 +1020,8 @@ impl TxSender {
                         }
                     }
                 }
-                assert_eq!(sigs_and_slots.len(), txs.len());
 
-                if sigs_and_slots.is_empty() {
+                if backlog.is_empty() {
                     continue;
                 }
 
@@ -166,14 +1033,569 @@ impl TxSender {
                     }
                 };
 
-                if !txs.is_empty() {
-                    TX_BATCH_SIZES.set(txs.len() as i64);
+                // leaders push back, spread batches out rather than have them dropped
+                let pause = self.tpu_manager.congestion().pause(tx_send_interval);
+                if !pause.is_zero() {
+                    tokio::time::sleep_until((last_batch + pause).into()).await;
+                }
+
+                // compare fees against what queued up while waiting for the permit too
+                while backlog.len() < backlog_capacity {
+                    let Ok(queued) = recv.try_recv() else {
+                        break;
+                    };
+                    TXS_IN_CHANNEL.dec();
+                    backlog.push_back(queued);
+                }
+
+                let mut batch =
+                    next_batch(&mut backlog, tx_batch_size, DEFAULT_TX_FAIR_SHARE_PERCENT);
+                // claim them for sending, dropped ones are skipped
+                batch.retain(|queued| self.unsent.remove(&queued.sig).is_some());
+                TXS_BACKLOG.set(backlog.len() as i64);
+
+                if !batch.is_empty() {
+                    last_batch = Instant::now();
+                    TX_BATCH_SIZES.set(batch.len() as i64);
+                    TX_BATCH_SIZE_HISTOGRAM.observe(batch.len() as f64);
+                    TX_QUEUE_DEPTH.observe(self.unsent.len() as f64);
+                    // targeted transactions go out to their validator alone
+                    let by_target = batch
+                        .into_iter()
+                        .map(|queued| (queued.target, queued))
+                        .into_group_map();
                     let tx_sender = self.clone();
                     tokio::spawn(async move {
-                        tx_sender.forward_txs(sigs_and_slots, txs, permit).await;
+                        let forwards = by_target.into_iter().map(|(target, batch)| {
+                            let (sigs_and_slots, txs) = batch
+                                .into_iter()
+                                .map(|queued| ((queued.sig, queued.slot), queued.tx))
+                                .unzip();
+                            tx_sender.forward_txs(sigs_and_slots, txs, target)
+                        });
+                        futures::future::join_all(forwards).await;
+                        drop(permit);
                     });
                 }
             }
         })
     }
 }
+
+/// Marks the tracked transactions whose blockhash expired at `block_height` as expired
+fn expire_tracked(
+    txs_sent_store: &DashMap<String, TxProps>,
+    block_height: u64,
+) -> Vec<(String, TxFailure)> {
+    let mut expired = Vec::new();
+    for mut props in txs_sent_store.iter_mut() {
+        // only processed may still be forked out, and never land
+        if props.is_confirmed() || !matches!(props.state, TxState::Queued | TxState::Sent) {
+            continue;
+        }
+        let Some(last_valid_block_height) = props.last_valid_block_height else {
+            continue;
+        };
+        if block_height > last_valid_block_height {
+            let failure = TxFailure::Expired {
+                last_valid_block_height,
+            };
+            props.state = TxState::Expired;
+            props.failure = Some(failure.clone());
+            expired.push((props.key().clone(), failure));
+        }
+    }
+    expired
+}
+
+/// Tracks `sig` with the queued `props` unless it's queued or sent already, checked
+/// and inserted under the entry's lock. False if it was
+fn track_queued(txs_sent_store: &DashMap<String, TxProps>, sig: String, props: TxProps) -> bool {
+    match txs_sent_store.entry(sig) {
+        Entry::Occupied(entry) if matches!(entry.get().state, TxState::Queued | TxState::Sent) => {
+            false
+        }
+        Entry::Occupied(mut entry) => {
+            entry.insert(props);
+            true
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(props);
+            true
+        }
+    }
+}
+
+/// Takes the next batch off the backlog, highest priority fee first. The oldest
+/// transactions get `fair_share_percent` of the batch whatever their fee, so low fee
+/// transactions still go out under a flood of high fee ones
+fn next_batch(
+    backlog: &mut VecDeque<QueuedTx>,
+    batch_size: usize,
+    fair_share_percent: usize,
+) -> Vec<QueuedTx> {
+    let fair_share = (batch_size * fair_share_percent.min(100) / 100)
+        .max(1)
+        .min(batch_size)
+        .min(backlog.len());
+
+    let mut by_fee: Vec<usize> = (fair_share..backlog.len()).collect();
+    // stable, equal fees keep their arrival order
+    by_fee.sort_by_key(|&index| Reverse(backlog[index].priority_fee));
+
+    let mut picked = vec![false; backlog.len()];
+    for index in (0..fair_share).chain(by_fee.into_iter().take(batch_size - fair_share)) {
+        picked[index] = true;
+    }
+
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut rest = VecDeque::with_capacity(backlog.len());
+    for (queued, picked) in backlog.drain(..).zip(picked) {
+        if picked {
+            batch.push(queued);
+        } else {
+            rest.push_back(queued);
+        }
+    }
+    *backlog = rest;
+
+    batch.sort_by_key(|queued| Reverse(queued.priority_fee));
+    batch
+}
+
+/// The tpus of the current and next leaders, which are always sent to, then up to
+/// `fanout` of the upcoming leader tpus, in schedule order, that aren't in `tried`
+fn next_leaders(
+    leaders: &[SocketAddr],
+    upcoming: &[SocketAddr],
+    tried: &HashSet<SocketAddr>,
+    fanout: usize,
+) -> Vec<SocketAddr> {
+    let untried = upcoming
+        .iter()
+        .filter(|tpu| !leaders.contains(tpu) && !tried.contains(tpu))
+        .unique()
+        .take(fanout);
+    leaders.iter().chain(untried).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backlog(fees: &[u64]) -> VecDeque<QueuedTx> {
+        fees.iter()
+            .enumerate()
+            .map(|(index, &priority_fee)| QueuedTx {
+                sig: index.to_string(),
+                tx: vec![],
+                slot: 0,
+                priority_fee,
+                target: None,
+            })
+            .collect()
+    }
+
+    fn sigs(batch: &[QueuedTx]) -> Vec<&str> {
+        batch.iter().map(|queued| queued.sig.as_str()).collect()
+    }
+
+    #[test]
+    fn failures_tell_subscribers_why() {
+        let expired = TxFailure::Expired {
+            last_valid_block_height: 10,
+        };
+        assert_eq!(
+            expired.notification(),
+            serde_json::json!({
+                "err": "BlockhashNotFound",
+                "reason": { "kind": "expired", "lastValidBlockHeight": 10 },
+            })
+        );
+
+        let on_chain = TxFailure::OnChain {
+            err: TransactionError::AccountInUse,
+        };
+        assert_eq!(on_chain.err(), TransactionError::AccountInUse);
+        assert_eq!(on_chain.notification()["err"], "AccountInUse");
+
+        assert_eq!(
+            TxFailure::of_state(&TxState::Replaced {
+                by: String::from("other")
+            }),
+            Some(TxFailure::Replaced {
+                by: String::from("other")
+            })
+        );
+        assert_eq!(
+            TxFailure::of_state(&TxState::Dropped),
+            Some(TxFailure::Dropped)
+        );
+        for state in [TxState::Queued, TxState::Sent, TxState::Expired] {
+            assert_eq!(TxFailure::of_state(&state), None);
+        }
+    }
+
+    #[test]
+    fn pending_retries_survive_a_restart() {
+        let record = PersistedRetry {
+            signature: String::from("sig"),
+            retry: PendingRetry {
+                target: Some(SocketAddr::from(([127, 0, 0, 1], 8003))),
+                sent_slot: Some(7),
+                tried: HashSet::from([SocketAddr::from(([127, 0, 0, 1], 8004))]),
+                ..pending(3, Some(100))
+            },
+        };
+        let persisted = serde_json::to_value(&record).unwrap();
+        assert_eq!(persisted["signature"], "sig");
+        assert_eq!(persisted["retriesLeft"], 3);
+        assert!(persisted.get("tried").is_none());
+
+        let PersistedRetry { signature, retry } = serde_json::from_value(persisted).unwrap();
+        assert_eq!(signature, "sig");
+        assert_eq!(retry.retries_left, 3);
+        assert_eq!(retry.last_valid_block_height, Some(100));
+        assert_eq!(retry.target, record.retry.target);
+        assert_eq!(retry.sent_slot, Some(7));
+        assert!(retry.tried.is_empty());
+
+        // persisted before targets and sent slots were
+        let retry: PendingRetry =
+            serde_json::from_str(r#"{"tx":[],"retriesLeft":1,"lastValidBlockHeight":null}"#)
+                .unwrap();
+        assert_eq!((retry.target, retry.sent_slot), (None, None));
+    }
+
+    #[test]
+    fn sent_times_survive_a_restart() {
+        let sent_at = Instant::now() - Duration::from_secs(1);
+        let restored = from_unix_ms(to_unix_ms(sent_at));
+        let drift = restored.max(sent_at).duration_since(restored.min(sent_at));
+        assert!(drift < Duration::from_millis(50), "{drift:?}");
+    }
+
+    #[test]
+    fn batches_go_out_highest_fee_first() {
+        let mut queued = backlog(&[1, 5, 3, 5, 2, 4]);
+        let batch = next_batch(&mut queued, 4, 0);
+        // the oldest always gets its share, equal fees keep their arrival order
+        assert_eq!(sigs(&batch), ["1", "3", "5", "0"]);
+        assert_eq!(
+            queued
+                .iter()
+                .map(|queued| queued.sig.as_str())
+                .collect::<Vec<_>>(),
+            ["2", "4"]
+        );
+    }
+
+    #[test]
+    fn oldest_transactions_get_their_share() {
+        let mut queued = backlog(&[1, 1, 1, 9, 9, 9, 9, 9, 9, 9]);
+        let batch = next_batch(&mut queued, 5, 40);
+        assert_eq!(batch.len(), 5);
+        assert!(sigs(&batch).contains(&"0") && sigs(&batch).contains(&"1"));
+        assert!(!sigs(&batch).contains(&"2"));
+    }
+
+    #[test]
+    fn fair_share_is_at_most_the_batch() {
+        let mut queued = backlog(&[1, 2, 3, 4, 5, 6]);
+        let batch = next_batch(&mut queued, 3, 250);
+        assert_eq!(sigs(&batch), ["2", "1", "0"]);
+        assert_eq!(queued.len(), 3);
+
+        let mut queued = backlog(&[1, 2]);
+        assert_eq!(next_batch(&mut queued, 5, 100).len(), 2);
+        assert!(queued.is_empty());
+    }
+
+    fn queued() -> TxProps {
+        TxProps {
+            state: TxState::Queued,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn concurrent_sends_queue_a_transaction_once() {
+        let txs_sent_store = Arc::new(DashMap::new());
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let sends: Vec<_> = (0..8)
+            .map(|_| {
+                let txs_sent_store = txs_sent_store.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    track_queued(&txs_sent_store, String::from("sig"), queued())
+                })
+            })
+            .collect();
+        let queued = sends.into_iter().map(|send| send.join().unwrap());
+        assert_eq!(queued.filter(|&queued| queued).count(), 1);
+    }
+
+    #[test]
+    fn only_dropped_and_replaced_transactions_are_queued_again() {
+        let txs_sent_store = DashMap::new();
+        let sig = || String::from("sig");
+        assert!(track_queued(&txs_sent_store, sig(), queued()));
+        assert!(!track_queued(&txs_sent_store, sig(), queued()));
+
+        for (state, queued_again) in [
+            (TxState::Sent, false),
+            (TxState::Expired, true),
+            (TxState::Dropped, true),
+            (
+                TxState::Replaced {
+                    by: String::from("other"),
+                },
+                true,
+            ),
+        ] {
+            txs_sent_store.get_mut("sig").unwrap().state = state.clone();
+            assert_eq!(
+                track_queued(&txs_sent_store, sig(), queued()),
+                queued_again,
+                "{state:?}"
+            );
+        }
+        assert_eq!(txs_sent_store.get("sig").unwrap().state, TxState::Queued);
+    }
+
+    #[test]
+    fn expires_unconfirmed_transactions_past_their_block_height() {
+        let status = |confirmation_status| TransactionStatus {
+            slot: 1,
+            confirmations: None,
+            status: Ok(()),
+            err: None,
+            confirmation_status: Some(confirmation_status),
+        };
+        let txs_sent_store = DashMap::new();
+        let track = |sig: &str, state, status, last_valid_block_height| {
+            txs_sent_store.insert(
+                sig.to_owned(),
+                TxProps {
+                    state,
+                    status,
+                    last_valid_block_height,
+                    ..Default::default()
+                },
+            );
+        };
+        track("queued", TxState::Queued, None, Some(10));
+        track("sent", TxState::Sent, None, Some(10));
+        track("later", TxState::Sent, None, Some(11));
+        track("durable_nonce", TxState::Sent, None, None);
+        track("dropped", TxState::Dropped, None, Some(10));
+        track(
+            "processed",
+            TxState::Sent,
+            Some(status(TransactionConfirmationStatus::Processed)),
+            Some(10),
+        );
+        track(
+            "confirmed",
+            TxState::Sent,
+            Some(status(TransactionConfirmationStatus::Confirmed)),
+            Some(10),
+        );
+
+        // still valid at its last valid block height
+        assert!(expire_tracked(&txs_sent_store, 10).is_empty());
+
+        let mut expired = expire_tracked(&txs_sent_store, 11);
+        expired.sort_by(|(sig, _), (other, _)| sig.cmp(other));
+        let failure = TxFailure::Expired {
+            last_valid_block_height: 10,
+        };
+        assert_eq!(
+            expired,
+            [
+                (String::from("processed"), failure.clone()),
+                (String::from("queued"), failure.clone()),
+                (String::from("sent"), failure.clone()),
+            ]
+        );
+        for (sig, _) in &expired {
+            let props = txs_sent_store.get(sig).unwrap();
+            assert_eq!(props.state, TxState::Expired);
+            assert_eq!(props.failure, Some(failure.clone()));
+        }
+        assert_eq!(
+            txs_sent_store.get("dropped").unwrap().state,
+            TxState::Dropped
+        );
+
+        // expired once
+        assert!(expire_tracked(&txs_sent_store, 11).is_empty());
+    }
+
+    fn tpu(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn pending(retries_left: u16, last_valid_block_height: Option<u64>) -> PendingRetry {
+        PendingRetry {
+            tx: vec![],
+            retries_left,
+            last_valid_block_height,
+            target: None,
+            sent_slot: None,
+            tried: HashSet::new(),
+        }
+    }
+
+    fn round(slot: u64) -> RetryRound<'static> {
+        RetryRound {
+            block_height: 100,
+            slot,
+            leader_tpus: &[],
+            upcoming_tpus: &[],
+        }
+    }
+
+    #[test]
+    fn retries_once_a_window_after_the_first_send() {
+        let mut retry = pending(2, Some(100));
+        // queued, not sent yet
+        assert_eq!(
+            retry.step(&round(10), false, false, true),
+            RetryStep::Wait { first_send: false }
+        );
+        assert_eq!(
+            retry.step(&round(10), false, false, false),
+            RetryStep::Wait { first_send: true }
+        );
+        let due = 10 + DEFAULT_TX_RETRY_WINDOW_SLOTS;
+        assert_eq!(
+            retry.step(&round(due - 1), false, false, false),
+            RetryStep::Wait { first_send: false }
+        );
+
+        for slot in [due, due + DEFAULT_TX_RETRY_WINDOW_SLOTS] {
+            assert!(matches!(
+                retry.step(&round(slot), false, false, false),
+                RetryStep::Send { .. }
+            ));
+        }
+        assert_eq!(retry.retries_left, 0);
+        assert_eq!(
+            retry.step(
+                &round(due + 2 * DEFAULT_TX_RETRY_WINDOW_SLOTS),
+                false,
+                false,
+                false
+            ),
+            RetryStep::Done
+        );
+    }
+
+    #[test]
+    fn stops_retrying_once_confirmed_or_expired() {
+        let sent = |last_valid_block_height| PendingRetry {
+            sent_slot: Some(0),
+            ..pending(5, last_valid_block_height)
+        };
+        let due = round(DEFAULT_TX_RETRY_WINDOW_SLOTS);
+
+        assert_eq!(
+            sent(Some(100)).step(&due, true, false, false),
+            RetryStep::Done
+        );
+        // the round's block height passed it
+        assert_eq!(
+            sent(Some(99)).step(&due, false, false, false),
+            RetryStep::Done
+        );
+        // queued ones expire too
+        assert_eq!(
+            sent(Some(99)).step(&due, false, false, true),
+            RetryStep::Done
+        );
+        // durable nonce transactions until they're no longer tracked
+        assert!(matches!(
+            sent(None).step(&due, false, true, false),
+            RetryStep::Send { .. }
+        ));
+        assert_eq!(sent(None).step(&due, false, false, false), RetryStep::Done);
+    }
+
+    #[test]
+    fn retries_go_around_the_upcoming_leaders() {
+        let leader_tpus = [tpu(1)];
+        let upcoming_tpus = [tpu(2), tpu(3)];
+        let round = |slot| RetryRound {
+            block_height: 100,
+            slot,
+            leader_tpus: &leader_tpus,
+            upcoming_tpus: &upcoming_tpus,
+        };
+        let mut retry = PendingRetry {
+            sent_slot: Some(0),
+            ..pending(10, Some(100))
+        };
+
+        let mut sent_to = vec![];
+        for retried in 1..=4 {
+            match retry.step(
+                &round(retried * DEFAULT_TX_RETRY_WINDOW_SLOTS),
+                false,
+                false,
+                false,
+            ) {
+                RetryStep::Send { leaders, rotated } => {
+                    assert!(rotated);
+                    sent_to.push(leaders);
+                }
+                step => panic!("expected a retry, got {step:?}"),
+            }
+        }
+        assert_eq!(
+            sent_to,
+            [
+                [tpu(1), tpu(2)],
+                [tpu(1), tpu(3)],
+                [tpu(1), tpu(2)],
+                [tpu(1), tpu(3)],
+            ]
+        );
+
+        // targeted ones go to their target alone
+        let mut retry = PendingRetry {
+            target: Some(tpu(9)),
+            sent_slot: Some(0),
+            ..pending(1, Some(100))
+        };
+        assert_eq!(
+            retry.step(&round(DEFAULT_TX_RETRY_WINDOW_SLOTS), false, false, false),
+            RetryStep::Send {
+                leaders: vec![],
+                rotated: false
+            }
+        );
+    }
+
+    #[test]
+    fn rebroadcasts_to_leaders_and_untried_upcoming_ones() {
+        let leaders = [tpu(1), tpu(2)];
+        let upcoming = [tpu(2), tpu(3), tpu(3), tpu(4), tpu(5)];
+
+        let tried = HashSet::new();
+        assert_eq!(
+            next_leaders(&leaders, &upcoming, &tried, 2),
+            [tpu(1), tpu(2), tpu(3), tpu(4)]
+        );
+
+        let tried = HashSet::from([tpu(1), tpu(3)]);
+        assert_eq!(
+            next_leaders(&leaders, &upcoming, &tried, 2),
+            [tpu(1), tpu(2), tpu(4), tpu(5)]
+        );
+
+        let tried = HashSet::from([tpu(3), tpu(4), tpu(5)]);
+        assert_eq!(next_leaders(&leaders, &upcoming, &tried, 2), leaders);
+    }
+}
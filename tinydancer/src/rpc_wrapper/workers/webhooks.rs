@@ -0,0 +1,289 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use prometheus::{register_int_counter, IntCounter};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect, Url,
+};
+use serde::Serialize;
+use solana_transaction_status::TransactionStatus;
+use tiny_logger::logs::warn;
+use tokio::sync::Semaphore;
+
+lazy_static::lazy_static! {
+    static ref WEBHOOKS_SENT: IntCounter =
+        register_int_counter!("literpc_webhooks_sent", "Number of transaction statuses posted to callback urls").unwrap();
+    static ref WEBHOOKS_ERRORS: IntCounter =
+        register_int_counter!("literpc_webhooks_errors", "Number of callback urls that failed or refused a transaction status").unwrap();
+    static ref WEBHOOKS_SKIPPED: IntCounter =
+        register_int_counter!("literpc_webhooks_skipped", "Number of transaction statuses not posted as too many posts were in flight").unwrap();
+}
+
+/// Posts in flight, statuses beyond it are skipped until some complete
+const MAX_INFLIGHT_WEBHOOKS: usize = 256;
+/// Time a callback url has to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body posted to a callback url
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxWebhook {
+    pub signature: String,
+    #[serde(flatten)]
+    pub status: TransactionStatus,
+}
+
+/// Posts the status of a tracked transaction to its callback url, or the configured
+/// one, once it's confirmed and again once it's finalized. Callback urls come from
+/// clients, they're only posted to on public addresses and never redirected
+#[derive(Clone)]
+pub struct Webhooks {
+    /// used for transactions sent without a callback url, none if `None`
+    global_url: Option<String>,
+    client: reqwest::Client,
+    /// resolves hosts of callback urls to their public addresses only
+    callback_client: reqwest::Client,
+    inflight: Arc<Semaphore>,
+}
+
+impl Webhooks {
+    pub fn new(global_url: Option<String>) -> Self {
+        let callback_client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .expect("webhook client builds");
+
+        Self {
+            global_url,
+            client: reqwest::Client::new(),
+            callback_client,
+            inflight: Arc::new(Semaphore::new(MAX_INFLIGHT_WEBHOOKS)),
+        }
+    }
+
+    /// Checks a callback url before a transaction is tracked with it, hosts resolving
+    /// to private addresses are caught when it's posted to
+    pub fn validate_url(url: &str) -> anyhow::Result<()> {
+        let url = Url::parse(url)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Callback url must be http or https");
+        }
+        match url.host() {
+            Some(url::Host::Domain(domain)) if is_local_domain(domain) => {
+                anyhow::bail!("Callback url must not point at a local host")
+            }
+            Some(url::Host::Domain(_)) => Ok(()),
+            Some(url::Host::Ipv4(ip)) if is_public(IpAddr::V4(ip)) => Ok(()),
+            Some(url::Host::Ipv6(ip)) if is_public(IpAddr::V6(ip)) => Ok(()),
+            Some(_) => anyhow::bail!("Callback url must point at a public address"),
+            None => anyhow::bail!("Callback url has no host"),
+        }
+    }
+
+    /// Posts `status` in the background, never blocks the caller
+    pub fn notify(&self, callback_url: Option<&str>, signature: String, status: TransactionStatus) {
+        let (url, client) = match callback_url {
+            Some(url) => {
+                // checked again, it may have been restored from an older version
+                if let Err(err) = Self::validate_url(url) {
+                    WEBHOOKS_ERRORS.inc();
+                    warn!("Callback url of {signature} refused {err}");
+                    return;
+                }
+                (url, &self.callback_client)
+            }
+            None => match self.global_url.as_deref() {
+                Some(url) => (url, &self.client),
+                None => return,
+            },
+        };
+        let Ok(permit) = self.inflight.clone().try_acquire_owned() else {
+            WEBHOOKS_SKIPPED.inc();
+            warn!("Too many webhooks in flight, skipped the status of {signature}");
+            return;
+        };
+
+        let body = match serde_json::to_string(&TxWebhook {
+            signature: signature.clone(),
+            status,
+        }) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Couldn't serialize the status of {signature} {err}");
+                return;
+            }
+        };
+        let request = client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .timeout(WEBHOOK_TIMEOUT)
+            .body(body);
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            match request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(_) => WEBHOOKS_SENT.inc(),
+                Err(err) => {
+                    WEBHOOKS_ERRORS.inc();
+                    warn!("Webhook of {signature} failed {err}");
+                }
+            }
+        });
+    }
+}
+
+/// Resolves hosts like the system does and leaves out every address that isn't
+/// public, a host with none left fails to resolve
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name.as_str().to_string()))
+    }
+}
+
+async fn resolve_public(host: String) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .filter(|addr| is_public(addr.ip()))
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{host} has no public address").into());
+    }
+    Ok(Box::new(addrs.into_iter()))
+}
+
+fn is_local_domain(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    domain == "localhost" || domain.ends_with(".localhost")
+}
+
+/// Whether `ip` is reachable over the internet, loopback, link local (cloud metadata
+/// included), private and otherwise reserved ranges aren't
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// Ipv4 address an ipv6 one reaches, for mapped, compatible, NAT64 and 6to4 addresses
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    let low = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => Some(low),
+        // compatible, :: and ::1 left to the ipv6 checks
+        [0, 0, 0, 0, 0, 0, _, _] if !ip.is_unspecified() && !ip.is_loopback() => Some(low),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(low),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => None,
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // shared address space
+        || (a == 100 && (64..128).contains(&b))
+        // this network and reserved
+        || a == 0
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let [first, ..] = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || (first & 0xfe00) == 0xfc00
+        // link local
+        || (first & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_public_addresses() {
+        for (ip, public) in [
+            ("8.8.8.8", true),
+            ("127.0.0.1", false),
+            ("10.1.2.3", false),
+            ("169.254.169.254", false),
+            ("100.64.0.1", false),
+            ("0.1.2.3", false),
+            ("240.0.0.1", false),
+            ("2606:4700::1111", true),
+            ("::", false),
+            ("::1", false),
+            ("fd00::1", false),
+            ("fe80::1", false),
+            ("ff02::1", false),
+            ("::ffff:8.8.8.8", true),
+            ("::ffff:127.0.0.1", false),
+            ("::127.0.0.1", false),
+            ("::169.254.169.254", false),
+            ("64:ff9b::8.8.8.8", true),
+            ("64:ff9b::a9fe:a9fe", false),
+            ("2002:0808:0808::1", true),
+            ("2002:7f00:0001::1", false),
+            ("2002:0a00:0001::1", false),
+        ] {
+            assert_eq!(is_public(ip.parse().unwrap()), public, "{ip}");
+        }
+    }
+
+    #[test]
+    fn tells_local_domains() {
+        for (domain, local) in [
+            ("localhost", true),
+            ("LocalHost.", true),
+            ("api.localhost", true),
+            ("example.com", false),
+            ("localhost.example.com", false),
+        ] {
+            assert_eq!(is_local_domain(domain), local, "{domain}");
+        }
+    }
+
+    #[test]
+    fn validates_callback_urls() {
+        for (url, valid) in [
+            ("https://example.com/callback", true),
+            ("http://8.8.8.8/callback", true),
+            ("ftp://example.com/callback", false),
+            ("http://localhost:8080/callback", false),
+            ("http://127.0.0.1/callback", false),
+            ("http://169.254.169.254/latest/meta-data", false),
+            ("http://[::1]/callback", false),
+            ("http://[::ffff:7f00:1]/callback", false),
+            ("http://[64:ff9b::a9fe:a9fe]/callback", false),
+            ("http://[2002:7f00:1::1]/callback", false),
+            ("not a url", false),
+        ] {
+            assert_eq!(Webhooks::validate_url(url).is_ok(), valid, "{url}");
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! Websocket server of the lite rpc. jsonrpsee's server runs the calls of a connection
+//! where nothing tells which client made them, this one runs them with the caller of
+//! the connection in scope so methods take per client tokens and quotas over
+//! websocket like over http
+use std::{
+    future::{ready, Ready},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::mpsc::UnboundedReceiver,
+    future::join_all,
+    io::{BufReader, BufWriter},
+    StreamExt,
+};
+use hyper::{server::conn::Http, upgrade::Upgraded, Body, Request, Response, StatusCode};
+use jsonrpsee::{
+    core::{
+        server::{
+            helpers::{prepare_error, MethodResponse},
+            rpc_module::{MethodKind, Methods},
+        },
+        JsonRawValue,
+    },
+    types::{
+        error::{reject_too_big_request, reject_too_many_subscriptions, ErrorCode},
+        ErrorObject, Id, Notification, Request as RpcRequest,
+    },
+};
+use soketto::{
+    connection::Error as SokettoError,
+    data::ByteSlice125,
+    handshake::http::{is_upgrade_request, Server},
+};
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+use tokio_util::{
+    compat::{Compat, TokioAsyncReadCompatExt},
+    sync::CancellationToken,
+};
+use tower::Service;
+
+use crate::rpc_wrapper::{
+    caller::{Caller, CallerLayer},
+    proxy::accept_loop,
+};
+
+type Sender = soketto::Sender<BufReader<BufWriter<Compat<Upgraded>>>>;
+type Receiver = soketto::Receiver<BufReader<BufWriter<Compat<Upgraded>>>>;
+
+/// Limits of the websocket server
+pub struct WsConfig {
+    /// bytes a message may hold
+    pub max_message_size: u32,
+    /// further connections are refused
+    pub max_connections: u32,
+    pub max_subscriptions_per_connection: u32,
+    /// messages queued for a client, one falling further behind is disconnected
+    pub max_queued_messages: usize,
+    /// calls of a connection run at once, its next messages wait to be read
+    pub max_in_flight_calls: usize,
+    /// dead connections are noticed when a ping fails
+    pub ping_interval: Duration,
+}
+
+/// Upgrades requests to websocket connections serving `methods`
+#[derive(Clone)]
+pub struct WsServer {
+    methods: Methods,
+    callers: CallerLayer,
+    connections: Arc<Semaphore>,
+    config: Arc<WsConfig>,
+}
+
+impl WsServer {
+    pub fn new(methods: impl Into<Methods>, callers: CallerLayer, config: WsConfig) -> Self {
+        Self {
+            methods: methods.into(),
+            callers,
+            connections: Arc::new(Semaphore::new(config.max_connections as usize)),
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl Service<Request<Body>> for WsServer {
+    type Response = Response<Body>;
+    type Error = hyper::Error;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if !is_upgrade_request(&request) {
+            return ready(Ok(status(StatusCode::FORBIDDEN)));
+        }
+        let Ok(permit) = self.connections.clone().try_acquire_owned() else {
+            return ready(Ok(status(StatusCode::SERVICE_UNAVAILABLE)));
+        };
+
+        let mut handshake = Server::new();
+        let response = match handshake.receive_request(&request) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Couldn't upgrade a websocket connection: {err}");
+                return ready(Ok(status(StatusCode::BAD_REQUEST)));
+            }
+        };
+
+        let caller = self.callers.identify(&request);
+        let methods = self.methods.clone();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            let upgraded = match hyper::upgrade::on(request).await {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    warn!("Couldn't upgrade a websocket connection: {err}");
+                    return;
+                }
+            };
+
+            let stream = BufReader::new(BufWriter::new(upgraded.compat()));
+            let mut builder = handshake.into_builder(stream);
+            builder.set_max_message_size(config.max_message_size as usize);
+            let (sender, receiver) = builder.finish();
+            serve_connection(sender, receiver, methods, caller, config, permit).await;
+        });
+
+        ready(Ok(response.map(|()| Body::empty())))
+    }
+}
+
+/// Serves `service`, the websocket server behind its middleware, on `addr`
+pub async fn listen<S>(
+    addr: impl ToSocketAddrs,
+    service: S,
+) -> anyhow::Result<(std::net::SocketAddr, JoinHandle<anyhow::Result<()>>)>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Websocket Server started at {local_addr}");
+
+    let handle = tokio::spawn(accept_loop(listener, move |stream, peer| {
+        let service = service.clone();
+        async move {
+            if let Err(err) = Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                warn!("Websocket connection from {peer} failed: {err}");
+            }
+        }
+    }));
+    Ok((local_addr, handle))
+}
+
+/// Messages queued for the client of a connection, a client that doesn't read them as
+/// fast as they come is disconnected
+#[derive(Clone)]
+struct Outgoing {
+    messages: mpsc::Sender<String>,
+    /// cancelled once the client fell behind, the writer then closes the connection
+    lagging: CancellationToken,
+}
+
+impl Outgoing {
+    fn new(capacity: usize) -> (Self, mpsc::Receiver<String>) {
+        let (messages, receiver) = mpsc::channel(capacity);
+        let outgoing = Self {
+            messages,
+            lagging: CancellationToken::new(),
+        };
+        (outgoing, receiver)
+    }
+
+    /// Queues `message`, false if the connection is closed or closing
+    fn send(&self, message: String) -> bool {
+        match self.messages.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if !self.lagging.is_cancelled() {
+                    warn!("Closing a websocket connection whose client fell behind");
+                    self.lagging.cancel();
+                }
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Resolves once the connection is closed or closing
+    async fn closed(&self) {
+        tokio::select! {
+            _ = self.messages.closed() => {}
+            _ = self.lagging.cancelled() => {}
+        }
+    }
+}
+
+async fn serve_connection(
+    mut sender: Sender,
+    mut receiver: Receiver,
+    methods: Methods,
+    caller: Caller,
+    config: Arc<WsConfig>,
+    _permit: OwnedSemaphorePermit,
+) {
+    let (out, mut outgoing) = Outgoing::new(config.max_queued_messages);
+    let lagging = out.lagging.clone();
+    let ping_interval = config.ping_interval;
+    // a failed send or ping drops `outgoing`, which ends the connection
+    let writer = tokio::spawn(async move {
+        let mut ping = tokio::time::interval(ping_interval);
+        loop {
+            let sent = tokio::select! {
+                message = outgoing.recv() => match message {
+                    Some(message) => send_text(&mut sender, message).await,
+                    None => break,
+                },
+                _ = ping.tick() => send_ping(&mut sender).await,
+                _ = lagging.cancelled() => break,
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+        let _ = sender.close().await;
+    });
+
+    let in_flight = Arc::new(Semaphore::new(config.max_in_flight_calls));
+    let subscriptions = Arc::new(AtomicU32::new(0));
+    let mut message = Vec::new();
+    loop {
+        // the next message isn't read before a call of this connection is done
+        let permit = tokio::select! {
+            permit = in_flight.clone().acquire_owned() => {
+                permit.expect("the semaphore is never closed")
+            }
+            _ = out.closed() => break,
+        };
+        message.clear();
+        let received = tokio::select! {
+            received = receiver.receive_data(&mut message) => received,
+            _ = out.closed() => break,
+        };
+
+        match received {
+            Ok(_) => {
+                let call = handle_message(
+                    methods.clone(),
+                    std::mem::take(&mut message),
+                    out.clone(),
+                    subscriptions.clone(),
+                    config.max_subscriptions_per_connection,
+                );
+                tokio::spawn(caller.clone().scope(async move {
+                    call.await;
+                    drop(permit);
+                }));
+            }
+            Err(SokettoError::MessageTooLarge { .. }) => {
+                let response = MethodResponse::error(
+                    Id::Null,
+                    reject_too_big_request(config.max_message_size),
+                );
+                out.send(response.result);
+            }
+            Err(SokettoError::Closed) => break,
+            Err(err) => {
+                warn!("Websocket connection failed: {err}");
+                break;
+            }
+        }
+    }
+
+    // dropping `outgoing` ends the notifications of the connection's subscriptions
+    writer.abort();
+}
+
+/// Answers a call or a batch of them, then starts forwarding the notifications of the
+/// subscriptions they opened
+async fn handle_message(
+    methods: Methods,
+    message: Vec<u8>,
+    out: Outgoing,
+    subscriptions: Arc<AtomicU32>,
+    max_subscriptions: u32,
+) {
+    let mut opened = Vec::new();
+    let response = match serde_json::from_slice::<Vec<&JsonRawValue>>(&message) {
+        Ok(batch) if batch.is_empty() => Some(
+            MethodResponse::error(Id::Null, ErrorObject::from(ErrorCode::InvalidRequest)).result,
+        ),
+        Ok(batch) => {
+            let calls = batch
+                .iter()
+                .map(|call| run_call(&methods, call.get(), &subscriptions, max_subscriptions));
+            let mut responses = Vec::new();
+            for (response, notifications) in join_all(calls).await {
+                responses.extend(response);
+                opened.extend(notifications);
+            }
+            // a batch of notifications only isn't answered
+            (!responses.is_empty()).then(|| format!("[{}]", responses.join(",")))
+        }
+        Err(_) => match std::str::from_utf8(&message) {
+            Ok(call) => {
+                let (response, notifications) =
+                    run_call(&methods, call, &subscriptions, max_subscriptions).await;
+                opened.extend(notifications);
+                response
+            }
+            Err(_) => Some(
+                MethodResponse::error(Id::Null, ErrorObject::from(ErrorCode::ParseError)).result,
+            ),
+        },
+    };
+
+    if let Some(response) = response {
+        out.send(response);
+    }
+    // only once the subscription is answered, so its id is known
+    for notifications in opened {
+        tokio::spawn(forward_notifications(
+            notifications,
+            out.clone(),
+            subscriptions.clone(),
+        ));
+    }
+}
+
+/// Response to a single call, `None` for a notification, and the notifications of the
+/// subscription it opened if it did
+async fn run_call(
+    methods: &Methods,
+    call: &str,
+    subscriptions: &AtomicU32,
+    max_subscriptions: u32,
+) -> (Option<String>, Option<UnboundedReceiver<String>>) {
+    let request = match serde_json::from_str::<RpcRequest>(call) {
+        Ok(request) => request,
+        Err(_) => {
+            if serde_json::from_str::<Notification<Option<&JsonRawValue>>>(call).is_ok() {
+                return (None, None);
+            }
+            let (id, code) = prepare_error(call.as_bytes());
+            return (
+                Some(MethodResponse::error(id, ErrorObject::from(code)).result),
+                None,
+            );
+        }
+    };
+
+    let is_subscription = matches!(
+        methods.method(&request.method).map(|method| method.inner()),
+        Some(MethodKind::Subscription(_))
+    );
+    if is_subscription && subscriptions.fetch_add(1, Ordering::Relaxed) >= max_subscriptions {
+        subscriptions.fetch_sub(1, Ordering::Relaxed);
+        let response =
+            MethodResponse::error(request.id, reject_too_many_subscriptions(max_subscriptions));
+        return (Some(response.result), None);
+    }
+
+    match methods.raw_json_request(call).await {
+        Ok((response, notifications)) if is_subscription && response.success => {
+            (Some(response.result), Some(notifications))
+        }
+        Ok((response, _)) => {
+            if is_subscription {
+                subscriptions.fetch_sub(1, Ordering::Relaxed);
+            }
+            (Some(response.result), None)
+        }
+        Err(_) => {
+            if is_subscription {
+                subscriptions.fetch_sub(1, Ordering::Relaxed);
+            }
+            let response =
+                MethodResponse::error(request.id, ErrorObject::from(ErrorCode::ParseError));
+            (Some(response.result), None)
+        }
+    }
+}
+
+/// Sends the notifications of a subscription until it's closed or the connection is.
+/// Dropping `notifications` tells the subscription the connection is gone
+async fn forward_notifications(
+    mut notifications: UnboundedReceiver<String>,
+    out: Outgoing,
+    subscriptions: Arc<AtomicU32>,
+) {
+    loop {
+        tokio::select! {
+            notification = notifications.next() => match notification {
+                Some(notification) if out.send(notification) => {}
+                _ => break,
+            },
+            _ = out.closed() => break,
+        }
+    }
+    subscriptions.fetch_sub(1, Ordering::Relaxed);
+}
+
+async fn send_text(sender: &mut Sender, message: String) -> Result<(), SokettoError> {
+    sender.send_text_owned(message).await?;
+    sender.flush().await
+}
+
+async fn send_ping(sender: &mut Sender) -> Result<(), SokettoError> {
+    let empty = ByteSlice125::try_from(&[][..]).expect("an empty ping fits");
+    sender.send_ping(empty).await?;
+    sender.flush().await
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpsee::{types::error::TOO_MANY_SUBSCRIPTIONS_CODE, RpcModule};
+    use serde_json::Value;
+
+    use super::*;
+
+    fn methods() -> Methods {
+        let mut module = RpcModule::new(());
+        module
+            .register_method("echo", |params, _| params.one::<u64>().map_err(Into::into))
+            .unwrap();
+        // sends as many ticks as asked for and ends, stays open if none are
+        module
+            .register_subscription(
+                "subscribeTicks",
+                "tick",
+                "unsubscribeTicks",
+                |params, mut sink, _| {
+                    let ticks: u64 = params.one()?;
+                    sink.accept()?;
+                    tokio::spawn(async move {
+                        for tick in 0..ticks {
+                            sink.send(&tick).unwrap();
+                        }
+                        if ticks == 0 {
+                            std::future::pending::<()>().await;
+                        }
+                    });
+                    Ok(())
+                },
+            )
+            .unwrap();
+        module.into()
+    }
+
+    fn subscribe(ticks: u64, id: u64) -> String {
+        format!(r#"{{"jsonrpc":"2.0","method":"subscribeTicks","params":[{ticks}],"id":{id}}}"#)
+    }
+
+    /// What a connection keeps besides its socket
+    struct Connection {
+        methods: Methods,
+        out: Outgoing,
+        messages: mpsc::Receiver<String>,
+        subscriptions: Arc<AtomicU32>,
+        max_subscriptions: u32,
+    }
+
+    impl Connection {
+        fn new(max_queued_messages: usize, max_subscriptions: u32) -> Self {
+            let (out, messages) = Outgoing::new(max_queued_messages);
+            Self {
+                methods: methods(),
+                out,
+                messages,
+                subscriptions: Default::default(),
+                max_subscriptions,
+            }
+        }
+
+        /// Handles `message` as if the client sent it, returning the answer
+        async fn send(&mut self, message: &str) -> Option<Value> {
+            handle_message(
+                self.methods.clone(),
+                message.as_bytes().to_vec(),
+                self.out.clone(),
+                self.subscriptions.clone(),
+                self.max_subscriptions,
+            )
+            .await;
+            self.next()
+        }
+
+        /// Next message queued for the client
+        fn next(&mut self) -> Option<Value> {
+            let message = self.messages.try_recv().ok()?;
+            Some(serde_json::from_str(&message).unwrap())
+        }
+
+        /// Waits until the connection holds `subscriptions` subscriptions
+        async fn settle(&self, subscriptions: u32) {
+            let settled = async {
+                while self.subscriptions.load(Ordering::Relaxed) != subscriptions {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), settled)
+                .await
+                .expect("subscriptions settle");
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_batches() {
+        let mut connection = Connection::new(16, 16);
+
+        let response = connection
+            .send(
+                r#"[
+                    {"jsonrpc":"2.0","method":"echo","params":[1],"id":1},
+                    {"jsonrpc":"2.0","method":"echo","params":[2]},
+                    {"jsonrpc":"2.0","method":"unknown","id":3}
+                ]"#,
+            )
+            .await
+            .unwrap();
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"], 1);
+        assert_eq!(responses[1]["id"], 3);
+        assert_eq!(responses[1]["error"]["code"], -32601);
+
+        // a batch of notifications only isn't answered, an empty one is refused
+        let notifications = r#"[{"jsonrpc":"2.0","method":"echo","params":[2]}]"#;
+        assert!(connection.send(notifications).await.is_none());
+        let response = connection.send("[]").await.unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+    }
+
+    #[tokio::test]
+    async fn caps_the_subscriptions_of_a_connection() {
+        let mut connection = Connection::new(16, 1);
+
+        let response = connection.send(&subscribe(0, 1)).await.unwrap();
+        assert!(response["result"].is_u64(), "{response}");
+        let response = connection.send(&subscribe(0, 2)).await.unwrap();
+        assert_eq!(response["error"]["code"], TOO_MANY_SUBSCRIPTIONS_CODE);
+        // refused subscriptions don't hold on to a slot
+        assert_eq!(connection.subscriptions.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn forwards_notifications_until_the_subscription_ends() {
+        let mut connection = Connection::new(16, 1);
+
+        let response = connection.send(&subscribe(2, 1)).await.unwrap();
+        let subscription = response["result"].clone();
+        connection.settle(0).await;
+        for tick in 0..2 {
+            let notification = connection.next().unwrap();
+            assert_eq!(notification["method"], "tick");
+            assert_eq!(notification["params"]["subscription"], subscription);
+            assert_eq!(notification["params"]["result"], tick);
+        }
+
+        // its slot is free again
+        let response = connection.send(&subscribe(0, 2)).await.unwrap();
+        assert!(response["result"].is_u64(), "{response}");
+    }
+
+    #[tokio::test]
+    async fn closing_the_connection_ends_its_subscriptions() {
+        let mut connection = Connection::new(16, 2);
+        for id in 0..2 {
+            connection.send(&subscribe(0, id)).await.unwrap();
+        }
+        assert_eq!(connection.subscriptions.load(Ordering::Relaxed), 2);
+
+        // as the writer does once the socket is gone
+        connection.messages.close();
+        connection.settle(0).await;
+    }
+
+    #[tokio::test]
+    async fn disconnects_clients_that_fall_behind() {
+        let mut connection = Connection::new(2, 1);
+
+        connection.send(&subscribe(5, 1)).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), connection.out.closed())
+            .await
+            .expect("the connection closes");
+        assert!(connection.out.lagging.is_cancelled());
+        connection.settle(0).await;
+    }
+}
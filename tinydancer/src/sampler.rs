@@ -1,22 +1,29 @@
-use crate::tinydancer::{endpoint, ClientService, ClientStatus, Cluster};
-use crate::{convert_to_websocket, send_rpc_call, try_coerce_shred};
+use crate::leader_schedule::LeaderScheduleCache;
+use crate::rpc_wrapper::{coalesce::Coalescer, upstreams::Upstreams};
+use crate::sampling_peers::SamplingPeers;
+use crate::tinydancer::{ClientService, ClientStatus};
+use crate::{send_rpc_call, try_coerce_shred};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::{Receiver, Sender, TrySendError};
 use futures::Sink;
 use itertools::Itertools;
+use prometheus::{opts, register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
 use rand::distributions::Uniform;
 use rand::prelude::*;
 use rayon::prelude::*;
 use reqwest::Request;
-use rocksdb::{ColumnFamily, Options as RocksOptions, DB};
+use rocksdb::{ColumnFamily, IteratorMode, Options as RocksOptions, DB};
 use serde::de::DeserializeOwned;
 use solana_ledger::shred::{ShredId, ShredType};
 use solana_ledger::{
     ancestor_iterator::{AncestorIterator, AncestorIteratorWithHash},
     blockstore::Blockstore,
     // blockstore_db::columns::ShredCode,
-    shred::{Nonce, Shred, ShredCode, ShredData, ShredFetchStats, SIZE_OF_NONCE},
+    shred::{
+        Nonce, Shred, ShredCode, ShredData, ShredFetchStats, DATA_SHREDS_PER_FEC_BLOCK,
+        SIZE_OF_NONCE,
+    },
 };
 use solana_sdk::hash::hashv;
 use solana_sdk::{
@@ -29,23 +36,99 @@ use solana_sdk::{
     signer::keypair::Keypair,
     timing::{duration_as_ms, timestamp},
 };
+use std::collections::HashSet;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use std::{error::Error, ops::Add};
 use std::{
     net::{SocketAddr, UdpSocket},
     thread::Builder,
 };
-use tiny_logger::logs::{debug, error, info};
+use tiny_logger::logs::{debug, error, info, warn};
 use tokio::{
-    sync::mpsc::UnboundedSender,
+    sync::{broadcast, mpsc::UnboundedSender},
     task::{JoinError, JoinHandle},
 };
 use tungstenite::{connect, Message};
 use url::Url;
 
 pub const SHRED_CF: &str = "archived_shreds";
+pub const SAMPLE_HISTORY_CF: &str = "sample_history";
+
+/// How far behind the latest sampled slot the backfill worker looks for gaps
+const BACKFILL_WINDOW: u64 = 256;
+const BACKFILL_INTERVAL: Duration = Duration::from_secs(30);
+/// Max slots verified together by shred_verify_loop
+const VERIFY_BATCH_MAX_SLOTS: usize = 16;
+/// Slots waiting to be sampled, new slots are shed once full
+const SLOT_QUEUE_CAPACITY: usize = 64;
+/// Average getShreds latency above which the upstream is considered slow
+const SLOW_UPSTREAM_LATENCY_MS: f64 = 2_000.0;
+/// Consecutive failed getShreds calls after which the upstream is considered unhealthy
+const UPSTREAM_ERROR_THRESHOLD: u32 = 3;
+
+lazy_static::lazy_static! {
+    static ref SAMPLING_SHED_SLOTS: IntCounterVec = register_int_counter_vec!(
+        opts!("tinydancer_sampling_shed_slots", "Slots skipped by the sampler to shed load"),
+        &["reason"]
+    )
+    .unwrap();
+    static ref UPSTREAM_LATENCY_MS: IntGauge = register_int_gauge!(opts!(
+        "tinydancer_upstream_latency_ms",
+        "Moving average of getShreds latency"
+    ))
+    .unwrap();
+    /// getShreds calls in flight by endpoint, slot and indices
+    static ref SHRED_REQUESTS: Coalescer<(String, usize, Vec<usize>), String> =
+        Coalescer::new("get_shreds");
+}
+
+/// Tracks how the upstream copes with sampling requests so load can be shed
+#[derive(Clone, Default)]
+pub struct UpstreamHealth {
+    stats: Arc<Mutex<UpstreamStats>>,
+}
+
+#[derive(Default)]
+struct UpstreamStats {
+    /// exponential moving average
+    latency_ms: f64,
+    consecutive_errors: u32,
+}
+
+impl UpstreamHealth {
+    pub fn record_success(&self, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        let latency_ms = latency.as_millis() as f64;
+        stats.latency_ms = if stats.latency_ms == 0.0 {
+            latency_ms
+        } else {
+            0.8 * stats.latency_ms + 0.2 * latency_ms
+        };
+        stats.consecutive_errors = 0;
+        UPSTREAM_LATENCY_MS.set(stats.latency_ms as i64);
+    }
+
+    pub fn record_error(&self) {
+        self.stats.lock().unwrap().consecutive_errors += 1;
+    }
+
+    /// When degraded only the tip of the chain is sampled
+    pub fn is_degraded(&self) -> bool {
+        let stats = self.stats.lock().unwrap();
+        stats.latency_ms > SLOW_UPSTREAM_LATENCY_MS
+            || stats.consecutive_errors >= UPSTREAM_ERROR_THRESHOLD
+    }
+}
+
+/// Counts slots the sampler skipped, `reason` is used as metric label
+pub fn record_shed_slots(reason: &str, count: u64) {
+    SAMPLING_SHED_SLOTS
+        .with_label_values(&[reason])
+        .inc_by(count);
+}
 
 pub struct SampleService {
     sample_indices: Vec<u64>,
@@ -53,11 +136,20 @@ pub struct SampleService {
     sampler_handle: JoinHandle<()>,
 }
 pub struct SampleServiceConfig {
-    pub cluster: Cluster,
+    pub upstreams: Upstreams,
     pub archive_config: ArchiveConfig,
     pub instance: Arc<rocksdb::DB>,
     pub status_sampler: Arc<Mutex<ClientStatus>>,
     pub sample_qty: usize,
+    pub leader_schedule: LeaderScheduleCache,
+    pub sampling_peers: SamplingPeers,
+    pub verification_events: broadcast::Sender<VerificationEvent>,
+    pub upstream_health: UpstreamHealth,
+    /// Threads used to verify shreds, 0 to use one per core
+    pub verify_threads: usize,
+    pub sampling_strategy: SamplingStrategy,
+    /// Highest slot sampled so far, shared with the lite rpc's health check
+    pub latest_sampled_slot: Arc<AtomicU64>,
 }
 
 #[derive(Clone, Debug)]
@@ -72,16 +164,17 @@ impl ClientService<SampleServiceConfig> for SampleService {
 
     fn new(config: SampleServiceConfig) -> Self {
         let sampler_handle = tokio::spawn(async move {
-            let rpc_url = endpoint(config.cluster);
-            let pub_sub = convert_to_websocket!(rpc_url);
+            let pub_sub = config.upstreams.ws_url();
 
             let mut threads = Vec::default();
 
-            let (slot_update_tx, slot_update_rx) = crossbeam::channel::unbounded::<u64>();
+            let (slot_update_tx, slot_update_rx) =
+                crossbeam::channel::bounded::<u64>(SLOT_QUEUE_CAPACITY);
             let (shred_tx, shred_rx) = crossbeam::channel::unbounded();
             let (verified_shred_tx, verified_shred_rx) = crossbeam::channel::unbounded();
 
             let status_arc = config.status_sampler.clone();
+            let latest_sampled_slot = config.latest_sampled_slot;
 
             // waits on new slots => triggers shred_update_loop
             threads.push(tokio::spawn(slot_update_loop(
@@ -94,14 +187,36 @@ impl ClientService<SampleServiceConfig> for SampleService {
             // verify each shred in shred_verify_loop
             threads.push(tokio::spawn(shred_update_loop(
                 slot_update_rx,
-                rpc_url,
-                shred_tx,
+                config.sampling_peers.clone(),
+                shred_tx.clone(),
                 status_arc,
                 config.sample_qty,
+                config.leader_schedule.clone(),
+                config.upstream_health.clone(),
+                latest_sampled_slot.clone(),
+                config.sampling_strategy.clone(),
+            )));
+
+            // sample slots missed by shred_update_loop
+            threads.push(tokio::spawn(backfill_loop(
+                latest_sampled_slot,
+                config.instance.clone(),
+                config.sampling_peers,
+                shred_tx,
+                config.sample_qty,
+                config.leader_schedule,
+                config.upstream_health,
+                config.sampling_strategy,
             )));
 
             // verify shreds + store in db in shred_archiver
-            threads.push(tokio::spawn(shred_verify_loop(shred_rx, verified_shred_tx)));
+            threads.push(tokio::spawn(shred_verify_loop(
+                shred_rx,
+                verified_shred_tx,
+                config.instance.clone(),
+                config.verification_events,
+                config.verify_threads,
+            )));
 
             threads.push(tokio::spawn(shred_archiver(
                 verified_shred_rx,
@@ -133,6 +248,50 @@ pub fn gen_random_indices(max_shreds_per_slot: usize, sample_qty: usize) -> Vec<
     vec
 }
 
+/// How the indices of the shreds sampled in a slot are picked
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SamplingStrategy {
+    /// uniformly random over the whole slot
+    #[default]
+    Uniform,
+    /// spread evenly over the FEC sets of the slot, random within a set
+    StratifiedFec,
+    /// uniformly random from an rng seeded with `seed` and the slot, for reproducible audits
+    Seeded { seed: u64 },
+}
+
+pub fn gen_sample_indices(
+    strategy: &SamplingStrategy,
+    slot: u64,
+    max_shreds_per_slot: usize,
+    sample_qty: usize,
+) -> Vec<usize> {
+    match strategy {
+        SamplingStrategy::Uniform => gen_random_indices(max_shreds_per_slot, sample_qty),
+        SamplingStrategy::StratifiedFec => {
+            let mut rng = StdRng::from_entropy();
+            let num_fec_sets = max_shreds_per_slot
+                .div_ceil(DATA_SHREDS_PER_FEC_BLOCK)
+                .max(1);
+            (0..sample_qty)
+                .map(|i| {
+                    let fec_set = i * num_fec_sets / sample_qty.max(1);
+                    let start = fec_set * DATA_SHREDS_PER_FEC_BLOCK;
+                    let end = (start + DATA_SHREDS_PER_FEC_BLOCK).min(max_shreds_per_slot);
+                    rng.gen_range(start..end.max(start + 1))
+                })
+                .collect()
+        }
+        SamplingStrategy::Seeded { seed } => {
+            let mut rng = StdRng::seed_from_u64(seed ^ slot);
+            (0..sample_qty)
+                .map(|_| rng.gen_range(0..max_shreds_per_slot))
+                .collect()
+        }
+    }
+}
+
 pub async fn request_shreds(
     slot: usize,
     indices: Vec<usize>,
@@ -150,7 +309,10 @@ pub async fn request_shreds(
     }) // getting one shred just to get max shreds per slot, can maybe randomize the selection here
     .to_string();
 
-    let res = send_rpc_call!(endpoint, request);
+    let key = (endpoint.clone(), slot, indices);
+    let res = SHRED_REQUESTS
+        .run(key, async move { send_rpc_call!(endpoint, request) })
+        .await;
     // info!("{:?}", res);
     serde_json::from_str::<GetShredResponse>(&res)
 }
@@ -185,10 +347,15 @@ async fn slot_update_loop(
 
                 // info!("res: {:?}", msg.to_string().as_str());
                 if let Ok(res) = res {
-                    match slot_update_tx.send(res.params.result.root as u64) {
+                    match slot_update_tx.try_send(res.params.result.root as u64) {
                         Ok(_) => {
                             info!("slot updated: {:?}", res.params.result.root);
                         }
+                        Err(TrySendError::Full(slot)) => {
+                            // sampler can't keep up, drop the slot instead of queueing it
+                            warn!("slot queue full, shedding slot {:?}", slot);
+                            record_shed_slots("queue_full", 1);
+                        }
                         Err(e) => {
                             info!("error here: {:?} {:?}", e, res.params.result.root as u64);
                             continue; // @TODO: we should add retries here incase send fails for some reason
@@ -225,10 +392,14 @@ macro_rules! unwrap_or_return {
     };
 }
 
+/// if `leader_schedule` is provided the leader is taken from the locally derived
+/// schedule instead of the one reported by the rpc, as long as it's derived yet
 async fn get_shreds_and_leader_for_slot(
     slot: u64,
     endpoint: &String,
     sample_qty: usize,
+    strategy: &SamplingStrategy,
+    leader_schedule: Option<&LeaderScheduleCache>,
 ) -> anyhow::Result<(Vec<Option<Shred>>, Pubkey)> {
     // get shred length (max_shreds_per_slot)
     let first_shred = request_shreds(slot as usize, vec![0], endpoint.clone()).await;
@@ -252,8 +423,9 @@ async fn get_shreds_and_leader_for_slot(
         }
     };
 
-    // get a random sample of shreds
-    let mut shred_indices_for_slot = gen_random_indices(max_shreds_per_slot as usize, sample_qty); // unwrap only temporary
+    // get a sample of shreds
+    let mut shred_indices_for_slot =
+        gen_sample_indices(strategy, slot, max_shreds_per_slot as usize, sample_qty);
     shred_indices_for_slot.push(0_usize);
     info!("indices of: {:?} {:?}", shred_indices_for_slot, slot);
 
@@ -274,7 +446,29 @@ async fn get_shreds_and_leader_for_slot(
         .collect();
 
     // info!("before leader");
-    let leader = solana_ledger::shred::Pubkey::from_str(shreds_for_slot.result.leader.as_str())?;
+    let rpc_leader =
+        solana_ledger::shred::Pubkey::from_str(shreds_for_slot.result.leader.as_str())?;
+    let leader = match leader_schedule {
+        Some(leader_schedule) => match leader_schedule.slot_leader(slot).await {
+            Ok(leader) => {
+                if leader != rpc_leader {
+                    warn!(
+                        "rpc reported leader {} for slot {} but local schedule has {}",
+                        rpc_leader, slot, leader
+                    );
+                }
+                leader
+            }
+            Err(e) => {
+                warn!(
+                    "Using the rpc's leader for slot {}, no local schedule yet: {}",
+                    slot, e
+                );
+                rpc_leader
+            }
+        },
+        None => rpc_leader,
+    };
 
     // info!("leader {:?}", leader);
     let mut fullfill_count = AtomicU32::new(0u32);
@@ -316,10 +510,14 @@ async fn get_shreds_and_leader_for_slot(
 
 async fn shred_update_loop(
     slot_update_rx: Receiver<u64>,
-    endpoint: String,
-    shred_tx: Sender<(Vec<Option<Shred>>, solana_ledger::shred::Pubkey)>,
+    sampling_peers: SamplingPeers,
+    shred_tx: Sender<SampledSlot>,
     status_sampler: Arc<Mutex<ClientStatus>>,
     sample_qty: usize,
+    leader_schedule: LeaderScheduleCache,
+    upstream_health: UpstreamHealth,
+    latest_sampled_slot: Arc<AtomicU64>,
+    sampling_strategy: SamplingStrategy,
 ) -> anyhow::Result<()> {
     loop {
         {
@@ -333,21 +531,130 @@ async fn shred_update_loop(
             }
         }
 
-        if let Ok(slot) = slot_update_rx.recv() {
-            let shreds = get_shreds_and_leader_for_slot(slot, &endpoint, sample_qty).await;
+        if let Ok(mut slot) = slot_update_rx.recv() {
+            if upstream_health.is_degraded() {
+                // keep up with the tip, skipping the slots queued behind it
+                let mut shed = 0;
+                while let Ok(newer_slot) = slot_update_rx.try_recv() {
+                    slot = newer_slot;
+                    shed += 1;
+                }
+                if shed > 0 {
+                    warn!("upstream degraded, shed {} stale slots", shed);
+                    record_shed_slots("stale", shed);
+                }
+            }
+            latest_sampled_slot.fetch_max(slot, Ordering::Relaxed);
+
+            let endpoint = sampling_peers.select();
+            let start = Instant::now();
+            let shreds = get_shreds_and_leader_for_slot(
+                slot,
+                &endpoint,
+                sample_qty,
+                &sampling_strategy,
+                Some(&leader_schedule),
+            )
+            .await;
             if let Err(e) = shreds {
+                upstream_health.record_error();
                 info!("{}", e);
                 continue;
             }
+            upstream_health.record_success(start.elapsed());
             let (shreds, leader) = shreds.unwrap();
 
             shred_tx
-                .send((shreds, leader))
+                .send(SampledSlot {
+                    slot,
+                    shreds,
+                    leader,
+                    source: endpoint,
+                    backfilled: false,
+                })
                 .expect("shred tx send error");
         }
     }
 }
 
+/// Periodically looks for slots behind the tip without sampling history (restarts, shed
+/// or skipped slots) and samples them, backing off while the upstream is degraded
+async fn backfill_loop(
+    latest_sampled_slot: Arc<AtomicU64>,
+    instance: Arc<rocksdb::DB>,
+    sampling_peers: SamplingPeers,
+    shred_tx: Sender<SampledSlot>,
+    sample_qty: usize,
+    leader_schedule: LeaderScheduleCache,
+    upstream_health: UpstreamHealth,
+    sampling_strategy: SamplingStrategy,
+) -> anyhow::Result<()> {
+    // slots attempted but without shreds (most probably skipped by the leader)
+    let mut attempted = HashSet::new();
+
+    loop {
+        tokio::time::sleep(BACKFILL_INTERVAL).await;
+
+        let tip = latest_sampled_slot.load(Ordering::Relaxed);
+        if tip == 0 {
+            continue;
+        }
+        let window_start = tip.saturating_sub(BACKFILL_WINDOW);
+        attempted.retain(|slot| *slot >= window_start);
+
+        let gaps: Vec<Slot> = (window_start..tip)
+            .filter(|slot| !attempted.contains(slot) && !has_sample_record(&instance, *slot))
+            .collect();
+        if gaps.is_empty() {
+            continue;
+        }
+        info!("backfilling {} unsampled slots", gaps.len());
+
+        for (i, slot) in gaps.iter().enumerate() {
+            if upstream_health.is_degraded() {
+                // historical samples are the first to go
+                record_shed_slots("historical", (gaps.len() - i) as u64);
+                break;
+            }
+            attempted.insert(*slot);
+
+            let endpoint = sampling_peers.select();
+            let start = Instant::now();
+            match get_shreds_and_leader_for_slot(
+                *slot,
+                &endpoint,
+                sample_qty,
+                &sampling_strategy,
+                Some(&leader_schedule),
+            )
+            .await
+            {
+                Ok((shreds, leader)) => {
+                    upstream_health.record_success(start.elapsed());
+                    shred_tx.send(SampledSlot {
+                        slot: *slot,
+                        shreds,
+                        leader,
+                        source: endpoint,
+                        backfilled: true,
+                    })?;
+                }
+                Err(e) => {
+                    debug!("couldn't backfill slot {}: {}", slot, e);
+                }
+            }
+        }
+    }
+}
+
+fn has_sample_record(instance: &rocksdb::DB, slot: Slot) -> bool {
+    instance
+        .cf_handle(SAMPLE_HISTORY_CF)
+        .and_then(|cf| instance.get_cf(cf, sample_record_key(slot)).ok())
+        .flatten()
+        .is_some()
+}
+
 // use solana_ledger::shred::dispatch;
 
 // verifies the merkle proof of the shread
@@ -374,40 +681,192 @@ pub fn verify_sample(shred: &Shred, leader: solana_ledger::shred::Pubkey) -> boo
     verified
 }
 
+/// runs the signature and merkle checks on a shred without panicking on failure
+pub fn check_sample(shred: &Shred, leader: solana_ledger::shred::Pubkey) -> ShredCheck {
+    let merkle_verified = match shred {
+        Shred::ShredData(ShredData::Merkle(shred)) => {
+            Some(shred.verify_merkle_proof().unwrap_or(false))
+        }
+        Shred::ShredCode(ShredCode::Merkle(shred)) => {
+            Some(shred.verify_merkle_proof().unwrap_or(false))
+        }
+        _ => None,
+    };
+
+    ShredCheck {
+        index: shred.index(),
+        shred_type: format!("{:?}", shred.shred_type()),
+        signature_verified: shred.verify(&leader),
+        merkle_verified,
+    }
+}
+
 pub async fn shred_verify_loop(
-    shred_rx: Receiver<(Vec<Option<Shred>>, solana_ledger::shred::Pubkey)>,
+    shred_rx: Receiver<SampledSlot>,
     verified_shred_tx: Sender<(Shred, solana_ledger::shred::Pubkey)>,
+    instance: Arc<rocksdb::DB>,
+    verification_events: broadcast::Sender<VerificationEvent>,
+    verify_threads: usize,
 ) -> anyhow::Result<()> {
+    let verify_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(verify_threads)
+        .thread_name(|i| format!("tdShredVerify{:02}", i))
+        .build()?;
+
     loop {
-        if let Ok((shreds, leader)) = shred_rx.recv() {
-            shreds.par_iter().for_each(|sh| match sh {
-                Some(shred) => {
-                    let verified = verify_sample(shred, leader);
-                    match verified {
-                        true => {
-                            info!(
-                                "sample {:?} verified for slot: {:?}",
-                                shred.index(),
-                                shred.slot()
-                            );
-                            match verified_shred_tx.send((shred.clone(), leader)) {
-                                Ok(_) => {}
-                                Err(e) => error!("Error verified_shred_tx: {}", e),
-                            }
-                        }
-                        false => info!("sample INVALID for slot : {:?}", shred.slot()),
-                    }
-                }
-                None => {
-                    // info!("none")
-                }
+        if let Ok(sampled_slot) = shred_rx.recv() {
+            // batch whatever is queued so the shreds of every slot are verified at once
+            let mut batch = vec![sampled_slot];
+            batch.extend(shred_rx.try_iter().take(VERIFY_BATCH_MAX_SLOTS - 1));
+
+            let batch_checks: Vec<Vec<ShredCheck>> = verify_pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|sampled_slot| {
+                        sampled_slot
+                            .shreds
+                            .par_iter()
+                            .flatten()
+                            .map(|shred| {
+                                verify_and_forward(
+                                    shred,
+                                    sampled_slot,
+                                    &verified_shred_tx,
+                                    &verification_events,
+                                )
+                            })
+                            .collect()
+                    })
+                    .collect()
             });
+
+            for (sampled_slot, checks) in batch.into_iter().zip(batch_checks) {
+                let SampledSlot {
+                    slot,
+                    leader,
+                    source,
+                    backfilled,
+                    ..
+                } = sampled_slot;
+                let record = SlotSampleRecord {
+                    slot,
+                    leader: leader.to_string(),
+                    source,
+                    verified: !checks.is_empty() && checks.iter().all(|c| c.passed()),
+                    checks,
+                    sampled_at: timestamp(),
+                    backfilled,
+                };
+                if let Err(e) = archive_sample_record(&instance, &record) {
+                    error!("Error archiving sample record for slot {}: {}", slot, e);
+                }
+                let _ = verification_events.send(VerificationEvent::Slot(record));
+            }
         } else {
             // info!("None")
         }
     }
 }
 
+/// checks a shred, forwarding it to the archiver if valid or emitting a fraud proof if not
+fn verify_and_forward(
+    shred: &Shred,
+    sampled_slot: &SampledSlot,
+    verified_shred_tx: &Sender<(Shred, solana_ledger::shred::Pubkey)>,
+    verification_events: &broadcast::Sender<VerificationEvent>,
+) -> ShredCheck {
+    let leader = sampled_slot.leader;
+    let check = check_sample(shred, leader);
+    match check.passed() {
+        true => {
+            info!(
+                "sample {:?} verified for slot: {:?}",
+                shred.index(),
+                shred.slot()
+            );
+            match verified_shred_tx.send((shred.clone(), leader)) {
+                Ok(_) => {}
+                Err(e) => error!("Error verified_shred_tx: {}", e),
+            }
+        }
+        false => {
+            info!("sample INVALID for slot : {:?}", shred.slot());
+            // no receivers is not an error
+            let _ = verification_events.send(VerificationEvent::FraudProof(FraudProof {
+                slot: shred.slot(),
+                leader: leader.to_string(),
+                source: sampled_slot.source.clone(),
+                check: check.clone(),
+                shred_payload: shred.payload().clone(),
+            }));
+        }
+    }
+    check
+}
+
+fn sample_record_key(slot: u64) -> [u8; 32] {
+    hashv(&[SAMPLE_HISTORY_CF.as_bytes(), &slot.to_le_bytes()]).to_bytes()
+}
+
+pub fn archive_sample_record(
+    instance: &rocksdb::DB,
+    record: &SlotSampleRecord,
+) -> Result<(), String> {
+    let cf = instance
+        .cf_handle(SAMPLE_HISTORY_CF)
+        .ok_or_else(|| format!("ColumnFamily {} not found", SAMPLE_HISTORY_CF))?;
+    put_serialized(instance, cf, sample_record_key(record.slot), record)
+}
+
+pub fn get_sample_record(
+    instance: &rocksdb::DB,
+    slot: u64,
+) -> Result<Option<SlotSampleRecord>, String> {
+    let cf = instance
+        .cf_handle(SAMPLE_HISTORY_CF)
+        .ok_or_else(|| format!("ColumnFamily {} not found", SAMPLE_HISTORY_CF))?;
+    get_serialized::<SlotSampleRecord>(instance, cf, sample_record_key(slot))
+}
+
+/// lowest slot in the sampling history, only counting verified slots if `verified_only`
+pub fn lowest_sampled_slot(
+    instance: &rocksdb::DB,
+    verified_only: bool,
+) -> Result<Option<Slot>, String> {
+    let cf = instance
+        .cf_handle(SAMPLE_HISTORY_CF)
+        .ok_or_else(|| format!("ColumnFamily {} not found", SAMPLE_HISTORY_CF))?;
+
+    // records are keyed by hash, the whole history has to be scanned
+    let mut lowest = None;
+    for entry in instance.iterator_cf(cf, IteratorMode::Start) {
+        let (_, value) = entry.map_err(|err| format!("Failed to iterate: {:?}", err))?;
+        let record = serde_json::from_slice::<SlotSampleRecord>(&value)
+            .map_err(|err| format!("Failed to deserialize: {:?}", err))?;
+        if verified_only && !record.verified {
+            continue;
+        }
+        lowest = Some(lowest.map_or(record.slot, |lowest: Slot| lowest.min(record.slot)));
+    }
+
+    Ok(lowest)
+}
+
+/// reads the sampling history of a slot from the archive of a (possibly running) client
+pub fn read_sample_record(
+    archive_path: &str,
+    slot: u64,
+) -> anyhow::Result<Option<SlotSampleRecord>> {
+    let opts = RocksOptions::default();
+    let instance = DB::open_cf_for_read_only(
+        &opts,
+        archive_path,
+        vec![SHRED_CF, SAMPLE_HISTORY_CF],
+        false,
+    )?;
+    get_sample_record(&instance, slot).map_err(|e| anyhow!(e))
+}
+
 // store verified shreds in db
 pub async fn shred_archiver(
     verified_shred_rx: Receiver<(Shred, solana_ledger::shred::Pubkey)>,
@@ -450,8 +909,14 @@ pub async fn shred_archiver(
     }
 }
 
-pub async fn pull_and_verify_shreds(slot: usize, endpoint: String, sample_qty: usize) -> bool {
-    let shreds = get_shreds_and_leader_for_slot(slot as u64, &endpoint, sample_qty).await;
+pub async fn pull_and_verify_shreds(
+    slot: usize,
+    endpoint: String,
+    sample_qty: usize,
+    strategy: &SamplingStrategy,
+) -> bool {
+    let shreds =
+        get_shreds_and_leader_for_slot(slot as u64, &endpoint, sample_qty, strategy, None).await;
     if let Err(e) = shreds {
         info!("{}", e);
         return false;
@@ -505,6 +970,69 @@ pub fn get_serialized<T: DeserializeOwned>(
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+/// Outcome of the checks run against a single sampled shred
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShredCheck {
+    pub index: u32,
+    pub shred_type: String,
+    pub signature_verified: bool,
+    /// None if the shred is a legacy (non merkle) shred, which has no proof to check
+    pub merkle_verified: Option<bool>,
+}
+
+impl ShredCheck {
+    /// Legacy shreds pass on their signature alone
+    pub fn passed(&self) -> bool {
+        self.signature_verified && self.merkle_verified != Some(false)
+    }
+}
+
+/// Sampling history of a slot, persisted in `SAMPLE_HISTORY_CF`
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotSampleRecord {
+    pub slot: u64,
+    pub leader: String,
+    /// rpc endpoint the shreds were pulled from
+    pub source: String,
+    pub checks: Vec<ShredCheck>,
+    pub verified: bool,
+    /// unix timestamp in ms
+    pub sampled_at: u64,
+    /// sampled by the backfill worker after the slot was missed
+    #[serde(default)]
+    pub backfilled: bool,
+}
+
+/// Shreds pulled for a slot, waiting to be verified
+pub struct SampledSlot {
+    pub slot: Slot,
+    pub shreds: Vec<Option<Shred>>,
+    pub leader: solana_ledger::shred::Pubkey,
+    pub source: String,
+    pub backfilled: bool,
+}
+
+/// A shred served by `source` that failed verification against `leader`
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FraudProof {
+    pub slot: u64,
+    pub leader: String,
+    pub source: String,
+    pub check: ShredCheck,
+    pub shred_payload: Vec<u8>,
+}
+
+/// Emitted by the sampler for downstream consumers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VerificationEvent {
+    Slot(SlotSampleRecord),
+    FraudProof(FraudProof),
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SlotSubscribeResponse {
@@ -552,7 +1080,7 @@ pub struct RpcShred {
 
 #[cfg(test)]
 mod tests {
-    use super::{get_serialized, SHRED_CF};
+    use super::{gen_sample_indices, get_serialized, SamplingStrategy, ShredCheck, SHRED_CF};
     use rocksdb::{Options as RocksOptions, DB};
     use solana_client::nonblocking::rpc_client::RpcClient;
     use solana_ledger::shred::{hashv, Shred, ShredType, Signer};
@@ -596,4 +1124,42 @@ mod tests {
             .unwrap();
         println!("AIRDROP CONFIRMED:{}", airdrop_sign);
     }
+
+    #[test]
+    fn legacy_shreds_pass_on_their_signature() {
+        let legacy = ShredCheck {
+            signature_verified: true,
+            merkle_verified: None,
+            ..Default::default()
+        };
+        assert!(legacy.passed());
+
+        let bad_proof = ShredCheck {
+            merkle_verified: Some(false),
+            ..legacy.clone()
+        };
+        assert!(!bad_proof.passed());
+
+        let bad_signature = ShredCheck {
+            signature_verified: false,
+            ..legacy
+        };
+        assert!(!bad_signature.passed());
+    }
+
+    #[test]
+    fn seeded_sampling_is_reproducible() {
+        let strategy = SamplingStrategy::Seeded { seed: 42 };
+        let indices = gen_sample_indices(&strategy, 1963754, 128, 10);
+        assert_eq!(indices, gen_sample_indices(&strategy, 1963754, 128, 10));
+        assert!(indices.iter().all(|i| *i < 128));
+    }
+
+    #[test]
+    fn stratified_sampling_covers_every_fec_set() {
+        // 4 fec sets of 32 data shreds
+        let indices = gen_sample_indices(&SamplingStrategy::StratifiedFec, 1963754, 128, 4);
+        let fec_sets: Vec<usize> = indices.iter().map(|i| i / 32).collect();
+        assert_eq!(fec_sets, vec![0, 1, 2, 3]);
+    }
 }
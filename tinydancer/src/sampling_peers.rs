@@ -0,0 +1,95 @@
+//! Selection of the rpc nodes shreds are sampled from
+use std::str::FromStr;
+
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use tiny_logger::logs::{info, warn};
+
+use crate::leader_schedule::LeaderScheduleCache;
+
+/// A node serving `getShreds`, optionally tied to the validator identity it runs as
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShredSource {
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+}
+
+/// Picks a shred source per slot with a probability proportional to its stake
+#[derive(Clone, Debug)]
+pub struct SamplingPeers {
+    sources: Vec<(ShredSource, u64)>,
+    /// When set every sample is pulled from this endpoint
+    pinned: Option<String>,
+}
+
+impl SamplingPeers {
+    /// `default_endpoint` is used when no sources are configured
+    pub async fn new(
+        sources: Vec<ShredSource>,
+        pinned: Option<String>,
+        default_endpoint: String,
+        leader_schedule: &LeaderScheduleCache,
+    ) -> Self {
+        if sources.is_empty() {
+            return Self {
+                sources: vec![(
+                    ShredSource {
+                        endpoint: default_endpoint,
+                        identity: None,
+                    },
+                    1,
+                )],
+                pinned,
+            };
+        }
+
+        let stakes = match leader_schedule.node_stakes().await {
+            Ok(stakes) => stakes,
+            Err(e) => {
+                warn!("Couldn't fetch stakes, sampling peers uniformly: {}", e);
+                Default::default()
+            }
+        };
+
+        let sources = sources
+            .into_iter()
+            .map(|source| {
+                let stake = source
+                    .identity
+                    .as_ref()
+                    .and_then(|identity| Pubkey::from_str(identity).ok())
+                    .and_then(|identity| stakes.get(&identity).copied())
+                    .unwrap_or_default();
+                info!("Shred source {} has stake {}", source.endpoint, stake);
+                (source, stake)
+            })
+            .collect();
+
+        Self { sources, pinned }
+    }
+
+    /// Endpoint to sample the next slot from
+    pub fn select(&self) -> String {
+        if let Some(pinned) = &self.pinned {
+            return pinned.clone();
+        }
+
+        let mut rng = thread_rng();
+        match WeightedIndex::new(self.sources.iter().map(|(_, stake)| *stake)) {
+            Ok(index) => self.sources[index.sample(&mut rng)].0.endpoint.clone(),
+            // none of the sources are staked
+            Err(_) => self
+                .sources
+                .choose(&mut rng)
+                .map(|(source, _)| source.endpoint.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn sources(&self) -> &[(ShredSource, u64)] {
+        &self.sources
+    }
+}
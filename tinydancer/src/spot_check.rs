@@ -0,0 +1,159 @@
+//! Optional heavier verification tier, periodically running deeper consistency checks
+//! on the bank of a slot that already passed sampling.
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcBlockConfig};
+use solana_sdk::{clock::Slot, commitment_config::CommitmentConfig};
+use solana_transaction_status::{TransactionDetails, UiConfirmedBlock};
+use tiny_logger::logs::{info, warn};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+use crate::{sampler::VerificationEvent, tinydancer::ClientService};
+
+lazy_static::lazy_static! {
+    static ref SPOT_CHECKS: IntCounterVec = register_int_counter_vec!(
+        opts!("tinydancer_spot_checks", "Bank spot checks run, by check and outcome"),
+        &["check", "outcome"]
+    )
+    .unwrap();
+}
+
+/// A consistency check run against the bank of a verified slot
+#[async_trait]
+pub trait SpotCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `Ok(false)` if the bank is inconsistent, `Err` if the check couldn't be run
+    async fn check(&self, rpc_client: &RpcClient, slot: Slot) -> anyhow::Result<bool>;
+}
+
+/// Checks the bank hash chain components the rpc exposes: the block's
+/// `previous_blockhash` must be the parent's last blockhash and block heights must be contiguous
+pub struct BankHashLinkCheck;
+
+async fn get_block(rpc_client: &RpcClient, slot: Slot) -> anyhow::Result<UiConfirmedBlock> {
+    Ok(rpc_client
+        .get_block_with_config(
+            slot,
+            RpcBlockConfig {
+                encoding: None,
+                transaction_details: Some(TransactionDetails::None),
+                rewards: Some(false),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?)
+}
+
+#[async_trait]
+impl SpotCheck for BankHashLinkCheck {
+    fn name(&self) -> &'static str {
+        "bank_hash_link"
+    }
+
+    async fn check(&self, rpc_client: &RpcClient, slot: Slot) -> anyhow::Result<bool> {
+        let block = get_block(rpc_client, slot).await?;
+        let parent = get_block(rpc_client, block.parent_slot).await?;
+
+        if parent.blockhash != block.previous_blockhash {
+            warn!(
+                "slot {} previous blockhash {} doesn't match parent {} blockhash {}",
+                slot, block.previous_blockhash, block.parent_slot, parent.blockhash
+            );
+            return Ok(false);
+        }
+
+        match (parent.block_height, block.block_height) {
+            (Some(parent_height), Some(height)) if parent_height + 1 != height => {
+                warn!(
+                    "slot {} block height {} doesn't follow parent block height {}",
+                    slot, height, parent_height
+                );
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+pub struct SpotCheckService {
+    spot_check_handle: JoinHandle<()>,
+}
+
+pub struct SpotCheckServiceConfig {
+    pub rpc_client: Arc<RpcClient>,
+    pub interval: Duration,
+    pub verification_events: broadcast::Receiver<VerificationEvent>,
+    pub checks: Vec<Box<dyn SpotCheck>>,
+}
+
+#[async_trait]
+impl ClientService<SpotCheckServiceConfig> for SpotCheckService {
+    type ServiceError = tokio::task::JoinError;
+
+    fn new(config: SpotCheckServiceConfig) -> Self {
+        let SpotCheckServiceConfig {
+            rpc_client,
+            interval,
+            mut verification_events,
+            checks,
+        } = config;
+
+        let spot_check_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut latest_verified_slot = None;
+
+            loop {
+                tokio::select! {
+                    event = verification_events.recv() => match event {
+                        Ok(VerificationEvent::Slot(record)) if record.verified => {
+                            latest_verified_slot = Some(record.slot);
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = interval.tick() => {
+                        let Some(slot) = latest_verified_slot.take() else {
+                            continue;
+                        };
+                        run_checks(&rpc_client, &checks, slot).await;
+                    }
+                }
+            }
+        });
+
+        Self { spot_check_handle }
+    }
+
+    async fn join(self) -> std::result::Result<(), Self::ServiceError> {
+        self.spot_check_handle.await
+    }
+}
+
+async fn run_checks(rpc_client: &RpcClient, checks: &[Box<dyn SpotCheck>], slot: Slot) {
+    for check in checks {
+        let outcome = match check.check(rpc_client, slot).await {
+            Ok(true) => "passed",
+            Ok(false) => "failed",
+            Err(e) => {
+                warn!(
+                    "spot check {} errored for slot {}: {}",
+                    check.name(),
+                    slot,
+                    e
+                );
+                "errored"
+            }
+        };
+        SPOT_CHECKS
+            .with_label_values(&[check.name(), outcome])
+            .inc();
+        info!("spot check {} {} for slot {}", check.name(), outcome, slot);
+    }
+}
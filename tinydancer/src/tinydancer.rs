@@ -3,27 +3,45 @@
 
 use std::{
     env,
-    sync::{Arc, Mutex, MutexGuard},
-    thread::Result,
+    sync::{atomic::AtomicU64, Arc, Mutex, MutexGuard},
 };
 
 // use tokio::time::Duration;
 use crate::{
     block_on,
-    rpc_wrapper::{TransactionService, TransactionServiceConfig},
-    sampler::{ArchiveConfig, SampleService, SampleServiceConfig, SHRED_CF},
+    grpc::{GrpcService, GrpcServiceConfig},
+    leader_schedule::{LeaderScheduleCache, EPOCH_STAKES_CF},
+    metrics::{MetricsService, MetricsServiceConfig},
+    proof_sharing::{ProofSharingService, ProofSharingServiceConfig},
+    rpc_wrapper::{
+        configs::{
+            AccessConfig, CleanerConfig, PriorityFeePolicy, ProgramAccountsLimits, TlsConfig,
+            TpuConfig,
+        },
+        grpc::LiteGrpcConfig,
+        upstreams::Upstreams,
+        workers::{LISTENER_PROGRESS_CF, TX_JOURNAL_CF, TX_RETRY_CF, TX_STATUS_CF},
+        TransactionService, TransactionServiceConfig,
+    },
+    sampler::{
+        ArchiveConfig, SampleService, SampleServiceConfig, SamplingStrategy, UpstreamHealth,
+        SAMPLE_HISTORY_CF, SHRED_CF,
+    },
+    sampling_peers::{SamplingPeers, ShredSource},
+    spot_check::{BankHashLinkCheck, SpotCheckService, SpotCheckServiceConfig},
     ui::{UiConfig, UiService},
 };
-use anyhow::anyhow;
 use async_trait::async_trait;
 use futures::{future::join_all, TryFutureExt};
 use rand::seq::index::sample;
 use serde::{Deserialize, Serialize};
-use tiny_logger::logs::info;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_transaction_status::TransactionConfirmationStatus;
+use tiny_logger::logs::{info, warn};
 // use log::info;
 // use log4rs;
 use std::error::Error;
-use tokio::{runtime::Runtime, task::JoinError, try_join};
+use tokio::{runtime::Runtime, sync::broadcast, task::JoinError, try_join};
 // use std::{thread, thread::JoinHandle, time::Duration};
 
 #[async_trait]
@@ -45,22 +63,75 @@ pub struct TinyDancer {
 #[derive(Clone)]
 pub struct TinyDancerConfig {
     pub rpc_endpoint: Cluster,
+    /// Upstream rpcs failed over to when `rpc_endpoint` degrades
+    pub upstreams: Vec<String>,
     pub sample_qty: usize,
     pub enable_ui_service: bool,
     pub archive_config: ArchiveConfig,
     pub tui_monitor: bool,
     pub log_path: String,
+    /// Overrides the known genesis hash of `rpc_endpoint`, required to verify custom clusters
+    pub expected_genesis_hash: Option<String>,
+    /// Nodes shreds are sampled from, defaults to `rpc_endpoint`
+    pub shred_sources: Vec<ShredSource>,
+    /// Pin sampling to a single endpoint instead of a stake weighted selection
+    pub pinned_shred_source: Option<String>,
+    /// Address to serve the verification event stream on, disabled if `None`
+    pub grpc_addr: Option<SocketAddr>,
+    /// Interval between bank spot checks of a verified slot, disabled if `None`
+    pub spot_check_interval: Option<Duration>,
+    /// Threads used to verify shreds, 0 to use one per core
+    pub verify_threads: usize,
+    /// Address our verification events are shared with other instances on
+    pub proof_sharing_addr: Option<SocketAddr>,
+    /// Instances whose verdicts and fraud proofs are corroborated against ours
+    pub proof_sharing_peers: Vec<String>,
+    pub sampling_strategy: SamplingStrategy,
+    pub program_accounts_limits: ProgramAccountsLimits,
+    /// Jito block engine `sendBundle` forwards to, disabled if `None`
+    pub block_engine_url: Option<String>,
+    /// Url confirmations of transactions sent without a callback url are posted to,
+    /// none are posted if `None`
+    pub tx_webhook_url: Option<String>,
+    /// Confirmation status sent transactions are done at, finalized if `None`
+    pub tx_terminal_status: Option<TransactionConfirmationStatus>,
+    /// Priority fee required of sent transactions
+    pub priority_fee_policy: PriorityFeePolicy,
+    /// How transactions are sent to the leaders' tpus
+    pub tpu_config: TpuConfig,
+    /// How often the lite rpc cleans up and how long it keeps transactions, blocks
+    /// and signature subscriptions
+    pub cleaner_config: CleanerConfig,
+    /// Who may call the lite rpc
+    pub access_config: AccessConfig,
+    /// Serve the lite rpc over tls, plaintext if `None`
+    pub tls_config: Option<TlsConfig>,
+    pub lite_rpc_http_addr: SocketAddr,
+    pub lite_rpc_ws_addr: SocketAddr,
+    /// Address to serve the lite rpc over grpc on, disabled if `None`
+    pub lite_grpc_addr: Option<SocketAddr>,
+    /// Address to serve prometheus metrics on, disabled if `None`
+    pub metrics_addr: Option<SocketAddr>,
+    /// Keypair file of a staked validator identity to send transactions to leaders
+    /// with, an ephemeral unstaked identity if `None`
+    pub tpu_identity: Option<String>,
 }
 
+use itertools::Itertools;
 use solana_metrics::datapoint_info;
 use std::ffi::OsString;
 use std::fs::read_dir;
 use std::io;
 use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
+
+/// Verification events buffered for slow grpc subscribers
+const VERIFICATION_EVENTS_CAPACITY: usize = 1024;
 
 impl TinyDancer {
-    pub async fn start(config: TinyDancerConfig) -> Result<()> {
+    pub async fn start(config: TinyDancerConfig) -> anyhow::Result<()> {
         let status = ClientStatus::Initializing(String::from("Starting Up Tinydancer"));
 
         let client_status = Arc::new(Mutex::new(status));
@@ -69,36 +140,173 @@ impl TinyDancer {
         let TinyDancerConfig {
             enable_ui_service,
             rpc_endpoint,
+            upstreams,
             sample_qty,
             tui_monitor,
             log_path,
             archive_config,
+            expected_genesis_hash,
+            shred_sources,
+            pinned_shred_source,
+            grpc_addr,
+            spot_check_interval,
+            verify_threads,
+            proof_sharing_addr,
+            proof_sharing_peers,
+            sampling_strategy,
+            program_accounts_limits,
+            block_engine_url,
+            tx_webhook_url,
+            tx_terminal_status,
+            priority_fee_policy,
+            tpu_config,
+            cleaner_config,
+            access_config,
+            tls_config,
+            lite_rpc_http_addr,
+            lite_rpc_ws_addr,
+            lite_grpc_addr,
+            metrics_addr,
+            tpu_identity,
         } = config.clone();
         std::env::set_var("RUST_LOG", "info");
         tiny_logger::setup_file_with_default(&log_path, "RUST_LOG");
 
+        let upstreams = Upstreams::new(
+            std::iter::once(endpoint(rpc_endpoint.clone()))
+                .chain(upstreams)
+                .unique()
+                .collect(),
+        );
+
+        // refuse to start against a cluster we don't expect, every upstream has to
+        // serve the same one. Unreachable upstreams are only moved off for now
+        let mut expected_genesis_hash =
+            expected_genesis_hash.or_else(|| rpc_endpoint.genesis_hash().map(String::from));
+        for url in upstreams.urls() {
+            match verify_genesis_hash(&RpcClient::new(url.clone()), expected_genesis_hash.clone())
+                .await
+            {
+                Ok(genesis_hash) => {
+                    expected_genesis_hash.get_or_insert(genesis_hash);
+                }
+                Err(err) if err.is::<GenesisHashMismatch>() => return Err(err),
+                Err(err) => {
+                    warn!("Couldn't check the genesis hash of upstream rpc {url}: {err}");
+                    upstreams.mark_unreachable(&url);
+                }
+            }
+        }
+
+        let rpc_client = Arc::new(upstreams.rpc_client());
+
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
         opts.set_error_if_exists(false);
         opts.create_missing_column_families(true);
 
         // setup db
-        let db = rocksdb::DB::open_cf(&opts, archive_config.clone().archive_path, vec![SHRED_CF])
-            .unwrap();
+        let db = rocksdb::DB::open_cf(
+            &opts,
+            archive_config.clone().archive_path,
+            vec![
+                SHRED_CF,
+                SAMPLE_HISTORY_CF,
+                TX_STATUS_CF,
+                TX_RETRY_CF,
+                LISTENER_PROGRESS_CF,
+                TX_JOURNAL_CF,
+                EPOCH_STAKES_CF,
+            ],
+        )
+        .unwrap();
         let db = Arc::new(db);
 
+        let leader_schedule = LeaderScheduleCache::new(rpc_client.clone(), db.clone())
+            .await
+            .expect("error fetching epoch schedule");
+        leader_schedule.clone().snapshot_stakes();
+        let sampling_peers = SamplingPeers::new(
+            shred_sources,
+            pinned_shred_source,
+            endpoint(rpc_endpoint.clone()),
+            &leader_schedule,
+        )
+        .await;
+
+        let (verification_events, _) = broadcast::channel(VERIFICATION_EVENTS_CAPACITY);
+        let latest_sampled_slot = Arc::new(AtomicU64::new(0));
+
         let sample_service_config = SampleServiceConfig {
-            cluster: rpc_endpoint.clone(),
+            upstreams: upstreams.clone(),
             archive_config,
             instance: db.clone(),
             status_sampler,
             sample_qty,
+            leader_schedule: leader_schedule.clone(),
+            sampling_peers,
+            verification_events: verification_events.clone(),
+            upstream_health: UpstreamHealth::default(),
+            verify_threads,
+            sampling_strategy: sampling_strategy.clone(),
+            latest_sampled_slot: latest_sampled_slot.clone(),
         };
         let sample_service = SampleService::new(sample_service_config);
 
+        let spot_check_service = spot_check_interval.map(|interval| {
+            SpotCheckService::new(SpotCheckServiceConfig {
+                rpc_client: rpc_client.clone(),
+                interval,
+                verification_events: verification_events.subscribe(),
+                checks: vec![Box::new(BankHashLinkCheck)],
+            })
+        });
+
+        let proof_sharing_service =
+            if proof_sharing_addr.is_some() || !proof_sharing_peers.is_empty() {
+                Some(ProofSharingService::new(ProofSharingServiceConfig {
+                    listen_addr: proof_sharing_addr,
+                    peers: proof_sharing_peers,
+                    verification_events: verification_events.clone(),
+                    instance: db.clone(),
+                    leader_schedule: leader_schedule.clone(),
+                }))
+            } else {
+                None
+            };
+
+        let grpc_service = grpc_addr.map(|addr| {
+            GrpcService::new(GrpcServiceConfig {
+                addr,
+                verification_events: verification_events.clone(),
+            })
+        });
+
+        let metrics_service =
+            metrics_addr.map(|addr| MetricsService::new(MetricsServiceConfig { addr }));
+
         let transaction_service = TransactionService::new(TransactionServiceConfig {
-            cluster: rpc_endpoint.clone(),
+            upstreams,
             db_instance: db.clone(),
+            sampling_strategy,
+            program_accounts_limits,
+            latest_sampled_slot,
+            leader_schedule,
+            block_engine_url,
+            tx_webhook_url,
+            tx_terminal_status,
+            priority_fee_policy,
+            tpu_config,
+            access_config,
+            tls_config,
+            http_addr: lite_rpc_http_addr,
+            ws_addr: lite_rpc_ws_addr,
+            grpc_config: lite_grpc_addr.map(|addr| LiteGrpcConfig {
+                addr,
+                verification_events,
+            }),
+            tpu_identity,
+            cleaner_config,
         });
 
         let ui_service = if enable_ui_service || tui_monitor {
@@ -122,6 +330,31 @@ impl TinyDancer {
             .await
             .expect("ERROR IN SIMPLE PAYMENT SERVICE");
 
+        if let Some(grpc_service) = grpc_service {
+            grpc_service.join().await.expect("error in grpc service");
+        }
+
+        if let Some(metrics_service) = metrics_service {
+            metrics_service
+                .join()
+                .await
+                .expect("error in metrics service");
+        }
+
+        if let Some(proof_sharing_service) = proof_sharing_service {
+            proof_sharing_service
+                .join()
+                .await
+                .expect("error in proof sharing service");
+        }
+
+        if let Some(spot_check_service) = spot_check_service {
+            spot_check_service
+                .join()
+                .await
+                .expect("error in spot check service");
+        }
+
         if let Some(ui_service) = ui_service {
             block_on!(async { ui_service.join().await }, "Ui Service Error");
         }
@@ -138,6 +371,53 @@ pub enum Cluster {
     Custom(String),
 }
 
+impl Cluster {
+    /// Genesis hash of the well known clusters, `None` for clusters that can't be known upfront
+    pub fn genesis_hash(&self) -> Option<&'static str> {
+        match self {
+            Cluster::Mainnet => Some("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d"),
+            Cluster::Devnet => Some("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG"),
+            Cluster::Localnet | Cluster::Custom(_) => None,
+        }
+    }
+}
+
+/// The rpc serves another cluster than the expected one
+#[derive(Debug, thiserror::Error)]
+#[error("genesis hash mismatch, expected {expected} but rpc reported {reported}")]
+pub struct GenesisHashMismatch {
+    pub expected: String,
+    pub reported: String,
+}
+
+/// Compares the genesis hash reported by the rpc against the expected one, returns
+/// the reported one
+pub async fn verify_genesis_hash(
+    rpc_client: &RpcClient,
+    expected_genesis_hash: Option<String>,
+) -> anyhow::Result<String> {
+    let genesis_hash = rpc_client.get_genesis_hash().await?.to_string();
+
+    match expected_genesis_hash {
+        Some(expected) if expected != genesis_hash => Err(GenesisHashMismatch {
+            expected,
+            reported: genesis_hash,
+        }
+        .into()),
+        Some(_) => {
+            info!("Verified genesis hash {}", genesis_hash);
+            Ok(genesis_hash)
+        }
+        None => {
+            info!(
+                "No expected genesis hash configured, rpc reported {}",
+                genesis_hash
+            );
+            Ok(genesis_hash)
+        }
+    }
+}
+
 pub fn endpoint(cluster: Cluster) -> String {
     let cluster = cluster;
     match cluster {